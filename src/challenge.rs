@@ -0,0 +1,96 @@
+use crate::request_handler::ResponseData;
+
+/// Body bytes searched for challenge markers
+const SCAN_BYTES: usize = 64 * 1024;
+
+/// Recognizes anti-bot interstitials (captchas, "checking your browser" pages) that come
+/// back instead of the requested content, often with a 200 status
+pub trait ChallengeDetector: Send + Sync {
+    /// Why `response` looks like a challenge page, or `None` if it looks like real content
+    fn detect(&self, response: &ResponseData) -> Option<String>;
+}
+
+/// Detects challenges by the `cf-mitigated` header and by markers known from common
+/// challenge pages (Cloudflare, DDoS-Guard, reCAPTCHA, hCaptcha)
+#[derive(Debug, Clone)]
+pub struct ChallengeMarkers {
+    markers: Vec<String>,
+}
+
+impl Default for ChallengeMarkers {
+    fn default() -> Self {
+        Self::new(
+            [
+                "cf-browser-verification",
+                "/cdn-cgi/challenge-platform/",
+                "<title>just a moment...</title>",
+                "attention required! | cloudflare",
+                "ddos-guard",
+                "class=\"g-recaptcha\"",
+                "class=\"h-captcha\"",
+            ]
+            .iter()
+            .map(|marker| marker.to_string())
+            .collect(),
+        )
+    }
+}
+
+impl ChallengeMarkers {
+    /// Match any of `markers` (case-insensitive) in the start of the body
+    pub fn new(markers: Vec<String>) -> Self {
+        Self {
+            markers: markers.into_iter().map(|marker| marker.to_lowercase()).collect(),
+        }
+    }
+}
+
+impl ChallengeDetector for ChallengeMarkers {
+    fn detect(&self, response: &ResponseData) -> Option<String> {
+        if response
+            .header("cf-mitigated")
+            .is_some_and(|value| value.eq_ignore_ascii_case("challenge"))
+        {
+            return Some("cf-mitigated: challenge".to_string());
+        }
+
+        let scanned = &response.body[..response.body.len().min(SCAN_BYTES)];
+        let body = String::from_utf8_lossy(scanned).to_lowercase();
+        self.markers
+            .iter()
+            .find(|marker| body.contains(marker.as_str()))
+            .map(|marker| format!("challenge marker {:?} in body", marker))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &str, headers: &[(&str, &str)]) -> ResponseData {
+        ResponseData {
+            status: 200,
+            headers: headers
+                .iter()
+                .map(|(key, value)| (key.to_string(), vec![value.to_string()]))
+                .collect(),
+            body: body.as_bytes().to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_default_markers() {
+        let detector = ChallengeMarkers::default();
+        assert!(detector.detect(&response("<html><title>Just a moment...</title>", &[])).is_some());
+        assert!(detector.detect(&response("", &[("cf-mitigated", "challenge")])).is_some());
+        assert!(detector.detect(&response("<html>regular page</html>", &[])).is_none());
+    }
+
+    #[test]
+    fn test_custom_markers() {
+        let detector = ChallengeMarkers::new(vec!["Prove You Are Human".to_string()]);
+        assert!(detector.detect(&response("please prove you are human", &[])).is_some());
+        assert!(detector.detect(&response("<title>Just a moment...</title>", &[])).is_none());
+    }
+}
@@ -1,5 +1,9 @@
 use std::ffi::CString;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
 use tracing::{debug, error, info, warn};
 use once_cell::sync::Lazy;
 
@@ -10,24 +14,343 @@ static ROUTER_STATE: Lazy<Arc<Mutex<RouterState>>> = Lazy::new(|| {
     Arc::new(Mutex::new(RouterState {
         initialized: false,
         running: false,
+        watchdog_spawned: false,
+        started_at: None,
+        last_probe: None,
+        last_recovery: None,
     }))
 });
 
+/// File in the data directory holding the router identity
+const ROUTER_KEYS_FILE: &str = "router.keys";
+
+// Woken when the router starts shutting down, so requests through it stop waiting
+static ROUTER_SHUTDOWN: Lazy<Arc<Notify>> = Lazy::new(|| Arc::new(Notify::new()));
+
+// Time of the last request that needed the router, and how many still use it
+static ROUTER_ACTIVITY: Lazy<IdleTracker> = Lazy::new(IdleTracker::new);
+
+/// Receives router log lines, see `I2PDRouter::set_log_sink`
+pub type LogSink = Arc<dyn Fn(String) + Send + Sync>;
+
+static LOG_SINK: Lazy<Mutex<Option<LogSink>>> = Lazy::new(|| Mutex::new(None));
+
+// Called by the wrapper for every router log line once a sink is set
+unsafe extern "C" fn forward_log_line(line: *const std::os::raw::c_char) {
+    if line.is_null() {
+        return;
+    }
+    let line = std::ffi::CStr::from_ptr(line).to_string_lossy().into_owned();
+    let sink = LOG_SINK.lock().unwrap().clone();
+    if let Some(sink) = sink {
+        // A panic must not unwind into the router's logging thread
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| sink(line)));
+    }
+}
+
 struct RouterState {
     initialized: bool,
     running: bool,
+    watchdog_spawned: bool,
+    started_at: Option<Instant>,
+    // Last check of the proxy ports, and last restart because they were not listening
+    last_probe: Option<Instant>,
+    last_recovery: Option<Instant>,
+}
+
+/// Configuration for the embedded i2pd router
+#[derive(Debug, Clone)]
+pub struct RouterConfig {
+    /// i2pd data directory (`-datadir`), holding the router identity among other state.
+    /// Defaults to the working directory.
+    pub config_dir: Option<String>,
+    pub idle_shutdown: Option<Duration>,
+    /// Local port of the router's HTTP proxy
+    pub http_proxy_port: u16,
+    /// Local port of the router's HTTPS (CONNECT) proxy
+    pub https_proxy_port: u16,
+}
+
+impl Default for RouterConfig {
+    fn default() -> Self {
+        Self {
+            config_dir: None,
+            idle_shutdown: None,
+            http_proxy_port: 4444,
+            https_proxy_port: 4447,
+        }
+    }
+}
+
+impl RouterConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn http_proxy_port(mut self, port: u16) -> Self {
+        self.http_proxy_port = port;
+        self
+    }
+
+    pub fn https_proxy_port(mut self, port: u16) -> Self {
+        self.https_proxy_port = port;
+        self
+    }
+
+    pub fn config_dir(mut self, dir: impl Into<String>) -> Self {
+        self.config_dir = Some(dir.into());
+        self
+    }
+
+    /// Keep the router's state in `dir` so restarts reuse the identity stored there
+    /// (`router.keys`) instead of generating a new one. Same as `config_dir`.
+    pub fn datadir(self, dir: impl Into<String>) -> Self {
+        self.config_dir(dir)
+    }
+
+    /// Where i2pd keeps the router's private identity keys
+    pub fn router_keys_path(&self) -> PathBuf {
+        PathBuf::from(self.config_dir.as_deref().unwrap_or(".")).join(ROUTER_KEYS_FILE)
+    }
+
+    /// Stop the router after `after` without any request needing it. The next I2P request
+    /// restarts it and waits until it is up again.
+    pub fn idle_shutdown(mut self, after: Duration) -> Self {
+        self.idle_shutdown = Some(after);
+        self
+    }
+}
+
+/// The router's config directory cannot be created or written to, so i2pd could not
+/// store its keys and state there
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigDirNotWritable {
+    pub dir: PathBuf,
+    pub reason: String,
+}
+
+impl std::fmt::Display for ConfigDirNotWritable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Router config directory {} is not writable: {}", self.dir.display(), self.reason)
+    }
+}
+
+impl std::error::Error for ConfigDirNotWritable {}
+
+/// Why `I2PDRouter::init` failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum RouterInitError {
+    /// The config directory cannot hold the keys and state i2pd generates
+    ConfigDirNotWritable(ConfigDirNotWritable),
+    /// Any other failure
+    Other(String),
+}
+
+impl std::fmt::Display for RouterInitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RouterInitError::ConfigDirNotWritable(e) => write!(f, "{}", e),
+            RouterInitError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for RouterInitError {}
+
+impl From<ConfigDirNotWritable> for RouterInitError {
+    fn from(e: ConfigDirNotWritable) -> Self {
+        RouterInitError::ConfigDirNotWritable(e)
+    }
+}
+
+impl From<RouterInitError> for String {
+    fn from(e: RouterInitError) -> Self {
+        e.to_string()
+    }
+}
+
+/// Make sure `dir` exists (creating it if needed) and that files can be created in it
+pub fn check_config_dir(dir: &str) -> Result<(), ConfigDirNotWritable> {
+    let path = PathBuf::from(dir);
+    let not_writable = |reason: String| ConfigDirNotWritable { dir: path.clone(), reason };
+
+    std::fs::create_dir_all(&path).map_err(|e| not_writable(format!("cannot create it: {}", e)))?;
+    let probe = path.join(format!(".i2ptunnel-write-check-{}", std::process::id()));
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&probe)
+        .map_err(|e| not_writable(format!("cannot create files in it: {}", e)))?;
+    let _ = std::fs::remove_file(&probe);
+    Ok(())
+}
+
+/// Tracks when the router was last needed and how many requests are using it right now
+struct IdleTracker {
+    last_activity: Mutex<Instant>,
+    in_flight: AtomicUsize,
+}
+
+impl IdleTracker {
+    fn new() -> Self {
+        Self {
+            last_activity: Mutex::new(Instant::now()),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    fn idle_for(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(*self.last_activity.lock().unwrap())
+    }
+
+    fn begin(&self) -> RouterInUse<'_> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        self.touch();
+        RouterInUse { tracker: self }
+    }
+
+    fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+}
+
+/// Keeps the idle watchdog from stopping the router while held, e.g. for the whole of a long
+/// download. Idle time counts from when the last one is dropped.
+pub struct RouterInUse<'a> {
+    tracker: &'a IdleTracker,
+}
+
+impl Drop for RouterInUse<'_> {
+    fn drop(&mut self) {
+        self.tracker.touch();
+        self.tracker.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// One watchdog tick: call `stop` if the router is running, no request is using it and it has
+/// been idle for at least `idle_after`. Returns true if a shutdown was triggered.
+fn idle_tick<F>(tracker: &IdleTracker, idle_after: Duration, now: Instant, running: bool, stop: F) -> bool
+where
+    F: FnOnce() -> Result<(), String>,
+{
+    if !running || tracker.in_flight() > 0 || tracker.idle_for(now) < idle_after {
+        return false;
+    }
+
+    info!("i2pd router idle for more than {:?}, shutting it down", idle_after);
+    if let Err(e) = stop() {
+        warn!("Failed to stop idle i2pd router: {}", e);
+        return false;
+    }
+    true
+}
+
+/// Background thread stopping the embedded router once it has been idle for `idle_after`.
+/// It lives for the rest of the process, so restarts don't spawn additional watchdogs.
+fn spawn_idle_watchdog(idle_after: Duration) {
+    let poll = (idle_after / 4).clamp(Duration::from_millis(100), Duration::from_secs(30));
+    let spawned = std::thread::Builder::new()
+        .name("i2pd-idle-watchdog".to_string())
+        .spawn(move || loop {
+            std::thread::sleep(poll);
+            let running = ROUTER_STATE.lock().unwrap().running;
+            idle_tick(&ROUTER_ACTIVITY, idle_after, Instant::now(), running, stop_embedded_router);
+        });
+
+    if let Err(e) = spawned {
+        warn!("Failed to spawn i2pd idle watchdog: {}", e);
+    }
+}
+
+/// What the rest of the crate needs from an I2P router: a way to start it and the local
+/// proxy ports to send traffic to. Lets tests stand in a fake for the embedded i2pd.
+pub trait Router: Send + Sync {
+    fn ensure_running(&self) -> Result<(), String>;
+    fn is_running(&self) -> bool;
+    fn http_proxy_port(&self) -> u16;
+    fn https_proxy_port(&self) -> u16;
+
+    /// Stop the router; stopping one that is not running does nothing
+    fn stop(&self) -> Result<(), String>;
+
+    /// Stop and start again to rebuild tunnels, e.g. after the network changed. A router
+    /// that is not running stays stopped.
+    fn restart(&self) -> Result<(), String> {
+        if !self.is_running() {
+            debug!("Router not running, nothing to restart");
+            return Ok(());
+        }
+        self.stop()?;
+        self.ensure_running()
+    }
+
+    /// Notified when the router begins shutting down; requests waiting on it give up then
+    fn shutdown_signal(&self) -> Arc<Notify> {
+        ROUTER_SHUTDOWN.clone()
+    }
+
+    /// Held by a request for as long as it sends or receives through the router, so an idle
+    /// shutdown doesn't cut it off
+    fn in_use(&self) -> RouterInUse<'static> {
+        ROUTER_ACTIVITY.begin()
+    }
+}
+
+/// The process-wide router from `get_or_init_router`, looked up on every call so that
+/// `configure_router` still applies after handlers holding this were created
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GlobalRouter;
+
+impl Router for GlobalRouter {
+    fn ensure_running(&self) -> Result<(), String> {
+        ensure_router_running()
+    }
+
+    fn is_running(&self) -> bool {
+        let router = GLOBAL_ROUTER.lock().unwrap().clone();
+        router.is_some_and(|router| router.is_running())
+    }
+
+    // Reading the ports must not create the router, or `configure_router` would fail later
+    fn http_proxy_port(&self) -> u16 {
+        global_router_config().http_proxy_port
+    }
+
+    fn https_proxy_port(&self) -> u16 {
+        global_router_config().https_proxy_port
+    }
+
+    fn stop(&self) -> Result<(), String> {
+        stop_embedded_router()
+    }
 }
 
 pub struct I2PDRouter {
-    config_dir: Option<String>,
+    config: RouterConfig,
 }
 
 impl I2PDRouter {
     pub fn new(config_dir: Option<String>) -> Self {
-        Self { config_dir }
+        Self::with_config(RouterConfig {
+            config_dir,
+            ..RouterConfig::default()
+        })
+    }
+
+    pub fn with_config(config: RouterConfig) -> Self {
+        Self { config }
     }
 
-    pub fn init(&self) -> Result<(), String> {
+    pub fn config(&self) -> &RouterConfig {
+        &self.config
+    }
+
+    pub fn init(&self) -> Result<(), RouterInitError> {
         let mut state = ROUTER_STATE.lock().unwrap();
         if state.initialized {
             debug!("i2pd router already initialized");
@@ -35,11 +358,14 @@ impl I2PDRouter {
         }
 
         info!("Initializing i2pd router");
-        let config_dir_cstr = if let Some(ref dir) = self.config_dir {
-            CString::new(dir.clone()).map_err(|e| format!("Invalid config directory: {}", e))?
-        } else {
-            CString::new(".").unwrap()
-        };
+        // i2pd needs to store the keys it generates, and only reports an opaque code if it can't
+        let dir = self.config.config_dir.as_deref().unwrap_or(".");
+        if let Err(e) = check_config_dir(dir) {
+            error!("{}", e);
+            return Err(e.into());
+        }
+        let config_dir_cstr = CString::new(dir)
+            .map_err(|e| RouterInitError::Other(format!("Invalid config directory: {}", e)))?;
 
         let result = unsafe {
             i2pd_router_init(config_dir_cstr.as_ptr())
@@ -47,11 +373,16 @@ impl I2PDRouter {
 
         if result == 0 {
             state.initialized = true;
-            info!("i2pd router initialized successfully");
+            let keys = self.config.router_keys_path();
+            if keys.exists() {
+                info!("i2pd router initialized, reusing identity from {}", keys.display());
+            } else {
+                info!("i2pd router initialized, a new identity will be stored in {}", keys.display());
+            }
             Ok(())
         } else {
             error!("Failed to initialize i2pd router");
-            Err("Failed to initialize i2pd router".to_string())
+            Err(RouterInitError::Other("Failed to initialize i2pd router".to_string()))
         }
     }
 
@@ -77,17 +408,29 @@ impl I2PDRouter {
             // Start HTTP and HTTPS proxies
             let http_result = unsafe {
                 let addr = CString::new("127.0.0.1").unwrap();
-                i2pd_http_proxy_start(addr.as_ptr(), 4444)
+                i2pd_http_proxy_start(addr.as_ptr(), self.config.http_proxy_port)
             };
             
             let https_result = unsafe {
                 let addr = CString::new("127.0.0.1").unwrap();
-                i2pd_https_proxy_start(addr.as_ptr(), 4447)
+                i2pd_https_proxy_start(addr.as_ptr(), self.config.https_proxy_port)
             };
 
+            ROUTER_ACTIVITY.touch();
+            if let Some(idle_after) = self.config.idle_shutdown {
+                if !state.watchdog_spawned {
+                    state.watchdog_spawned = true;
+                    spawn_idle_watchdog(idle_after);
+                }
+            }
+
+            state.started_at = Some(Instant::now());
             if http_result == 0 && https_result == 0 {
                 state.running = true;
-                info!("i2pd router started successfully with HTTP (4444) and HTTPS (4447) proxies");
+                info!(
+                    "i2pd router started successfully with HTTP ({}) and HTTPS ({}) proxies",
+                    self.config.http_proxy_port, self.config.https_proxy_port
+                );
                 Ok(())
             } else {
                 warn!("i2pd router started but proxy initialization had issues");
@@ -100,26 +443,33 @@ impl I2PDRouter {
         }
     }
 
-    pub fn stop(&self) -> Result<(), String> {
-        let mut state = ROUTER_STATE.lock().unwrap();
-        if !state.running {
-            debug!("i2pd router not running");
-            return Ok(());
+    /// Hand each line the router logs to `sink`, e.g. to show router diagnostics in the
+    /// app's own UI, instead of writing it to the log file in the data directory. Takes effect
+    /// when the router next starts; a later call replaces the sink right away. Lines arrive
+    /// on the router's logging thread, so `sink` should return quickly.
+    pub fn set_log_sink(&self, sink: LogSink) {
+        *LOG_SINK.lock().unwrap() = Some(sink);
+        unsafe {
+            i2pd_set_log_callback(Some(forward_log_line));
+        }
+        if self.is_running() {
+            info!("Log sink set while the router runs, it receives lines after the next start");
         }
+    }
 
-        info!("Stopping i2pd router");
-        let result = unsafe {
-            i2pd_router_stop()
-        };
+    pub fn stop(&self) -> Result<(), String> {
+        stop_embedded_router()
+    }
 
-        if result == 0 {
-            state.running = false;
-            info!("i2pd router stopped successfully");
-            Ok(())
-        } else {
-            error!("Failed to stop i2pd router");
-            Err("Failed to stop i2pd router".to_string())
+    /// Stop and start the router again if it is running, leaving a stopped one alone
+    pub fn restart(&self) -> Result<(), String> {
+        if !self.is_running() {
+            debug!("i2pd router not running, nothing to restart");
+            return Ok(());
         }
+        info!("Restarting i2pd router");
+        self.stop()?;
+        self.start()
     }
 
     pub fn is_running(&self) -> bool {
@@ -133,6 +483,119 @@ impl I2PDRouter {
         }
         Ok(())
     }
+
+    /// Whether the router's HTTP and HTTPS proxies accept TCP connections right now
+    pub fn proxies_reachable(&self) -> bool {
+        proxies_listening(self.config.http_proxy_port, self.config.https_proxy_port)
+    }
+}
+
+// The embedded router is one per process, whichever `I2PDRouter` started it
+fn stop_embedded_router() -> Result<(), String> {
+    let mut state = ROUTER_STATE.lock().unwrap();
+    if !state.running {
+        debug!("i2pd router not running");
+        return Ok(());
+    }
+
+    info!("Stopping i2pd router");
+    // Requests through the router would otherwise wait out their timeouts
+    ROUTER_SHUTDOWN.notify_waiters();
+    let result = unsafe {
+        i2pd_router_stop()
+    };
+
+    if result == 0 {
+        state.running = false;
+        info!("i2pd router stopped successfully");
+        Ok(())
+    } else {
+        error!("Failed to stop i2pd router");
+        Err("Failed to stop i2pd router".to_string())
+    }
+}
+
+// How long a proxy port may take to accept a probe connection
+const PROXY_PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Minimum time between two checks of the proxy ports
+const PROXY_PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+// Time the proxies get to come up after the router starts before they count as missing
+const ROUTER_WARMUP: Duration = Duration::from_secs(60);
+
+// Minimum time between two restarts because the proxies were not listening
+const MIN_RECOVERY_INTERVAL: Duration = Duration::from_secs(300);
+
+fn port_listening(port: u16) -> bool {
+    let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+    match std::net::TcpStream::connect_timeout(&addr, PROXY_PROBE_TIMEOUT) {
+        Ok(_) => true,
+        Err(e) => {
+            debug!("Router proxy port {} not reachable: {}", port, e);
+            false
+        }
+    }
+}
+
+fn proxies_listening(http_port: u16, https_port: u16) -> bool {
+    port_listening(http_port) && port_listening(https_port)
+}
+
+/// Whether a router started at `started_at` may be restarted at `now` because its proxies
+/// are not listening: not while it is still warming up, nor soon after the last such restart
+fn recovery_due(now: Instant, started_at: Option<Instant>, last_recovery: Option<Instant>) -> bool {
+    let within = |since: Option<Instant>, window: Duration| {
+        since.is_some_and(|since| now.saturating_duration_since(since) < window)
+    };
+    !within(started_at, ROUTER_WARMUP) && !within(last_recovery, MIN_RECOVERY_INTERVAL)
+}
+
+/// Restart `router` if it reports running while its proxies are not listening. The ports are
+/// probed at most every `PROXY_PROBE_INTERVAL`, and only once recovery is due.
+fn recover_unreachable_proxies(router: &I2PDRouter) {
+    {
+        let mut state = ROUTER_STATE.lock().unwrap();
+        let now = Instant::now();
+        if state.last_probe.is_some_and(|probed| now.saturating_duration_since(probed) < PROXY_PROBE_INTERVAL)
+            || !recovery_due(now, state.started_at, state.last_recovery)
+        {
+            return;
+        }
+        state.last_probe = Some(now);
+    }
+
+    if router.proxies_reachable() {
+        return;
+    }
+    ROUTER_STATE.lock().unwrap().last_recovery = Some(Instant::now());
+    warn!("i2pd router is running but its proxies are not listening, restarting it");
+    if let Err(e) = router.stop().and_then(|_| router.start()) {
+        warn!("Failed to restart i2pd router with unreachable proxies: {}", e);
+    }
+}
+
+impl Router for I2PDRouter {
+    fn ensure_running(&self) -> Result<(), String> {
+        ROUTER_ACTIVITY.touch();
+        I2PDRouter::ensure_running(self)
+    }
+
+    fn is_running(&self) -> bool {
+        I2PDRouter::is_running(self)
+    }
+
+    fn http_proxy_port(&self) -> u16 {
+        self.config.http_proxy_port
+    }
+
+    fn https_proxy_port(&self) -> u16 {
+        self.config.https_proxy_port
+    }
+
+    fn stop(&self) -> Result<(), String> {
+        I2PDRouter::stop(self)
+    }
 }
 
 impl Drop for I2PDRouter {
@@ -160,7 +623,323 @@ pub fn get_or_init_router() -> Arc<I2PDRouter> {
     }
 }
 
+/// Configuration of the global router without creating it: the one installed with
+/// `configure_router`, or the default `get_or_init_router` would use
+fn global_router_config() -> RouterConfig {
+    match GLOBAL_ROUTER.lock().unwrap().as_ref() {
+        Some(router) => router.config().clone(),
+        None => RouterConfig::default(),
+    }
+}
+
+/// Install the configuration for the global router. Must happen before the router is first
+/// used, since later calls would otherwise silently run with the old configuration.
+pub fn configure_router(config: RouterConfig) -> Result<(), String> {
+    let mut router_opt = GLOBAL_ROUTER.lock().unwrap();
+    if router_opt.is_some() {
+        return Err("i2pd router already initialized, configure it before first use".to_string());
+    }
+    *router_opt = Some(Arc::new(I2PDRouter::with_config(config)));
+    Ok(())
+}
+
+/// Stop the global router, if it was ever started. Requests still waiting on it fail
+/// right away with `RequestError::RouterShuttingDown`.
+pub fn shutdown_router() -> Result<(), String> {
+    let router = GLOBAL_ROUTER.lock().unwrap().clone();
+    match router {
+        Some(router) => router.stop(),
+        None => Ok(()),
+    }
+}
+
 pub fn ensure_router_running() -> Result<(), String> {
+    // Every caller is about to route traffic through the router
+    ROUTER_ACTIVITY.touch();
     let router = get_or_init_router();
-    router.ensure_running()
+    router.ensure_running()?;
+
+    // The router can report running while its proxies never came up or went away. Probing
+    // blocks on TCP connects, so it must not run on an async worker.
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => {
+            runtime.spawn_blocking(move || recover_unreachable_proxies(&router));
+        }
+        Err(_) => recover_unreachable_proxies(&router),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// Records the calls the default `Router::restart` makes
+    struct RecordingRouter {
+        running: std::sync::atomic::AtomicBool,
+        calls: Mutex<Vec<&'static str>>,
+    }
+
+    impl Router for RecordingRouter {
+        fn ensure_running(&self) -> Result<(), String> {
+            self.calls.lock().unwrap().push("ensure_running");
+            self.running.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn is_running(&self) -> bool {
+            self.running.load(Ordering::SeqCst)
+        }
+
+        fn http_proxy_port(&self) -> u16 {
+            4444
+        }
+
+        fn https_proxy_port(&self) -> u16 {
+            4447
+        }
+
+        fn stop(&self) -> Result<(), String> {
+            self.calls.lock().unwrap().push("stop");
+            self.running.store(false, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_default_restart_stops_then_starts_a_running_router() {
+        let router = RecordingRouter {
+            running: std::sync::atomic::AtomicBool::new(false),
+            calls: Mutex::new(Vec::new()),
+        };
+        router.restart().unwrap();
+        assert!(router.calls.lock().unwrap().is_empty());
+
+        router.running.store(true, Ordering::SeqCst);
+        router.restart().unwrap();
+        assert_eq!(*router.calls.lock().unwrap(), vec!["stop", "ensure_running"]);
+        assert!(router.is_running());
+    }
+
+    #[test]
+    fn test_proxies_listening_requires_both_ports() {
+        let http = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let https = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let http_port = http.local_addr().unwrap().port();
+        let https_port = https.local_addr().unwrap().port();
+        assert!(proxies_listening(http_port, https_port));
+
+        drop(https);
+        assert!(port_listening(http_port));
+        assert!(!port_listening(https_port));
+        assert!(!proxies_listening(http_port, https_port));
+    }
+
+    #[test]
+    fn test_recovery_waits_for_warmup_and_interval() {
+        let now = Instant::now();
+        let ago = |secs| now.checked_sub(Duration::from_secs(secs));
+
+        assert!(recovery_due(now, None, None));
+        // Still warming up
+        assert!(!recovery_due(now, ago(10), None));
+        assert!(recovery_due(now, ago(120), None));
+        // Restarted for this a moment ago, which set the start time too
+        assert!(!recovery_due(now, ago(120), ago(120)));
+        assert!(recovery_due(now, ago(600), ago(600)));
+    }
+
+    #[test]
+    fn test_idle_tracker_touch_resets_idle_time() {
+        let tracker = IdleTracker::new();
+        let later = Instant::now() + Duration::from_secs(60);
+        assert!(tracker.idle_for(later) >= Duration::from_secs(59));
+
+        std::thread::sleep(Duration::from_millis(20));
+        tracker.touch();
+        assert!(tracker.idle_for(Instant::now()) < Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_idle_tick_stops_router_after_threshold() {
+        let tracker = IdleTracker::new();
+        let stops = Cell::new(0);
+        let stop = || {
+            stops.set(stops.get() + 1);
+            Ok(())
+        };
+
+        let idle_after = Duration::from_secs(30);
+        let soon = Instant::now() + Duration::from_secs(5);
+        assert!(!idle_tick(&tracker, idle_after, soon, true, stop));
+        assert_eq!(stops.get(), 0);
+
+        let later = Instant::now() + Duration::from_secs(31);
+        assert!(idle_tick(&tracker, idle_after, later, true, stop));
+        assert_eq!(stops.get(), 1);
+    }
+
+    #[test]
+    fn test_idle_tick_keeps_router_in_use() {
+        let tracker = IdleTracker::new();
+        let idle_after = Duration::from_secs(30);
+        let stopped = Cell::new(false);
+        let stop = || {
+            stopped.set(true);
+            Ok(())
+        };
+
+        // A download that started long ago and is still running
+        let download = tracker.begin();
+        let later = Instant::now() + Duration::from_secs(120);
+        assert!(!idle_tick(&tracker, idle_after, later, true, stop));
+        assert!(!stopped.get());
+
+        // Idle time only starts once it finished
+        drop(download);
+        assert_eq!(tracker.in_flight(), 0);
+        assert!(!idle_tick(&tracker, idle_after, Instant::now() + Duration::from_secs(5), true, stop));
+        assert!(idle_tick(&tracker, idle_after, Instant::now() + Duration::from_secs(31), true, stop));
+        assert!(stopped.get());
+    }
+
+    #[test]
+    fn test_idle_tick_ignores_stopped_router() {
+        let tracker = IdleTracker::new();
+        let stopped = Cell::new(false);
+        let later = Instant::now() + Duration::from_secs(120);
+
+        let triggered = idle_tick(&tracker, Duration::from_secs(30), later, false, || {
+            stopped.set(true);
+            Ok(())
+        });
+
+        assert!(!triggered);
+        assert!(!stopped.get());
+    }
+
+    #[test]
+    fn test_idle_tick_reports_failed_stop() {
+        let tracker = IdleTracker::new();
+        let later = Instant::now() + Duration::from_secs(120);
+
+        let triggered = idle_tick(&tracker, Duration::from_secs(30), later, true, || {
+            Err("stop failed".to_string())
+        });
+
+        assert!(!triggered);
+    }
+
+    #[test]
+    #[ignore = "requires the i2pd router"]
+    fn test_log_sink_receives_router_lines() {
+        let dir = std::env::temp_dir().join(format!("i2ptunnel-log-sink-{}", std::process::id()));
+        let router = I2PDRouter::with_config(RouterConfig::new().datadir(dir.to_string_lossy()));
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let collected = lines.clone();
+        router.set_log_sink(Arc::new(move |line| collected.lock().unwrap().push(line)));
+
+        router.start().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while lines.lock().unwrap().is_empty() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        router.stop().unwrap();
+
+        assert!(!lines.lock().unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_router_config_builder() {
+        let config = RouterConfig::new()
+            .config_dir("/tmp/i2pd")
+            .idle_shutdown(Duration::from_secs(600));
+        assert_eq!(config.config_dir.as_deref(), Some("/tmp/i2pd"));
+        assert_eq!(config.idle_shutdown, Some(Duration::from_secs(600)));
+
+        let default = RouterConfig::default();
+        assert!(default.config_dir.is_none());
+        assert!(default.idle_shutdown.is_none());
+        assert_eq!(default.http_proxy_port, 4444);
+        assert_eq!(default.https_proxy_port, 4447);
+    }
+
+    #[test]
+    fn test_router_keys_path_follows_datadir() {
+        let config = RouterConfig::new().datadir("/var/lib/i2ptunnel");
+        assert_eq!(config.config_dir.as_deref(), Some("/var/lib/i2ptunnel"));
+        assert_eq!(config.router_keys_path(), PathBuf::from("/var/lib/i2ptunnel/router.keys"));
+        assert_eq!(RouterConfig::default().router_keys_path(), PathBuf::from("./router.keys"));
+    }
+
+    #[test]
+    #[ignore = "requires the i2pd router"]
+    fn test_restart_with_same_datadir_keeps_identity() {
+        let dir = std::env::temp_dir().join(format!("i2ptunnel-keys-{}", std::process::id()));
+        let router = I2PDRouter::with_config(RouterConfig::new().datadir(dir.to_string_lossy()));
+        let keys_path = router.config().router_keys_path();
+
+        router.start().unwrap();
+        let first = std::fs::read(&keys_path).unwrap();
+        router.stop().unwrap();
+
+        router.start().unwrap();
+        let second = std::fs::read(&keys_path).unwrap();
+        router.stop().unwrap();
+
+        assert_eq!(first, second);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_check_config_dir() {
+        let base = std::env::temp_dir().join(format!("i2ptunnel-config-check-{}", std::process::id()));
+        let nested = base.join("new").join("dir");
+        assert!(check_config_dir(&nested.to_string_lossy()).is_ok());
+        assert!(nested.is_dir());
+        // The probe file is cleaned up
+        assert_eq!(std::fs::read_dir(&nested).unwrap().count(), 0);
+
+        // A regular file in the way can't become the config directory
+        let file = base.join("occupied");
+        std::fs::write(&file, b"not a directory").unwrap();
+        let err = check_config_dir(&file.join("dir").to_string_lossy()).unwrap_err();
+        assert_eq!(err.dir, file.join("dir"));
+        assert!(err.to_string().contains("not writable"));
+        assert!(check_config_dir(&file.to_string_lossy()).is_err());
+
+        // The default working directory is checked like any other
+        assert!(check_config_dir(".").is_ok());
+        let _ = std::fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn test_init_reports_config_dir_without_room_for_files() {
+        // The directory exists, but a directory sits where the probe file would be created,
+        // which fails even for root (unlike permission bits)
+        let dir = std::env::temp_dir().join(format!("i2ptunnel-config-full-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join(format!(".i2ptunnel-write-check-{}", std::process::id()))).unwrap();
+
+        let err = check_config_dir(&dir.to_string_lossy()).unwrap_err();
+        assert_eq!(err.dir, dir);
+        assert!(err.reason.contains("cannot create files"));
+
+        // Dropping the router would stop and clean up the embedded one
+        let config = RouterConfig::new().config_dir(dir.to_string_lossy());
+        let router = std::mem::ManuallyDrop::new(I2PDRouter::with_config(config));
+        match router.init() {
+            Err(RouterInitError::ConfigDirNotWritable(e)) => assert_eq!(e.dir, dir),
+            other => panic!("expected ConfigDirNotWritable, got {:?}", other),
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_router_config_custom_ports() {
+        let config = RouterConfig::new().http_proxy_port(14444).https_proxy_port(14447);
+        assert_eq!(config.http_proxy_port, 14444);
+        assert_eq!(config.https_proxy_port, 14447);
+    }
 }
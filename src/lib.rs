@@ -1,14 +1,28 @@
+mod challenge;
 mod proxy_manager;
 mod proxy_selector;
 mod proxy_tester;
+mod rate_limiter;
+mod recorder;
 mod request_handler;
+mod response_cache;
+mod selection_strategy;
+mod timeouts;
 mod i2pd_router;
-
-pub use proxy_manager::{Proxy, ProxyManager, ProxyType};
-pub use proxy_selector::{ProxySelector, SelectedProxy};
-pub use proxy_tester::{ProxyTestResult, ProxyTester};
-pub use request_handler::{RequestConfig, RequestHandler, ResponseData};
-pub use i2pd_router::{I2PDRouter, ensure_router_running};
+#[cfg(test)]
+mod test_support;
+
+pub use challenge::{ChallengeDetector, ChallengeMarkers};
+pub use proxy_manager::{parse_uptime, NoProxiesFound, Proxy, ProxyManager, ProxyParseError, ProxyType};
+pub use proxy_selector::{rank_by_uptime, CandidateExplanation, KnownProxy, NoAcceptableProxy, ProxySelector, SelectedProxy, SelectionExplanation, SelectionMode, TargetNetwork};
+pub use proxy_tester::{CancelToken, DnsResolution, FailureStage, ProxyTestResult, ProxyTester, TestProgress};
+pub use rate_limiter::{RateLimitPolicy, RateLimiter};
+pub use recorder::{RecordedExchange, Recorder};
+pub use request_handler::{extract_headers, partial_download_path, AttemptInfo, BodyTransformer, DownloadSummary, HandlerMetrics, HeaderLimitPolicy, ProxyInfo, RequestConfig, RequestError, RequestHandler, RequestHandlerBuilder, RequestParts, RequestSigner, ResponseData};
+pub use response_cache::ResponseCache;
+pub use selection_strategy::{FastestStrategy, LowestLatencyStrategy, SelectionStrategy, WeightedStrategy};
+pub use timeouts::TimeoutConfig;
+pub use i2pd_router::{ConfigDirNotWritable, GlobalRouter, I2PDRouter, LogSink, Router, RouterConfig, RouterInitError, check_config_dir, configure_router, ensure_router_running, shutdown_router};
 
 use pyo3::prelude::*;
 use pyo3::types::{PyBytes, PyDict, PyList, PyString};
@@ -25,6 +39,33 @@ fn get_runtime() -> &'static Runtime {
     })
 }
 
+/// Python sees one string per header; repeated headers are joined with ", ", except
+/// `set-cookie`, whose values contain commas of their own and are joined with "\n" instead.
+/// `header_lists_to_py` keeps every value separate.
+fn headers_to_py<'py>(
+    py: Python<'py>,
+    headers: &std::collections::HashMap<String, Vec<String>>,
+) -> PyResult<&'py PyDict> {
+    let headers_dict = PyDict::new(py);
+    for (key, values) in headers {
+        let separator = if key.eq_ignore_ascii_case("set-cookie") { "\n" } else { ", " };
+        headers_dict.set_item(key, values.join(separator))?;
+    }
+    Ok(headers_dict)
+}
+
+/// Every value of each header as a list, for headers that may repeat such as `set-cookie`
+fn header_lists_to_py<'py>(
+    py: Python<'py>,
+    headers: &std::collections::HashMap<String, Vec<String>>,
+) -> PyResult<&'py PyDict> {
+    let lists_dict = PyDict::new(py);
+    for (key, values) in headers {
+        lists_dict.set_item(key, PyList::new(py, values))?;
+    }
+    Ok(lists_dict)
+}
+
 #[pyclass]
 pub struct I2PProxyDaemon {
     manager: Arc<ProxyManager>,
@@ -43,9 +84,10 @@ impl I2PProxyDaemon {
             warn!("Failed to ensure i2pd router is running: {}. Continuing anyway.", e);
         }
         
-        let manager = Arc::new(ProxyManager::new());
+        let timeouts = TimeoutConfig::from_env();
+        let manager = Arc::new(ProxyManager::new().with_timeouts(&timeouts));
         let selector = Arc::new(ProxySelector::new(300));
-        let handler = Arc::new(RequestHandler::new(selector.clone()));
+        let handler = Arc::new(RequestHandler::new(selector.clone()).with_timeouts(timeouts));
 
         Ok(Self {
             manager,
@@ -79,7 +121,7 @@ impl I2PProxyDaemon {
     fn test_proxies(&self, proxy_urls: Vec<String>) -> PyResult<PyObject> {
         info!("Python: test_proxies called with {} proxies", proxy_urls.len());
         let rt = get_runtime();
-        let tester = ProxyTester::new(None);
+        let tester = ProxyTester::new(None).with_timeouts(&TimeoutConfig::from_env());
 
         let proxies: Vec<Proxy> = proxy_urls
             .iter()
@@ -131,6 +173,7 @@ impl I2PProxyDaemon {
             headers: None,
             body: None,
             stream: stream.unwrap_or(false),
+            ..Default::default()
         };
 
         // Convert headers
@@ -163,12 +206,10 @@ impl I2PProxyDaemon {
                 let dict = PyDict::new(py);
                 dict.set_item("status", response_data.status)?;
                 dict.set_item("proxy_used", response_data.proxy_used.as_str())?;
+                dict.set_item("final_url", response_data.final_url.as_str())?;
 
-                let headers_dict = PyDict::new(py);
-                for (key, value) in response_data.headers {
-                    headers_dict.set_item(key, value)?;
-                }
-                dict.set_item("headers", headers_dict)?;
+                dict.set_item("headers", headers_to_py(py, &response_data.headers)?)?;
+                dict.set_item("header_lists", header_lists_to_py(py, &response_data.headers)?)?;
 
                 let body_bytes = PyBytes::new(py, &response_data.body);
                 dict.set_item("body", body_bytes)?;
@@ -178,7 +219,7 @@ impl I2PProxyDaemon {
             Err(e) => {
                 error!("Request failed: {}", e);
                 error!("Request error details (debug): {:#?}", e);
-                Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e))
+                Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))
             }
         }
     }
@@ -222,6 +263,7 @@ impl I2PProxyDaemon {
             headers: None,
             body: None,
             stream: stream.unwrap_or(false),
+            ..Default::default()
         };
 
         // Convert headers
@@ -254,12 +296,10 @@ impl I2PProxyDaemon {
                 let dict = PyDict::new(py);
                 dict.set_item("status", response_data.status)?;
                 dict.set_item("proxy_used", response_data.proxy_used.as_str())?;
+                dict.set_item("final_url", response_data.final_url.as_str())?;
 
-                let headers_dict = PyDict::new(py);
-                for (key, value) in response_data.headers {
-                    headers_dict.set_item(key, value)?;
-                }
-                dict.set_item("headers", headers_dict)?;
+                dict.set_item("headers", headers_to_py(py, &response_data.headers)?)?;
+                dict.set_item("header_lists", header_lists_to_py(py, &response_data.headers)?)?;
 
                 let body_bytes = PyBytes::new(py, &response_data.body);
                 dict.set_item("body", body_bytes)?;
@@ -269,7 +309,7 @@ impl I2PProxyDaemon {
             Err(e) => {
                 error!("Request failed: {}", e);
                 error!("Request error details (debug): {:#?}", e);
-                Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e))
+                Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))
             }
         }
     }
@@ -305,6 +345,7 @@ impl I2PProxyDaemon {
             headers: None,
             body: None,
             stream: false,  // Read full body first, then split into chunks for streaming interface
+            ..Default::default()
         };
 
         // Convert headers
@@ -338,7 +379,7 @@ impl I2PProxyDaemon {
             Ok(data) => (data.status, data.headers, data.body, data.proxy_used),
             Err(e) => {
                 error!("Request failed: {}", e);
-                return Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e));
+                return Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()));
             }
         };
 
@@ -364,11 +405,8 @@ impl I2PProxyDaemon {
             dict.set_item("status", status)?;
             dict.set_item("proxy_used", proxy_used.as_str())?;
 
-            let headers_dict = PyDict::new(py);
-            for (key, value) in &response_headers {
-                headers_dict.set_item(key, value)?;
-            }
-            dict.set_item("headers", headers_dict)?;
+            dict.set_item("headers", headers_to_py(py, &response_headers)?)?;
+            dict.set_item("header_lists", header_lists_to_py(py, &response_headers)?)?;
 
             let chunks_list = PyList::empty(py);
             for chunk in chunks {
@@ -405,6 +443,7 @@ impl I2PProxyDaemon {
             headers: None,
             body: None,
             stream: true,
+            ..Default::default()
         };
 
         // Convert headers
@@ -431,7 +470,7 @@ impl I2PProxyDaemon {
         // Get proxy candidates using the handler's internal logic
         // We need to check if it's I2P and get candidates accordingly
         let url_clone = request_config.url.clone();
-        let is_i2p = crate::request_handler::RequestHandler::is_i2p_domain(&url_clone);
+        let is_i2p = handler.routes_via_i2p(&url_clone);
         
         info!("Getting proxy candidates for {} (is_i2p={}, available_proxies={})", url_clone, is_i2p, available_proxies.len());
         
@@ -463,7 +502,7 @@ impl I2PProxyDaemon {
             Ok(result) => result,
             Err(e) => {
                 error!("Request failed: {}", e);
-                return Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e));
+                return Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()));
             }
         };
 
@@ -471,12 +510,7 @@ impl I2PProxyDaemon {
         let status = response.status().as_u16();
         info!("Received streaming response: status {}", status);
 
-        let mut response_headers = std::collections::HashMap::new();
-        for (key, value) in response.headers() {
-            if let Ok(value_str) = value.to_str() {
-                response_headers.insert(key.to_string(), value_str.to_string());
-            }
-        }
+        let response_headers = extract_headers(response.headers());
 
         // Read response in chunks (response is moved here)
         let chunks = rt.block_on(async move {
@@ -520,11 +554,8 @@ impl I2PProxyDaemon {
             dict.set_item("status", status)?;
             dict.set_item("proxy_used", proxy_used.as_str())?;
 
-            let headers_dict = PyDict::new(py);
-            for (key, value) in &response_headers {
-                headers_dict.set_item(key, value)?;
-            }
-            dict.set_item("headers", headers_dict)?;
+            dict.set_item("headers", headers_to_py(py, &response_headers)?)?;
+            dict.set_item("header_lists", header_lists_to_py(py, &response_headers)?)?;
 
             let chunks_list = PyList::empty(py);
             for chunk in chunks {
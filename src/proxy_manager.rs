@@ -1,10 +1,16 @@
 use reqwest::Client;
-use scraper::{Html, Selector};
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
+use std::path::Path;
 use tracing::{debug, error, info, warn};
 use url::Url;
 use regex;
-use crate::i2pd_router::ensure_router_running;
+use crate::i2pd_router::{GlobalRouter, Router};
+use crate::proxy_selector::{ProxySelector, SelectedProxy};
+use std::sync::Arc;
+use crate::timeouts::TimeoutConfig;
+use std::time::Duration;
 
 /// Log error with full details, splitting long messages to avoid truncation
 fn log_error_full(prefix: &str, err: &dyn std::error::Error) {
@@ -24,24 +30,195 @@ fn log_error_full(prefix: &str, err: &dyn std::error::Error) {
     error!("{} Error debug: {:#?}", prefix, err);
 }
 
-#[derive(Debug, Clone)]
+/// One entry of a JSON proxy list as served by some directory mirrors
+#[derive(Debug, Deserialize)]
+struct JsonProxyEntry {
+    host: String,
+    port: u16,
+    #[serde(rename = "type")]
+    proxy_type: String,
+    #[serde(default)]
+    uptime: Option<serde_json::Value>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// Compile a CSS selector, logging instead of failing so one bad selector only disables
+/// the pattern that uses it
+fn build_selector(css: &str) -> Option<Selector> {
+    match Selector::parse(css) {
+        Ok(selector) => Some(selector),
+        Err(e) => {
+            warn!("Failed to create selector {:?}: {:?}", css, e);
+            None
+        }
+    }
+}
+
+fn build_regex(pattern: &str) -> Option<regex::Regex> {
+    match regex::Regex::new(pattern) {
+        Ok(regex) => Some(regex),
+        Err(e) => {
+            warn!("Failed to compile pattern {:?}: {}", pattern, e);
+            None
+        }
+    }
+}
+
+/// Proxy listed in one table row (address, port, uptime, type). Rows that are not proxy
+/// entries give `Ok(None)`, rows that look like entries but cannot be read give an error.
+fn proxy_from_row(row: ElementRef, cell_selector: &Selector) -> Result<Option<Proxy>, String> {
+    let cells: Vec<String> = row
+        .select(cell_selector)
+        .map(|cell| cell.text().collect::<String>().trim().to_string())
+        .collect();
+    if cells.len() < 4 {
+        return Ok(None);
+    }
+
+    let address = &cells[0];
+    let port: u16 = cells[1]
+        .parse()
+        .map_err(|e| format!("invalid port {:?} for {:?}: {}", cells[1], address, e))?;
+    let uptime = parse_uptime(&cells[2]);
+    let proxy_type = cells[3].to_lowercase();
+
+    // Only include HTTPS and SOCKS proxies on I2P domains, exclude HTTP
+    Ok(proxy_from_listing(address, port, &proxy_type, uptime))
+}
+
+/// Parse one line of a plain-text list: `scheme://host:port` or `host:port`. Without a scheme
+/// the type follows the port (443 HTTPS, 1080/9050 SOCKS); other ports are taken as HTTP and
+/// skipped like HTTP proxies elsewhere.
+fn proxy_from_text_line(line: &str) -> Result<Option<Proxy>, String> {
+    let (proxy_type, address) = match line.split_once("://") {
+        Some((scheme, rest)) => {
+            let proxy_type = match scheme.to_lowercase().as_str() {
+                "https" => "https",
+                "socks" | "socks5" | "socks5h" => "socks",
+                _ => return Ok(None),
+            };
+            (Some(proxy_type), rest.trim_end_matches('/'))
+        }
+        None => (None, line),
+    };
+
+    let (host, port) = address
+        .rsplit_once(':')
+        .ok_or_else(|| format!("missing port in {:?}", line))?;
+    let port: u16 = port
+        .parse()
+        .map_err(|e| format!("invalid port in {:?}: {}", line, e))?;
+    let proxy_type = proxy_type.unwrap_or(match port {
+        443 => "https",
+        1080 | 9050 => "socks",
+        _ => "http",
+    });
+    Ok(proxy_from_listing(&host.to_lowercase(), port, proxy_type, None))
+}
+
+/// Build a proxy from a directory listing entry, applying the same rules to every format:
+/// only HTTPS and SOCKS proxies on I2P domains are accepted
+fn proxy_from_listing(address: &str, port: u16, proxy_type: &str, uptime: Option<f64>) -> Option<Proxy> {
+    let pt = match proxy_type {
+        "https" => ProxyType::Https,
+        "socks" => ProxyType::Socks,
+        _ => return None,
+    };
+    if !(address.ends_with(".i2p") || address.ends_with(".b32.i2p")) {
+        return None;
+    }
+    let mut proxy = Proxy::new_with_type(address.to_string(), port, pt);
+    proxy.uptime = uptime;
+    Some(proxy)
+}
+
+/// Parse the uptime column of a proxy listing into a reliability fraction in `0.0..=1.0`.
+///
+/// Percentages ("95%", "99.5 %") map directly, bare fractions ("0.95") are taken as-is and
+/// bare numbers above 1 are read as percentages. Durations ("3d", "12h", "45m") mean the proxy
+/// has been up that long without interruption and saturate at one week. Anything else
+/// ("N/A", "-", "") yields `None`.
+pub fn parse_uptime(raw: &str) -> Option<f64> {
+    let value = raw.trim().to_lowercase();
+    if value.is_empty() {
+        return None;
+    }
+
+    if let Some(percent) = value.strip_suffix('%') {
+        let percent: f64 = percent.trim().parse().ok()?;
+        return Some((percent / 100.0).clamp(0.0, 1.0));
+    }
+
+    let duration_unit = match value.chars().last() {
+        Some('d') => Some(86400.0),
+        Some('h') => Some(3600.0),
+        Some('m') => Some(60.0),
+        _ => None,
+    };
+    if let Some(unit_secs) = duration_unit {
+        let amount: f64 = value[..value.len() - 1].trim().parse().ok()?;
+        let week_secs = 7.0 * 86400.0;
+        return Some((amount * unit_secs / week_secs).clamp(0.0, 1.0));
+    }
+
+    let number: f64 = value.parse().ok()?;
+    if !number.is_finite() || number < 0.0 {
+        return None;
+    }
+    if number <= 1.0 {
+        Some(number)
+    } else {
+        Some((number / 100.0).min(1.0))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ProxyType {
     Http,
     Https,
     Socks,
 }
 
-#[derive(Debug, Clone)]
+impl ProxyType {
+    /// Every supported proxy type
+    pub fn all() -> &'static [ProxyType] {
+        &[ProxyType::Http, ProxyType::Https, ProxyType::Socks]
+    }
+
+    /// URL scheme for proxies of this type
+    pub fn scheme(&self) -> &'static str {
+        match self {
+            ProxyType::Http => "http",
+            ProxyType::Https => "https",
+            ProxyType::Socks => "socks5",
+        }
+    }
+
+    /// Proxy URL for `host:port` with this type's scheme
+    pub fn url_for(&self, host: &str, port: u16) -> String {
+        format!("{}://{}:{}", self.scheme(), host, port)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Proxy {
     pub host: String,
     pub port: u16,
     pub url: String,
     pub proxy_type: ProxyType,
+    /// Reliability reported by the proxy directory (0.0 to 1.0), if it had any
+    #[serde(default)]
+    pub uptime: Option<f64>,
+    /// Free-form labels such as "trusted" or "us-region", from the listing or set by the user
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 impl Proxy {
     pub fn new(host: String, port: u16) -> Self {
-        let url = format!("http://{}:{}", host, port);
+        let url = ProxyType::Http.url_for(&host, port);
         // Default to HTTPS for I2P proxies (most common)
         let proxy_type = if port == 1080 || port == 9050 {
             ProxyType::Socks
@@ -50,16 +227,12 @@ impl Proxy {
         } else {
             ProxyType::Http
         };
-        Self { host, port, url, proxy_type }
+        Self { host, port, url, proxy_type, uptime: None, tags: Vec::new() }
     }
     
     pub fn new_with_type(host: String, port: u16, proxy_type: ProxyType) -> Self {
-        let url = match proxy_type {
-            ProxyType::Socks => format!("socks5://{}:{}", host, port),
-            ProxyType::Https => format!("https://{}:{}", host, port),
-            ProxyType::Http => format!("http://{}:{}", host, port),
-        };
-        Self { host, port, url, proxy_type }
+        let url = proxy_type.url_for(&host, port);
+        Self { host, port, url, proxy_type, uptime: None, tags: Vec::new() }
     }
 
     pub fn from_url(url_str: &str) -> Option<Self> {
@@ -67,9 +240,9 @@ impl Proxy {
             Ok(url) => {
                 let host = url.host_str()?.to_string();
                 let port = url.port().unwrap_or(80);
-                let proxy_type = if url_str.starts_with("socks5://") || port == 1080 || port == 9050 {
+                let proxy_type = if url.scheme() == ProxyType::Socks.scheme() || port == 1080 || port == 9050 {
                     ProxyType::Socks
-                } else if url_str.starts_with("https://") || port == 443 {
+                } else if url.scheme() == ProxyType::Https.scheme() || port == 443 {
                     ProxyType::Https
                 } else {
                     ProxyType::Http
@@ -83,60 +256,222 @@ impl Proxy {
         }
     }
     
+    /// Check that `url_str` is usable as a proxy URL (an `http`, `https` or `socks5` URL
+    /// with a host and a valid port), saying what is wrong with it otherwise. Unlike
+    /// `from_url`, which gives up without a reason, this is meant for feedback on user input.
+    pub fn validate(url_str: &str) -> Result<(), ProxyParseError> {
+        Self::parse_checked(url_str).map(|_| ())
+    }
+
+    /// Like `validate`, but also require the host to be an I2P destination, for places that
+    /// only take I2P outproxies
+    pub fn validate_i2p(url_str: &str) -> Result<(), ProxyParseError> {
+        let url = Self::parse_checked(url_str)?;
+        let host = url.host_str().unwrap_or_default();
+        if host.ends_with(".i2p") {
+            Ok(())
+        } else {
+            Err(ProxyParseError::NotI2p(host.to_string()))
+        }
+    }
+
+    fn parse_checked(url_str: &str) -> Result<Url, ProxyParseError> {
+        let url = Url::parse(url_str.trim()).map_err(|e| match e {
+            url::ParseError::EmptyHost => ProxyParseError::MissingHost,
+            url::ParseError::InvalidPort => ProxyParseError::InvalidPort(url_str.to_string()),
+            other => ProxyParseError::Malformed(other.to_string()),
+        })?;
+
+        if !ProxyType::all().iter().any(|proxy_type| proxy_type.scheme() == url.scheme()) {
+            return Err(ProxyParseError::UnsupportedScheme(url.scheme().to_string()));
+        }
+        if url.host_str().unwrap_or_default().is_empty() {
+            return Err(ProxyParseError::MissingHost);
+        }
+        if url.port() == Some(0) {
+            return Err(ProxyParseError::InvalidPort(url_str.to_string()));
+        }
+        Ok(url)
+    }
+
+    pub fn with_tags<I, S>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.tags = tags.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Whether this proxy carries every one of `required` (case-insensitive).
+    /// An empty requirement matches every proxy.
+    pub fn has_tags(&self, required: &[&str]) -> bool {
+        required
+            .iter()
+            .all(|wanted| self.tags.iter().any(|tag| tag.eq_ignore_ascii_case(wanted)))
+    }
+
     pub fn is_i2p_proxy(&self) -> bool {
         self.host.ends_with(".i2p") || self.host.ends_with(".b32.i2p")
     }
 }
 
+/// Why a string is not a usable proxy URL
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProxyParseError {
+    /// Not a URL at all
+    Malformed(String),
+    /// A scheme other than `http`, `https` or `socks5`
+    UnsupportedScheme(String),
+    MissingHost,
+    /// A port that is out of range, not a number or zero
+    InvalidPort(String),
+    /// A clearnet host where only I2P destinations are accepted
+    NotI2p(String),
+}
+
+impl std::fmt::Display for ProxyParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProxyParseError::Malformed(reason) => write!(f, "Not a valid URL: {}", reason),
+            ProxyParseError::UnsupportedScheme(scheme) => {
+                write!(f, "Unsupported proxy scheme {:?}, expected http, https or socks5", scheme)
+            }
+            ProxyParseError::MissingHost => write!(f, "Proxy URL has no host"),
+            ProxyParseError::InvalidPort(url) => write!(f, "Invalid port in proxy URL {}", url),
+            ProxyParseError::NotI2p(host) => write!(f, "{} is not an I2P destination", host),
+        }
+    }
+}
+
+impl std::error::Error for ProxyParseError {}
+
+/// The proxy list was fetched fine but held no usable proxies
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoProxiesFound {
+    /// Size of the fetched listing
+    pub body_bytes: usize,
+}
+
+impl std::fmt::Display for NoProxiesFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No proxies found in the proxy list ({} bytes)", self.body_bytes)
+    }
+}
+
+impl std::error::Error for NoProxiesFound {}
+
 pub struct ProxyManager {
     client: Client,
+    router: Arc<dyn Router>,
+    timeout: Duration,
+    connect_timeout: Option<Duration>,
 }
 
 impl ProxyManager {
     pub fn new() -> Self {
+        Self::with_router(Arc::new(GlobalRouter))
+    }
+
+    /// Fetch proxy lists through `router` instead of the process-wide embedded router
+    pub fn with_router(router: Arc<dyn Router>) -> Self {
         info!("Initializing ProxyManager");
         
         // Ensure i2pd router is running
-        if let Err(e) = ensure_router_running() {
+        if let Err(e) = router.ensure_running() {
             warn!("Failed to ensure i2pd router is running: {}. Will try to connect anyway.", e);
         }
-        
+
+        let timeout = TimeoutConfig::default().fetch;
+        Self {
+            client: Self::build_client(router.as_ref(), timeout, None),
+            router,
+            timeout,
+            connect_timeout: None,
+        }
+    }
+
+    /// Give proxy list fetches `timeout` in total instead of the default 30s, e.g. more time
+    /// for slow eepsites
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self.client = Self::build_client(self.router.as_ref(), self.timeout, self.connect_timeout);
+        self
+    }
+
+    /// Use `timeouts.fetch` for proxy list fetches
+    pub fn with_timeouts(self, timeouts: &TimeoutConfig) -> Self {
+        self.with_timeout(timeouts.fetch)
+    }
+
+    /// Fail fetches that cannot connect within `connect_timeout`, e.g. while the router is cold
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self.client = Self::build_client(self.router.as_ref(), self.timeout, self.connect_timeout);
+        self
+    }
+
+    fn build_client(router: &dyn Router, timeout: Duration, connect_timeout: Option<Duration>) -> Client {
         // Use I2P HTTP proxy to access .i2p domains
-        // Default I2P HTTP proxy ports: 4444 (HTTP) or 4447 (HTTPS)
-        let i2p_proxy_http = reqwest::Proxy::http("http://127.0.0.1:4444")
+        let http_port = router.http_proxy_port();
+        let https_port = router.https_proxy_port();
+        let i2p_proxy_http = reqwest::Proxy::http(format!("http://127.0.0.1:{}", http_port))
             .unwrap_or_else(|_| {
-                warn!("Failed to set I2P HTTP proxy on port 4444, trying alternative port");
-                reqwest::Proxy::http("http://127.0.0.1:4447")
+                warn!("Failed to set I2P HTTP proxy on port {}, trying alternative port", http_port);
+                reqwest::Proxy::http(format!("http://127.0.0.1:{}", https_port))
                     .unwrap_or_else(|_| {
-                        error!("Failed to set I2P proxy on both ports 4444 and 4447");
+                        error!("Failed to set I2P proxy on both ports {} and {}", http_port, https_port);
                         panic!("Cannot initialize ProxyManager without I2P proxy");
                     })
             });
         
         // Also set HTTPS proxy for HTTPS I2P sites
-        let i2p_proxy_https = reqwest::Proxy::https("http://127.0.0.1:4447")
+        let i2p_proxy_https = reqwest::Proxy::https(format!("http://127.0.0.1:{}", https_port))
             .unwrap_or_else(|_| {
-                warn!("Failed to set I2P HTTPS proxy on port 4447, using HTTP proxy port");
-                reqwest::Proxy::https("http://127.0.0.1:4444")
+                warn!("Failed to set I2P HTTPS proxy on port {}, using HTTP proxy port", https_port);
+                reqwest::Proxy::https(format!("http://127.0.0.1:{}", http_port))
                     .unwrap_or_else(|_| {
                         warn!("Failed to set I2P HTTPS proxy, continuing without it");
                         // Create a dummy proxy that will fail gracefully
-                        reqwest::Proxy::http("http://127.0.0.1:4444").unwrap()
+                        reqwest::Proxy::http(format!("http://127.0.0.1:{}", http_port)).unwrap()
                     })
             });
         
-        Self {
-            client: Client::builder()
-                .proxy(i2p_proxy_http)
-                .proxy(i2p_proxy_https)
-                .timeout(std::time::Duration::from_secs(30))
-                .build()
-                .expect("Failed to create HTTP client"),
+        let mut builder = Client::builder()
+            .proxy(i2p_proxy_http)
+            .proxy(i2p_proxy_https)
+            .timeout(timeout);
+        if let Some(connect_timeout) = connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
         }
+        builder.build().expect("Failed to create HTTP client")
     }
 
+    /// Fetch and parse the proxy list. A listing without any proxies is `Ok` and empty; see
+    /// `fetch_proxies_strict` to get an error instead.
     pub async fn fetch_proxies(&self) -> Result<Vec<Proxy>, Box<dyn std::error::Error>> {
+        self.fetch_listing().await.map(|(proxies, _)| proxies)
+    }
+
+    /// Like `fetch_proxies`, but a listing without any proxies fails with `NoProxiesFound`,
+    /// so cold-start code can retry, fall back to a cache or bootstrap differently
+    pub async fn fetch_proxies_strict(&self) -> Result<Vec<Proxy>, Box<dyn std::error::Error>> {
+        let (proxies, body_bytes) = self.fetch_listing().await?;
+        if proxies.is_empty() {
+            warn!("Proxy list of {} bytes held no proxies", body_bytes);
+            return Err(Box::new(NoProxiesFound { body_bytes }));
+        }
+        Ok(proxies)
+    }
+
+    /// The parsed proxy list and the size of the listing it came from
+    async fn fetch_listing(&self) -> Result<(Vec<Proxy>, usize), Box<dyn std::error::Error>> {
         info!("Fetching proxy list from I2P proxy address");
+
+        // The router may have been stopped for idling since this manager was created
+        if let Err(e) = self.router.ensure_running() {
+            warn!("Failed to ensure i2pd router is running: {}. Will try to connect anyway.", e);
+        }
         
         let url = "http://proxygwdhg5z7mn326hfqqzsbnkrbzea4xrss2v7exrjx4c65uka.b32.i2p/";
         debug!("Making request to {}", url);
@@ -152,17 +487,151 @@ impl ProxyManager {
             })?;
 
         info!("Received response with status: {}", response.status());
+
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
         
-        let html = response.text().await.map_err(|e| {
+        let body = response.text().await.map_err(|e| {
             log_error_full("Failed to read response body:", &e);
             e
         })?;
 
-        debug!("Response body length: {} bytes", html.len());
+        debug!("Response body length: {} bytes", body.len());
         
-        let proxies = self.parse_proxies(&html)?;
+        let proxies = self.parse_proxy_list(content_type.as_deref(), &body)?;
         info!("Parsed {} unique proxies", proxies.len());
         
+        Ok((proxies, body.len()))
+    }
+
+    /// Read the proxy list from a local file instead of fetching it over I2P, for offline
+    /// setups. The format comes from the extension (`.json`, `.txt`, `.html`/`.htm`), or
+    /// else from the content: a leading `[` is JSON, markup is HTML, anything else is text.
+    pub async fn fetch_from_file(&self, path: impl AsRef<Path>) -> Result<Vec<Proxy>, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        info!("Reading proxy list from {}", path.display());
+        let body = tokio::fs::read_to_string(path).await.map_err(|e| {
+            error!("Failed to read proxy list {}: {}", path.display(), e);
+            e
+        })?;
+
+        let proxies = self.parse_proxy_list(Some(Self::file_content_type(path, &body)), &body)?;
+        info!("Parsed {} unique proxies from {}", proxies.len(), path.display());
+        Ok(proxies)
+    }
+
+    /// Content type to parse a proxy list file as, like the one a server would have sent
+    fn file_content_type(path: &Path, body: &str) -> &'static str {
+        let extension = path.extension().and_then(|e| e.to_str()).map(str::to_lowercase);
+        match extension.as_deref() {
+            Some("json") => "application/json",
+            Some("txt") => "text/plain",
+            Some("html") | Some("htm") => "text/html",
+            _ => {
+                let start = body.trim_start();
+                if start.starts_with('[') {
+                    "application/json"
+                } else if start.starts_with('<') {
+                    "text/html"
+                } else {
+                    "text/plain"
+                }
+            }
+        }
+    }
+
+    /// Fetch the proxy list, test it through `selector` and return up to `count` of the
+    /// fastest working proxies, fastest first
+    pub async fn bootstrap(
+        &self,
+        selector: &ProxySelector,
+        count: usize,
+    ) -> Result<Vec<SelectedProxy>, Box<dyn std::error::Error>> {
+        let proxies = self.fetch_proxies_strict().await?;
+
+        info!("Bootstrapping {} candidates from {} proxies", count, proxies.len());
+        selector.ensure_multiple_proxy_candidates(proxies, count).await
+    }
+
+    /// Pick the parser matching the Content-Type of the proxy list (JSON, plain text), defaulting to HTML
+    fn parse_proxy_list(
+        &self,
+        content_type: Option<&str>,
+        body: &str,
+    ) -> Result<Vec<Proxy>, Box<dyn std::error::Error>> {
+        match content_type {
+            Some(ct) if ct.to_lowercase().contains("json") => {
+                debug!("Proxy list served as {}, parsing as JSON", ct);
+                self.parse_proxies_json(body)
+            }
+            Some(ct) if ct.to_lowercase().contains("text/plain") => {
+                debug!("Proxy list served as {}, parsing as plain text", ct);
+                self.parse_proxies_text(body)
+            }
+            _ => self.parse_proxies(body),
+        }
+    }
+
+    /// Parse a JSON proxy list: an array of `{host, port, type, uptime}` objects
+    pub fn parse_proxies_json(&self, json: &str) -> Result<Vec<Proxy>, Box<dyn std::error::Error>> {
+        debug!("Parsing JSON for proxy addresses");
+        let entries: Vec<JsonProxyEntry> = serde_json::from_str(json)?;
+
+        let mut proxies = Vec::new();
+        let mut seen = HashSet::new();
+        for entry in entries {
+            let proxy_type = entry.proxy_type.trim().to_lowercase();
+            let uptime = match entry.uptime {
+                Some(serde_json::Value::String(raw)) => parse_uptime(&raw),
+                Some(serde_json::Value::Number(n)) => n.as_f64().and_then(|n| parse_uptime(&n.to_string())),
+                _ => None,
+            };
+            if let Some(mut proxy) = proxy_from_listing(entry.host.trim(), entry.port, &proxy_type, uptime) {
+                proxy.tags = entry.tags;
+                if seen.insert(format!("{}:{}", proxy.host, proxy.port)) {
+                    debug!("Found {} proxy from JSON: {}:{}", proxy_type, proxy.host, proxy.port);
+                    proxies.push(proxy);
+                }
+            }
+        }
+
+        if proxies.is_empty() {
+            warn!("No proxies found in JSON, returning empty list");
+        }
+
+        Ok(proxies)
+    }
+
+    /// Parse a plain-text proxy list with one `host:port` or `scheme://host:port` per line.
+    /// Blank lines and `#` comments are skipped, as are malformed lines (with a warning).
+    pub fn parse_proxies_text(&self, text: &str) -> Result<Vec<Proxy>, Box<dyn std::error::Error>> {
+        debug!("Parsing plain text for proxy addresses");
+        let mut proxies = Vec::new();
+        let mut seen = HashSet::new();
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match proxy_from_text_line(line) {
+                Ok(Some(proxy)) => {
+                    if seen.insert(format!("{}:{}", proxy.host, proxy.port)) {
+                        debug!("Found {:?} proxy from text: {}:{}", proxy.proxy_type, proxy.host, proxy.port);
+                        proxies.push(proxy);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Skipping malformed proxy list line: {}", e),
+            }
+        }
+
+        if proxies.is_empty() {
+            warn!("No proxies found in text, returning empty list");
+        }
+
         Ok(proxies)
     }
 
@@ -176,36 +645,21 @@ impl ProxyManager {
         
         // Pattern 0: Parse HTML table structure (primary method for outproxys.i2p)
         // The table has rows with: <td>address</td><td>port</td><td>uptime</td><td>type</td>
-        let row_selector = Selector::parse("table tr").unwrap_or_else(|_| {
-            warn!("Failed to create table row selector");
-            Selector::parse("tr").unwrap()
-        });
-        
-        for row in document.select(&row_selector) {
-            let cells: Vec<_> = row.select(&Selector::parse("td").unwrap()).collect();
-            if cells.len() >= 4 {
-                // Extract address (first cell), port (second cell), and type (fourth cell)
-                let address = cells[0].text().collect::<String>().trim().to_string();
-                let port_str = cells[1].text().collect::<String>().trim().to_string();
-                let proxy_type = cells[3].text().collect::<String>().trim().to_lowercase();
-                
-                // Only include HTTPS and SOCKS proxies, exclude HTTP
-                if proxy_type == "https" || proxy_type == "socks" {
-                    // Check if address is a valid I2P domain
-                    if address.ends_with(".i2p") || address.ends_with(".b32.i2p") {
-                        if let Ok(port) = port_str.parse::<u16>() {
-                            let key = format!("{}:{}", address, port);
-                            if seen.insert(key.clone()) {
-                                debug!("Found {} proxy from table: {}:{}", proxy_type, address, port);
-                                let pt = if proxy_type == "socks" {
-                                    ProxyType::Socks
-                                } else {
-                                    ProxyType::Https
-                                };
-                                proxies.push(Proxy::new_with_type(address, port, pt));
-                            }
+        let row_selector = build_selector("table tr").or_else(|| build_selector("tr"));
+        let cell_selector = build_selector("td");
+
+        if let (Some(row_selector), Some(cell_selector)) = (&row_selector, &cell_selector) {
+            for row in document.select(row_selector) {
+                match proxy_from_row(row, cell_selector) {
+                    Ok(Some(proxy)) => {
+                        let key = format!("{}:{}", proxy.host, proxy.port);
+                        if seen.insert(key) {
+                            debug!("Found {:?} proxy from table: {}:{}", proxy.proxy_type, proxy.host, proxy.port);
+                            proxies.push(proxy);
                         }
                     }
+                    Ok(None) => {}
+                    Err(e) => warn!("Skipping malformed proxy table row: {}", e),
                 }
             }
         }
@@ -219,12 +673,9 @@ impl ProxyManager {
         // We only want I2P proxies (which are in .i2p or .b32.i2p domains)
 
         // Pattern 2: Look for URLs in links (only HTTPS)
-        let link_selector = Selector::parse("a[href]").unwrap_or_else(|_| {
-            warn!("Failed to create link selector");
-            Selector::parse("a").unwrap()
-        });
+        let link_selector = build_selector("a[href]").or_else(|| build_selector("a"));
 
-        for element in document.select(&link_selector) {
+        for element in link_selector.iter().flat_map(|selector| document.select(selector)) {
             if let Some(href) = element.value().attr("href") {
                 // Only process HTTPS URLs
                 if href.starts_with("https://") {
@@ -245,8 +696,8 @@ impl ProxyManager {
         }
 
         // Pattern 3: Look for HTTPS URLs (skip HTTP URLs)
-        let url_pattern = regex::Regex::new(r"https://([^/\s:]+):?(\d{2,5})?")?;
-        for cap in url_pattern.captures_iter(&text) {
+        let url_pattern = build_regex(r"https://([^/\s:]+):?(\d{2,5})?");
+        for cap in url_pattern.iter().flat_map(|pattern| pattern.captures_iter(&text)) {
             if let Some(host) = cap.get(1) {
                 let host = host.as_str().to_string();
                 // Only process I2P domains
@@ -268,8 +719,8 @@ impl ProxyManager {
         // Pattern 4: Look for .i2p domains with common HTTPS/SOCKS ports
         // This is a fallback pattern, but we prefer table parsing which has type information
         // Only include ports that are commonly used for HTTPS (443) or SOCKS (1080, 9050)
-        let i2p_pattern = regex::Regex::new(r"([a-z0-9-]+\.i2p)(?::(\d{2,5}))?")?;
-        for cap in i2p_pattern.captures_iter(&text) {
+        let i2p_pattern = build_regex(r"([a-z0-9-]+\.i2p)(?::(\d{2,5}))?");
+        for cap in i2p_pattern.iter().flat_map(|pattern| pattern.captures_iter(&text)) {
             if let Some(host) = cap.get(1) {
                 let host = host.as_str().to_string();
                 let port: u16 = cap
@@ -311,6 +762,131 @@ impl Default for ProxyManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::{FakeRouter, MockResponse, MockServer};
+
+    #[tokio::test]
+    async fn test_fetch_proxies_through_injected_router() {
+        let listing = "<table><tr><td>proxy1.b32.i2p</td><td>443</td><td>99%</td><td>https</td></tr></table>";
+        let router_proxy = MockServer::respond_with(
+            MockResponse::ok(listing).header("Content-Type", "text/html"),
+        )
+        .await;
+        let router = Arc::new(FakeRouter::new(router_proxy.addr.port(), router_proxy.addr.port()));
+        let manager = ProxyManager::with_router(router.clone());
+
+        let proxies = manager.fetch_proxies().await.unwrap();
+
+        assert_eq!(proxies.len(), 1);
+        assert_eq!(proxies[0].host, "proxy1.b32.i2p");
+        assert!(router_proxy.requests()[0].target.contains(".b32.i2p"));
+        // Once when the manager was built and once before the fetch
+        assert_eq!(router.ensure_running_calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_empty_listing_lenient_and_strict() {
+        let router_proxy = MockServer::respond_with(
+            MockResponse::ok("<table></table>").header("Content-Type", "text/html"),
+        )
+        .await;
+        let router = Arc::new(FakeRouter::new(router_proxy.addr.port(), router_proxy.addr.port()));
+        let manager = ProxyManager::with_router(router);
+
+        assert!(manager.fetch_proxies().await.unwrap().is_empty());
+
+        let err = manager.fetch_proxies_strict().await.unwrap_err();
+        let not_found = err.downcast_ref::<NoProxiesFound>().unwrap();
+        assert_eq!(not_found.body_bytes, "<table></table>".len());
+    }
+
+    #[tokio::test]
+    async fn test_strict_fetch_returns_found_proxies() {
+        let listing = "proxy1.b32.i2p:443\nproxy2.b32.i2p:4444\n";
+        let router_proxy = MockServer::respond_with(
+            MockResponse::ok(listing).header("Content-Type", "text/plain"),
+        )
+        .await;
+        let router = Arc::new(FakeRouter::new(router_proxy.addr.port(), router_proxy.addr.port()));
+        let manager = ProxyManager::with_router(router);
+
+        assert_eq!(manager.fetch_proxies_strict().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_uses_configured_timeout() {
+        let router_proxy = MockServer::respond_with(
+            MockResponse::ok("<table></table>").delay(Duration::from_millis(500)),
+        )
+        .await;
+        let router = Arc::new(FakeRouter::new(router_proxy.addr.port(), router_proxy.addr.port()));
+        let manager = ProxyManager::with_router(router)
+            .with_connect_timeout(Duration::from_secs(1))
+            .with_timeout(Duration::from_millis(100));
+
+        let start = std::time::Instant::now();
+        let err = manager.fetch_proxies().await.unwrap_err();
+
+        assert!(start.elapsed() < Duration::from_millis(450), "{}", err);
+        assert_eq!(router_proxy.requests().len(), 1);
+    }
+
+    #[test]
+    fn test_proxy_type_serde_roundtrip() {
+        for (proxy_type, json) in [
+            (ProxyType::Http, "\"http\""),
+            (ProxyType::Https, "\"https\""),
+            (ProxyType::Socks, "\"socks\""),
+        ] {
+            assert_eq!(serde_json::to_string(&proxy_type).unwrap(), json);
+            let restored: ProxyType = serde_json::from_str(json).unwrap();
+            assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+        }
+        assert!(serde_json::from_str::<ProxyType>("\"ftp\"").is_err());
+    }
+
+    #[test]
+    fn test_proxy_type_schemes() {
+        assert_eq!(ProxyType::all(), &[ProxyType::Http, ProxyType::Https, ProxyType::Socks]);
+        for (proxy_type, scheme) in [
+            (ProxyType::Http, "http"),
+            (ProxyType::Https, "https"),
+            (ProxyType::Socks, "socks5"),
+        ] {
+            assert_eq!(proxy_type.scheme(), scheme);
+            let proxy = Proxy::new_with_type("proxy.i2p".to_string(), 8080, proxy_type.clone());
+            assert_eq!(proxy.url, format!("{}://proxy.i2p:8080", scheme));
+            // Every scheme round-trips through from_url
+            assert_eq!(Proxy::from_url(&proxy.url).unwrap().proxy_type, proxy_type);
+        }
+    }
+
+    #[test]
+    fn test_proxy_serde_roundtrip() {
+        for proxy_type in [ProxyType::Http, ProxyType::Https, ProxyType::Socks] {
+            let mut proxy = Proxy::new_with_type("proxy.b32.i2p".to_string(), 443, proxy_type)
+                .with_tags(["trusted"]);
+            proxy.uptime = Some(0.97);
+
+            let json = serde_json::to_string(&proxy).unwrap();
+            let restored: Proxy = serde_json::from_str(&json).unwrap();
+
+            assert_eq!(restored.host, proxy.host);
+            assert_eq!(restored.port, proxy.port);
+            assert_eq!(restored.url, proxy.url);
+            assert_eq!(restored.uptime, proxy.uptime);
+            assert_eq!(restored.tags, proxy.tags);
+            assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+        }
+    }
+
+    #[test]
+    fn test_proxy_deserialize_without_optional_fields() {
+        let json = r#"{"host": "proxy.i2p", "port": 443, "url": "https://proxy.i2p:443", "proxy_type": "https"}"#;
+        let proxy: Proxy = serde_json::from_str(json).unwrap();
+        assert!(matches!(proxy.proxy_type, ProxyType::Https));
+        assert!(proxy.uptime.is_none());
+        assert!(proxy.tags.is_empty());
+    }
 
     #[test]
     fn test_proxy_new() {
@@ -360,6 +936,32 @@ mod tests {
         assert!(proxy.is_none());
     }
 
+    #[test]
+    fn test_proxy_validate_accepts_proxy_urls() {
+        assert_eq!(Proxy::validate("http://10.0.0.1:8080"), Ok(()));
+        assert_eq!(Proxy::validate("https://proxy.example.com"), Ok(()));
+        assert_eq!(Proxy::validate("socks5://127.0.0.1:9050"), Ok(()));
+        assert_eq!(Proxy::validate_i2p("http://outproxy.b32.i2p:443"), Ok(()));
+    }
+
+    #[test]
+    fn test_proxy_validate_errors() {
+        assert!(matches!(Proxy::validate("not-a-url"), Err(ProxyParseError::Malformed(_))));
+        assert_eq!(
+            Proxy::validate("ftp://10.0.0.1:21"),
+            Err(ProxyParseError::UnsupportedScheme("ftp".to_string()))
+        );
+        assert_eq!(Proxy::validate("http://:8080"), Err(ProxyParseError::MissingHost));
+        assert_eq!(Proxy::validate("socks5://"), Err(ProxyParseError::MissingHost));
+        assert!(matches!(Proxy::validate("http://10.0.0.1:99999"), Err(ProxyParseError::InvalidPort(_))));
+        assert!(matches!(Proxy::validate("http://10.0.0.1:port"), Err(ProxyParseError::InvalidPort(_))));
+        assert!(matches!(Proxy::validate("http://10.0.0.1:0"), Err(ProxyParseError::InvalidPort(_))));
+        assert_eq!(
+            Proxy::validate_i2p("http://proxy.example.com:8080"),
+            Err(ProxyParseError::NotI2p("proxy.example.com".to_string()))
+        );
+    }
+
     #[test]
     fn test_proxy_is_i2p_proxy() {
         let proxy1 = Proxy::new("example.i2p".to_string(), 443);
@@ -502,6 +1104,208 @@ mod tests {
         assert!(proxies.len() >= 0);
     }
 
+    #[test]
+    fn test_parse_proxies_skips_broken_rows() {
+        let manager = ProxyManager::new();
+        let html = r#"<table>
+            <tr><td>bad-port.b32.i2p</td><td>99999</td><td>90%</td><td>https</td></tr>
+            <tr><td>good.b32.i2p</td><td>443</td><td>%%</td><td>https</td></tr>
+            <tr><td>words.b32.i2p</td><td>port</td><td></td><td>socks</td></tr>
+            <tr><td><table><tr><td>nested.b32.i2p</td></tr></table></td><td>1080</td><td>3d</td><td>SOCKS</td></tr>
+            <tr><td>also-good.b32.i2p</td><td>1080</td><td>12h</td><td>socks</td><td>extra</td></tr>
+            <tr><td></td><td></td><td></td><td></td></tr>
+        </table>
+        <a href="https://[::1">broken link</a>"#;
+
+        let proxies = manager.parse_proxies(html).unwrap();
+
+        let hosts: Vec<&str> = proxies.iter().map(|p| p.host.as_str()).collect();
+        assert!(hosts.contains(&"good.b32.i2p"));
+        assert!(hosts.contains(&"also-good.b32.i2p"));
+        assert!(!hosts.contains(&"bad-port.b32.i2p"));
+        assert!(!hosts.contains(&"words.b32.i2p"));
+    }
+
+    #[test]
+    fn test_parse_uptime_formats() {
+        assert_eq!(parse_uptime("100%"), Some(1.0));
+        assert_eq!(parse_uptime("95%"), Some(0.95));
+        assert_eq!(parse_uptime(" 50 % "), Some(0.5));
+        assert_eq!(parse_uptime("0.8"), Some(0.8));
+        assert_eq!(parse_uptime("90"), Some(0.9));
+        assert_eq!(parse_uptime("7d"), Some(1.0));
+        assert_eq!(parse_uptime("30d"), Some(1.0));
+        assert_eq!(parse_uptime("84h"), Some(0.5));
+        assert!(parse_uptime("3d").unwrap() > 0.42 && parse_uptime("3d").unwrap() < 0.43);
+        assert_eq!(parse_uptime("N/A"), None);
+        assert_eq!(parse_uptime("-"), None);
+        assert_eq!(parse_uptime(""), None);
+        assert_eq!(parse_uptime("abc%"), None);
+    }
+
+    #[test]
+    fn test_parse_proxies_keeps_uptime() {
+        let manager = ProxyManager::new();
+        let html = r#"
+            <table>
+                <tr><td>proxy1.i2p</td><td>443</td><td>95%</td><td>https</td></tr>
+                <tr><td>proxy2.i2p</td><td>443</td><td>N/A</td><td>https</td></tr>
+            </table>
+        "#;
+
+        let proxies = manager.parse_proxies(html).unwrap();
+        assert_eq!(proxies[0].uptime, Some(0.95));
+        assert_eq!(proxies[1].uptime, None);
+
+        let json = r#"[
+            {"host": "proxy1.i2p", "port": 443, "type": "https", "uptime": "99%"},
+            {"host": "proxy2.i2p", "port": 443, "type": "https", "uptime": 80},
+            {"host": "proxy3.i2p", "port": 443, "type": "https"}
+        ]"#;
+        let proxies = manager.parse_proxies_json(json).unwrap();
+        assert_eq!(proxies[0].uptime, Some(0.99));
+        assert_eq!(proxies[1].uptime, Some(0.8));
+        assert_eq!(proxies[2].uptime, None);
+    }
+
+    #[test]
+    fn test_parse_proxies_json() {
+        let manager = ProxyManager::new();
+        let json = r#"[
+            {"host": "proxy1.i2p", "port": 443, "type": "https", "uptime": "99%"},
+            {"host": "proxy2.b32.i2p", "port": 1080, "type": "SOCKS", "uptime": "95%"},
+            {"host": "proxy3.i2p", "port": 80, "type": "http", "uptime": "100%"},
+            {"host": "clearnet.example.com", "port": 443, "type": "https", "uptime": "100%"},
+            {"host": "proxy1.i2p", "port": 443, "type": "https", "uptime": "99%"}
+        ]"#;
+
+        let proxies = manager.parse_proxies_json(json).unwrap();
+        assert_eq!(proxies.len(), 2);
+        assert_eq!(proxies[0].host, "proxy1.i2p");
+        assert!(matches!(proxies[0].proxy_type, ProxyType::Https));
+        assert_eq!(proxies[1].host, "proxy2.b32.i2p");
+        assert!(matches!(proxies[1].proxy_type, ProxyType::Socks));
+    }
+
+    #[test]
+    fn test_parse_proxies_json_keeps_tags() {
+        let manager = ProxyManager::new();
+        let json = r#"[
+            {"host": "proxy1.i2p", "port": 443, "type": "https", "tags": ["trusted", "fast"]},
+            {"host": "proxy2.i2p", "port": 443, "type": "https"}
+        ]"#;
+
+        let proxies = manager.parse_proxies_json(json).unwrap();
+        assert_eq!(proxies[0].tags, vec!["trusted".to_string(), "fast".to_string()]);
+        assert!(proxies[1].tags.is_empty());
+    }
+
+    #[test]
+    fn test_proxy_has_tags() {
+        let proxy = Proxy::new("proxy.i2p".to_string(), 443).with_tags(["Trusted", "us-region"]);
+
+        assert!(proxy.has_tags(&[]));
+        assert!(proxy.has_tags(&["trusted"]));
+        assert!(proxy.has_tags(&["trusted", "us-region"]));
+        assert!(!proxy.has_tags(&["trusted", "fast"]));
+        assert!(Proxy::new("bare.i2p".to_string(), 443).has_tags(&[]));
+    }
+
+    #[test]
+    fn test_parse_proxies_json_malformed() {
+        let manager = ProxyManager::new();
+        assert!(manager.parse_proxies_json("[{\"host\": \"proxy1.i2p\",").is_err());
+        assert!(manager.parse_proxies_json("{\"host\": \"proxy1.i2p\"}").is_err());
+        assert!(manager
+            .parse_proxies_json(r#"[{"host": "proxy1.i2p", "port": "not-a-port", "type": "https"}]"#)
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_proxy_list_picks_parser_by_content_type() {
+        let manager = ProxyManager::new();
+        let json = r#"[{"host": "proxy1.i2p", "port": 443, "type": "https"}]"#;
+        let html = "<table><tr><td>proxy2.i2p</td><td>443</td><td>100%</td><td>https</td></tr></table>";
+
+        let from_json = manager
+            .parse_proxy_list(Some("application/json; charset=utf-8"), json)
+            .unwrap();
+        assert_eq!(from_json.len(), 1);
+        assert_eq!(from_json[0].host, "proxy1.i2p");
+
+        let from_html = manager.parse_proxy_list(Some("text/html"), html).unwrap();
+        assert_eq!(from_html.len(), 1);
+        assert_eq!(from_html[0].host, "proxy2.i2p");
+
+        let without_type = manager.parse_proxy_list(None, html).unwrap();
+        assert_eq!(without_type.len(), 1);
+
+        let from_text = manager
+            .parse_proxy_list(Some("text/plain; charset=utf-8"), "proxy3.i2p:443\n")
+            .unwrap();
+        assert_eq!(from_text.len(), 1);
+        assert_eq!(from_text[0].host, "proxy3.i2p");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_from_file_each_format() {
+        let dir = std::env::temp_dir().join(format!("i2ptunnel-proxy-files-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let manager = ProxyManager::new();
+        let html = "<table><tr><td>proxy1.i2p</td><td>443</td><td>100%</td><td>https</td></tr></table>";
+        let json = r#"[{"host": "proxy2.b32.i2p", "port": 1080, "type": "socks"}]"#;
+        let text = "# local list\nproxy3.i2p:443\n";
+        let files = [
+            ("list.html", html, "proxy1.i2p"),
+            ("list.json", json, "proxy2.b32.i2p"),
+            ("list.txt", text, "proxy3.i2p"),
+            // Without a known extension the content decides
+            ("html-list", html, "proxy1.i2p"),
+            ("json-list", json, "proxy2.b32.i2p"),
+            ("text-list", text, "proxy3.i2p"),
+        ];
+
+        for (name, content, host) in files {
+            let path = dir.join(name);
+            std::fs::write(&path, content).unwrap();
+            let proxies = manager.fetch_from_file(&path).await.unwrap();
+            let hosts: Vec<&str> = proxies.iter().map(|p| p.host.as_str()).collect();
+            assert_eq!(hosts, vec![host], "{}", name);
+        }
+
+        assert!(manager.fetch_from_file(dir.join("missing.txt")).await.is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_parse_proxies_text() {
+        let manager = ProxyManager::new();
+        let text = "# outproxies, updated daily\n\
+            proxy1.i2p:443\n\
+            \n\
+            socks5://proxy2.b32.i2p:4447\n\
+            https://proxy3.i2p:8443/\n\
+            proxy4.i2p:1080\n\
+            http://proxy5.i2p:4444\n\
+            proxy6.i2p:80\n\
+            clearnet.example.com:443\n\
+            proxy7.i2p\n\
+            proxy8.i2p:not-a-port\n\
+            PROXY1.i2p:443\n";
+
+        let proxies = manager.parse_proxies_text(text).unwrap();
+
+        let found: Vec<(&str, u16)> = proxies.iter().map(|p| (p.host.as_str(), p.port)).collect();
+        assert_eq!(
+            found,
+            vec![("proxy1.i2p", 443), ("proxy2.b32.i2p", 4447), ("proxy3.i2p", 8443), ("proxy4.i2p", 1080)]
+        );
+        assert!(matches!(proxies[0].proxy_type, ProxyType::Https));
+        assert!(matches!(proxies[1].proxy_type, ProxyType::Socks));
+        assert!(matches!(proxies[2].proxy_type, ProxyType::Https));
+        assert!(matches!(proxies[3].proxy_type, ProxyType::Socks));
+    }
+
     #[test]
     fn test_proxy_from_url_without_port() {
         let proxy = Proxy::from_url("https://test.i2p");
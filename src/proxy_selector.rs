@@ -1,8 +1,14 @@
-use crate::proxy_manager::Proxy;
-use crate::proxy_tester::{ProxyTestResult, ProxyTester};
+use crate::i2pd_router::Router;
+use crate::proxy_manager::{Proxy, ProxyType};
+use crate::proxy_tester::{compare_by_speed, ProxyTestResult, ProxyTester};
+use crate::selection_strategy::{FastestStrategy, LowestLatencyStrategy, SelectionStrategy};
 use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tracing::{debug, info, warn};
 
 #[derive(Debug, Clone)]
@@ -12,11 +18,248 @@ pub struct SelectedProxy {
     pub selected_at: Instant,
 }
 
+/// Which proxies are worth testing, given the sites the application talks to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TargetNetwork {
+    /// Test every proxy
+    #[default]
+    All,
+    /// Only I2P outproxies
+    I2p,
+    /// Only clearnet proxies
+    Clearnet,
+}
+
+/// What the `ensure_*` methods optimize for when picking among tested proxies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SelectionMode {
+    /// Highest throughput, for downloads
+    #[default]
+    Fastest,
+    /// Lowest latency, for interactive browsing
+    LowestLatency,
+}
+
+impl SelectionMode {
+    fn strategy(self) -> Box<dyn SelectionStrategy> {
+        match self {
+            SelectionMode::Fastest => Box::new(FastestStrategy),
+            SelectionMode::LowestLatency => Box::new(LowestLatencyStrategy),
+        }
+    }
+}
+
+impl TargetNetwork {
+    fn includes(self, proxy: &Proxy) -> bool {
+        match self {
+            TargetNetwork::All => true,
+            TargetNetwork::I2p => proxy.is_i2p_proxy(),
+            TargetNetwork::Clearnet => !proxy.is_i2p_proxy(),
+        }
+    }
+}
+
+/// How a single tested proxy fared in the last selection
+#[derive(Debug, Clone)]
+pub struct CandidateExplanation {
+    pub proxy: Proxy,
+    pub score: f64,
+    pub speed_bytes_per_sec: f64,
+    pub latency_ms: f64,
+    pub eligible: bool,
+    pub reason: String,
+}
+
+/// Read-only breakdown of the last selection, candidates in ranked order
+#[derive(Debug, Clone, Default)]
+pub struct SelectionExplanation {
+    pub candidates: Vec<CandidateExplanation>,
+    pub winner: Option<Proxy>,
+}
+
+/// Proxies passed their tests, but none reached the selector's minimum speed
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoAcceptableProxy {
+    pub min_speed_bytes_per_sec: f64,
+    /// Speed of the fastest proxy that passed
+    pub fastest_bytes_per_sec: f64,
+}
+
+impl std::fmt::Display for NoAcceptableProxy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "No acceptable proxy: fastest was {:.2} KB/s, minimum is {:.2} KB/s",
+            self.fastest_bytes_per_sec / 1024.0,
+            self.min_speed_bytes_per_sec / 1024.0
+        )
+    }
+}
+
+impl std::error::Error for NoAcceptableProxy {}
+
+/// A test result as saved by `ProxySelector::save_results`
+#[derive(Serialize, Deserialize)]
+struct PersistedResult {
+    #[serde(flatten)]
+    result: ProxyTestResult,
+    /// When the test ran, in seconds since the Unix epoch
+    tested_at: u64,
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0)
+}
+
+/// A proxy as remembered in the failure table
+#[derive(Debug, Clone)]
+pub struct KnownProxy {
+    pub proxy: Proxy,
+    pub successes: u32,
+    pub failures: u32,
+    /// Success/failure alternations since the proxy last behaved consistently
+    pub flaps: u32,
+    pub quarantined: bool,
+    /// Time left before a quarantined proxy may be selected again
+    pub quarantine_remaining: Option<Duration>,
+}
+
+/// Outcome history of one proxy across tests and requests
+#[derive(Debug, Clone)]
+struct ProxyHealth {
+    proxy: Proxy,
+    successes: u32,
+    failures: u32,
+    last_outcome: Option<bool>,
+    streak: u32,
+    flaps: u32,
+    quarantined_until: Option<Instant>,
+    last_failure: Option<Instant>,
+    last_seen: Instant,
+}
+
+impl ProxyHealth {
+    fn new(proxy: Proxy, now: Instant) -> Self {
+        Self {
+            proxy,
+            successes: 0,
+            failures: 0,
+            last_outcome: None,
+            streak: 0,
+            flaps: 0,
+            quarantined_until: None,
+            last_failure: None,
+            last_seen: now,
+        }
+    }
+
+    /// Record one outcome. A change from the previous outcome is a flap; `flap_threshold`
+    /// outcomes in a row clear the flaps, and reaching `flap_threshold` flaps quarantines
+    /// the proxy for `cooldown`. Returns true when this outcome started a quarantine.
+    fn record(&mut self, success: bool, now: Instant, flap_threshold: u32, cooldown: Duration) -> bool {
+        self.last_seen = now;
+        if success {
+            self.successes += 1;
+        } else {
+            self.failures += 1;
+            self.last_failure = Some(now);
+        }
+
+        match self.last_outcome {
+            Some(previous) if previous != success => {
+                self.flaps += 1;
+                self.streak = 1;
+            }
+            Some(_) => {
+                self.streak += 1;
+                if self.streak >= flap_threshold {
+                    self.flaps = 0;
+                }
+            }
+            None => self.streak = 1,
+        }
+        self.last_outcome = Some(success);
+
+        if self.flaps >= flap_threshold && !self.is_quarantined(now) {
+            self.quarantined_until = Some(now + cooldown);
+            self.flaps = 0;
+            return true;
+        }
+        false
+    }
+
+    fn is_quarantined(&self, now: Instant) -> bool {
+        self.quarantined_until.is_some_and(|until| now < until)
+    }
+
+    fn failed_within(&self, now: Instant, window: Duration) -> bool {
+        self.last_failure.is_some_and(|at| now.duration_since(at) < window)
+    }
+
+    /// Whether the history still matters at `now`: the proxy had an outcome within `window`
+    /// or is still quarantined
+    fn is_current(&self, now: Instant, window: Duration) -> bool {
+        now.duration_since(self.last_seen) < window || self.is_quarantined(now)
+    }
+
+    fn snapshot(&self, now: Instant) -> KnownProxy {
+        KnownProxy {
+            proxy: self.proxy.clone(),
+            successes: self.successes,
+            failures: self.failures,
+            flaps: self.flaps,
+            quarantined: self.is_quarantined(now),
+            quarantine_remaining: self
+                .quarantined_until
+                .filter(|until| now < *until)
+                .map(|until| until - now),
+        }
+    }
+}
+
+/// Order untested proxies by the uptime reported by the directory, most reliable first.
+/// Proxies without uptime information go last; the sort is stable otherwise.
+pub fn rank_by_uptime(mut proxies: Vec<Proxy>) -> Vec<Proxy> {
+    proxies.sort_by(|a, b| match (a.uptime, b.uptime) {
+        (Some(a), Some(b)) => b.partial_cmp(&a).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+    proxies
+}
+
+/// Share of each new realized speed in a proxy's moving average
+const REALIZED_SPEED_SMOOTHING: f64 = 0.3;
+
+/// Realized speeds without a request for this long are forgotten
+const REALIZED_SPEED_MAX_AGE: Duration = Duration::from_secs(3600);
+
 pub struct ProxySelector {
     current_proxy: Arc<RwLock<Option<SelectedProxy>>>,
     tester: ProxyTester,
-    retest_interval: Duration,
+    retest_interval: Arc<RwLock<Duration>>,
     last_retest: Arc<RwLock<Instant>>,
+    last_results: Arc<RwLock<Vec<ProxyTestResult>>>,
+    // When `last_results` were measured
+    results_tested_at: Arc<RwLock<Option<SystemTime>>>,
+    failure_table: Arc<RwLock<HashMap<String, ProxyHealth>>>,
+    flap_threshold: u32,
+    quarantine_cooldown: Duration,
+    target_network: TargetNetwork,
+    strategy: Box<dyn SelectionStrategy>,
+    min_speed_bytes_per_sec: f64,
+    max_test_candidates: Option<usize>,
+    i2p_test_share: f64,
+    // Types learned from working fallbacks, by "host:port"
+    type_corrections: Arc<RwLock<HashMap<String, ProxyType>>>,
+    // Proxy URLs that got anti-bot challenges, by target host
+    challenged: Arc<RwLock<HashMap<String, HashSet<String>>>>,
+    // Last proxy that worked for each target host
+    sticky_hosts: Arc<RwLock<HashMap<String, SelectedProxy>>>,
+    // Moving average of the throughput real requests got and when it was last updated, by proxy URL
+    realized_speeds: Arc<RwLock<HashMap<String, (f64, Instant)>>>,
+    realized_weight: f64,
 }
 
 impl ProxySelector {
@@ -28,47 +271,385 @@ impl ProxySelector {
         Self {
             current_proxy: Arc::new(RwLock::new(None)),
             tester: ProxyTester::new(None),
-            retest_interval: Duration::from_secs(retest_interval_secs),
+            retest_interval: Arc::new(RwLock::new(Duration::from_secs(retest_interval_secs))),
             last_retest: Arc::new(RwLock::new(Instant::now())),
+            last_results: Arc::new(RwLock::new(Vec::new())),
+            results_tested_at: Arc::new(RwLock::new(None)),
+            failure_table: Arc::new(RwLock::new(HashMap::new())),
+            flap_threshold: 4,
+            quarantine_cooldown: Duration::from_secs(600),
+            target_network: TargetNetwork::All,
+            strategy: SelectionMode::default().strategy(),
+            min_speed_bytes_per_sec: 0.0,
+            max_test_candidates: None,
+            i2p_test_share: 0.5,
+            type_corrections: Arc::new(RwLock::new(HashMap::new())),
+            challenged: Arc::new(RwLock::new(HashMap::new())),
+            sticky_hosts: Arc::new(RwLock::new(HashMap::new())),
+            realized_speeds: Arc::new(RwLock::new(HashMap::new())),
+            realized_weight: 0.5,
         }
     }
 
-    pub async fn select_fastest(
-        &self,
-        test_results: Vec<ProxyTestResult>,
-    ) -> Option<SelectedProxy> {
-        info!("Selecting fastest proxy from {} results", test_results.len());
+    /// Test I2P outproxies and recognize the router's own proxy through `router` instead of
+    /// the process-wide embedded router. Pass the same router as to `RequestHandler::with_router`.
+    pub fn with_router(mut self, router: Arc<dyn Router>) -> Self {
+        self.tester = self.tester.with_router(router);
+        self
+    }
+
+    /// Only test proxies relevant to `network` when ensuring candidates
+    pub fn with_target_network(mut self, network: TargetNetwork) -> Self {
+        self.target_network = network;
+        self
+    }
+
+    /// Rank tested proxies by `mode` in `ensure_fastest_proxy`, `force_retest` and
+    /// `ensure_multiple_proxy_candidates`. Shorthand for the matching built-in strategy.
+    pub fn with_selection_mode(self, mode: SelectionMode) -> Self {
+        self.with_strategy(mode.strategy())
+    }
+
+    /// Pick among tested proxies with `strategy` in `ensure_fastest_proxy`, `force_retest` and
+    /// `ensure_multiple_proxy_candidates` (`FastestStrategy` by default)
+    pub fn with_strategy(mut self, strategy: Box<dyn SelectionStrategy>) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Never select proxies that tested slower than `bytes_per_sec` (0, the default, accepts any)
+    pub fn with_min_speed(mut self, bytes_per_sec: f64) -> Self {
+        self.min_speed_bytes_per_sec = bytes_per_sec.max(0.0);
+        self
+    }
+
+    fn fast_enough(&self, result: &ProxyTestResult) -> bool {
+        result.speed_bytes_per_sec >= self.min_speed_bytes_per_sec
+    }
 
-        let successful_results: Vec<&ProxyTestResult> = test_results
+    /// When nothing was selected although proxies passed, they were all below the minimum speed
+    fn ensure_acceptable(&self, selected_any: bool) -> Result<(), NoAcceptableProxy> {
+        if selected_any || self.min_speed_bytes_per_sec <= 0.0 {
+            return Ok(());
+        }
+        let fastest = self
+            .last_results
+            .read()
             .iter()
-            .filter(|r| r.success)
+            .filter(|r| r.success && !self.is_quarantined(&r.proxy))
+            .map(|r| r.speed_bytes_per_sec)
+            .reduce(f64::max);
+        match fastest {
+            Some(fastest_bytes_per_sec) => Err(NoAcceptableProxy {
+                min_speed_bytes_per_sec: self.min_speed_bytes_per_sec,
+                fastest_bytes_per_sec,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Remember that `proxy` actually works as `new_type` (e.g. a listed SOCKS proxy that only
+    /// worked through the HTTPS fallback). The correction applies to the current selection and
+    /// to every proxy list given to this selector from now on.
+    pub fn update_proxy_type(&self, proxy: &Proxy, new_type: ProxyType) {
+        let key = format!("{}:{}", proxy.host, proxy.port);
+        info!("Proxy {} works as {:?}, remembering the corrected type", proxy.url, new_type);
+        self.type_corrections.write().insert(key, new_type.clone());
+
+        let same_proxy = |p: &Proxy| p.host == proxy.host && p.port == proxy.port;
+        if let Some(current) = self.current_proxy.write().as_mut() {
+            if same_proxy(&current.proxy) {
+                current.proxy = Self::retyped(&current.proxy, new_type.clone());
+            }
+        }
+        for result in self.last_results.write().iter_mut() {
+            if same_proxy(&result.proxy) {
+                result.proxy = Self::retyped(&result.proxy, new_type.clone());
+            }
+        }
+    }
+
+    fn retyped(proxy: &Proxy, proxy_type: ProxyType) -> Proxy {
+        let mut retyped = Proxy::new_with_type(proxy.host.clone(), proxy.port, proxy_type);
+        retyped.uptime = proxy.uptime;
+        retyped.tags = proxy.tags.clone();
+        retyped
+    }
+
+    /// Apply the types learned through `update_proxy_type`
+    fn with_type_corrections(&self, proxies: Vec<Proxy>) -> Vec<Proxy> {
+        let corrections = self.type_corrections.read();
+        if corrections.is_empty() {
+            return proxies;
+        }
+        proxies
+            .into_iter()
+            .map(|proxy| match corrections.get(&format!("{}:{}", proxy.host, proxy.port)) {
+                Some(proxy_type) => Self::retyped(&proxy, proxy_type.clone()),
+                None => proxy,
+            })
+            .collect()
+    }
+
+    /// Test at most `max_candidates` proxies per round, giving I2P outproxies `i2p_share`
+    /// (0.0 to 1.0) of the slots and clearnet proxies the rest. A network that needs fewer
+    /// slots than its share leaves them to the other one.
+    pub fn with_test_budget(mut self, max_candidates: usize, i2p_share: f64) -> Self {
+        self.max_test_candidates = Some(max_candidates.max(1));
+        self.i2p_test_share = i2p_share.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Cut an uptime-ranked list down to the test budget, keeping the order within each network
+    fn apply_test_budget(&self, proxies: Vec<Proxy>) -> Vec<Proxy> {
+        let Some(budget) = self.max_test_candidates else {
+            return proxies;
+        };
+        if proxies.len() <= budget {
+            return proxies;
+        }
+
+        let (mut i2p, mut clearnet): (Vec<Proxy>, Vec<Proxy>) =
+            proxies.into_iter().partition(|proxy| proxy.is_i2p_proxy());
+        let i2p_share = ((budget as f64) * self.i2p_test_share).round() as usize;
+        let clearnet_slots = (budget - i2p_share.min(i2p.len())).min(clearnet.len());
+        let i2p_slots = (budget - clearnet_slots).min(i2p.len());
+        debug!(
+            "Test budget {}: testing {} of {} I2P and {} of {} clearnet proxies",
+            budget,
+            i2p_slots,
+            i2p.len(),
+            clearnet_slots,
+            clearnet.len()
+        );
+
+        i2p.truncate(i2p_slots);
+        clearnet.truncate(clearnet_slots);
+        i2p.extend(clearnet);
+        i2p
+    }
+
+    /// Drop proxies outside the target network before they are tested
+    fn relevant_proxies(&self, available_proxies: Vec<Proxy>) -> Vec<Proxy> {
+        if self.target_network == TargetNetwork::All {
+            return available_proxies;
+        }
+        let total = available_proxies.len();
+        let relevant: Vec<Proxy> = available_proxies
+            .into_iter()
+            .filter(|proxy| self.target_network.includes(proxy))
             .collect();
+        debug!(
+            "Target network {:?}: testing {} of {} proxies",
+            self.target_network,
+            relevant.len(),
+            total
+        );
+        relevant
+    }
 
-        if successful_results.is_empty() {
-            warn!("No successful proxy tests found");
-            return None;
+    /// How long a selection is reused before proxies are tested again
+    pub fn retest_interval(&self) -> Duration {
+        *self.retest_interval.read()
+    }
+
+    /// Change the retest interval at runtime, e.g. shorter while connectivity is flaky.
+    /// Takes effect on the next check, measured from the last retest.
+    pub fn set_retest_interval(&self, interval: Duration) {
+        info!("Retest interval changed to {:?}", interval);
+        *self.retest_interval.write() = interval;
+    }
+
+    /// Quarantine proxies after `flap_threshold` success/failure alternations, keeping
+    /// them out of selection for `cooldown`. Proxies that failed within `cooldown` without
+    /// being quarantined are still selected, after all the others.
+    pub fn with_quarantine(mut self, flap_threshold: u32, cooldown: Duration) -> Self {
+        self.flap_threshold = flap_threshold.max(1);
+        self.quarantine_cooldown = cooldown;
+        self
+    }
+
+    /// How much the speed real requests got counts against the tested speed when ranking a
+    /// proxy with recorded requests: 0 ranks by tested speed only, 1 by realized speed only
+    /// (0.5 by default). See `record_realized`.
+    pub fn with_realized_weight(mut self, weight: f64) -> Self {
+        self.realized_weight = weight.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Feed back a completed request through `proxy` that moved `bytes` in `elapsed`. The
+    /// first one sets the proxy's realized speed; later ones move it a fraction of the way
+    /// towards their own speed, so recent requests count most. `RequestHandler` records every
+    /// body it reads through a proxy; proxies without a request for an hour are forgotten.
+    pub fn record_realized(&self, proxy: &Proxy, bytes: u64, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        if secs <= 0.0 {
+            return;
         }
+        let speed = bytes as f64 / secs;
+        let now = Instant::now();
+        let mut realized = self.realized_speeds.write();
+        realized.retain(|_, (_, updated)| now.duration_since(*updated) < REALIZED_SPEED_MAX_AGE);
+        let average = match realized.get(&proxy.url) {
+            Some((average, _)) => REALIZED_SPEED_SMOOTHING * speed + (1.0 - REALIZED_SPEED_SMOOTHING) * average,
+            None => speed,
+        };
+        debug!(
+            "Proxy {} realized {:.2} KB/s, average now {:.2} KB/s",
+            proxy.url,
+            speed / 1024.0,
+            average / 1024.0
+        );
+        realized.insert(proxy.url.clone(), (average, now));
+    }
 
-        let fastest = successful_results.iter().max_by(|a, b| {
-            a.speed_bytes_per_sec
-                .partial_cmp(&b.speed_bytes_per_sec)
-                .unwrap_or(std::cmp::Ordering::Equal)
-        })?;
+    /// `test_results` with the speed of proxies that have realized speeds blended towards them
+    fn with_realized_speeds(&self, mut test_results: Vec<ProxyTestResult>) -> Vec<ProxyTestResult> {
+        let realized = self.realized_speeds.read();
+        for result in &mut test_results {
+            if let Some((speed, updated)) = realized.get(&result.proxy.url) {
+                if updated.elapsed() >= REALIZED_SPEED_MAX_AGE {
+                    continue;
+                }
+                result.speed_bytes_per_sec =
+                    (1.0 - self.realized_weight) * result.speed_bytes_per_sec + self.realized_weight * speed;
+            }
+        }
+        test_results
+    }
 
-        let selected = SelectedProxy {
-            proxy: fastest.proxy.clone(),
-            speed_bytes_per_sec: fastest.speed_bytes_per_sec,
-            selected_at: Instant::now(),
+    /// Every proxy seen in tests or requests within the quarantine cooldown (or still
+    /// quarantined), with its outcome history and quarantine state
+    pub fn known_proxies(&self) -> Vec<KnownProxy> {
+        let now = Instant::now();
+        let mut known: Vec<KnownProxy> = self
+            .failure_table
+            .read()
+            .values()
+            .map(|health| health.snapshot(now))
+            .collect();
+        known.sort_by(|a, b| a.proxy.url.cmp(&b.proxy.url));
+        known
+    }
+
+    pub fn is_quarantined(&self, proxy: &Proxy) -> bool {
+        self.failure_table
+            .read()
+            .get(&proxy.url)
+            .is_some_and(|health| health.is_quarantined(Instant::now()))
+    }
+
+    /// URLs of proxies that failed within the quarantine cooldown
+    fn recently_failed(&self) -> HashSet<String> {
+        let now = Instant::now();
+        self.failure_table
+            .read()
+            .values()
+            .filter(|health| health.failed_within(now, self.quarantine_cooldown))
+            .map(|health| health.proxy.url.clone())
+            .collect()
+    }
+
+    /// Move proxies that failed recently behind the others, keeping the order within each group
+    fn sink_recently_failed<T>(&self, candidates: &mut [T], url: impl Fn(&T) -> &str) {
+        let failed = self.recently_failed();
+        if !failed.is_empty() {
+            candidates.sort_by_key(|candidate| failed.contains(url(candidate)));
+        }
+    }
+
+    /// Record an outcome for `proxy`, first forgetting proxies without one within the
+    /// quarantine cooldown so the table doesn't keep every proxy ever tried
+    fn record_outcome(&self, proxy: &Proxy, success: bool) {
+        let now = Instant::now();
+        let quarantined = {
+            let mut table = self.failure_table.write();
+            table.retain(|_, health| health.is_current(now, self.quarantine_cooldown));
+            table
+                .entry(proxy.url.clone())
+                .or_insert_with(|| ProxyHealth::new(proxy.clone(), now))
+                .record(success, now, self.flap_threshold, self.quarantine_cooldown)
         };
 
+        if quarantined {
+            warn!(
+                "Proxy {} keeps flapping between success and failure, quarantined for {:?}",
+                proxy.url, self.quarantine_cooldown
+            );
+            let mut current = self.current_proxy.write();
+            if current.as_ref().is_some_and(|c| c.proxy.url == proxy.url) {
+                *current = None;
+            }
+        }
+    }
+
+    /// Keep `test_results` as the latest measurements and record their outcomes
+    fn remember_results(&self, test_results: &[ProxyTestResult]) {
+        *self.last_results.write() = test_results.to_vec();
+        *self.results_tested_at.write() = Some(SystemTime::now());
+        for result in test_results {
+            self.record_outcome(&result.proxy, result.success);
+        }
+    }
+
+    /// Save the latest test results with the time they were measured to `path` as JSON, so
+    /// `load_results` can pick them up after a restart instead of testing again
+    pub fn save_results(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let tested_at = unix_secs(self.results_tested_at.read().unwrap_or_else(SystemTime::now));
+        let persisted: Vec<PersistedResult> = self
+            .last_results
+            .read()
+            .iter()
+            .map(|result| PersistedResult { result: result.clone(), tested_at })
+            .collect();
+        std::fs::write(path.as_ref(), serde_json::to_vec_pretty(&persisted)?)?;
+        info!("Saved {} proxy test results to {}", persisted.len(), path.as_ref().display());
+        Ok(())
+    }
+
+    /// Load results saved by `save_results`, dropping those measured more than `max_age` ago.
+    /// The rest become the latest results, the selection strategy picks the current proxy
+    /// from them, and the retest interval counts from when they were measured. Returns the
+    /// number of results kept.
+    pub fn load_results(
+        &self,
+        path: impl AsRef<Path>,
+        max_age: Duration,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let persisted: Vec<PersistedResult> = serde_json::from_slice(&std::fs::read(path.as_ref())?)?;
+        let total = persisted.len();
+        let now = unix_secs(SystemTime::now());
+        let fresh: Vec<PersistedResult> = persisted
+            .into_iter()
+            .filter(|entry| now.saturating_sub(entry.tested_at) <= max_age.as_secs())
+            .collect();
         info!(
-            "Selected fastest proxy: {} ({:.2} KB/s)",
-            selected.proxy.url,
-            selected.speed_bytes_per_sec / 1024.0
+            "Loaded {} of {} saved proxy test results from {} ({} expired)",
+            fresh.len(),
+            total,
+            path.as_ref().display(),
+            total - fresh.len()
         );
+        let Some(newest) = fresh.iter().map(|entry| entry.tested_at).max() else {
+            return Ok(0);
+        };
+
+        let age = Duration::from_secs(now.saturating_sub(newest));
+        let results: Vec<ProxyTestResult> = fresh.into_iter().map(|entry| entry.result).collect();
+        *self.current_proxy.write() =
+            self.rank_with(self.strategy.as_ref(), results.clone()).into_iter().next();
+        *self.last_retest.write() = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+        *self.results_tested_at.write() = Some(UNIX_EPOCH + Duration::from_secs(newest));
+        let kept = results.len();
+        *self.last_results.write() = results;
+        Ok(kept)
+    }
 
-        *self.current_proxy.write() = Some(selected.clone());
-        Some(selected)
+    pub async fn select_fastest(
+        &self,
+        test_results: Vec<ProxyTestResult>,
+    ) -> Option<SelectedProxy> {
+        self.select_with(&FastestStrategy, test_results, 1).into_iter().next()
     }
 
     pub async fn select_fastest_multiple(
@@ -76,73 +657,190 @@ impl ProxySelector {
         test_results: Vec<ProxyTestResult>,
         count: usize,
     ) -> Vec<SelectedProxy> {
-        info!("Selecting top {} fastest proxies from {} results", count, test_results.len());
+        self.select_with(&FastestStrategy, test_results, count)
+    }
 
-        let mut successful_results: Vec<&ProxyTestResult> = test_results
-            .iter()
-            .filter(|r| r.success)
-            .collect();
+    /// Up to `count` successful proxies with the lowest `latency_ms` first. Caches the first
+    /// one as the current proxy, like `select_fastest_multiple`.
+    pub async fn select_lowest_latency(
+        &self,
+        test_results: Vec<ProxyTestResult>,
+        count: usize,
+    ) -> Vec<SelectedProxy> {
+        self.select_with(&LowestLatencyStrategy, test_results, count)
+    }
 
-        if successful_results.is_empty() {
-            warn!("No successful proxy tests found");
-            return Vec::new();
-        }
+    /// Up to `count` acceptable proxies from `test_results` in the order `strategy` picks
+    /// them, caching the first one as the current proxy
+    fn select_with(
+        &self,
+        strategy: &dyn SelectionStrategy,
+        test_results: Vec<ProxyTestResult>,
+        count: usize,
+    ) -> Vec<SelectedProxy> {
+        info!("Selecting {} proxies from {} results", count, test_results.len());
+        self.remember_results(&test_results);
 
-        // Sort by speed (descending)
-        successful_results.sort_by(|a, b| {
-            b.speed_bytes_per_sec
-                .partial_cmp(&a.speed_bytes_per_sec)
-                .unwrap_or(std::cmp::Ordering::Equal)
-        });
+        let mut selected = self.rank_with(strategy, test_results);
+        selected.truncate(count);
 
-        // Take top N
-        let selected: Vec<SelectedProxy> = successful_results
-            .iter()
-            .take(count)
-            .map(|result| SelectedProxy {
-                proxy: result.proxy.clone(),
-                speed_bytes_per_sec: result.speed_bytes_per_sec,
-                selected_at: Instant::now(),
-            })
+        match selected.first() {
+            Some(first) => {
+                info!("Selected {} proxies, first: {}", selected.len(), first.proxy.url);
+                *self.current_proxy.write() = Some(first.clone());
+            }
+            None => warn!("No successful proxy tests found"),
+        }
+        selected
+    }
+
+    /// Every acceptable proxy in `test_results`, ordered by `strategy` on the blended speeds
+    /// with recently failed proxies last
+    fn rank_with(
+        &self,
+        strategy: &dyn SelectionStrategy,
+        test_results: Vec<ProxyTestResult>,
+    ) -> Vec<SelectedProxy> {
+        let eligible: Vec<ProxyTestResult> = self
+            .with_realized_speeds(test_results)
+            .into_iter()
+            .filter(|r| r.success && !self.is_quarantined(&r.proxy) && self.fast_enough(r))
             .collect();
+        let mut ranked = strategy.select(&eligible, eligible.len());
+        self.sink_recently_failed(&mut ranked, |s| &s.proxy.url);
+        ranked
+    }
 
-        if !selected.is_empty() {
-            info!(
-                "Selected top {} proxies, fastest: {} ({:.2} KB/s)",
-                selected.len(),
-                selected[0].proxy.url,
-                selected[0].speed_bytes_per_sec / 1024.0
-            );
-            // Cache the fastest one
-            *self.current_proxy.write() = Some(selected[0].clone());
-        }
+    async fn select_by_strategy(&self, test_results: Vec<ProxyTestResult>) -> Option<SelectedProxy> {
+        self.select_with(self.strategy.as_ref(), test_results, 1).into_iter().next()
+    }
 
-        selected
+    async fn select_multiple_by_strategy(
+        &self,
+        test_results: Vec<ProxyTestResult>,
+        count: usize,
+    ) -> Vec<SelectedProxy> {
+        self.select_with(self.strategy.as_ref(), test_results, count)
+    }
+
+    /// Like `select_fastest_multiple`, restricted to proxies carrying all of `required_tags`.
+    /// No required tags means every proxy qualifies.
+    pub async fn select_with_tags(
+        &self,
+        test_results: Vec<ProxyTestResult>,
+        required_tags: &[&str],
+        count: usize,
+    ) -> Vec<SelectedProxy> {
+        let tagged: Vec<ProxyTestResult> = test_results
+            .into_iter()
+            .filter(|result| result.proxy.has_tags(required_tags))
+            .collect();
+        debug!("{} tested proxies carry tags {:?}", tagged.len(), required_tags);
+        self.select_fastest_multiple(tagged, count).await
     }
 
     pub fn get_current_proxy(&self) -> Option<SelectedProxy> {
         self.current_proxy.read().as_ref().cloned()
     }
 
+    /// Explain the last selection: every tested candidate with its score and the reason it
+    /// won, lost or was excluded. Eligible candidates come first, ranked and scored by the
+    /// selection strategy; the rest follow, successful ones first.
+    pub fn explain_selection(&self) -> SelectionExplanation {
+        // Ranked on the same blended speeds as the selection itself
+        let tested = self.last_results.read().clone();
+        let ranked = self.rank_with(self.strategy.as_ref(), tested.clone());
+        let mut results = self.with_realized_speeds(tested);
+        let recently_failed = self.recently_failed();
+        let ranks: HashMap<String, usize> = ranked
+            .into_iter()
+            .enumerate()
+            .map(|(rank, selected)| (selected.proxy.url, rank))
+            .collect();
+        results.sort_by(|a, b| match (ranks.get(&a.proxy.url), ranks.get(&b.proxy.url)) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => b.success.cmp(&a.success).then_with(|| compare_by_speed(a, b)),
+        });
+
+        let winner = self.get_current_proxy().map(|selected| selected.proxy);
+        let criterion = self.strategy.describe();
+
+        let candidates = results
+            .into_iter()
+            .map(|result| {
+                let is_winner = winner.as_ref().is_some_and(|w| w.url == result.proxy.url);
+                let quarantined = self.is_quarantined(&result.proxy);
+                let too_slow = !self.fast_enough(&result);
+                let rank = ranks.get(&result.proxy.url).copied();
+                let score = match rank {
+                    Some(_) => self.strategy.score(&result, &eligible),
+                    None => 0.0,
+                };
+                let reason = if !result.success {
+                    format!(
+                        "excluded: test failed ({})",
+                        result.error.as_deref().unwrap_or("unknown error")
+                    )
+                } else if quarantined {
+                    "excluded: quarantined for flapping between success and failure".to_string()
+                } else if too_slow {
+                    format!(
+                        "excluded: {:.2} KB/s is below the minimum of {:.2} KB/s",
+                        result.speed_bytes_per_sec / 1024.0,
+                        self.min_speed_bytes_per_sec / 1024.0
+                    )
+                } else if is_winner {
+                    format!(
+                        "selected: {} ({:.2} KB/s, {:.2} ms latency)",
+                        criterion,
+                        result.speed_bytes_per_sec / 1024.0,
+                        result.latency_ms
+                    )
+                } else if recently_failed.contains(&result.proxy.url) {
+                    "ranked lower: failed recently".to_string()
+                } else {
+                    format!("ranked lower: #{} by {}", rank.map_or(0, |rank| rank + 1), criterion)
+                };
+
+                CandidateExplanation {
+                    score,
+                    speed_bytes_per_sec: result.speed_bytes_per_sec,
+                    latency_ms: result.latency_ms,
+                    eligible: rank.is_some(),
+                    reason,
+                    proxy: result.proxy,
+                }
+            })
+            .collect();
+
+        SelectionExplanation { candidates, winner }
+    }
+
     pub async fn ensure_fastest_proxy(
         &self,
         available_proxies: Vec<Proxy>,
     ) -> Result<Option<SelectedProxy>, Box<dyn std::error::Error>> {
+        let available_proxies = self.relevant_proxies(self.with_type_corrections(available_proxies));
         let now = Instant::now();
         let last_retest_time = *self.last_retest.read();
 
         // Check if we need to retest
-        if now.duration_since(last_retest_time) >= self.retest_interval {
+        if now.duration_since(last_retest_time) >= self.retest_interval() {
             info!("Retest interval reached, testing proxies again");
             *self.last_retest.write() = now;
 
+            let available_proxies = self.apply_test_budget(rank_by_uptime(available_proxies));
             let max_concurrent = (available_proxies.len().min(10)).max(1);
             let test_results = self
                 .tester
                 .test_proxies_parallel(available_proxies, max_concurrent)
                 .await;
 
-            return Ok(self.select_fastest(test_results).await);
+            let selected = self.select_by_strategy(test_results).await;
+            self.ensure_acceptable(selected.is_some())?;
+            return Ok(selected);
         }
 
         // Return current proxy if we have one
@@ -151,13 +849,43 @@ impl ProxySelector {
             Ok(Some(proxy))
         } else {
             warn!("No current proxy available, testing proxies");
+            let available_proxies = self.apply_test_budget(rank_by_uptime(available_proxies));
             let max_concurrent = (available_proxies.len().min(10)).max(1);
             let test_results = self
                 .tester
                 .test_proxies_parallel(available_proxies, max_concurrent)
                 .await;
 
-            Ok(self.select_fastest(test_results).await)
+            let selected = self.select_by_strategy(test_results).await;
+            self.ensure_acceptable(selected.is_some())?;
+            Ok(selected)
+        }
+    }
+
+    /// Retest `available_proxies` right away, ignoring the retest interval, and replace the
+    /// current selection with the result. Fails (and clears the selection) if none passed.
+    pub async fn force_retest(
+        &self,
+        available_proxies: Vec<Proxy>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        info!("Forced retest of {} proxies", available_proxies.len());
+        let available_proxies = self.relevant_proxies(self.with_type_corrections(available_proxies));
+        *self.last_retest.write() = Instant::now();
+
+        let available_proxies = self.apply_test_budget(rank_by_uptime(available_proxies));
+        let max_concurrent = (available_proxies.len().min(10)).max(1);
+        let test_results = self
+            .tester
+            .test_proxies_parallel(available_proxies, max_concurrent)
+            .await;
+
+        match self.select_by_strategy(test_results).await {
+            Some(_) => Ok(()),
+            None => {
+                *self.current_proxy.write() = None;
+                self.ensure_acceptable(false)?;
+                Err("No proxy passed the forced retest".into())
+            }
         }
     }
 
@@ -166,21 +894,45 @@ impl ProxySelector {
         available_proxies: Vec<Proxy>,
         count: usize,
     ) -> Result<Vec<SelectedProxy>, Box<dyn std::error::Error>> {
+        self.ensure_candidates(available_proxies, count, false).await
+    }
+
+    /// Like `ensure_multiple_proxy_candidates`, but test `available_proxies` now even within
+    /// the retest interval and without the current proxy shortcut, for when the cached
+    /// selection is suspect. The retest interval starts over from this test.
+    pub async fn ensure_fresh_proxy_candidates(
+        &self,
+        available_proxies: Vec<Proxy>,
+        count: usize,
+    ) -> Result<Vec<SelectedProxy>, Box<dyn std::error::Error>> {
+        self.ensure_candidates(available_proxies, count, true).await
+    }
+
+    async fn ensure_candidates(
+        &self,
+        available_proxies: Vec<Proxy>,
+        count: usize,
+        fresh: bool,
+    ) -> Result<Vec<SelectedProxy>, Box<dyn std::error::Error>> {
+        let available_proxies = self.relevant_proxies(self.with_type_corrections(available_proxies));
         let now = Instant::now();
         let last_retest_time = *self.last_retest.read();
 
         // Check if we need to retest
-        if now.duration_since(last_retest_time) >= self.retest_interval {
+        if fresh || now.duration_since(last_retest_time) >= self.retest_interval() {
             info!("Retest interval reached, testing proxies again");
             *self.last_retest.write() = now;
 
+            let available_proxies = self.apply_test_budget(rank_by_uptime(available_proxies));
             let max_concurrent = (available_proxies.len().min(10)).max(1);
             let test_results = self
                 .tester
                 .test_proxies_parallel(available_proxies, max_concurrent)
                 .await;
 
-            return Ok(self.select_fastest_multiple(test_results, count).await);
+            let selected = self.select_multiple_by_strategy(test_results, count).await;
+            self.ensure_acceptable(!selected.is_empty())?;
+            return Ok(selected);
         }
 
         // If we have a current proxy, try to return it plus get more if needed
@@ -197,6 +949,7 @@ impl ProxySelector {
 
         // Test to get multiple candidates
         info!("Testing {} proxies to get {} candidates", available_proxies.len(), count);
+        let available_proxies = self.apply_test_budget(rank_by_uptime(available_proxies));
         let max_concurrent = (available_proxies.len().min(10)).max(1);
         info!("Testing proxies in parallel (max_concurrent={})", max_concurrent);
         let test_results = self
@@ -205,21 +958,126 @@ impl ProxySelector {
             .await;
         
         info!("Proxy testing completed: {} results", test_results.len());
-        let selected = self.select_fastest_multiple(test_results, count).await;
+        let selected = self.select_multiple_by_strategy(test_results, count).await;
         info!("Selected {} proxy candidates from test results", selected.len());
+        self.ensure_acceptable(!selected.is_empty())?;
         Ok(selected)
     }
 
+    /// `ensure_multiple_proxy_candidates`, then check that the top candidate can reach
+    /// `target_url`, since it was only tested against a generic URL. A top candidate that
+    /// can't is demoted to the end and the next one checked in its place; the one that
+    /// passes becomes the current proxy. If none passes, the candidates come back in their
+    /// original order.
+    pub async fn ensure_verified_proxy_candidates(
+        &self,
+        available_proxies: Vec<Proxy>,
+        count: usize,
+        target_url: &str,
+    ) -> Result<Vec<SelectedProxy>, Box<dyn std::error::Error>> {
+        let mut candidates = self.ensure_multiple_proxy_candidates(available_proxies, count).await?;
+        for _ in 0..candidates.len() {
+            if self.tester.verify_reachable(&candidates[0].proxy, target_url).await {
+                *self.current_proxy.write() = Some(candidates[0].clone());
+                return Ok(candidates);
+            }
+            info!("Proxy {} failed to reach {}, demoting it", candidates[0].proxy.url, target_url);
+            candidates.rotate_left(1);
+        }
+        warn!("No candidate proxy could reach {}", target_url);
+        Ok(candidates)
+    }
+
+    /// Forget what was learned on the previous network: the current selection, sticky
+    /// hosts, outcome history (quarantines included) and realized speeds. The next request
+    /// tests proxies again instead of reusing a selection made on the old network.
+    pub fn reset_for_network_change(&self) {
+        info!("Network changed, dropping the proxy selection and proxy history");
+        *self.current_proxy.write() = None;
+        self.sticky_hosts.write().clear();
+        self.failure_table.write().clear();
+        self.realized_speeds.write().clear();
+    }
+
+    /// Remember that `host` answered requests through `proxy` with an anti-bot challenge,
+    /// so the proxy is tried last for that host
+    pub fn record_challenge(&self, host: &str, proxy: &Proxy) {
+        info!("Proxy {} got a challenge from {}, trying it last for that host", proxy.url, host);
+        self.challenged
+            .write()
+            .entry(host.to_lowercase())
+            .or_default()
+            .insert(proxy.url.clone());
+
+        let mut sticky = self.sticky_hosts.write();
+        if sticky.get(&host.to_lowercase()).is_some_and(|s| s.proxy.url == proxy.url) {
+            sticky.remove(&host.to_lowercase());
+        }
+    }
+
+    /// Remember that a request to `host` worked through `proxy`, so later requests to the
+    /// same host try it first (for server-side sessions and caches)
+    pub fn record_host_success(&self, host: &str, proxy: &SelectedProxy) {
+        debug!("Sticking {} to proxy {}", host, proxy.proxy.url);
+        self.sticky_hosts.write().insert(host.to_lowercase(), proxy.clone());
+    }
+
+    /// The proxy that last worked for `host`, unless it failed or was quarantined since
+    pub fn sticky_for_host(&self, host: &str) -> Option<SelectedProxy> {
+        let sticky = self.sticky_hosts.read().get(&host.to_lowercase()).cloned()?;
+        if self.is_quarantined(&sticky.proxy) {
+            return None;
+        }
+        Some(sticky)
+    }
+
+    /// Move the sticky proxy for `host` to the front of `candidates`, if it is one of them.
+    /// A sticky proxy that was not selected this time (e.g. filtered out) is not added back.
+    pub fn prefer_sticky(&self, host: &str, mut candidates: Vec<SelectedProxy>) -> Vec<SelectedProxy> {
+        if let Some(sticky) = self.sticky_for_host(host) {
+            if let Some(position) = candidates.iter().position(|c| c.proxy.url == sticky.proxy.url) {
+                let sticky = candidates.remove(position);
+                candidates.insert(0, sticky);
+            }
+        }
+        candidates
+    }
+
+    pub fn is_challenged(&self, host: &str, proxy: &Proxy) -> bool {
+        self.challenged
+            .read()
+            .get(&host.to_lowercase())
+            .is_some_and(|urls| urls.contains(&proxy.url))
+    }
+
+    /// Move candidates that got a challenge from `host` behind the others, keeping the order
+    /// within both groups
+    pub fn demote_challenged(&self, host: &str, candidates: Vec<SelectedProxy>) -> Vec<SelectedProxy> {
+        let (challenged, clean): (Vec<_>, Vec<_>) = candidates
+            .into_iter()
+            .partition(|candidate| self.is_challenged(host, &candidate.proxy));
+        clean.into_iter().chain(challenged).collect()
+    }
+
+    /// Record that a request through `proxy` worked
+    pub async fn handle_proxy_success(&self, proxy: &Proxy) {
+        self.record_outcome(proxy, true);
+    }
+
     pub async fn handle_proxy_failure(&self, failed_proxy: &Proxy) {
         warn!("Proxy failure detected: {}", failed_proxy.url);
-        
-        let current = self.current_proxy.read();
-        if let Some(ref current_proxy) = *current {
-            if current_proxy.proxy.url == failed_proxy.url {
-                info!("Failed proxy is the current one, clearing selection");
-                drop(current);
-                *self.current_proxy.write() = None;
-            }
+        self.record_outcome(failed_proxy, false);
+        // Hosts stuck to this proxy go back to normal selection
+        self.sticky_hosts
+            .write()
+            .retain(|_, sticky| sticky.proxy.url != failed_proxy.url);
+
+        // Check and clear under one lock, so a selection made meanwhile by another request
+        // is never cleared by mistake
+        let mut current = self.current_proxy.write();
+        if current.as_ref().is_some_and(|c| c.proxy.url == failed_proxy.url) {
+            info!("Failed proxy is the current one, clearing selection");
+            *current = None;
         }
     }
 }
@@ -234,6 +1092,225 @@ impl Default for ProxySelector {
 mod tests {
     use super::*;
     use crate::proxy_tester::ProxyTestResult;
+    use crate::test_support::{MockResponse, MockServer};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_failure_never_clears_a_concurrent_selection() {
+        let failing = Proxy::new("failing.i2p".to_string(), 443);
+        let replacement = Proxy::new("replacement.i2p".to_string(), 443);
+
+        for _ in 0..200 {
+            let selector = Arc::new(ProxySelector::new(300));
+            *selector.current_proxy.write() = Some(SelectedProxy {
+                proxy: failing.clone(),
+                speed_bytes_per_sec: 1000.0,
+                selected_at: Instant::now(),
+            });
+
+            let failure = {
+                let selector = selector.clone();
+                let failing = failing.clone();
+                tokio::spawn(async move { selector.handle_proxy_failure(&failing).await })
+            };
+            let selection = {
+                let selector = selector.clone();
+                let result = ProxyTestResult::succeeded(replacement.clone(), 2000.0, 50.0);
+                tokio::spawn(async move { selector.select_fastest(vec![result]).await })
+            };
+            failure.await.unwrap();
+            selection.await.unwrap();
+
+            // Whichever ran first, the new selection survives
+            assert_eq!(selector.get_current_proxy().unwrap().proxy.url, replacement.url);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sticky_proxy_preferred_until_it_fails() {
+        let selector = ProxySelector::new(300);
+        let candidate = |host: &str| SelectedProxy {
+            proxy: Proxy::new(host.to_string(), 443),
+            speed_bytes_per_sec: 1000.0,
+            selected_at: Instant::now(),
+        };
+        let sticky = candidate("sticky.i2p");
+        assert!(selector.sticky_for_host("shop.example").is_none());
+
+        selector.record_host_success("shop.example", &sticky);
+        assert_eq!(selector.sticky_for_host("SHOP.example").unwrap().proxy.url, sticky.proxy.url);
+
+        let ordered = selector.prefer_sticky("shop.example", vec![candidate("a.i2p"), sticky.clone()]);
+        let hosts: Vec<&str> = ordered.iter().map(|c| c.proxy.host.as_str()).collect();
+        assert_eq!(hosts, vec!["sticky.i2p", "a.i2p"]);
+
+        // Other hosts keep the normal order
+        let ordered = selector.prefer_sticky("other.example", vec![candidate("a.i2p"), sticky.clone()]);
+        assert_eq!(ordered[0].proxy.host, "a.i2p");
+
+        // A sticky proxy missing from the candidates is not brought back
+        let ordered = selector.prefer_sticky("shop.example", vec![candidate("a.i2p"), candidate("b.i2p")]);
+        let hosts: Vec<&str> = ordered.iter().map(|c| c.proxy.host.as_str()).collect();
+        assert_eq!(hosts, vec!["a.i2p", "b.i2p"]);
+
+        // Once the sticky proxy fails, selection falls back to the normal order
+        selector.handle_proxy_failure(&sticky.proxy).await;
+        assert!(selector.sticky_for_host("shop.example").is_none());
+        let ordered = selector.prefer_sticky("shop.example", vec![candidate("a.i2p"), sticky]);
+        assert_eq!(ordered[0].proxy.host, "a.i2p");
+    }
+
+    #[test]
+    fn test_challenged_proxies_demoted_per_host() {
+        let selector = ProxySelector::new(300);
+        let candidate = |host: &str| SelectedProxy {
+            proxy: Proxy::new(host.to_string(), 443),
+            speed_bytes_per_sec: 1000.0,
+            selected_at: Instant::now(),
+        };
+        let blocked = candidate("blocked.i2p");
+        selector.record_challenge("Shop.example", &blocked.proxy);
+
+        let ordered = selector.demote_challenged(
+            "shop.example",
+            vec![blocked.clone(), candidate("a.i2p"), candidate("b.i2p")],
+        );
+        let hosts: Vec<&str> = ordered.iter().map(|c| c.proxy.host.as_str()).collect();
+        assert_eq!(hosts, vec!["a.i2p", "b.i2p", "blocked.i2p"]);
+
+        // Other hosts are not affected
+        assert!(!selector.is_challenged("other.example", &blocked.proxy));
+    }
+
+    #[tokio::test]
+    async fn test_select_lowest_latency() {
+        let selector = ProxySelector::new(300);
+        let bulk = Proxy::new("bulk.i2p".to_string(), 443);
+        let snappy = Proxy::new("snappy.i2p".to_string(), 443);
+        let middle = Proxy::new("middle.i2p".to_string(), 443);
+        let results = vec![
+            ProxyTestResult::succeeded(bulk.clone(), 9000.0, 800.0),
+            ProxyTestResult::succeeded(snappy.clone(), 500.0, 40.0),
+            ProxyTestResult::succeeded(middle.clone(), 2000.0, 150.0),
+            ProxyTestResult::failed(Proxy::new("down.i2p".to_string(), 443), "refused".to_string()),
+        ];
+
+        let selected = selector.select_lowest_latency(results.clone(), 2).await;
+        let hosts: Vec<&str> = selected.iter().map(|s| s.proxy.host.as_str()).collect();
+        assert_eq!(hosts, vec!["snappy.i2p", "middle.i2p"]);
+        assert_eq!(selector.get_current_proxy().unwrap().proxy.url, snappy.url);
+
+        // The mode switches what the ensure_* methods pick
+        let latency_first = ProxySelector::new(300).with_selection_mode(SelectionMode::LowestLatency);
+        assert_eq!(latency_first.select_by_strategy(results.clone()).await.unwrap().proxy.url, snappy.url);
+        assert_eq!(selector.select_by_strategy(results).await.unwrap().proxy.url, bulk.url);
+    }
+
+    #[tokio::test]
+    async fn test_saved_results_round_trip() {
+        let path = std::env::temp_dir().join(format!("i2ptunnel-results-{}.json", std::process::id()));
+        let selector = ProxySelector::new(300);
+        selector
+            .select_fastest(vec![
+                ProxyTestResult::succeeded(Proxy::new("fast.i2p".to_string(), 443), 9000.0, 80.0),
+                ProxyTestResult::succeeded(Proxy::new("slow.i2p".to_string(), 443), 100.0, 900.0),
+                ProxyTestResult::failed(Proxy::new("down.i2p".to_string(), 443), "refused".to_string()),
+            ])
+            .await;
+        selector.save_results(&path).unwrap();
+
+        let restarted = ProxySelector::new(300);
+        assert_eq!(restarted.load_results(&path, Duration::from_secs(3600)).unwrap(), 3);
+        assert_eq!(restarted.get_current_proxy().unwrap().proxy.host, "fast.i2p");
+        let explanation = restarted.explain_selection();
+        assert_eq!(explanation.candidates.len(), 3);
+        // Freshly loaded results do not trigger a retest yet
+        assert!(restarted.last_retest.read().elapsed() < restarted.retest_interval());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_load_results_ranks_like_selection() {
+        let path = std::env::temp_dir().join(format!("i2ptunnel-ranked-{}.json", std::process::id()));
+        let fast = Proxy::new("fast.i2p".to_string(), 443);
+        let now = unix_secs(SystemTime::now());
+        let saved = vec![
+            PersistedResult { result: ProxyTestResult::succeeded(fast.clone(), 9000.0, 80.0), tested_at: now },
+            PersistedResult {
+                result: ProxyTestResult::succeeded(Proxy::new("slow.i2p".to_string(), 443), 100.0, 900.0),
+                tested_at: now,
+            },
+        ];
+        std::fs::write(&path, serde_json::to_vec(&saved).unwrap()).unwrap();
+
+        // A proxy that just failed ranks last, as it would in `select_fastest`
+        let selector = ProxySelector::new(300);
+        selector.handle_proxy_failure(&fast).await;
+        assert_eq!(selector.load_results(&path, Duration::from_secs(3600)).unwrap(), 2);
+        assert_eq!(selector.get_current_proxy().unwrap().proxy.host, "slow.i2p");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_expired_saved_results_are_discarded() {
+        let path = std::env::temp_dir().join(format!("i2ptunnel-expired-{}.json", std::process::id()));
+        let now = unix_secs(SystemTime::now());
+        let saved = vec![
+            PersistedResult {
+                result: ProxyTestResult::succeeded(Proxy::new("old.i2p".to_string(), 443), 9000.0, 80.0),
+                tested_at: now - 7200,
+            },
+            PersistedResult {
+                result: ProxyTestResult::succeeded(Proxy::new("recent.i2p".to_string(), 443), 100.0, 900.0),
+                tested_at: now - 60,
+            },
+        ];
+        std::fs::write(&path, serde_json::to_vec(&saved).unwrap()).unwrap();
+
+        let selector = ProxySelector::new(300);
+        assert_eq!(selector.load_results(&path, Duration::from_secs(3600)).unwrap(), 1);
+        assert_eq!(selector.get_current_proxy().unwrap().proxy.host, "recent.i2p");
+
+        let selector = ProxySelector::new(300);
+        assert_eq!(selector.load_results(&path, Duration::from_secs(30)).unwrap(), 0);
+        assert!(selector.get_current_proxy().is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_custom_strategy() {
+        // Prefers proxies by host name, ignoring the measurements
+        struct Alphabetical;
+        impl SelectionStrategy for Alphabetical {
+            fn select(&self, results: &[ProxyTestResult], count: usize) -> Vec<SelectedProxy> {
+                let mut results = results.to_vec();
+                results.sort_by(|a, b| a.proxy.host.cmp(&b.proxy.host));
+                results
+                    .into_iter()
+                    .take(count)
+                    .map(|r| SelectedProxy {
+                        proxy: r.proxy,
+                        speed_bytes_per_sec: r.speed_bytes_per_sec,
+                        selected_at: Instant::now(),
+                    })
+                    .collect()
+            }
+        }
+
+        let selector = ProxySelector::new(300)
+            .with_strategy(Box::new(Alphabetical))
+            .with_min_speed(100.0);
+        let results = vec![
+            ProxyTestResult::succeeded(Proxy::new("zeta.i2p".to_string(), 443), 9000.0, 10.0),
+            ProxyTestResult::succeeded(Proxy::new("beta.i2p".to_string(), 443), 500.0, 400.0),
+            // Filtered out before the strategy sees it
+            ProxyTestResult::succeeded(Proxy::new("alpha.i2p".to_string(), 443), 50.0, 400.0),
+        ];
+
+        let selected = selector.select_multiple_by_strategy(results, 3).await;
+        let hosts: Vec<&str> = selected.iter().map(|s| s.proxy.host.as_str()).collect();
+        assert_eq!(hosts, vec!["beta.i2p", "zeta.i2p"]);
+        assert_eq!(selector.get_current_proxy().unwrap().proxy.host, "beta.i2p");
+    }
 
     #[tokio::test]
     async fn test_select_fastest_from_results() {
@@ -256,6 +1333,255 @@ mod tests {
         assert_eq!(selected.speed_bytes_per_sec, 5000.0);
     }
 
+    #[tokio::test]
+    async fn test_equal_speeds_break_ties_by_latency_then_host() {
+        let selector = ProxySelector::new(300);
+        let beta = Proxy::new("beta.i2p".to_string(), 443);
+        let alpha = Proxy::new("alpha.i2p".to_string(), 443);
+        let gamma = Proxy::new("gamma.i2p".to_string(), 443);
+
+        // Same speed everywhere; gamma has the lowest latency, alpha and beta tie on latency
+        let results = vec![
+            ProxyTestResult::succeeded(beta.clone(), 51200.0, 200.0),
+            ProxyTestResult::succeeded(alpha.clone(), 51200.0, 200.0),
+            ProxyTestResult::succeeded(gamma.clone(), 51200.0, 100.0),
+        ];
+
+        let mut reversed = results.clone();
+        reversed.reverse();
+        assert_eq!(selector.select_fastest(results.clone()).await.unwrap().proxy.url, gamma.url);
+        assert_eq!(selector.select_fastest(reversed.clone()).await.unwrap().proxy.url, gamma.url);
+
+        for input in [results, reversed] {
+            let ranked: Vec<String> = selector
+                .select_fastest_multiple(input, 3)
+                .await
+                .into_iter()
+                .map(|s| s.proxy.url)
+                .collect();
+            assert_eq!(ranked, vec![gamma.url.clone(), alpha.url.clone(), beta.url.clone()]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shorter_retest_interval_triggers_earlier_retest() {
+        let selector = ProxySelector::new(300);
+        assert_eq!(selector.retest_interval(), Duration::from_secs(300));
+        let proxy = Proxy::new("proxy1.i2p".to_string(), 443);
+        selector
+            .select_fastest(vec![ProxyTestResult::succeeded(proxy.clone(), 1000.0, 100.0)])
+            .await;
+
+        // Within the interval the cached proxy is reused
+        let cached = selector.ensure_fastest_proxy(Vec::new()).await.unwrap();
+        assert_eq!(cached.unwrap().proxy.url, proxy.url);
+
+        // Once the interval is shortened the next check retests (nothing to test here)
+        selector.set_retest_interval(Duration::ZERO);
+        assert_eq!(selector.retest_interval(), Duration::ZERO);
+        assert!(selector.ensure_fastest_proxy(Vec::new()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_i2p_target_network_skips_clearnet_proxies() {
+        // Nothing listens on port 1, so a tested clearnet proxy fails quickly; the outproxy
+        // passes with the untested default while no router runs
+        let clearnet = Proxy::new_with_type("127.0.0.1".to_string(), 1, crate::proxy_manager::ProxyType::Http);
+        let outproxy = Proxy::new("outproxy.b32.i2p".to_string(), 443);
+        let proxies = vec![clearnet, outproxy.clone()];
+        let proxies_for_retest = proxies.clone();
+
+        let all = ProxySelector::new(300);
+        all.ensure_multiple_proxy_candidates(proxies.clone(), 3).await.unwrap();
+        assert_eq!(all.explain_selection().candidates.len(), 2);
+
+        let i2p_only = ProxySelector::new(300).with_target_network(TargetNetwork::I2p);
+        let selected = i2p_only.ensure_multiple_proxy_candidates(proxies, 3).await.unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].proxy.url, outproxy.url);
+        let tested = i2p_only.explain_selection().candidates;
+        assert_eq!(tested.len(), 1);
+        assert_eq!(tested[0].proxy.url, outproxy.url);
+
+        let refreshed = ProxySelector::new(300).with_target_network(TargetNetwork::I2p);
+        refreshed.force_retest(proxies_for_retest).await.unwrap();
+        assert_eq!(refreshed.get_current_proxy().unwrap().proxy.url, outproxy.url);
+        let tested = refreshed.explain_selection().candidates;
+        assert_eq!(tested.len(), 1);
+        assert_eq!(tested[0].proxy.url, outproxy.url);
+    }
+
+    #[test]
+    fn test_target_network_filter() {
+        let outproxy = Proxy::new("outproxy.b32.i2p".to_string(), 443);
+        let clearnet = Proxy::new("198.51.100.7".to_string(), 8080);
+        assert!(TargetNetwork::All.includes(&outproxy) && TargetNetwork::All.includes(&clearnet));
+        assert!(TargetNetwork::I2p.includes(&outproxy) && !TargetNetwork::I2p.includes(&clearnet));
+        assert!(!TargetNetwork::Clearnet.includes(&outproxy) && TargetNetwork::Clearnet.includes(&clearnet));
+    }
+
+    #[tokio::test]
+    async fn test_min_speed_excludes_slow_proxies() {
+        let selector = ProxySelector::new(300).with_min_speed(10.0 * 1024.0);
+        let slow = Proxy::new("slow.i2p".to_string(), 443);
+        let fast = Proxy::new("fast.i2p".to_string(), 443);
+
+        let selected = selector
+            .select_fastest(vec![
+                ProxyTestResult::succeeded(slow.clone(), 2.0 * 1024.0, 50.0),
+                ProxyTestResult::succeeded(fast.clone(), 40.0 * 1024.0, 300.0),
+            ])
+            .await;
+        assert_eq!(selected.unwrap().proxy.url, fast.url);
+
+        let only_slow = vec![ProxyTestResult::succeeded(slow.clone(), 2.0 * 1024.0, 50.0)];
+        assert!(selector.select_fastest(only_slow.clone()).await.is_none());
+        assert!(selector.select_fastest_multiple(only_slow, 3).await.is_empty());
+        assert_eq!(
+            selector.ensure_acceptable(false),
+            Err(NoAcceptableProxy {
+                min_speed_bytes_per_sec: 10.0 * 1024.0,
+                fastest_bytes_per_sec: 2.0 * 1024.0,
+            })
+        );
+        assert!(!selector.explain_selection().candidates[0].eligible);
+    }
+
+    #[tokio::test]
+    async fn test_verification_promotes_runner_up() {
+        // The fastest proxy passes the speed test but can't reach the target
+        let fast = MockServer::start(|request| {
+            if request.target.contains("target.test") {
+                MockResponse::new(502, "bad gateway")
+            } else {
+                MockResponse::ok(vec![0u8; 4096])
+            }
+        })
+        .await;
+        let slow = MockServer::respond_with(
+            MockResponse::ok(vec![0u8; 4096]).chunked(1024, Duration::from_millis(50)),
+        )
+        .await;
+        let selector = ProxySelector::new(300);
+        let proxies = vec![fast.proxy(), slow.proxy()];
+
+        let ranked = selector.ensure_multiple_proxy_candidates(proxies.clone(), 2).await.unwrap();
+        assert_eq!(ranked[0].proxy.url, fast.proxy().url);
+
+        let verified = selector
+            .ensure_verified_proxy_candidates(proxies, 2, "http://target.test/page")
+            .await
+            .unwrap();
+        let urls: Vec<String> = verified.iter().map(|s| s.proxy.url.clone()).collect();
+        assert_eq!(urls, vec![slow.proxy().url, fast.proxy().url]);
+        assert_eq!(selector.get_current_proxy().unwrap().proxy.url, slow.proxy().url);
+    }
+
+    #[tokio::test]
+    async fn test_realized_speed_promotes_proxy() {
+        let proxy = |host: &str| Proxy::new(host.to_string(), 443);
+        let results = || {
+            vec![
+                ProxyTestResult::succeeded(proxy("fast.i2p"), 1000.0, 50.0),
+                ProxyTestResult::succeeded(proxy("mid.i2p"), 600.0, 50.0),
+                ProxyTestResult::succeeded(proxy("slow.i2p"), 100.0, 50.0),
+            ]
+        };
+        let selector = ProxySelector::new(300);
+        assert_eq!(selector.select_fastest(results()).await.unwrap().proxy.host, "fast.i2p");
+
+        // Real requests through the mid proxy turned out much faster than its test
+        selector.record_realized(&proxy("mid.i2p"), 4000, Duration::from_secs(1));
+        selector.record_realized(&proxy("fast.i2p"), 800, Duration::from_secs(1));
+
+        let selected = selector.select_fastest_multiple(results(), 3).await;
+        let hosts: Vec<&str> = selected.iter().map(|s| s.proxy.host.as_str()).collect();
+        assert_eq!(hosts, vec!["mid.i2p", "fast.i2p", "slow.i2p"]);
+        assert_eq!(selected[0].speed_bytes_per_sec, 2300.0);
+
+        let explanation = selector.explain_selection();
+        assert_eq!(explanation.candidates[0].proxy.host, "mid.i2p");
+        assert_eq!(explanation.candidates[0].score, 2300.0);
+        assert!(explanation.candidates[0].reason.starts_with("selected"));
+        assert_eq!(explanation.candidates[1].proxy.host, "fast.i2p");
+
+        // Tested speed alone keeps the original order
+        let tested_only = ProxySelector::new(300).with_realized_weight(0.0);
+        tested_only.record_realized(&proxy("mid.i2p"), 4000, Duration::from_secs(1));
+        assert_eq!(tested_only.select_fastest(results()).await.unwrap().proxy.host, "fast.i2p");
+    }
+
+    #[tokio::test]
+    async fn test_fresh_candidates_tested_within_interval() {
+        let proxy_server = MockServer::respond_with(MockResponse::ok(vec![0u8; 1024])).await;
+        let proxy = proxy_server.proxy();
+        let selector = ProxySelector::new(300);
+        selector
+            .select_fastest(vec![ProxyTestResult::succeeded(proxy.clone(), 1000.0, 10.0)])
+            .await;
+
+        // Within the interval a single candidate comes from the cache
+        selector.ensure_multiple_proxy_candidates(vec![proxy.clone()], 1).await.unwrap();
+        assert!(proxy_server.requests().is_empty());
+
+        let fresh = selector.ensure_fresh_proxy_candidates(vec![proxy.clone()], 1).await.unwrap();
+        assert_eq!(fresh[0].proxy.url, proxy.url);
+        assert!(!proxy_server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_corrected_proxy_type_persists() {
+        let selector = ProxySelector::new(300);
+        let listed = Proxy::new_with_type("outproxy.b32.i2p".to_string(), 1080, ProxyType::Socks);
+        selector
+            .select_fastest(vec![ProxyTestResult::succeeded(listed.clone(), 1000.0, 100.0)])
+            .await;
+
+        selector.update_proxy_type(&listed, ProxyType::Https);
+        let current = selector.get_current_proxy().unwrap();
+        assert!(matches!(current.proxy.proxy_type, ProxyType::Https));
+        assert_eq!(current.proxy.url, "https://outproxy.b32.i2p:1080");
+
+        // A later selection from the same (still SOCKS) listing keeps the correction
+        selector.set_retest_interval(Duration::ZERO);
+        let selected = selector.ensure_multiple_proxy_candidates(vec![listed], 3).await.unwrap();
+        assert!(matches!(selected[0].proxy.proxy_type, ProxyType::Https));
+    }
+
+    #[test]
+    fn test_test_budget_split_between_networks() {
+        let i2p: Vec<Proxy> = (0..10).map(|i| Proxy::new(format!("out{}.b32.i2p", i), 443)).collect();
+        let clearnet: Vec<Proxy> = (0..3).map(|i| Proxy::new(format!("198.51.100.{}", i), 8080)).collect();
+        let count = |proxies: &[Proxy]| proxies.iter().filter(|p| p.is_i2p_proxy()).count();
+
+        let selector = ProxySelector::new(300).with_test_budget(4, 0.5);
+        let budgeted = selector.apply_test_budget(i2p.iter().chain(&clearnet).cloned().collect());
+        assert_eq!(budgeted.len(), 4);
+        assert_eq!(count(&budgeted), 2);
+
+        // Unused clearnet slots go to I2P
+        let budgeted = selector.apply_test_budget(i2p.iter().chain(&clearnet[..1]).cloned().collect());
+        assert_eq!(budgeted.len(), 4);
+        assert_eq!(count(&budgeted), 3);
+    }
+
+    #[tokio::test]
+    async fn test_mixed_list_tests_both_networks_within_budget() {
+        // Nothing listens on ports 1-3, so the clearnet tests fail quickly
+        let mut proxies: Vec<Proxy> = (0..10).map(|i| Proxy::new(format!("out{}.b32.i2p", i), 443)).collect();
+        proxies.extend((1..=3).map(|port| {
+            Proxy::new_with_type("127.0.0.1".to_string(), port, crate::proxy_manager::ProxyType::Http)
+        }));
+        let selector = ProxySelector::new(300).with_test_budget(6, 0.5);
+
+        selector.ensure_multiple_proxy_candidates(proxies, 3).await.unwrap();
+
+        let tested = selector.explain_selection().candidates;
+        assert_eq!(tested.len(), 6);
+        assert_eq!(tested.iter().filter(|c| c.proxy.is_i2p_proxy()).count(), 3);
+        assert_eq!(tested.iter().filter(|c| !c.proxy.is_i2p_proxy()).count(), 3);
+    }
+
     #[tokio::test]
     async fn test_select_fastest_no_successful() {
         let selector = ProxySelector::new(300);
@@ -330,6 +1656,36 @@ mod tests {
         assert!(selector.get_current_proxy().is_some()); // Should still have current
     }
 
+    #[tokio::test]
+    async fn test_recently_failed_proxy_ordered_last() {
+        let selector = ProxySelector::new(300);
+        let fast = Proxy::new("fast.i2p".to_string(), 443);
+        let mid = Proxy::new("mid.i2p".to_string(), 443);
+        let slow = Proxy::new("slow.i2p".to_string(), 443);
+        let results = vec![
+            ProxyTestResult::succeeded(fast.clone(), 1000.0, 100.0),
+            ProxyTestResult::succeeded(mid.clone(), 600.0, 100.0),
+            ProxyTestResult::succeeded(slow.clone(), 100.0, 100.0),
+        ];
+
+        selector.handle_proxy_failure(&fast).await;
+        assert!(!selector.is_quarantined(&fast));
+
+        let selected = selector.select_fastest_multiple(results.clone(), 3).await;
+        let hosts: Vec<&str> = selected.iter().map(|s| s.proxy.host.as_str()).collect();
+        assert_eq!(hosts, vec!["mid.i2p", "slow.i2p", "fast.i2p"]);
+        assert_eq!(selector.get_current_proxy().unwrap().proxy.host, "mid.i2p");
+
+        let explanation = selector.explain_selection();
+        let hosts: Vec<&str> = explanation.candidates.iter().map(|c| c.proxy.host.as_str()).collect();
+        assert_eq!(hosts, vec!["mid.i2p", "slow.i2p", "fast.i2p"]);
+        assert_eq!(explanation.candidates[2].reason, "ranked lower: failed recently");
+
+        // Only the top two: the failed proxy drops out instead of the slow one
+        let selected = selector.select_fastest_multiple(results, 2).await;
+        assert!(selected.iter().all(|s| s.proxy.host != "fast.i2p"));
+    }
+
     #[tokio::test]
     async fn test_select_fastest_empty_results() {
         let selector = ProxySelector::new(300);
@@ -400,6 +1756,263 @@ mod tests {
         assert_eq!(selected.speed_bytes_per_sec, cloned.speed_bytes_per_sec);
     }
 
+    #[tokio::test]
+    async fn test_select_with_tags_filters_by_all_tags() {
+        let selector = ProxySelector::new(300);
+        let results = vec![
+            ProxyTestResult::succeeded(
+                Proxy::new("fast-untrusted.i2p".to_string(), 443).with_tags(["fast"]),
+                9000.0,
+                50.0,
+            ),
+            ProxyTestResult::succeeded(
+                Proxy::new("trusted.i2p".to_string(), 443).with_tags(["trusted", "us-region"]),
+                3000.0,
+                80.0,
+            ),
+            ProxyTestResult::succeeded(
+                Proxy::new("trusted-eu.i2p".to_string(), 443).with_tags(["trusted", "eu-region"]),
+                5000.0,
+                60.0,
+            ),
+        ];
+
+        let trusted = selector.select_with_tags(results.clone(), &["trusted"], 5).await;
+        let hosts: Vec<&str> = trusted.iter().map(|s| s.proxy.host.as_str()).collect();
+        assert_eq!(hosts, vec!["trusted-eu.i2p", "trusted.i2p"]);
+
+        let trusted_us = selector.select_with_tags(results.clone(), &["trusted", "us-region"], 5).await;
+        assert_eq!(trusted_us.len(), 1);
+        assert_eq!(trusted_us[0].proxy.host, "trusted.i2p");
+
+        assert!(selector.select_with_tags(results.clone(), &["nonexistent"], 5).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_select_with_no_tags_matches_all() {
+        let selector = ProxySelector::new(300);
+        let results = vec![
+            ProxyTestResult::succeeded(Proxy::new("tagged.i2p".to_string(), 443).with_tags(["fast"]), 9000.0, 50.0),
+            ProxyTestResult::succeeded(Proxy::new("untagged.i2p".to_string(), 443), 3000.0, 80.0),
+        ];
+
+        let selected = selector.select_with_tags(results, &[], 5).await;
+        assert_eq!(selected.len(), 2);
+        // Tags travel with the cached selection
+        assert_eq!(selector.get_current_proxy().unwrap().proxy.tags, vec!["fast".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_force_retest_replaces_selection_within_interval() {
+        let selector = ProxySelector::new(300);
+        let degraded = Proxy::new("degraded.i2p".to_string(), 443);
+        selector
+            .select_fastest(vec![ProxyTestResult::succeeded(degraded, 100.0, 900.0)])
+            .await;
+        let before = *selector.last_retest.read();
+
+        // Well within the 300s interval, ensure_fastest_proxy would keep the cached proxy
+        let replacement = Proxy::new("replacement.b32.i2p".to_string(), 443);
+        selector.force_retest(vec![replacement]).await.unwrap();
+
+        let current = selector.get_current_proxy().unwrap();
+        assert_eq!(current.proxy.host, "replacement.b32.i2p");
+        assert!(*selector.last_retest.read() >= before);
+    }
+
+    #[tokio::test]
+    async fn test_force_retest_without_proxies_clears_selection() {
+        let selector = ProxySelector::new(300);
+        selector
+            .select_fastest(vec![ProxyTestResult::succeeded(Proxy::new("old.i2p".to_string(), 443), 100.0, 900.0)])
+            .await;
+
+        assert!(selector.force_retest(Vec::new()).await.is_err());
+        assert!(selector.get_current_proxy().is_none());
+    }
+
+    #[test]
+    fn test_proxy_health_quarantines_after_flap_threshold() {
+        let now = Instant::now();
+        let mut health = ProxyHealth::new(Proxy::new("flappy.i2p".to_string(), 443), now);
+        let cooldown = Duration::from_secs(60);
+
+        // S F S: two flaps, still below the threshold of three
+        assert!(!health.record(true, now, 3, cooldown));
+        assert!(!health.record(false, now, 3, cooldown));
+        assert!(!health.record(true, now, 3, cooldown));
+        assert!(!health.is_quarantined(now));
+
+        // The third flap starts the quarantine
+        assert!(health.record(false, now, 3, cooldown));
+        assert!(health.is_quarantined(now + Duration::from_secs(59)));
+        assert!(!health.is_quarantined(now + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn test_proxy_health_current_within_window_or_quarantine() {
+        let now = Instant::now();
+        let window = Duration::from_secs(60);
+        let mut health = ProxyHealth::new(Proxy::new("old.i2p".to_string(), 443), now);
+        health.record(false, now, 3, window);
+        assert!(health.is_current(now + Duration::from_secs(59), window));
+        assert!(!health.is_current(now + Duration::from_secs(61), window));
+
+        // A quarantine outlasting the window keeps the history until it ends
+        health.record(true, now, 1, Duration::from_secs(600));
+        assert!(health.is_current(now + Duration::from_secs(61), window));
+        assert!(!health.is_current(now + Duration::from_secs(601), window));
+    }
+
+    #[tokio::test]
+    async fn test_failure_table_forgets_proxies_outside_the_window() {
+        let selector = ProxySelector::new(300).with_quarantine(3, Duration::from_millis(100));
+        let stale = Proxy::new("stale.i2p".to_string(), 443);
+        selector.handle_proxy_failure(&stale).await;
+        assert_eq!(selector.known_proxies().len(), 1);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        selector.handle_proxy_failure(&Proxy::new("fresh.i2p".to_string(), 443)).await;
+        let hosts: Vec<String> = selector.known_proxies().into_iter().map(|k| k.proxy.host).collect();
+        assert_eq!(hosts, vec!["fresh.i2p".to_string()]);
+    }
+
+    #[test]
+    fn test_proxy_health_steady_outcomes_clear_flaps() {
+        let now = Instant::now();
+        let mut health = ProxyHealth::new(Proxy::new("recovering.i2p".to_string(), 443), now);
+        let cooldown = Duration::from_secs(60);
+
+        health.record(true, now, 3, cooldown);
+        health.record(false, now, 3, cooldown);
+        health.record(true, now, 3, cooldown);
+        assert_eq!(health.flaps, 2);
+
+        // Consistently slow-but-working is fine: a steady streak forgives earlier flaps
+        health.record(true, now, 3, cooldown);
+        health.record(true, now, 3, cooldown);
+        assert_eq!(health.flaps, 0);
+        assert!(!health.record(false, now, 3, cooldown));
+        assert!(!health.is_quarantined(now));
+    }
+
+    #[tokio::test]
+    async fn test_flapping_proxy_quarantined_from_selection() {
+        let selector = ProxySelector::new(300).with_quarantine(3, Duration::from_secs(60));
+        let flappy = Proxy::new("flappy.i2p".to_string(), 443);
+        let steady = Proxy::new("steady.i2p".to_string(), 443);
+
+        for success in [true, false, true] {
+            let result = if success {
+                ProxyTestResult::succeeded(flappy.clone(), 9000.0, 50.0)
+            } else {
+                ProxyTestResult::failed(flappy.clone(), "timeout".to_string())
+            };
+            selector.select_fastest(vec![result]).await;
+        }
+        selector.handle_proxy_failure(&flappy).await;
+        assert!(selector.is_quarantined(&flappy));
+
+        // Faster, but quarantined: the slower steady proxy wins
+        let selected = selector
+            .select_fastest(vec![
+                ProxyTestResult::succeeded(flappy.clone(), 9000.0, 50.0),
+                ProxyTestResult::succeeded(steady.clone(), 1000.0, 300.0),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(selected.proxy.host, "steady.i2p");
+
+        let known = selector.known_proxies();
+        let flappy_entry = known.iter().find(|k| k.proxy.url == flappy.url).unwrap();
+        assert!(flappy_entry.quarantined);
+        assert!(flappy_entry.quarantine_remaining.unwrap() <= Duration::from_secs(60));
+        let steady_entry = known.iter().find(|k| k.proxy.url == steady.url).unwrap();
+        assert!(!steady_entry.quarantined);
+        assert_eq!(steady_entry.successes, 1);
+
+        let explanation = selector.explain_selection();
+        let flappy_candidate = explanation.candidates.iter().find(|c| c.proxy.url == flappy.url).unwrap();
+        assert!(!flappy_candidate.eligible);
+        assert!(flappy_candidate.reason.contains("quarantined"));
+    }
+
+    #[tokio::test]
+    async fn test_explain_selection_ranks_candidates() {
+        let selector = ProxySelector::new(300);
+
+        let slow = Proxy::new("slow.i2p".to_string(), 443);
+        let fast = Proxy::new("fast.i2p".to_string(), 443);
+        let broken = Proxy::new("broken.i2p".to_string(), 443);
+
+        let results = vec![
+            ProxyTestResult::succeeded(slow.clone(), 1000.0, 100.0),
+            ProxyTestResult::failed(broken.clone(), "Connection refused".to_string()),
+            ProxyTestResult::succeeded(fast.clone(), 4000.0, 80.0),
+        ];
+        selector.select_fastest(results).await;
+
+        let explanation = selector.explain_selection();
+        assert_eq!(explanation.winner.unwrap().url, fast.url);
+        assert_eq!(explanation.candidates.len(), 3);
+
+        assert_eq!(explanation.candidates[0].proxy.url, fast.url);
+        assert_eq!(explanation.candidates[0].score, 4000.0);
+        assert!(explanation.candidates[0].reason.starts_with("selected"));
+
+        assert_eq!(explanation.candidates[1].proxy.url, slow.url);
+        assert_eq!(explanation.candidates[1].score, 1000.0);
+        assert!(explanation.candidates[1].eligible);
+
+        assert_eq!(explanation.candidates[2].proxy.url, broken.url);
+        assert_eq!(explanation.candidates[2].score, 0.0);
+        assert!(!explanation.candidates[2].eligible);
+        assert!(explanation.candidates[2].reason.contains("Connection refused"));
+    }
+
+    #[tokio::test]
+    async fn test_explain_selection_follows_strategy() {
+        let selector = ProxySelector::new(300).with_strategy(Box::new(LowestLatencyStrategy));
+        let bulk = Proxy::new("bulk.i2p".to_string(), 443);
+        let snappy = Proxy::new("snappy.i2p".to_string(), 443);
+        selector
+            .select_by_strategy(vec![
+                ProxyTestResult::succeeded(bulk.clone(), 10000.0, 1000.0),
+                ProxyTestResult::succeeded(snappy.clone(), 1000.0, 50.0),
+            ])
+            .await;
+
+        let explanation = selector.explain_selection();
+        assert_eq!(explanation.winner.unwrap().url, snappy.url);
+        assert_eq!(explanation.candidates[0].proxy.url, snappy.url);
+        assert_eq!(explanation.candidates[0].score, 1.0);
+        assert!(explanation.candidates[0].reason.starts_with("selected: lowest latency"));
+        assert_eq!(explanation.candidates[1].proxy.url, bulk.url);
+        assert_eq!(explanation.candidates[1].score, 0.05);
+        assert_eq!(explanation.candidates[1].reason, "ranked lower: #2 by lowest latency");
+    }
+
+    #[test]
+    fn test_explain_selection_before_any_selection() {
+        let selector = ProxySelector::new(300);
+        let explanation = selector.explain_selection();
+        assert!(explanation.candidates.is_empty());
+        assert!(explanation.winner.is_none());
+    }
+
+    #[test]
+    fn test_rank_by_uptime_prefers_reliable_proxies() {
+        let mut flaky = Proxy::new("flaky.i2p".to_string(), 443);
+        flaky.uptime = Some(0.5);
+        let mut solid = Proxy::new("solid.i2p".to_string(), 443);
+        solid.uptime = Some(0.99);
+        let unknown = Proxy::new("unknown.i2p".to_string(), 443);
+
+        let ranked = rank_by_uptime(vec![unknown.clone(), flaky.clone(), solid.clone()]);
+        let hosts: Vec<&str> = ranked.iter().map(|p| p.host.as_str()).collect();
+        assert_eq!(hosts, vec!["solid.i2p", "flaky.i2p", "unknown.i2p"]);
+    }
+
     #[tokio::test]
     async fn test_proxy_selector_default() {
         let selector = ProxySelector::default();
@@ -1,15 +1,120 @@
-use crate::proxy_manager::Proxy;
+use crate::i2pd_router::{GlobalRouter, Router};
+use crate::proxy_manager::{Proxy, ProxyType};
+use crate::timeouts::TimeoutConfig;
+use futures::future;
+use futures::stream::{self, Stream, StreamExt};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use regex::Regex;
 use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
 use tracing::{debug, info, warn};
 
-#[derive(Debug, Clone)]
+/// Ranking order used for selection: faster proxies first. Equal speeds are broken by
+/// lower latency, then by host name (and port), so the same results always pick the same proxy.
+pub(crate) fn compare_by_speed(a: &ProxyTestResult, b: &ProxyTestResult) -> std::cmp::Ordering {
+    b.speed_bytes_per_sec
+        .partial_cmp(&a.speed_bytes_per_sec)
+        .unwrap_or(std::cmp::Ordering::Equal)
+        .then_with(|| {
+            a.latency_ms
+                .partial_cmp(&b.latency_ms)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+        .then_with(|| a.proxy.host.cmp(&b.proxy.host))
+        .then_with(|| a.proxy.port.cmp(&b.proxy.port))
+}
+
+/// Send `request` to the proxy at the other end of `stream` and return the status code of
+/// its answer, leaving the stream positioned after the response headers
+async fn proxy_exchange(stream: &mut TcpStream, request: &str) -> Result<u16, String> {
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|e| format!("Failed to send to proxy: {}", e))?;
+
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut byte).await {
+            Ok(0) => return Err("Proxy closed the connection".to_string()),
+            Ok(_) => head.push(byte[0]),
+            Err(e) => return Err(format!("Failed to read from proxy: {}", e)),
+        }
+        if head.len() > 16 * 1024 {
+            return Err("Proxy response headers too large".to_string());
+        }
+    }
+    String::from_utf8_lossy(&head)
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| "Malformed proxy response".to_string())
+}
+
+/// `url` with the payload size it asks for (`bytes=N` or `/bytes/N`) replaced by `bytes`
+fn with_payload_size(url: &str, bytes: usize) -> String {
+    static PAYLOAD_SIZE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(bytes=|/bytes/)\d+").unwrap());
+    PAYLOAD_SIZE.replace(url, format!("${{1}}{}", bytes)).into_owned()
+}
+
+/// Lowest latency first, then the same tie-breaks as `compare_by_speed`
+pub(crate) fn compare_by_latency(a: &ProxyTestResult, b: &ProxyTestResult) -> std::cmp::Ordering {
+    a.latency_ms
+        .partial_cmp(&b.latency_ms)
+        .unwrap_or(std::cmp::Ordering::Equal)
+        .then_with(|| compare_by_speed(a, b))
+}
+
+/// Successful results only, best first
+pub(crate) fn rank_results(results: Vec<ProxyTestResult>) -> Vec<ProxyTestResult> {
+    let mut ranked: Vec<ProxyTestResult> = results.into_iter().filter(|r| r.success).collect();
+    ranked.sort_by(compare_by_speed);
+    ranked
+}
+
+/// Where a proxy test failed. Timeouts are worth retrying sooner than a proxy whose client
+/// could not even be built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FailureStage {
+    /// The proxy URL or client configuration was rejected
+    ClientBuild,
+    /// The request could not be sent or no response arrived
+    Connect,
+    /// The test URL answered with a non-2xx status
+    HttpStatus(u16),
+    /// The response body broke off
+    BodyRead,
+    /// The test ran out of time
+    Timeout,
+}
+
+impl FailureStage {
+    /// `Timeout` for timeouts, `other` for every other send or read error
+    fn of(error: &reqwest::Error, other: FailureStage) -> Self {
+        if error.is_timeout() {
+            FailureStage::Timeout
+        } else {
+            other
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProxyTestResult {
     pub proxy: Proxy,
     pub speed_bytes_per_sec: f64,
     pub latency_ms: f64,
     pub success: bool,
     pub error: Option<String>,
+    /// Set for tests that failed in a known stage
+    #[serde(default)]
+    pub failure_stage: Option<FailureStage>,
 }
 
 impl ProxyTestResult {
@@ -20,6 +125,7 @@ impl ProxyTestResult {
             latency_ms: 0.0,
             success: false,
             error: None,
+            failure_stage: None,
         }
     }
 
@@ -31,6 +137,15 @@ impl ProxyTestResult {
             latency_ms: 0.0,
             success: false,
             error: Some(error),
+            failure_stage: None,
+        }
+    }
+
+    /// A failure in `stage`
+    pub fn failed_at(proxy: Proxy, stage: FailureStage, error: String) -> Self {
+        Self {
+            failure_stage: Some(stage),
+            ..Self::failed(proxy, error)
         }
     }
 
@@ -51,18 +166,93 @@ impl ProxyTestResult {
             latency_ms,
             success: true,
             error: None,
+            failure_stage: None,
         }
     }
 }
 
+/// Where the test URL's hostname gets resolved when testing through a SOCKS proxy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DnsResolution {
+    /// Resolve locally and hand the proxy an IP (`socks5://`). Leaks the hostname to local DNS.
+    #[default]
+    Local,
+    /// Let the proxy resolve the hostname (`socks5h://`)
+    Remote,
+}
+
+/// Stops a running `test_proxies_with_progress` from starting more tests. Clones share
+/// the same state, so one can be handed to the UI and another to the tester.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// How far `test_proxies_with_progress` has got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TestProgress {
+    /// Tests finished so far
+    pub tested: usize,
+    /// Proxies given, including any left untested after cancellation
+    pub total: usize,
+}
+
+/// Responses the current test URL has produced since it became current
+#[derive(Debug, Default)]
+struct TestUrlHealth {
+    responses: u32,
+    errors: u32,
+}
+
+/// Eepsite fetched when testing whether I2P sites are reachable through the router's proxy
+const DEFAULT_EEPSITE_TEST_URL: &str =
+    "http://proxygwdhg5z7mn326hfqqzsbnkrbzea4xrss2v7exrjx4c65uka.b32.i2p/";
+
+/// Payload downloaded by `ProxyTester::test_sustained` unless configured otherwise
+const DEFAULT_SUSTAINED_TEST_BYTES: usize = 1024 * 1024;
+
 pub struct ProxyTester {
     test_url: String,
+    fallback_test_urls: Vec<String>,
+    test_url_index: AtomicUsize,
+    test_url_health: Mutex<TestUrlHealth>,
+    url_error_threshold: f64,
+    url_min_samples: u32,
     test_timeout: Duration,
     test_size_bytes: usize,
+    sustained_test_bytes: usize,
+    sustained_test_timeout: Duration,
+    test_i2p: bool,
+    dns_resolution: DnsResolution,
+    eepsite_test_url: String,
+    latency_url: Option<String>,
+    measure_latency: bool,
+    measure_goodput: bool,
+    i2p_default_speed: f64,
+    i2p_default_latency: f64,
+    scale_i2p_speed_by_uptime: bool,
+    router: Arc<dyn Router>,
 }
 
 impl ProxyTester {
     pub fn new(test_url: Option<String>) -> Self {
+        // httpbin rate-limits often, so the default URL comes with a fallback
+        let fallback_test_urls = match test_url {
+            Some(_) => Vec::new(),
+            None => vec!["http://speed.cloudflare.com/__down?bytes=10240".to_string()],
+        };
         let test_url = test_url.unwrap_or_else(|| {
             "http://httpbin.org/bytes/10240".to_string() // 10KB test file
         });
@@ -74,8 +264,222 @@ impl ProxyTester {
         
         Self {
             test_url,
-            test_timeout: Duration::from_secs(10),
+            fallback_test_urls,
+            test_url_index: AtomicUsize::new(0),
+            test_url_health: Mutex::new(TestUrlHealth::default()),
+            url_error_threshold: 0.5,
+            url_min_samples: 5,
+            test_timeout: TimeoutConfig::default().proxy_test,
             test_size_bytes: 10240,
+            sustained_test_bytes: DEFAULT_SUSTAINED_TEST_BYTES,
+            sustained_test_timeout: Duration::from_secs(60),
+            test_i2p: false,
+            dns_resolution: DnsResolution::default(),
+            eepsite_test_url: DEFAULT_EEPSITE_TEST_URL.to_string(),
+            latency_url: None,
+            measure_latency: true,
+            measure_goodput: false,
+            i2p_default_speed: 1024.0 * 50.0,
+            i2p_default_latency: 200.0,
+            scale_i2p_speed_by_uptime: false,
+            router: Arc::new(GlobalRouter),
+        }
+    }
+
+    /// Reach I2P outproxies and recognize the router's own proxy through `router` instead of
+    /// the process-wide embedded router
+    pub fn with_router(mut self, router: Arc<dyn Router>) -> Self {
+        self.router = router;
+        self
+    }
+
+    /// Use `timeouts.proxy_test` for each proxy test
+    pub fn with_timeouts(mut self, timeouts: &TimeoutConfig) -> Self {
+        self.test_timeout = timeouts.proxy_test;
+        self
+    }
+
+    /// Actually test I2P outproxies through the local router instead of assuming they work.
+    /// Only takes effect while the router is running; otherwise the skip is kept.
+    pub fn with_test_i2p(mut self, test_i2p: bool) -> Self {
+        self.test_i2p = test_i2p;
+        self
+    }
+
+    /// Speed (bytes/s, 50 KB/s by default) assumed for I2P outproxies that are not tested
+    pub fn with_i2p_default_speed(mut self, bytes_per_sec: f64) -> Self {
+        self.i2p_default_speed = bytes_per_sec.max(0.0);
+        self
+    }
+
+    /// Latency (ms, 200 by default) assumed for I2P outproxies that are not tested
+    pub fn with_i2p_default_latency(mut self, latency_ms: f64) -> Self {
+        self.i2p_default_latency = latency_ms.max(0.0);
+        self
+    }
+
+    /// Scale the assumed speed of untested I2P outproxies by their listed uptime, so more
+    /// reliable ones rank higher. Proxies without an uptime keep the plain default.
+    pub fn with_uptime_scaled_i2p_speed(mut self, scale: bool) -> Self {
+        self.scale_i2p_speed_by_uptime = scale;
+        self
+    }
+
+    /// Result for an I2P outproxy that is not tested, from the configured defaults
+    fn assumed_i2p_result(&self, proxy: &Proxy) -> ProxyTestResult {
+        let speed = match proxy.uptime {
+            Some(uptime) if self.scale_i2p_speed_by_uptime => self.i2p_default_speed * uptime.clamp(0.0, 1.0),
+            _ => self.i2p_default_speed,
+        };
+        ProxyTestResult::succeeded(proxy.clone(), speed, self.i2p_default_latency)
+    }
+
+    /// Choose who resolves the test URL's hostname for SOCKS proxies. HTTP(S) proxies always
+    /// resolve it themselves since they receive the full URL.
+    pub fn with_dns_resolution(mut self, dns_resolution: DnsResolution) -> Self {
+        self.dns_resolution = dns_resolution;
+        self
+    }
+
+    /// SOCKS URL for `proxy` honoring the configured DNS resolution
+    fn socks_proxy_url(&self, proxy: &Proxy) -> String {
+        let scheme = match self.dns_resolution {
+            DnsResolution::Local => ProxyType::Socks.scheme(),
+            DnsResolution::Remote => "socks5h",
+        };
+        format!("{}://{}:{}", scheme, proxy.host, proxy.port)
+    }
+
+    /// Test URLs to rotate to when the current one keeps failing (replaces the defaults)
+    pub fn with_fallback_test_urls(mut self, urls: Vec<String>) -> Self {
+        self.fallback_test_urls = urls;
+        self.test_url_index.store(0, Ordering::Relaxed);
+        *self.test_url_health.lock() = TestUrlHealth::default();
+        self
+    }
+
+    /// Rotate once more than `threshold` of the test URL's responses (after at least
+    /// `min_samples` of them) were rate limits or server errors
+    pub fn with_url_error_threshold(mut self, threshold: f64, min_samples: u32) -> Self {
+        self.url_error_threshold = threshold.clamp(0.0, 1.0);
+        self.url_min_samples = min_samples.max(1);
+        self
+    }
+
+    /// Measure latency against `url`, ideally a tiny resource, instead of the throughput URL
+    pub fn with_latency_url(mut self, url: String) -> Self {
+        self.latency_url = Some(url);
+        self
+    }
+
+    /// Whether latency gets its own HEAD request (the default). Without it, latency is the
+    /// time until the throughput GET's response headers arrive, which saves a round trip
+    /// and works with servers that reject HEAD.
+    pub fn with_measure_latency(mut self, measure_latency: bool) -> Self {
+        self.measure_latency = measure_latency;
+        self
+    }
+
+    /// Rank by goodput, the rate between the first and last body byte, instead of the whole
+    /// transfer from sending the request (the default). Connect time and time to first byte
+    /// then no longer drag down the speed of a proxy that streams fast once it starts.
+    pub fn with_goodput_measurement(mut self, measure_goodput: bool) -> Self {
+        self.measure_goodput = measure_goodput;
+        self
+    }
+
+    /// Download `size_bytes` within `timeout` per proxy in `test_sustained` (1 MB within
+    /// 60s by default)
+    pub fn with_sustained_test(mut self, size_bytes: usize, timeout: Duration) -> Self {
+        self.sustained_test_bytes = size_bytes;
+        self.sustained_test_timeout = timeout;
+        self
+    }
+
+    /// URL `test_sustained` downloads: the current test URL asking for `sustained_test_bytes`
+    /// bytes, when it names its size like `bytes=N` (speed.cloudflare.com) or `/bytes/N`
+    /// (httpbin.org). Other test URLs are downloaded as they are.
+    pub fn sustained_test_url(&self) -> String {
+        with_payload_size(&self.current_test_url(), self.sustained_test_bytes)
+    }
+
+    /// Measure download speed against `url`, ideally large enough for a meaningful speed.
+    /// Replaces the test URL given to `new`; fallback URLs still apply.
+    pub fn with_throughput_url(mut self, url: String) -> Self {
+        self.test_url = url;
+        self.test_url_index.store(0, Ordering::Relaxed);
+        *self.test_url_health.lock() = TestUrlHealth::default();
+        self
+    }
+
+    /// Eepsite to fetch when testing the router's own proxy, which only serves I2P sites
+    pub fn with_eepsite_test_url(mut self, url: String) -> Self {
+        self.eepsite_test_url = url;
+        self
+    }
+
+    /// URL to measure `proxy` against. The router's local proxy is there to reach I2P sites,
+    /// so it gets the eepsite URL; every other proxy, I2P outproxies included, exists to
+    /// reach the clearnet and gets the clearnet test URL.
+    pub fn test_url_for(&self, proxy: &Proxy) -> String {
+        if self.is_router_proxy(proxy) {
+            self.eepsite_test_url.clone()
+        } else {
+            self.current_test_url()
+        }
+    }
+
+    /// URL to measure `proxy`'s latency against: the latency URL if one is set, otherwise the
+    /// same URL as the throughput test. The router's own proxy always uses the eepsite URL.
+    pub fn latency_url_for(&self, proxy: &Proxy) -> String {
+        match &self.latency_url {
+            Some(url) if !self.is_router_proxy(proxy) => url.clone(),
+            _ => self.test_url_for(proxy),
+        }
+    }
+
+    fn is_router_proxy(&self, proxy: &Proxy) -> bool {
+        let is_loopback = proxy.host == "localhost"
+            || proxy.host.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_loopback());
+        is_loopback && (proxy.port == self.router.http_proxy_port() || proxy.port == self.router.https_proxy_port())
+    }
+
+    /// The test URL currently used for measurements
+    pub fn current_test_url(&self) -> String {
+        let index = self.test_url_index.load(Ordering::Relaxed);
+        match index {
+            0 => self.test_url.clone(),
+            _ => self.fallback_test_urls[index - 1].clone(),
+        }
+    }
+
+    /// Record how the test URL itself behaved. Only responses count: a connection failure
+    /// says something about the proxy, a 429 or 5xx says something about the endpoint.
+    fn record_test_url_response(&self, url: &str, endpoint_error: bool) {
+        let url_count = self.fallback_test_urls.len() + 1;
+        let mut health = self.test_url_health.lock();
+
+        // Ignore stragglers measured against a URL we already rotated away from
+        if url_count == 1 || url != self.current_test_url() {
+            return;
+        }
+
+        health.responses += 1;
+        if endpoint_error {
+            health.errors += 1;
+        }
+
+        let error_rate = health.errors as f64 / health.responses as f64;
+        if health.responses >= self.url_min_samples && error_rate > self.url_error_threshold {
+            let next = (self.test_url_index.load(Ordering::Relaxed) + 1) % url_count;
+            self.test_url_index.store(next, Ordering::Relaxed);
+            *health = TestUrlHealth::default();
+            warn!(
+                "Test URL {} failing ({:.0}% errors), switching to {}",
+                url,
+                error_rate * 100.0,
+                self.current_test_url()
+            );
         }
     }
 
@@ -87,25 +491,40 @@ impl ProxyTester {
         // I2P-based outproxies can't be tested directly because they require router configuration
         // and DNS resolution through I2P router doesn't work for clearnet domains
         if proxy.is_i2p_proxy() {
+            if self.test_i2p && self.router.is_running() {
+                return self.test_i2p_proxy(proxy, start_time).await;
+            }
+
             info!(
                 "Skipping test for I2P-based proxy {} (assumes router is configured)",
                 proxy.url
             );
-            // Mark as successful with default speed/latency since we can't test it
-            // Use a reasonable default speed (assume it works)
-            return ProxyTestResult::succeeded(
-                proxy.clone(),
-                1024.0 * 50.0, // 50 KB/s default
-                200.0,         // 200ms default latency
-            );
+            // Mark as successful with the assumed speed/latency since we can't test it
+            return self.assumed_i2p_result(proxy);
         }
         
-        // Create client with proxy based on proxy type
-        let client = match &proxy.proxy_type {
+        let client = match self.client_for(proxy) {
+            Ok(c) => c,
+            Err(e) => {
+                return ProxyTestResult::failed_at(
+                    proxy.clone(),
+                    FailureStage::ClientBuild,
+                    e,
+                );
+            }
+        };
+
+        self.measure(proxy, &client, start_time, &self.test_url_for(proxy), None).await
+    }
+
+    /// Client sending requests through `proxy`. SOCKS proxies fall back to HTTPS when the
+    /// SOCKS client can't be built.
+    fn client_for(&self, proxy: &Proxy) -> Result<Client, String> {
+        match &proxy.proxy_type {
             crate::proxy_manager::ProxyType::Socks => {
                 // For SOCKS proxies, try SOCKS5 first, fallback to HTTPS if SOCKS fails
-                let socks_url = format!("socks5://{}:{}", proxy.host, proxy.port);
-                let https_url = format!("https://{}:{}", proxy.host, proxy.port);
+                let socks_url = self.socks_proxy_url(proxy);
+                let https_url = ProxyType::Https.url_for(&proxy.host, proxy.port);
                 
                 // Try SOCKS first
                 match reqwest::Proxy::all(&socks_url) {
@@ -170,54 +589,178 @@ impl ProxyTester {
                             .map_err(|e| format!("Failed to create client: {}", e))
                     })
             }
-        };
-        
-        let client = match client {
-            Ok(c) => c,
+        }
+    }
+
+    /// Client sending requests through the router's HTTP proxy
+    fn router_client(&self) -> Result<Client, String> {
+        reqwest::Proxy::http(format!("http://127.0.0.1:{}", self.router.http_proxy_port()))
+            .map_err(|e| format!("Failed to create router HTTP proxy: {}", e))
+            .and_then(|p| {
+                Client::builder()
+                    .proxy(p)
+                    .timeout(self.test_timeout)
+                    .build()
+                    .map_err(|e| format!("Failed to create client: {}", e))
+            })
+    }
+
+    /// Whether `target_url` answers through `proxy` at all: a HEAD request that gets any
+    /// response but a server error (which is what proxies send when they can't reach the
+    /// target). I2P outproxies are asked through a tunnel the router opens to them, and are
+    /// assumed to pass unless they are tested through the router.
+    pub async fn verify_reachable(&self, proxy: &Proxy, target_url: &str) -> bool {
+        if proxy.is_i2p_proxy() {
+            if !(self.test_i2p && self.router.is_running()) {
+                return true;
+            }
+            let check = self.reachable_through_outproxy(proxy, target_url);
+            return match tokio::time::timeout(self.test_timeout, check).await {
+                Ok(Ok(reachable)) => reachable,
+                Ok(Err(e)) => {
+                    debug!("{} unreachable through {}: {}", target_url, proxy.url, e);
+                    false
+                }
+                Err(_) => {
+                    debug!("Timed out verifying {} through {}", target_url, proxy.url);
+                    false
+                }
+            };
+        }
+
+        let client = match self.client_for(proxy) {
+            Ok(client) => client,
             Err(e) => {
-                return ProxyTestResult::failed(
-                    proxy.clone(),
-                    e,
-                );
+                debug!("Cannot verify {} through {}: {}", target_url, proxy.url, e);
+                return false;
             }
         };
+        match client.head(target_url).send().await {
+            Ok(response) => !response.status().is_server_error(),
+            Err(e) => {
+                debug!("{} unreachable through {}: {}", target_url, proxy.url, e);
+                false
+            }
+        }
+    }
+
+    /// Ask the I2P outproxy `proxy` itself for `target_url`: the router's HTTP proxy opens a
+    /// tunnel to the outproxy (a plain request would go out through the router's default
+    /// outproxy instead), then the outproxy gets a CONNECT for HTTPS targets, which must
+    /// succeed, or a HEAD for HTTP targets, which must not get a server error.
+    async fn reachable_through_outproxy(&self, proxy: &Proxy, target_url: &str) -> Result<bool, String> {
+        let target = url::Url::parse(target_url).map_err(|e| format!("Invalid target URL: {}", e))?;
+        let host = target.host_str().ok_or_else(|| "Target URL has no host".to_string())?;
+        let port = target.port_or_known_default().unwrap_or(80);
+
+        let mut stream = TcpStream::connect(("127.0.0.1", self.router.http_proxy_port()))
+            .await
+            .map_err(|e| format!("Failed to connect to the router HTTP proxy: {}", e))?;
+        let outproxy = format!("{}:{}", proxy.host, proxy.port);
+        let status = proxy_exchange(
+            &mut stream,
+            &format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", outproxy),
+        )
+        .await?;
+        if !(200..300).contains(&status) {
+            return Err(format!("Router could not open a tunnel to {} (status {})", outproxy, status));
+        }
+
+        if target.scheme() == "https" {
+            let status = proxy_exchange(
+                &mut stream,
+                &format!("CONNECT {0}:{1} HTTP/1.1\r\nHost: {0}:{1}\r\n\r\n", host, port),
+            )
+            .await?;
+            Ok((200..300).contains(&status))
+        } else {
+            let status = proxy_exchange(
+                &mut stream,
+                &format!("HEAD {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", target_url, host),
+            )
+            .await?;
+            Ok(status < 500)
+        }
+    }
+
+    /// Test an I2P outproxy by routing the test fetch through the router's HTTP proxy,
+    /// the same way RequestHandler reaches I2P outproxies
+    async fn test_i2p_proxy(&self, proxy: &Proxy, start_time: Instant) -> ProxyTestResult {
+        info!("Testing I2P-based proxy {} through the local router", proxy.url);
+
+        match self.router_client() {
+            Ok(client) => {
+                self.measure(proxy, &client, start_time, &self.test_url_for(proxy), None)
+                    .await
+            }
+            Err(e) => ProxyTestResult::failed_at(proxy.clone(), FailureStage::ClientBuild, e),
+        }
+    }
+
+    /// Measure latency (HEAD of the latency URL) and download speed (GET of `test_url`,
+    /// within `timeout` if given instead of the client's) using an already configured client
+    async fn measure(
+        &self,
+        proxy: &Proxy,
+        client: &Client,
+        start_time: Instant,
+        test_url: &str,
+        timeout: Option<Duration>,
+    ) -> ProxyTestResult {
 
         // Measure latency with HEAD request
-        let latency_start = Instant::now();
-        let _latency_result = client.head(&self.test_url).send().await;
-        let latency = latency_start.elapsed().as_secs_f64() * 1000.0;
+        let head_latency = if self.measure_latency {
+            let latency_start = Instant::now();
+            let _latency_result = client.head(self.latency_url_for(proxy)).send().await;
+            Some(latency_start.elapsed().as_secs_f64() * 1000.0)
+        } else {
+            None
+        };
 
-        // Measure download speed with GET request
+        // Measure download speed with GET request, the only fetch speed is computed from
         let download_start = Instant::now();
-        let response = match client.get(&self.test_url).send().await {
+        let mut request = client.get(test_url);
+        if let Some(timeout) = timeout {
+            request = request.timeout(timeout);
+        }
+        let response = match request.send().await {
             Ok(r) => r,
             Err(e) => {
-                return ProxyTestResult::failed(
+                return ProxyTestResult::failed_at(
                     proxy.clone(),
+                    FailureStage::of(&e, FailureStage::Connect),
                     format!("Request failed: {}", e),
                 );
             }
         };
+        let latency = head_latency.unwrap_or_else(|| download_start.elapsed().as_secs_f64() * 1000.0);
 
-        if !response.status().is_success() {
-            return ProxyTestResult::failed(
+        let status = response.status();
+        self.record_test_url_response(
+            test_url,
+            status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error(),
+        );
+
+        if !status.is_success() {
+            return ProxyTestResult::failed_at(
                 proxy.clone(),
-                format!("HTTP error: {}", response.status()),
+                FailureStage::HttpStatus(status.as_u16()),
+                format!("HTTP error: {}", status),
             );
         }
 
-        let body = match response.bytes().await {
-            Ok(b) => b,
+        let (bytes_downloaded, goodput) = match Self::read_body(response).await {
+            Ok(read) => read,
             Err(e) => {
-                return ProxyTestResult::failed(
+                return ProxyTestResult::failed_at(
                     proxy.clone(),
+                    FailureStage::of(&e, FailureStage::BodyRead),
                     format!("Failed to read body: {}", e),
                 );
             }
         };
 
         let download_time = download_start.elapsed().as_secs_f64();
-        let bytes_downloaded = body.len();
 
         if download_time <= 0.0 {
             return ProxyTestResult::failed(
@@ -226,7 +769,10 @@ impl ProxyTester {
             );
         }
 
-        let speed_bytes_per_sec = bytes_downloaded as f64 / download_time;
+        let speed_bytes_per_sec = match goodput {
+            Some(goodput) if self.measure_goodput => goodput,
+            _ => bytes_downloaded as f64 / download_time,
+        };
         let total_time = start_time.elapsed();
 
         info!(
@@ -240,6 +786,122 @@ impl ProxyTester {
         ProxyTestResult::succeeded(proxy.clone(), speed_bytes_per_sec, latency)
     }
 
+    /// Test again the proxies that passed the quick test, downloading the larger sustained
+    /// payload so per-request overhead weighs less than speed over a long transfer. Failed
+    /// results pass through unchanged, and so do I2P outproxies unless they are tested through
+    /// the router. The quick test's latency is kept. Results come back in the order given.
+    pub async fn test_sustained(&self, results: Vec<ProxyTestResult>, max_concurrent: usize) -> Vec<ProxyTestResult> {
+        info!(
+            "Sustained test of {} bytes for {} proxies (max {} concurrent)",
+            self.sustained_test_bytes,
+            results.iter().filter(|r| r.success).count(),
+            max_concurrent
+        );
+        stream::iter(results)
+            .map(|result| async move {
+                if result.success {
+                    self.measure_sustained(result).await
+                } else {
+                    result
+                }
+            })
+            .buffered(max_concurrent.max(1))
+            .collect()
+            .await
+    }
+
+    async fn measure_sustained(&self, quick: ProxyTestResult) -> ProxyTestResult {
+        let proxy = quick.proxy.clone();
+        let client = if proxy.is_i2p_proxy() {
+            if !(self.test_i2p && self.router.is_running()) {
+                return quick;
+            }
+            self.router_client()
+        } else {
+            self.client_for(&proxy)
+        };
+        let client = match client {
+            Ok(client) => client,
+            Err(e) => return ProxyTestResult::failed_at(proxy, FailureStage::ClientBuild, e),
+        };
+
+        let url = with_payload_size(&self.test_url_for(&proxy), self.sustained_test_bytes);
+        let sustained = self
+            .measure(&proxy, &client, Instant::now(), &url, Some(self.sustained_test_timeout))
+            .await;
+        if !sustained.success {
+            return sustained;
+        }
+
+        info!(
+            "Proxy {} sustained test: {:.2} KB/s (quick test {:.2} KB/s)",
+            proxy.url,
+            sustained.speed_bytes_per_sec / 1024.0,
+            quick.speed_bytes_per_sec / 1024.0
+        );
+        ProxyTestResult::succeeded(proxy, sustained.speed_bytes_per_sec, quick.latency_ms)
+    }
+
+    /// Stream the body, returning its length and the goodput in bytes/s: the bytes after the
+    /// first chunk over the time from the first chunk to the last. `None` when the body came
+    /// in a single chunk, leaving no interval to measure.
+    async fn read_body(mut response: reqwest::Response) -> Result<(usize, Option<f64>), reqwest::Error> {
+        let mut total = 0;
+        let mut first_chunk: Option<(Instant, usize)> = None;
+        let mut last_chunk_at = None;
+        while let Some(chunk) = response.chunk().await? {
+            let now = Instant::now();
+            total += chunk.len();
+            match first_chunk {
+                None => first_chunk = Some((now, chunk.len())),
+                Some(_) => last_chunk_at = Some(now),
+            }
+        }
+
+        let goodput = match (first_chunk, last_chunk_at) {
+            (Some((first_at, first_len)), Some(last_at)) => {
+                let elapsed = last_at.duration_since(first_at).as_secs_f64();
+                (elapsed > 0.0).then(|| (total - first_len) as f64 / elapsed)
+            }
+            _ => None,
+        };
+        Ok((total, goodput))
+    }
+
+    /// Test `proxies` with at most `max_concurrent` tests in flight, yielding each result as
+    /// soon as its test finishes (so in completion order)
+    pub fn test_proxies_stream(
+        &self,
+        proxies: Vec<Proxy>,
+        max_concurrent: usize,
+    ) -> impl Stream<Item = ProxyTestResult> + '_ {
+        stream::iter(proxies)
+            .map(move |proxy| async move { self.test_proxy(&proxy).await })
+            .buffer_unordered(max_concurrent.max(1))
+    }
+
+    /// `test_proxies_stream` for a UI: `on_progress` is called after each finished test, and
+    /// once `token` is cancelled no further tests start. Tests already running still finish
+    /// and are yielded.
+    pub fn test_proxies_with_progress<'a>(
+        &'a self,
+        proxies: Vec<Proxy>,
+        max_concurrent: usize,
+        token: CancelToken,
+        mut on_progress: impl FnMut(TestProgress) + 'a,
+    ) -> impl Stream<Item = ProxyTestResult> + 'a {
+        let total = proxies.len();
+        let mut tested = 0;
+        stream::iter(proxies)
+            .take_while(move |_| future::ready(!token.is_cancelled()))
+            .map(move |proxy| async move { self.test_proxy(&proxy).await })
+            .buffer_unordered(max_concurrent.max(1))
+            .inspect(move |_| {
+                tested += 1;
+                on_progress(TestProgress { tested, total });
+            })
+    }
+
     pub async fn test_proxies_parallel(
         &self,
         proxies: Vec<Proxy>,
@@ -251,12 +913,8 @@ impl ProxyTester {
             max_concurrent
         );
 
-        use futures::stream::{self, StreamExt};
-        let results: Vec<ProxyTestResult> = stream::iter(proxies)
-            .map(|proxy| async move {
-                self.test_proxy(&proxy).await
-            })
-            .buffer_unordered(max_concurrent)
+        let results: Vec<ProxyTestResult> = self
+            .test_proxies_stream(proxies, max_concurrent)
             .collect()
             .await;
 
@@ -289,6 +947,59 @@ impl ProxyTester {
 
         results
     }
+
+    /// Test `proxies` like `test_proxies_parallel`, keeping only the ones that passed,
+    /// best first
+    pub async fn test_and_rank(&self, proxies: Vec<Proxy>, max_concurrent: usize) -> Vec<ProxyTestResult> {
+        rank_results(self.test_proxies_parallel(proxies, max_concurrent).await)
+    }
+}
+
+impl ProxyTester {
+    /// Like `test_proxies_parallel`, but stop as soon as `needed` proxies passed: no new
+    /// tests are started and tests still in flight are dropped. Returns every result
+    /// collected up to that point, failures included, in completion order.
+    pub async fn test_until(
+        &self,
+        proxies: Vec<Proxy>,
+        max_concurrent: usize,
+        needed: usize,
+    ) -> Vec<ProxyTestResult> {
+        info!(
+            "Testing up to {} proxies until {} pass (max {} concurrent)",
+            proxies.len(),
+            needed,
+            max_concurrent
+        );
+
+        let mut results = Vec::new();
+        if needed == 0 {
+            return results;
+        }
+
+        let total = proxies.len();
+        let tests = self.test_proxies_stream(proxies, max_concurrent);
+        futures::pin_mut!(tests);
+
+        let mut successful = 0;
+        while let Some(result) = tests.next().await {
+            if result.success {
+                successful += 1;
+            }
+            results.push(result);
+            if successful >= needed {
+                info!(
+                    "{} proxies passed after {} of {} tests, stopping early",
+                    successful,
+                    results.len(),
+                    total
+                );
+                break;
+            }
+        }
+
+        results
+    }
 }
 
 impl Default for ProxyTester {
@@ -300,6 +1011,241 @@ impl Default for ProxyTester {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::{FakeRouter, MockResponse, MockServer};
+
+    #[tokio::test]
+    async fn test_test_and_rank_sorts_and_drops_failures() {
+        // I2P outproxies pass with the untested default speed, the clearnet proxy on port 1 fails
+        let proxies = vec![
+            Proxy::new("b.b32.i2p".to_string(), 443),
+            Proxy::new_with_type("127.0.0.1".to_string(), 1, crate::proxy_manager::ProxyType::Http),
+            Proxy::new("a.b32.i2p".to_string(), 443),
+        ];
+
+        let ranked = ProxyTester::new(None).test_and_rank(proxies, 3).await;
+
+        let hosts: Vec<&str> = ranked.iter().map(|r| r.proxy.host.as_str()).collect();
+        assert_eq!(hosts, vec!["a.b32.i2p", "b.b32.i2p"]);
+        assert!(ranked.iter().all(|r| r.success));
+    }
+
+    #[test]
+    fn test_rank_results_orders_by_speed() {
+        let proxy = |host: &str| Proxy::new(host.to_string(), 443);
+        let ranked = rank_results(vec![
+            ProxyTestResult::succeeded(proxy("slow.i2p"), 100.0, 10.0),
+            ProxyTestResult::failed(proxy("dead.i2p"), "refused".to_string()),
+            ProxyTestResult::succeeded(proxy("fast.i2p"), 900.0, 50.0),
+            ProxyTestResult::succeeded(proxy("mid.i2p"), 500.0, 20.0),
+        ]);
+
+        let speeds: Vec<f64> = ranked.iter().map(|r| r.speed_bytes_per_sec).collect();
+        assert_eq!(speeds, vec![900.0, 500.0, 100.0]);
+    }
+
+    #[test]
+    fn test_test_url_chosen_per_proxy_network() {
+        let router = Arc::new(FakeRouter::new(14444, 14447));
+        let tester = ProxyTester::new(Some("http://clearnet.test/bytes".to_string()))
+            .with_eepsite_test_url("http://site.b32.i2p/".to_string())
+            .with_router(router.clone());
+
+        // Outproxies and clearnet proxies lead to the clearnet
+        let outproxy = Proxy::new("outproxy.b32.i2p".to_string(), 443);
+        let clearnet = Proxy::new_with_type("198.51.100.7".to_string(), 8080, crate::proxy_manager::ProxyType::Http);
+        assert_eq!(tester.test_url_for(&outproxy), "http://clearnet.test/bytes");
+        assert_eq!(tester.test_url_for(&clearnet), "http://clearnet.test/bytes");
+
+        // The router's own proxy leads to I2P sites
+        let router_proxy = Proxy::new_with_type("127.0.0.1".to_string(), 14444, crate::proxy_manager::ProxyType::Http);
+        assert_eq!(tester.test_url_for(&router_proxy), "http://site.b32.i2p/");
+        assert_eq!(
+            ProxyTester::new(None).with_router(router).test_url_for(&router_proxy),
+            DEFAULT_EEPSITE_TEST_URL
+        );
+
+        // Other local proxies, e.g. a Tor SOCKS port, are clearnet proxies
+        let local_socks = Proxy::new_with_type("127.0.0.1".to_string(), 9050, crate::proxy_manager::ProxyType::Socks);
+        assert_eq!(tester.test_url_for(&local_socks), "http://clearnet.test/bytes");
+    }
+
+    #[tokio::test]
+    async fn test_latency_and_throughput_urls_measured_separately() {
+        let proxy_server = MockServer::start(|request| {
+            if request.target.contains("big") {
+                MockResponse::ok(vec![0u8; 4096])
+            } else {
+                MockResponse::ok("")
+            }
+        })
+        .await;
+        let tester = ProxyTester::new(None)
+            .with_latency_url("http://small.test/ping".to_string())
+            .with_throughput_url("http://big.test/bytes".to_string());
+
+        let result = tester.test_proxy(&proxy_server.proxy()).await;
+        assert!(result.success);
+
+        let requests: Vec<(String, String)> = proxy_server
+            .requests()
+            .into_iter()
+            .map(|request| (request.method, request.target))
+            .collect();
+        assert_eq!(
+            requests,
+            vec![
+                ("HEAD".to_string(), "http://small.test/ping".to_string()),
+                ("GET".to_string(), "http://big.test/bytes".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failure_stage_per_branch() {
+        use crate::proxy_manager::ProxyType;
+        let tester = ProxyTester::new(Some("http://clearnet.test/bytes".to_string()))
+            .with_measure_latency(false)
+            .with_timeouts(&TimeoutConfig {
+                proxy_test: Duration::from_millis(300),
+                ..TimeoutConfig::default()
+            });
+
+        let unparseable = Proxy::new_with_type("bad host".to_string(), 8080, ProxyType::Http);
+        let result = tester.test_proxy(&unparseable).await;
+        assert_eq!(result.failure_stage, Some(FailureStage::ClientBuild));
+
+        let refused = Proxy::new_with_type("127.0.0.1".to_string(), 1, ProxyType::Http);
+        let result = tester.test_proxy(&refused).await;
+        assert_eq!(result.failure_stage, Some(FailureStage::Connect));
+
+        let erroring = MockServer::respond_with(MockResponse::new(502, "bad gateway")).await;
+        let result = tester.test_proxy(&erroring.proxy()).await;
+        assert_eq!(result.failure_stage, Some(FailureStage::HttpStatus(502)));
+
+        let truncated = MockServer::respond_with(MockResponse::ok("short").header("Content-Length", "5000")).await;
+        let result = tester.test_proxy(&truncated.proxy()).await;
+        assert_eq!(result.failure_stage, Some(FailureStage::BodyRead));
+
+        let slow = MockServer::respond_with(MockResponse::ok("late").delay(Duration::from_secs(2))).await;
+        let result = tester.test_proxy(&slow.proxy()).await;
+        assert_eq!(result.failure_stage, Some(FailureStage::Timeout));
+
+        let fine = MockServer::respond_with(MockResponse::ok(vec![0u8; 1024])).await;
+        assert_eq!(tester.test_proxy(&fine.proxy()).await.failure_stage, None);
+    }
+
+    #[tokio::test]
+    async fn test_latency_from_get_when_head_skipped() {
+        let proxy_server = MockServer::respond_with(MockResponse::ok(vec![0u8; 1024])).await;
+        let tester = ProxyTester::new(Some("http://clearnet.test/bytes".to_string())).with_measure_latency(false);
+
+        let result = tester.test_proxy(&proxy_server.proxy()).await;
+
+        assert!(result.success);
+        assert!(result.latency_ms > 0.0);
+        let methods: Vec<String> = proxy_server.requests().into_iter().map(|r| r.method).collect();
+        assert_eq!(methods, vec!["GET"]);
+    }
+
+    #[test]
+    fn test_latency_url_defaults_to_throughput_url() {
+        let tester = ProxyTester::new(Some("http://clearnet.test/bytes".to_string()));
+        let proxy = Proxy::new_with_type("198.51.100.7".to_string(), 8080, crate::proxy_manager::ProxyType::Http);
+        assert_eq!(tester.latency_url_for(&proxy), "http://clearnet.test/bytes");
+
+        let tester = tester.with_latency_url("http://clearnet.test/tiny".to_string());
+        assert_eq!(tester.latency_url_for(&proxy), "http://clearnet.test/tiny");
+        assert_eq!(tester.test_url_for(&proxy), "http://clearnet.test/bytes");
+    }
+
+    #[tokio::test]
+    async fn test_proxies_stream_reports_every_proxy() {
+        let proxies = vec![
+            Proxy::new("a.b32.i2p".to_string(), 443),
+            Proxy::new_with_type("127.0.0.1".to_string(), 1, crate::proxy_manager::ProxyType::Http),
+            Proxy::new("b.b32.i2p".to_string(), 443),
+        ];
+        let tester = ProxyTester::new(None);
+
+        let mut hosts: Vec<String> = tester
+            .test_proxies_stream(proxies, 2)
+            .map(|result| result.proxy.host)
+            .collect()
+            .await;
+
+        hosts.sort();
+        assert_eq!(hosts, vec!["127.0.0.1", "a.b32.i2p", "b.b32.i2p"]);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_progress_stops_launching_tests() {
+        let proxy_server =
+            MockServer::respond_with(MockResponse::ok(vec![0u8; 1024]).delay(Duration::from_millis(100))).await;
+        let proxies = vec![proxy_server.proxy(); 10];
+        let tester = ProxyTester::new(None).with_measure_latency(false);
+        let token = CancelToken::new();
+
+        let mut progress = Vec::new();
+        let results: Vec<ProxyTestResult> = tester
+            .test_proxies_with_progress(proxies, 2, token.clone(), |update| {
+                progress.push(update);
+                if update.tested == 2 {
+                    token.cancel();
+                }
+            })
+            .collect()
+            .await;
+
+        // Only the tests already running when the token was cancelled finish after it
+        assert!(results.len() < 10, "{} tests ran", results.len());
+        assert_eq!(proxy_server.requests().len(), results.len());
+        assert_eq!(progress.len(), results.len());
+        assert!(progress.windows(2).all(|pair| pair[1].tested == pair[0].tested + 1));
+        assert!(progress.iter().all(|update| update.total == 10));
+    }
+
+    #[tokio::test]
+    async fn test_sustained_test_downloads_larger_payload() {
+        let proxy_server = MockServer::respond_with(MockResponse::ok(vec![0u8; 64 * 1024])).await;
+        let tester = ProxyTester::new(None).with_sustained_test(64 * 1024, Duration::from_secs(5));
+        let dead = Proxy::new("dead.example".to_string(), 8080);
+
+        let results = tester
+            .test_sustained(
+                vec![
+                    ProxyTestResult::succeeded(proxy_server.proxy(), 1.0, 42.0),
+                    ProxyTestResult::failed(dead, "refused".to_string()),
+                ],
+                2,
+            )
+            .await;
+
+        let targets: Vec<String> = proxy_server
+            .requests()
+            .into_iter()
+            .filter(|r| r.method == "GET")
+            .map(|r| r.target)
+            .collect();
+        assert_eq!(targets, vec!["http://httpbin.org/bytes/65536".to_string()]);
+        assert!(results[0].success);
+        assert!(results[0].speed_bytes_per_sec > 1.0);
+        assert_eq!(results[0].latency_ms, 42.0);
+        assert!(!results[1].success);
+        assert_eq!(results[1].proxy.host, "dead.example");
+    }
+
+    #[test]
+    fn test_sustained_test_url_follows_configured_test_url() {
+        let tester = ProxyTester::new(None).with_sustained_test(1000, Duration::from_secs(5));
+        assert_eq!(tester.sustained_test_url(), "http://httpbin.org/bytes/1000");
+
+        let tester = tester.with_throughput_url("https://speed.example/__down?bytes=10240&x=1".to_string());
+        assert_eq!(tester.sustained_test_url(), "https://speed.example/__down?bytes=1000&x=1");
+
+        // No size to change: the configured URL is downloaded as it is
+        let tester = tester.with_throughput_url("http://files.example/big.bin".to_string());
+        assert_eq!(tester.sustained_test_url(), "http://files.example/big.bin");
+    }
 
     #[test]
     fn test_proxy_test_result_new() {
@@ -338,6 +1284,50 @@ mod tests {
         assert_eq!(result.error, Some(error_msg));
     }
 
+    #[tokio::test]
+    async fn test_verify_reachable_asks_the_i2p_outproxy_itself() {
+        // Stands in for the router's HTTP proxy: tunnels to "good.b32.i2p", which reaches
+        // every target, and to "bad.b32.i2p", which answers every request with a 502
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        async fn read_head(stream: &mut TcpStream) -> String {
+            let mut head = Vec::new();
+            let mut byte = [0u8; 1];
+            while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).await.unwrap_or(0) == 1 {
+                head.push(byte[0]);
+            }
+            String::from_utf8_lossy(&head).to_string()
+        }
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let tunnel = read_head(&mut stream).await;
+                    let _ = stream.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").await;
+                    let request = read_head(&mut stream).await;
+                    let expected = request.starts_with("HEAD http://target.test/page ")
+                        || request.starts_with("CONNECT target.test:443 ");
+                    let answer: &[u8] = if tunnel.starts_with("CONNECT good.b32.i2p:443 ") && expected {
+                        b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"
+                    } else {
+                        b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n"
+                    };
+                    let _ = stream.write_all(answer).await;
+                });
+            }
+        });
+
+        let tester = ProxyTester::new(None)
+            .with_test_i2p(true)
+            .with_router(Arc::new(FakeRouter::new(port, port)));
+        let good = Proxy::new("good.b32.i2p".to_string(), 443);
+        let bad = Proxy::new("bad.b32.i2p".to_string(), 443);
+
+        assert!(tester.verify_reachable(&good, "http://target.test/page").await);
+        assert!(tester.verify_reachable(&good, "https://target.test/").await);
+        assert!(!tester.verify_reachable(&bad, "http://target.test/page").await);
+        assert!(!tester.verify_reachable(&bad, "https://target.test/").await);
+    }
+
     #[tokio::test]
     async fn test_i2p_proxy_skips_test() {
         let tester = ProxyTester::new(None);
@@ -354,12 +1344,63 @@ mod tests {
         assert!(result.error.is_none());
     }
 
+    #[tokio::test]
+    async fn test_i2p_proxy_skipped_when_i2p_testing_disabled() {
+        let tester = ProxyTester::new(None).with_test_i2p(false);
+        let proxy = Proxy::new("proxy.b32.i2p".to_string(), 443);
+
+        let result = tester.test_proxy(&proxy).await;
+
+        assert!(result.success);
+        assert_eq!(result.speed_bytes_per_sec, 1024.0 * 50.0);
+        assert_eq!(result.latency_ms, 200.0);
+    }
+
+    #[tokio::test]
+    async fn test_configured_i2p_defaults() {
+        let tester = ProxyTester::new(None)
+            .with_i2p_default_speed(8000.0)
+            .with_i2p_default_latency(900.0);
+        let mut proxy = Proxy::new("proxy.b32.i2p".to_string(), 443);
+        proxy.uptime = Some(0.5);
+
+        let result = tester.test_proxy(&proxy).await;
+        assert!(result.success);
+        assert_eq!(result.speed_bytes_per_sec, 8000.0);
+        assert_eq!(result.latency_ms, 900.0);
+
+        // With scaling, the listed uptime sets the share of the default speed
+        let tester = tester.with_uptime_scaled_i2p_speed(true);
+        assert_eq!(tester.test_proxy(&proxy).await.speed_bytes_per_sec, 4000.0);
+        proxy.uptime = Some(0.25);
+        assert_eq!(tester.test_proxy(&proxy).await.speed_bytes_per_sec, 2000.0);
+        proxy.uptime = None;
+        assert_eq!(tester.test_proxy(&proxy).await.speed_bytes_per_sec, 8000.0);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running i2pd router with a reachable outproxy"]
+    async fn test_i2p_proxy_tested_through_router_when_enabled() {
+        crate::i2pd_router::ensure_router_running().unwrap();
+        let tester = ProxyTester::new(None).with_test_i2p(true);
+        let proxy = Proxy::new("proxy.b32.i2p".to_string(), 443);
+
+        let result = tester.test_proxy(&proxy).await;
+
+        // A real test never reports the canned 50 KB/s / 200ms values
+        let is_canned = result.success
+            && result.speed_bytes_per_sec == 1024.0 * 50.0
+            && result.latency_ms == 200.0;
+        assert!(!is_canned);
+    }
+
     #[test]
     fn test_proxy_tester_new() {
         let tester = ProxyTester::new(None);
         assert_eq!(tester.test_url, "http://httpbin.org/bytes/10240");
         assert_eq!(tester.test_timeout, Duration::from_secs(10));
         assert_eq!(tester.test_size_bytes, 10240);
+        assert!(!tester.test_i2p);
     }
 
     #[test]
@@ -430,6 +1471,141 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_test_url_rotates_on_error_rate() {
+        let tester = ProxyTester::new(Some("http://primary.test/bytes".to_string()))
+            .with_fallback_test_urls(vec!["http://fallback.test/bytes".to_string()])
+            .with_url_error_threshold(0.5, 4);
+
+        // Below the sample minimum nothing happens, however bad it looks
+        for _ in 0..3 {
+            tester.record_test_url_response("http://primary.test/bytes", true);
+        }
+        assert_eq!(tester.current_test_url(), "http://primary.test/bytes");
+
+        tester.record_test_url_response("http://primary.test/bytes", true);
+        assert_eq!(tester.current_test_url(), "http://fallback.test/bytes");
+
+        // Late results for the old URL do not count against the new one
+        for _ in 0..10 {
+            tester.record_test_url_response("http://primary.test/bytes", true);
+        }
+        assert_eq!(tester.current_test_url(), "http://fallback.test/bytes");
+    }
+
+    #[test]
+    fn test_test_url_kept_when_mostly_healthy() {
+        let tester = ProxyTester::new(Some("http://primary.test/bytes".to_string()))
+            .with_fallback_test_urls(vec!["http://fallback.test/bytes".to_string()])
+            .with_url_error_threshold(0.5, 4);
+
+        for endpoint_error in [true, false, false, true, false, false] {
+            tester.record_test_url_response("http://primary.test/bytes", endpoint_error);
+        }
+        assert_eq!(tester.current_test_url(), "http://primary.test/bytes");
+    }
+
+    #[tokio::test]
+    async fn test_goodput_excludes_time_to_first_byte() {
+        let ttfb = Duration::from_millis(500);
+        let proxy_server = MockServer::start(move |_| {
+            MockResponse::ok(vec![0u8; 8 * 1024])
+                .delay(ttfb)
+                .chunked(1024, Duration::from_millis(20))
+        })
+        .await;
+        let proxy = proxy_server.proxy();
+        let tester = |goodput: bool| {
+            ProxyTester::new(Some("http://slow-start.test/bytes".to_string()))
+                .with_measure_latency(false)
+                .with_goodput_measurement(goodput)
+        };
+
+        let whole = tester(false).test_proxy(&proxy).await;
+        let goodput = tester(true).test_proxy(&proxy).await;
+        assert!(whole.success && goodput.success);
+
+        // The whole transfer includes the delay, so it can't beat all bytes over the delay alone
+        let ceiling_with_delay = (8 * 1024) as f64 / ttfb.as_secs_f64();
+        assert!(whole.speed_bytes_per_sec < ceiling_with_delay);
+        assert!(goodput.speed_bytes_per_sec > ceiling_with_delay);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_test_url_triggers_rotation() {
+        let proxy_server = MockServer::start(|request| {
+            if request.target.contains("primary") {
+                MockResponse::new(429, "slow down")
+            } else {
+                MockResponse::ok(vec![0u8; 1024])
+            }
+        })
+        .await;
+        let tester = ProxyTester::new(Some("http://primary.test/bytes".to_string()))
+            .with_fallback_test_urls(vec!["http://fallback.test/bytes".to_string()])
+            .with_url_error_threshold(0.5, 3);
+        let proxy = proxy_server.proxy();
+
+        for _ in 0..3 {
+            assert!(!tester.test_proxy(&proxy).await.success);
+        }
+        assert_eq!(tester.current_test_url(), "http://fallback.test/bytes");
+
+        // The same proxy passes once the tester stops hitting the rate-limited endpoint
+        assert!(tester.test_proxy(&proxy).await.success);
+    }
+
+    #[test]
+    fn test_dns_resolution_applied_to_socks_url() {
+        let proxy = Proxy::new_with_type("10.0.0.1".to_string(), 1080, crate::proxy_manager::ProxyType::Socks);
+
+        let tester = ProxyTester::new(None);
+        assert_eq!(tester.dns_resolution, DnsResolution::Local);
+        assert_eq!(tester.socks_proxy_url(&proxy), "socks5://10.0.0.1:1080");
+
+        let tester = ProxyTester::new(None).with_dns_resolution(DnsResolution::Remote);
+        assert_eq!(tester.socks_proxy_url(&proxy), "socks5h://10.0.0.1:1080");
+        assert!(reqwest::Proxy::all(tester.socks_proxy_url(&proxy)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_test_until_stops_after_enough_successes() {
+        let tester = ProxyTester::new(None);
+        let proxies: Vec<Proxy> = (0..10)
+            .map(|i| Proxy::new(format!("proxy{}.b32.i2p", i), 443))
+            .collect();
+
+        let results = tester.test_until(proxies, 2, 3).await;
+
+        assert!(results.iter().filter(|r| r.success).count() >= 3);
+        assert!(results.len() < 10);
+    }
+
+    #[tokio::test]
+    async fn test_test_until_drops_slow_in_flight_tests() {
+        let slow = MockServer::respond_with(MockResponse::ok("slow").delay(Duration::from_secs(5))).await;
+        let tester = ProxyTester::new(None);
+        let proxies = vec![
+            slow.proxy(),
+            Proxy::new("proxy1.b32.i2p".to_string(), 443),
+            Proxy::new("proxy2.b32.i2p".to_string(), 443),
+        ];
+
+        let started = Instant::now();
+        let results = tester.test_until(proxies, 3, 2).await;
+
+        assert_eq!(results.iter().filter(|r| r.success).count(), 2);
+        assert!(results.iter().all(|r| r.proxy.is_i2p_proxy()));
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn test_test_until_needing_nothing_tests_nothing() {
+        let tester = ProxyTester::new(None);
+        let results = tester.test_until(vec![Proxy::new("proxy.b32.i2p".to_string(), 443)], 1, 0).await;
+        assert!(results.is_empty());
+    }
+
     #[test]
     fn test_proxy_tester_default() {
         let tester = ProxyTester::default();
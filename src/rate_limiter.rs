@@ -0,0 +1,105 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::debug;
+
+/// What to do when a proxy has used up its request budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateLimitPolicy {
+    /// Wait until the proxy may be used again
+    #[default]
+    Wait,
+    /// Move on to the next candidate
+    Skip,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Token bucket per proxy: each proxy may take `rate` requests per second, with bursts of up
+/// to `rate` requests (at least one)
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_sec: f64) -> Self {
+        let rate = if requests_per_sec.is_finite() && requests_per_sec > 0.0 {
+            requests_per_sec
+        } else {
+            1.0
+        };
+        Self {
+            rate,
+            burst: rate.ceil().max(1.0),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn requests_per_sec(&self) -> f64 {
+        self.rate
+    }
+
+    /// Take a token for `key` if one is available, otherwise return how long until the next one
+    pub fn try_acquire(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock();
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            refilled_at: now,
+        });
+
+        let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * self.rate;
+        bucket.tokens = (bucket.tokens + refill).min(self.burst);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    /// Take a token for `key`, waiting for one if necessary
+    pub async fn acquire(&self, key: &str) {
+        while let Err(wait) = self.try_acquire(key) {
+            debug!("Rate limit reached for {}, waiting {:?}", key, wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_then_empty() {
+        let limiter = RateLimiter::new(3.0);
+        for _ in 0..3 {
+            assert!(limiter.try_acquire("a").is_ok());
+        }
+        let wait = limiter.try_acquire("a").unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_millis(334));
+
+        // Buckets are per key
+        assert!(limiter.try_acquire("b").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_acquire_waits_for_refill() {
+        let limiter = RateLimiter::new(10.0);
+        let start = Instant::now();
+        for _ in 0..15 {
+            limiter.acquire("a").await;
+        }
+        // 10 from the initial burst, 5 more at 100 ms intervals
+        assert!(start.elapsed() >= Duration::from_millis(450));
+    }
+}
@@ -1,12 +1,76 @@
-use crate::proxy_manager::Proxy;
+use crate::challenge::ChallengeDetector;
+use crate::proxy_manager::{Proxy, ProxyType};
 use crate::proxy_selector::{ProxySelector, SelectedProxy};
-use crate::i2pd_router::ensure_router_running;
+use crate::rate_limiter::{RateLimitPolicy, RateLimiter};
+use crate::recorder::Recorder;
+use crate::response_cache::{CachedResponse, ResponseCache};
+use crate::timeouts::TimeoutConfig;
+use crate::i2pd_router::{GlobalRouter, Router};
+use futures::stream::{self, FuturesUnordered, StreamExt};
 use reqwest::Client;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, UdpSocket};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::time::Instant;
 use tracing::{debug, error, info, warn};
 use url::Url;
 
+/// Requests in flight at once in `fetch_all_from_host`
+const FETCH_ALL_CONCURRENCY: usize = 4;
+
+/// Proxies probed at once in `probe_working`
+const PROBE_CONCURRENCY: usize = 8;
+
+/// Bytes requested by the preflight probe before a large GET
+const PREFLIGHT_PROBE_BYTES: usize = 1024;
+
+/// Appended to `proxy_used` when a SOCKS proxy was reached through the HTTPS fallback
+const SOCKS_FALLBACK_SUFFIX: &str = " (fallback from SOCKS)";
+
+/// Most body bytes quoted in an error message, and read from streaming error responses
+const ERROR_SNIPPET_BYTES: usize = 512;
+
+/// Attempt error for a candidate skipped by the rate limit, which is not a proxy failure
+const RATE_LIMITED: &str = "rate limited";
+
+/// Clearnet proxy clients kept by the client cache before it starts over
+const MAX_CACHED_CLIENTS: usize = 64;
+
+/// Why a preflight probe failed
+enum PreflightFailure {
+    /// The probe did not get through the proxy: connect, timeout or transfer errors
+    Proxy(String),
+    /// The target answered through the proxy, but not with the start of a download
+    Answer(String),
+}
+
+/// Which of the router's local proxies a port belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RouterProxyKind {
+    Http,
+    Https,
+}
+
+/// Map a router port hint to the router proxy listening on it
+fn router_proxy_kind(port: u16, router: &dyn Router) -> Result<RouterProxyKind, String> {
+    if port == router.http_proxy_port() {
+        Ok(RouterProxyKind::Http)
+    } else if port == router.https_proxy_port() {
+        Ok(RouterProxyKind::Https)
+    } else {
+        Err(format!(
+            "Router port hint {} matches neither the router HTTP proxy port {} nor its HTTPS proxy port {}",
+            port,
+            router.http_proxy_port(),
+            router.https_proxy_port()
+        ))
+    }
+}
+
 /// Format an error with full details including error chain and debug information
 fn format_error_full(err: &dyn std::error::Error) -> String {
     let mut error_parts = Vec::new();
@@ -50,814 +114,5422 @@ fn log_error_full(prefix: &str, err: &dyn std::error::Error) {
     error!("{} Error debug: {:#?}", prefix, err);
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct RequestConfig {
     pub url: String,
     pub method: String,
     pub headers: Option<std::collections::HashMap<String, String>>,
     pub body: Option<Vec<u8>>,
     pub stream: bool,
+    /// Route a clearnet URL through an I2P outproxy (via the router) instead of a clearnet
+    /// proxy. Has no effect on `.i2p` URLs, which always go straight through the router.
+    #[serde(default)]
+    pub force_outproxy: bool,
+    /// Give up when the first body bytes have not arrived this long after the headers
+    #[serde(default)]
+    pub first_byte_timeout: Option<Duration>,
+    /// Give up when the body stops arriving for this long once it has started.
+    /// Both body timeouts only apply when the body is read here, i.e. not with `stream`.
+    #[serde(default)]
+    pub body_stall_timeout: Option<Duration>,
+    /// Give up when the response headers have not arrived this long after sending. Unlike
+    /// `timeout_secs` it does not limit the body, so a silent proxy fails fast while a long
+    /// download through a responsive one can still finish.
+    #[serde(default)]
+    pub response_timeout: Option<Duration>,
+    /// Return `RequestError::HttpStatus` for non-2xx responses instead of `Ok`.
+    /// The proxy delivered such responses fine, so they never count as proxy failures.
+    #[serde(default)]
+    pub error_on_status: bool,
+    /// Before a GET through a specific proxy, probe the first kilobyte with a ranged request
+    /// and only start the full download if the probe works
+    #[serde(default)]
+    pub preflight: bool,
+    /// Host header to send instead of the URL's host, for vhosts sharing one b32 address.
+    /// Replaces any Host given in `headers`.
+    #[serde(default)]
+    pub host_override: Option<String>,
+    /// Total time allowed for this request, overriding the handler's `TimeoutConfig`
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    /// Send the request HTTP/1.0-style (`Connection: close`, no compressed or chunked
+    /// answers asked for), for legacy eepsites that break on keep-alive
+    #[serde(default)]
+    pub http10: bool,
+    /// Send through the first clearnet candidate only and never fail over to another, for
+    /// requests with side effects that must not be repeated (e.g. a non-idempotent POST)
+    #[serde(default)]
+    pub no_failover: bool,
+    /// Test the proxies again for this request instead of trusting the cached selection,
+    /// e.g. after a response that looked tampered with. The host's sticky proxy is not
+    /// preferred either.
+    #[serde(default)]
+    pub require_fresh_proxy: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ResponseData {
-    pub status: u16,
-    pub headers: std::collections::HashMap<String, String>,
-    pub body: Vec<u8>,
-    pub proxy_used: String,
-}
-
-
-pub struct RequestHandler {
-    proxy_selector: Arc<ProxySelector>,
-}
-
-impl RequestHandler {
-    pub fn new(proxy_selector: Arc<ProxySelector>) -> Self {
-        info!("Initializing RequestHandler");
-        Self { proxy_selector }
+impl RequestConfig {
+    /// Plain GET of `url`, everything else left at its default
+    pub fn get(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            method: "GET".to_string(),
+            ..Default::default()
+        }
     }
 
-    /// Check if a URL points to an I2P domain (.i2p or .b32.i2p)
-    pub fn is_i2p_domain(url: &str) -> bool {
-        match Url::parse(url) {
-            Ok(parsed_url) => {
-                if let Some(host) = parsed_url.host_str() {
-                    host.ends_with(".i2p") || host.ends_with(".b32.i2p")
-                } else {
-                    false
-                }
-            }
-            Err(_) => {
-                // Fallback: simple string check if URL parsing fails
-                url.contains(".i2p") || url.contains(".b32.i2p")
-            }
+    /// POST of `body` to `url`, everything else left at its default
+    pub fn post(url: impl Into<String>, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            url: url.into(),
+            method: "POST".to_string(),
+            body: Some(body.into()),
+            ..Default::default()
         }
     }
+}
 
-    /// Check if an error is a proxy connection error (unreachable, timeout, etc.)
-    fn is_proxy_connection_error(error: &str) -> bool {
-        let error_lower = error.to_lowercase();
-        error_lower.contains("unreachable") 
-            || error_lower.contains("connection refused")
-            || error_lower.contains("connection reset")
-            || error_lower.contains("connection timed out")
-            || error_lower.contains("timeout")
-            || error_lower.contains("socks connect error")
-            || error_lower.contains("proxy server unreachable")
-    }
+/// Why a request failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum RequestError {
+    /// Headers arrived but the body stopped coming for `waited`
+    Stalled { proxy_used: String, waited: Duration },
+    /// No response headers arrived within `response_timeout`
+    NoResponse { proxy_used: String, waited: Duration },
+    /// The server answered with a non-2xx status and `error_on_status` was set.
+    /// For streaming requests `body` holds only the first `ERROR_SNIPPET_BYTES`.
+    HttpStatus { code: u16, body: Vec<u8> },
+    /// The preflight probe did not get a usable answer, so the full request was not sent
+    PreflightFailed { proxy_used: String, reason: String },
+    /// A proxy candidate belongs to the wrong network for the URL: I2P sites only go through
+    /// the router, clearnet sites never through the router's own local proxy
+    ProxyNetworkMismatch { url: String, proxy: String },
+    /// The router began shutting down while the request was waiting on it
+    RouterShuttingDown,
+    /// The batch deadline passed before this request finished
+    DeadlineExceeded,
+    /// `host` answered with an anti-bot challenge page instead of the requested content
+    ChallengeEncountered { proxy_used: String, host: String, reason: String },
+    /// The response carried more headers than `RequestHandler::with_header_limits` allows,
+    /// with `HeaderLimitPolicy::Reject`. `count` and `bytes` are where the limit was hit.
+    HeadersTooLarge { proxy_used: String, count: usize, bytes: usize },
+    /// Every proxy candidate failed; `attempts` holds each proxy URL with its error, in the
+    /// order they were tried
+    AllProxiesFailed { attempts: Vec<(String, String)> },
+    /// No proxy candidate was found to send the request through
+    NoProxyCandidates { reason: String },
+    /// Any other failure
+    Other(String),
+}
 
-    /// Verify router SOCKS proxy is reachable by attempting to connect
-    async fn verify_router_socks_available(port: u16) -> bool {
-        use std::time::Duration;
-        
-        // Try to actually connect to the port
-        match tokio::time::timeout(
-            Duration::from_secs(2),
-            tokio::net::TcpStream::connect(format!("127.0.0.1:{}", port))
-        ).await {
-            Ok(Ok(_)) => {
-                debug!("Router SOCKS proxy on port {} is reachable", port);
-                true
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::Stalled { proxy_used, waited } => {
+                write!(f, "Response body stalled through proxy {} (no data for {:?})", proxy_used, waited)
             }
-            Ok(Err(e)) => {
-                debug!("Router SOCKS proxy on port {} not reachable: {}", port, e);
-                false
+            RequestError::NoResponse { proxy_used, waited } => {
+                write!(f, "No response through proxy {} within {:?}", proxy_used, waited)
             }
-            Err(_) => {
-                debug!("Router SOCKS proxy on port {} connection timeout", port);
-                false
+            RequestError::HttpStatus { code, .. } => match self.body_snippet() {
+                Some(snippet) => write!(f, "HTTP error {}: {}", code, snippet),
+                None => write!(f, "HTTP error {}", code),
+            },
+            RequestError::PreflightFailed { proxy_used, reason } => {
+                write!(f, "Preflight through proxy {} failed: {}", proxy_used, reason)
             }
-        }
-    }
-
-    /// Create a client from a proxy candidate with optional router port hint
-    async fn create_client_from_proxy(
-        &self,
-        selected_proxy: &SelectedProxy,
-        router_port_hint: Option<u16>,
-    ) -> Result<(Client, String), String> {
-        let is_i2p_outproxy = selected_proxy.proxy.is_i2p_proxy();
-        
-        let client = if is_i2p_outproxy {
-            // Ensure i2pd router is running for I2P outproxies
-            if let Err(e) = ensure_router_running() {
-                return Err(format!("Failed to ensure i2pd router is running: {}", e));
+            RequestError::ProxyNetworkMismatch { url, proxy } => {
+                write!(f, "Proxy {} is on the wrong network for {}", proxy, url)
             }
-            
-            // For I2P-based outproxies, connect to them through the router's HTTP/HTTPS proxy
-            // SOCKS5 cannot handle .b32.i2p addresses, so we skip SOCKS5 entirely
-            debug!("Connecting to I2P outproxy {} through router (HTTP/HTTPS only, no SOCKS5)", selected_proxy.proxy.url);
-            
-            // If router port hint is provided (for parallel downloads), use it
-            if let Some(port) = router_port_hint {
-                // Try HTTP or HTTPS based on port hint
-                if port == 4444 {
-                    // HTTP proxy
-                    match reqwest::Proxy::http("http://127.0.0.1:4444") {
-                        Ok(i2p_proxy) => {
-                            match Client::builder()
-                                .proxy(i2p_proxy)
-                                .timeout(std::time::Duration::from_secs(300))
-                                .build()
-                            {
-                                Ok(client) => {
-                                    info!("Using router HTTP proxy on port 4444 for I2P outproxy {} (parallel download)", selected_proxy.proxy.url);
-                                    return Ok((client, format!("router-http://127.0.0.1:4444 (for {})", selected_proxy.proxy.url)));
-                                }
-                                Err(e) => return Err(format!("Failed to create HTTP client: {}", e)),
-                            }
-                        }
-                        Err(e) => return Err(format!("Failed to create HTTP proxy: {}", e)),
-                    }
-                } else if port == 4447 {
-                    // HTTPS proxy (not SOCKS5, as SOCKS5 cannot handle .b32.i2p addresses)
-                    match reqwest::Proxy::https("http://127.0.0.1:4447") {
-                        Ok(i2p_proxy) => {
-                            match Client::builder()
-                                .proxy(i2p_proxy)
-                                .timeout(std::time::Duration::from_secs(300))
-                                .build()
-                            {
-                                Ok(client) => {
-                                    info!("Using router HTTPS proxy on port 4447 for I2P outproxy {} (parallel download)", selected_proxy.proxy.url);
-                                    return Ok((client, format!("router-https://127.0.0.1:4447 (for {})", selected_proxy.proxy.url)));
-                                }
-                                Err(e) => return Err(format!("Failed to create HTTPS client: {}", e)),
-                            }
-                        }
-                        Err(e) => return Err(format!("Failed to create HTTPS proxy: {}", e)),
-                    }
-                }
+            RequestError::RouterShuttingDown => write!(f, "i2pd router is shutting down"),
+            RequestError::DeadlineExceeded => write!(f, "Batch deadline exceeded"),
+            RequestError::ChallengeEncountered { proxy_used, host, reason } => {
+                write!(f, "{} answered with a challenge page through proxy {} ({})", host, proxy_used, reason)
             }
-            
-            // No router port hint: try HTTP proxy first, then HTTPS proxy
-            // HTTP proxy is better for streaming large files and can handle .b32.i2p addresses
-            match reqwest::Proxy::http("http://127.0.0.1:4444") {
-                Ok(i2p_proxy) => {
-                    match Client::builder()
-                        .proxy(i2p_proxy)
-                        .timeout(std::time::Duration::from_secs(300))  // Longer timeout for streaming
-                        .build()
-                    {
-                        Ok(client) => {
-                            info!("Using router HTTP proxy on port 4444 for I2P outproxy {} (better for streaming)", selected_proxy.proxy.url);
-                            Ok((client, format!("router-http://127.0.0.1:4444 (for {})", selected_proxy.proxy.url)))
-                        }
-                        Err(e) => {
-                            log_error_full("Failed to create client with router HTTP, falling back to HTTPS:", &e);
-                            // Fallback to HTTPS
-                            reqwest::Proxy::https("http://127.0.0.1:4447")
-                                .map_err(|e| {
-                                    log_error_full("Failed to create I2P HTTPS proxy (tried HTTP port 4444):", &e);
-                                    format!("Failed to create I2P HTTPS proxy: {} (tried HTTP port 4444)", e)
-                                })
-                                .and_then(|i2p_proxy| {
-                                    Client::builder()
-                                        .proxy(i2p_proxy)
-                                        .timeout(std::time::Duration::from_secs(300))
-                                        .build()
-                                        .map_err(|e| {
-                                            log_error_full("Failed to create HTTPS client:", &e);
-                                            format!("Failed to create HTTPS client: {}", e)
-                                        })
-                                })
-                                .map(|client| (client, format!("router-https://127.0.0.1:4447 (for {}, fallback from HTTP)", selected_proxy.proxy.url)))
-                        }
-                    }
-                }
-                Err(e) => {
-                    log_error_full("Router HTTP proxy not available, falling back to HTTPS:", &e);
-                    // Final fallback to HTTPS
-                    reqwest::Proxy::https("http://127.0.0.1:4447")
-                        .map_err(|e| {
-                            log_error_full("Failed to create I2P HTTPS proxy (tried HTTP port 4444):", &e);
-                            format!("Failed to create I2P HTTPS proxy: {} (tried HTTP port 4444)", e)
-                        })
-                        .and_then(|i2p_proxy| {
-                            Client::builder()
-                                .proxy(i2p_proxy)
-                                .timeout(std::time::Duration::from_secs(300))
-                                .build()
-                                .map_err(|e| {
-                                    log_error_full("Failed to create HTTPS client:", &e);
-                                    format!("Failed to create HTTPS client: {}", e)
-                                })
-                        })
-                        .map(|client| (client, format!("router-https://127.0.0.1:4447 (for {}, fallback from HTTP)", selected_proxy.proxy.url)))
+            RequestError::HeadersTooLarge { proxy_used, count, bytes } => write!(
+                f,
+                "Response headers through proxy {} over the limit ({} headers, {} bytes so far)",
+                proxy_used, count, bytes
+            ),
+            RequestError::AllProxiesFailed { attempts } => {
+                write!(f, "All {} proxy candidates failed", attempts.len())?;
+                for (i, (proxy, error)) in attempts.iter().enumerate() {
+                    write!(f, "{} {}: {}", if i == 0 { ":" } else { ";" }, proxy, error)?;
                 }
+                Ok(())
             }
-        } else {
-            // For non-I2P outproxies, use them directly based on type
-            match &selected_proxy.proxy.proxy_type {
-                crate::proxy_manager::ProxyType::Socks => {
-                    // Try SOCKS first, fallback to HTTPS if SOCKS fails
-                    let socks_url = format!("socks5://{}:{}", selected_proxy.proxy.host, selected_proxy.proxy.port);
-                    let https_url = format!("https://{}:{}", selected_proxy.proxy.host, selected_proxy.proxy.port);
-                    
-                    // Try SOCKS first
-                    match reqwest::Proxy::all(&socks_url) {
-                        Ok(socks_proxy) => {
-                            match Client::builder()
-                                .proxy(socks_proxy)
-                                .timeout(std::time::Duration::from_secs(60))
-                                .build()
-                            {
-                                Ok(client) => Ok((client, selected_proxy.proxy.url.clone())),
-                                Err(e) => {
-                                    warn!("SOCKS proxy {} failed to create client, falling back to HTTPS: {}", selected_proxy.proxy.url, e);
-                                    // Fallback to HTTPS
-                                    reqwest::Proxy::https(&https_url)
-                                        .map_err(|e| format!("Failed to create HTTPS fallback proxy for {}: {}", selected_proxy.proxy.url, e))
-                                        .and_then(|p| {
-                                            Client::builder()
-                                                .proxy(p)
-                                                .timeout(std::time::Duration::from_secs(60))
-                                                .build()
-                                                .map_err(|e| format!("Failed to create HTTPS fallback client for {}: {}", selected_proxy.proxy.url, e))
-                                        })
-                                        .map(|client| (client, format!("https://{}:{} (fallback from SOCKS)", selected_proxy.proxy.host, selected_proxy.proxy.port)))
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            warn!("SOCKS proxy {} not available, falling back to HTTPS: {}", selected_proxy.proxy.url, e);
-                            // Fallback to HTTPS
-                            reqwest::Proxy::https(&https_url)
-                                .map_err(|e| format!("Failed to create HTTPS fallback proxy for {}: {}", selected_proxy.proxy.url, e))
-                                .and_then(|p| {
-                                    Client::builder()
-                                        .proxy(p)
-                                        .timeout(std::time::Duration::from_secs(60))
-                                        .build()
-                                        .map_err(|e| format!("Failed to create HTTPS fallback client for {}: {}", selected_proxy.proxy.url, e))
-                                })
-                                .map(|client| (client, format!("https://{}:{} (fallback from SOCKS)", selected_proxy.proxy.host, selected_proxy.proxy.port)))
-                        }
-                    }
-                }
-                crate::proxy_manager::ProxyType::Https => {
-                    reqwest::Proxy::https(&selected_proxy.proxy.url)
-                        .map_err(|e| format!("Failed to create HTTPS proxy for {}: {}", selected_proxy.proxy.url, e))
-                        .and_then(|p| {
-                            Client::builder()
-                                .proxy(p)
-                                .timeout(std::time::Duration::from_secs(60))
-                                .build()
-                                .map_err(|e| format!("Failed to create client for {}: {}", selected_proxy.proxy.url, e))
-                        })
-                        .map(|client| (client, selected_proxy.proxy.url.clone()))
-                }
-                crate::proxy_manager::ProxyType::Http => {
-                    reqwest::Proxy::http(&selected_proxy.proxy.url)
-                        .map_err(|e| format!("Failed to create HTTP proxy for {}: {}", selected_proxy.proxy.url, e))
-                        .and_then(|p| {
-                            Client::builder()
-                                .proxy(p)
-                                .timeout(std::time::Duration::from_secs(60))
-                                .build()
-                                .map_err(|e| format!("Failed to create client for {}: {}", selected_proxy.proxy.url, e))
-                        })
-                        .map(|client| (client, selected_proxy.proxy.url.clone()))
+            RequestError::NoProxyCandidates { reason } => write!(f, "{}", reason),
+            RequestError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl RequestError {
+    /// Start of the error page that came with an HTTP error (often the outproxy's own
+    /// explanation), decoded lossily and cut to `ERROR_SNIPPET_BYTES`
+    pub fn body_snippet(&self) -> Option<String> {
+        match self {
+            RequestError::HttpStatus { body, .. } => {
+                let cut = &body[..body.len().min(ERROR_SNIPPET_BYTES)];
+                let snippet = String::from_utf8_lossy(cut).trim().to_string();
+                if snippet.is_empty() {
+                    None
+                } else if body.len() > ERROR_SNIPPET_BYTES {
+                    Some(format!("{}...", snippet))
+                } else {
+                    Some(snippet)
                 }
             }
-        };
-
-        client
+            _ => None,
+        }
     }
+}
 
-    // Helper method to create client and send request (extracted for reuse)
-    pub async fn create_client_and_send_request(
-        &self,
-        config: &RequestConfig,
-        proxy_candidates: Vec<SelectedProxy>,
-    ) -> Result<(reqwest::Response, String, bool), String> {
-        // Check if this is an I2P domain
-        let is_i2p = Self::is_i2p_domain(&config.url);
-        
-        // For I2P sites, use local I2P proxy (no retry needed)
-        if is_i2p {
-            info!("Detected I2P domain, using local I2P proxy");
-            
-            // Ensure i2pd router is running
-            if let Err(e) = ensure_router_running() {
-                return Err(format!("Failed to ensure i2pd router is running: {}", e));
-            }
-            
-            // Check if URL uses HTTPS to determine proxy port
-            let is_https = config.url.starts_with("https://");
-            let proxy_url = if is_https {
-                "http://127.0.0.1:4447"  // HTTPS proxy port
-            } else {
-                "http://127.0.0.1:4444"  // HTTP proxy port
-            };
-            
-            debug!("Using local I2P proxy: {}", proxy_url);
-            
-            let http_proxy = reqwest::Proxy::http(proxy_url)
-                .map_err(|e| format!("Failed to create I2P HTTP proxy: {}", e))?;
-            
-            let mut builder = Client::builder()
-                .proxy(http_proxy)
-                .timeout(std::time::Duration::from_secs(60));
-            
-            // Add HTTPS proxy if needed
-            if is_https {
-                let https_proxy = reqwest::Proxy::https("http://127.0.0.1:4447")
-                    .map_err(|e| format!("Failed to create I2P HTTPS proxy: {}", e))?;
-                builder = builder.proxy(https_proxy);
-            }
-            
-            let client = builder.build()
-                .map_err(|e| format!("Failed to create I2P client: {}", e))?;
-            
-            // Build request
-            let mut request = match config.method.as_str() {
-                "GET" => client.get(&config.url),
-                "POST" => client.post(&config.url),
-                "PUT" => client.put(&config.url),
-                "DELETE" => client.delete(&config.url),
-                "PATCH" => client.patch(&config.url),
-                "HEAD" => client.head(&config.url),
-                _ => {
-                    return Err(format!("Unsupported HTTP method: {}", config.method));
-                }
-            };
+impl std::error::Error for RequestError {}
 
-            // Add headers
-            if let Some(headers) = &config.headers {
-                for (key, value) in headers {
-                    request = request.header(key, value);
-                }
-            }
+impl From<String> for RequestError {
+    fn from(message: String) -> Self {
+        RequestError::Other(message)
+    }
+}
 
-            // Add body
-            if let Some(body) = &config.body {
-                request = request.body(body.clone());
-            }
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResponseData {
+    pub status: u16,
+    /// Lowercased header names, each with every value received in order (e.g. several Set-Cookie)
+    pub headers: std::collections::HashMap<String, Vec<String>>,
+    pub body: Vec<u8>,
+    pub proxy_used: String,
+    /// URL the response actually came from, after following redirects
+    #[serde(default)]
+    pub final_url: String,
+    /// What the proxies on the way said about themselves, if they said anything
+    #[serde(default)]
+    pub proxy_info: Option<ProxyInfo>,
+    /// The request went straight to the target without any proxy (see
+    /// `RequestHandler::with_direct_fallback`)
+    #[serde(default)]
+    pub direct: bool,
+    /// Proxies tried for this request in order, the one that answered last. Only filled in
+    /// with `RequestHandler::with_attempt_log`.
+    #[serde(default)]
+    pub attempts: Vec<AttemptInfo>,
+}
 
-            debug!("Sending request through I2P proxy: {}", proxy_url);
+/// One proxy tried for a request, as listed in `ResponseData::attempts`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttemptInfo {
+    pub proxy: String,
+    pub succeeded: bool,
+    /// Why the attempt failed
+    pub error: Option<String>,
+}
 
-            // Send request
-            let response = request.send().await
-                .map_err(|e| format!("Request failed through I2P proxy {}: {}", proxy_url, e))?;
+/// Diagnostic headers added by proxies on the way (`Via`, `X-Forwarded-For`, `Forwarded`,
+/// `Proxy-Connection`), to tell which outproxy really served a response
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProxyInfo {
+    /// One entry per proxy hop, e.g. "1.1 outproxy.example (squid/5.7)"
+    pub via: Vec<String>,
+    pub forwarded_for: Vec<String>,
+    pub forwarded: Vec<String>,
+    pub proxy_connection: Option<String>,
+}
 
-            return Ok((response, proxy_url.to_string(), true));
-        }
+impl ProxyInfo {
+    /// Collect the diagnostic headers from a response, `None` when there are none
+    pub fn from_headers(headers: &std::collections::HashMap<String, Vec<String>>) -> Option<Self> {
+        let values = |name: &str| -> Vec<String> {
+            headers
+                .iter()
+                .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+                .flat_map(|(_, values)| values.iter())
+                .flat_map(|value| value.split(','))
+                .map(|part| part.trim().to_string())
+                .filter(|part| !part.is_empty())
+                .collect()
+        };
 
-        // For clearnet sites, try multiple proxy candidates with retry logic
-        info!("Clearnet site detected, trying {} proxy candidates", proxy_candidates.len());
-        
-        if proxy_candidates.is_empty() {
-            error!("No proxy candidates available for clearnet request");
-            return Err("No proxy candidates available for clearnet request".to_string());
+        let info = Self {
+            via: values("via"),
+            forwarded_for: values("x-forwarded-for"),
+            forwarded: values("forwarded"),
+            proxy_connection: values("proxy-connection").into_iter().next(),
+        };
+        if info == Self::default() {
+            None
+        } else {
+            Some(info)
         }
+    }
+}
 
-        let mut last_error: Option<String> = None;
-        let mut failed_proxies: Vec<&SelectedProxy> = Vec::new();
+impl ResponseData {
+    /// First value of a header, looked up case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .and_then(|(_, values)| values.first())
+            .map(|value| value.as_str())
+    }
+}
 
-        // Try each proxy candidate in order (fastest first)
-        for (idx, selected_proxy) in proxy_candidates.iter().enumerate() {
-            info!("Trying proxy {} of {}: {} ({:.2} KB/s)", 
-                  idx + 1, proxy_candidates.len(), 
-                  selected_proxy.proxy.url,
-                  selected_proxy.speed_bytes_per_sec / 1024.0);
+/// Outcome of `RequestHandler::download_to_file`
+#[derive(Debug, Clone)]
+pub struct DownloadSummary {
+    pub status: u16,
+    /// Bytes written by this call, not counting what the file held before a resume
+    pub bytes_written: u64,
+    pub proxy_used: String,
+    /// URL the response actually came from, after following redirects
+    pub final_url: String,
+    /// Length of the existing file the download continued from, if it was resumed
+    pub resumed_from: Option<u64>,
+}
 
-            // Create client from this proxy
-            let (client, proxy_used) = match self.create_client_from_proxy(selected_proxy, None).await {
-                Ok(result) => result,
-                Err(e) => {
-                    warn!("Failed to create client for proxy {}: {}", selected_proxy.proxy.url, e);
-                    last_error = Some(format!("Proxy {}: {}", selected_proxy.proxy.url, e));
-                    failed_proxies.push(selected_proxy);
-                    continue;
-                }
-            };
+/// Where `download_to_file` keeps the body for `path` until it is complete: `<path>.part`
+pub fn partial_download_path(path: &Path) -> PathBuf {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".part");
+    PathBuf::from(partial)
+}
 
-            // Build request
-            let mut request = match config.method.as_str() {
-                "GET" => client.get(&config.url),
-                "POST" => client.post(&config.url),
-                "PUT" => client.put(&config.url),
-                "DELETE" => client.delete(&config.url),
-                "PATCH" => client.patch(&config.url),
-                "HEAD" => client.head(&config.url),
-                _ => {
-                    return Err(format!("Unsupported HTTP method: {}", config.method));
-                }
-            };
+/// Collect response headers keeping every value of repeated headers.
+/// Values that are not valid UTF-8 are skipped.
+pub fn extract_headers(headers: &reqwest::header::HeaderMap) -> std::collections::HashMap<String, Vec<String>> {
+    let mut extracted: std::collections::HashMap<String, Vec<String>> = std::collections::HashMap::new();
+    for (key, value) in headers {
+        if let Ok(value_str) = value.to_str() {
+            extracted
+                .entry(key.to_string())
+                .or_default()
+                .push(value_str.to_string());
+        }
+    }
+    extracted
+}
 
-            // Add headers
-            if let Some(headers) = &config.headers {
-                for (key, value) in headers {
-                    request = request.header(key, value);
-                }
-            }
+/// What to do with a response whose headers go over `RequestHandler::with_header_limits`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeaderLimitPolicy {
+    /// Keep the headers that fit and drop the rest. The first value of Content-Type,
+    /// Content-Length, Content-Encoding, Content-Range, Transfer-Encoding and Location is
+    /// always kept, limits or not, so the response can still be read; those values count
+    /// toward the limits first. Everything else, Set-Cookie included, may be dropped.
+    #[default]
+    Truncate,
+    /// Fail the request with `RequestError::HeadersTooLarge`
+    Reject,
+}
 
-            // Add body
-            if let Some(body) = &config.body {
-                request = request.body(body.clone());
-            }
+#[derive(Debug, Clone, Copy)]
+struct HeaderLimits {
+    max_count: usize,
+    max_bytes: usize,
+    policy: HeaderLimitPolicy,
+}
 
-            debug!("Sending request through proxy: {}", proxy_used);
+/// Headers whose first value `HeaderLimitPolicy::Truncate` never drops, since the body
+/// can't be interpreted or followed without them
+const PROTECTED_HEADERS: &[&str] = &[
+    "content-type",
+    "content-length",
+    "content-encoding",
+    "content-range",
+    "transfer-encoding",
+    "location",
+];
 
-            // Try to send request
-            match request.send().await {
-                Ok(response) => {
-                    info!("Request succeeded through proxy: {}", proxy_used);
-                    // Mark any previously failed proxies
-                    for failed_proxy in failed_proxies {
-                        self.proxy_selector.handle_proxy_failure(&failed_proxy.proxy).await;
-                    }
-                    return Ok((response, proxy_used, false));
-                }
-                Err(e) => {
-                    let error_str = format!("{}", e);
-                    let is_connection_error = Self::is_proxy_connection_error(&error_str);
-                    
-                    if is_connection_error {
-                        warn!("Proxy {} unreachable or connection error: {}", proxy_used, error_str);
-                        log_error_full(&format!("Full error details for proxy {}:", proxy_used), &e);
-                        // Mark this proxy as failed
-                        self.proxy_selector.handle_proxy_failure(&selected_proxy.proxy).await;
-                        failed_proxies.push(selected_proxy);
-                        last_error = Some(format!("Proxy {}: {}", proxy_used, error_str));
-                        // Continue to next proxy
-                        continue;
-                    } else {
-                        // For non-connection errors (like HTTP errors), return immediately
-                        // as retrying won't help
-                        let prefix = format!("Request failed through proxy {} with non-connection error:", proxy_used);
-                        log_error_full(&prefix, &e);
-                        return Err(format!("Request failed through proxy {}: {}", proxy_used, error_str));
-                    }
-                }
+/// Like `extract_headers`, but stop before `max_count` values or `max_bytes` of names and
+/// values are exceeded. The first value of each of `PROTECTED_HEADERS` is taken first and
+/// kept even if it alone goes over. Returns the headers kept and, if the limits were
+/// exceeded, the count and size at which that happened.
+fn extract_headers_within(
+    headers: &reqwest::header::HeaderMap,
+    max_count: usize,
+    max_bytes: usize,
+) -> (std::collections::HashMap<String, Vec<String>>, Option<(usize, usize)>) {
+    let mut extracted: std::collections::HashMap<String, Vec<String>> = std::collections::HashMap::new();
+    let (mut count, mut bytes) = (0, 0);
+    for name in PROTECTED_HEADERS {
+        if let Some(value_str) = headers.get(*name).and_then(|value| value.to_str().ok()) {
+            count += 1;
+            bytes += name.len() + value_str.len();
+            extracted.insert(name.to_string(), vec![value_str.to_string()]);
+        }
+    }
+    if count > max_count || bytes > max_bytes {
+        return (extracted, Some((count, bytes)));
+    }
+
+    let mut protected_seen: std::collections::HashSet<&str> = std::collections::HashSet::new();
+    for (key, value) in headers {
+        if PROTECTED_HEADERS.contains(&key.as_str()) && protected_seen.insert(key.as_str()) {
+            continue;
+        }
+        if let Ok(value_str) = value.to_str() {
+            let size = key.as_str().len() + value_str.len();
+            if count + 1 > max_count || bytes + size > max_bytes {
+                return (extracted, Some((count + 1, bytes + size)));
             }
+            count += 1;
+            bytes += size;
+            extracted
+                .entry(key.to_string())
+                .or_default()
+                .push(value_str.to_string());
         }
+    }
+    (extracted, None)
+}
 
-        // All proxies failed
-        let error_msg = if let Some(err) = last_error {
-            format!("All {} proxy candidates failed. Last error: {}", proxy_candidates.len(), err)
-        } else {
-            format!("All {} proxy candidates failed with unknown errors", proxy_candidates.len())
+/// What a request signer gets to see of an outgoing request
+pub struct RequestParts<'a> {
+    pub method: &'a str,
+    pub url: &'a str,
+    /// The body exactly as it is sent; empty if there is none, and for multipart forms,
+    /// which are encoded while they are sent
+    pub body: &'a [u8],
+}
+
+impl RequestParts<'_> {
+    /// Path and query of the URL, as sent in the request line
+    pub fn path(&self) -> String {
+        match Url::parse(self.url) {
+            Ok(url) => match url.query() {
+                Some(query) => format!("{}?{}", url.path(), query),
+                None => url.path().to_string(),
+            },
+            Err(_) => self.url.to_string(),
+        }
+    }
+}
+
+/// Computes headers to attach to a request, e.g. an HMAC signature over its parts
+pub type RequestSigner = Arc<dyn Fn(&RequestParts) -> reqwest::header::HeaderMap + Send + Sync>;
+
+/// Rewrites a response body given its bytes and content type, e.g. pointing the links in
+/// an HTML page back through the tunnel
+pub type BodyTransformer = Arc<dyn Fn(&[u8], &str) -> Vec<u8> + Send + Sync>;
+
+/// Memory a body being buffered holds in `RequestHandler::with_memory_budget`, given back
+/// to the budget when dropped
+struct BodyReservation {
+    budget: Option<(Arc<tokio::sync::Semaphore>, u32)>,
+    held: u32,
+}
+
+impl BodyReservation {
+    /// Hold at least `len` bytes. Fails instead of waiting when they don't fit in what is left
+    /// of the budget, since the body is already partly buffered.
+    fn grow_to(&mut self, len: usize, url: &str) -> Result<(), RequestError> {
+        let Some((budget, total)) = &self.budget else {
+            return Ok(());
         };
-        
-        error!("{}", error_msg);
-        Err(error_msg)
+        if len <= self.held as usize {
+            return Ok(());
+        }
+        if len > *total as usize {
+            return Err(format!("Response from {} grew past the memory budget of {} bytes", url, total).into());
+        }
+        let extra = len as u32 - self.held;
+        budget
+            .try_acquire_many(extra)
+            .map_err(|_| format!("Response from {} does not fit in what is left of the memory budget", url))?
+            .forget();
+        self.held = len as u32;
+        Ok(())
     }
+}
 
-    /// Get proxy candidates for a request (public helper method)
-    pub async fn get_proxy_candidates_for_request(
-        &self,
-        available_proxies: Vec<Proxy>,
-        count: usize,
-    ) -> Result<Vec<SelectedProxy>, Box<dyn std::error::Error>> {
-        self.proxy_selector.ensure_multiple_proxy_candidates(available_proxies, count).await
+impl Drop for BodyReservation {
+    fn drop(&mut self) {
+        if let Some((budget, _)) = &self.budget {
+            budget.add_permits(self.held as usize);
+        }
     }
+}
 
-    /// Handle a request using a specific proxy (for parallel downloads)
-    pub async fn handle_request_with_specific_proxy(
-        &self,
-        config: RequestConfig,
-        proxy: Proxy,
-        router_port_hint: Option<u16>,
-    ) -> Result<ResponseData, String> {
-        info!("Handling request with specific proxy: {} {} -> {}", config.method, config.url, proxy.url);
+pub struct RequestHandler {
+    proxy_selector: Arc<ProxySelector>,
+    root_certificates: Vec<reqwest::Certificate>,
+    router: Arc<dyn Router>,
+    response_cache: Option<Arc<ResponseCache>>,
+    metrics: Arc<parking_lot::Mutex<HandlerMetrics>>,
+    local_address: Option<IpAddr>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    rate_limit_policy: RateLimitPolicy,
+    timeouts: TimeoutConfig,
+    client_cache: Option<Arc<parking_lot::Mutex<std::collections::HashMap<String, (Client, String)>>>>,
+    challenge_detector: Option<Arc<dyn ChallengeDetector>>,
+    http10: Http10Policy,
+    allow_direct_fallback: bool,
+    // Bytes that buffered bodies may take up at once, and the total
+    memory_budget: Option<(Arc<tokio::sync::Semaphore>, u32)>,
+    body_read_retries: u32,
+    recorder: Option<Arc<Recorder>>,
+    min_tls_version: Option<reqwest::tls::Version>,
+    dns_resolver: Option<Arc<dyn reqwest::dns::Resolve>>,
+    // Sent with every request unless the request sets the same header
+    default_headers: reqwest::header::HeaderMap,
+    // Lowercased hosts never routed through I2P; a leading dot matches subdomains too
+    i2p_exclusions: Vec<String>,
+    header_limits: Option<HeaderLimits>,
+    restart_router_on_network_change: bool,
+    request_signer: Option<RequestSigner>,
+    strip_expect: bool,
+    body_transformer: Option<BodyTransformer>,
+    log_attempts: bool,
+}
 
-        // Create a SelectedProxy from the provided proxy
-        let selected_proxy = SelectedProxy {
-            proxy: proxy.clone(),
-            speed_bytes_per_sec: 1024.0 * 50.0, // Default speed assumption
-            selected_at: std::time::Instant::now(),
+/// Hands a shared resolver to client builders, which want a concrete resolver type
+struct SharedResolver(Arc<dyn reqwest::dns::Resolve>);
+
+impl reqwest::dns::Resolve for SharedResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        self.0.resolve(name)
+    }
+}
+
+/// Counters describing the requests a handler has made so far
+#[derive(Debug, Clone, Default)]
+pub struct HandlerMetrics {
+    /// Requests to I2P sites that got as far as sending
+    pub i2p_requests: u64,
+    /// Time spent making sure the router was up (starting it and building tunnels)
+    pub router_ready_total: Duration,
+    /// Time from sending I2P requests until their response headers arrived
+    pub i2p_round_trip_total: Duration,
+    pub last_router_ready: Option<Duration>,
+    pub last_i2p_round_trip: Option<Duration>,
+    /// Clearnet proxy clients built from scratch, each with its own connection pool
+    pub clients_created: u64,
+    /// Clearnet requests served by a cached client, so they could reuse a pooled connection
+    pub clients_reused: u64,
+}
+
+/// Which requests get the HTTP/1.0-style downgrade
+#[derive(Debug, Clone, PartialEq)]
+enum Http10Policy {
+    Off,
+    All,
+    Hosts(Vec<String>),
+}
+
+/// A cacheable request in flight: where its response goes in the cache and what to
+/// answer with if the server says 304
+struct CacheLookup {
+    key: String,
+    cached: Option<CachedResponse>,
+    error_on_status: bool,
+}
+
+/// Collects `RequestHandler` options and checks the fallible ones (local address, root
+/// certificates, default headers) once in `build`. `RequestHandler::new` is the same handler
+/// with defaults.
+pub struct RequestHandlerBuilder {
+    handler: RequestHandler,
+    local_address: Option<IpAddr>,
+    root_certificates_pem: Vec<Vec<u8>>,
+    default_accept: Option<String>,
+    default_accept_language: Option<String>,
+}
+
+impl RequestHandlerBuilder {
+    /// See `RequestHandler::with_router`
+    pub fn router(mut self, router: Arc<dyn Router>) -> Self {
+        self.handler = self.handler.with_router(router);
+        self
+    }
+
+    /// See `RequestHandler::with_timeouts`
+    pub fn timeouts(mut self, timeouts: TimeoutConfig) -> Self {
+        self.handler = self.handler.with_timeouts(timeouts);
+        self
+    }
+
+    /// See `RequestHandler::with_response_cache`
+    pub fn response_cache(mut self, capacity: usize) -> Self {
+        self.handler = self.handler.with_response_cache(capacity);
+        self
+    }
+
+    /// See `RequestHandler::with_client_cache`
+    pub fn client_cache(mut self) -> Self {
+        self.handler = self.handler.with_client_cache();
+        self
+    }
+
+    /// See `RequestHandler::with_rate_limit`
+    pub fn rate_limit(mut self, per_proxy_rps: f64) -> Self {
+        self.handler = self.handler.with_rate_limit(per_proxy_rps);
+        self
+    }
+
+    /// See `RequestHandler::with_rate_limit_policy`
+    pub fn rate_limit_policy(mut self, policy: RateLimitPolicy) -> Self {
+        self.handler = self.handler.with_rate_limit_policy(policy);
+        self
+    }
+
+    /// See `RequestHandler::with_min_tls_version`
+    pub fn min_tls_version(mut self, version: reqwest::tls::Version) -> Self {
+        self.handler = self.handler.with_min_tls_version(version);
+        self
+    }
+
+    /// See `RequestHandler::with_dns_resolver`
+    pub fn dns_resolver(mut self, resolver: Arc<dyn reqwest::dns::Resolve>) -> Self {
+        self.handler = self.handler.with_dns_resolver(resolver);
+        self
+    }
+
+    /// See `RequestHandler::with_challenge_detection`
+    pub fn challenge_detection(mut self, detector: Arc<dyn ChallengeDetector>) -> Self {
+        self.handler = self.handler.with_challenge_detection(detector);
+        self
+    }
+
+    /// See `RequestHandler::with_local_address`; checked in `build`
+    pub fn local_address(mut self, addr: IpAddr) -> Self {
+        self.local_address = Some(addr);
+        self
+    }
+
+    /// See `RequestHandler::add_root_certificate`
+    pub fn root_certificate(mut self, cert: reqwest::Certificate) -> Self {
+        self.handler.add_root_certificate(cert);
+        self
+    }
+
+    /// See `RequestHandler::add_root_certificate_pem`; parsed in `build`
+    pub fn root_certificate_pem(mut self, pem: &[u8]) -> Self {
+        self.root_certificates_pem.push(pem.to_vec());
+        self
+    }
+
+    /// See `RequestHandler::with_default_accept`; checked in `build`
+    pub fn default_accept(mut self, value: &str) -> Self {
+        self.default_accept = Some(value.to_string());
+        self
+    }
+
+    /// See `RequestHandler::with_default_accept_language`; checked in `build`
+    pub fn default_accept_language(mut self, value: &str) -> Self {
+        self.default_accept_language = Some(value.to_string());
+        self
+    }
+
+    /// See `RequestHandler::with_recorder`
+    pub fn recorder(mut self, capacity: usize) -> Self {
+        self.handler = self.handler.with_recorder(capacity);
+        self
+    }
+
+    /// See `RequestHandler::with_direct_fallback`
+    pub fn direct_fallback(mut self, allow_direct_fallback: bool) -> Self {
+        self.handler = self.handler.with_direct_fallback(allow_direct_fallback);
+        self
+    }
+
+    /// See `RequestHandler::with_body_read_retries`
+    pub fn body_read_retries(mut self, retries: u32) -> Self {
+        self.handler = self.handler.with_body_read_retries(retries);
+        self
+    }
+
+    /// See `RequestHandler::with_memory_budget`
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.handler = self.handler.with_memory_budget(bytes);
+        self
+    }
+
+    /// See `RequestHandler::with_http10_downgrade`
+    pub fn http10_downgrade(mut self) -> Self {
+        self.handler = self.handler.with_http10_downgrade();
+        self
+    }
+
+    /// See `RequestHandler::with_http10_hosts`
+    pub fn http10_hosts(mut self, hosts: Vec<String>) -> Self {
+        self.handler = self.handler.with_http10_hosts(hosts);
+        self
+    }
+
+    /// See `RequestHandler::with_i2p_exclusions`
+    pub fn i2p_exclusions(mut self, hosts: Vec<String>) -> Self {
+        self.handler = self.handler.with_i2p_exclusions(hosts);
+        self
+    }
+
+    /// See `RequestHandler::with_header_limits`
+    pub fn header_limits(mut self, max_count: usize, max_bytes: usize, policy: HeaderLimitPolicy) -> Self {
+        self.handler = self.handler.with_header_limits(max_count, max_bytes, policy);
+        self
+    }
+
+    /// See `RequestHandler::with_request_signer`
+    pub fn request_signer(mut self, signer: RequestSigner) -> Self {
+        self.handler = self.handler.with_request_signer(signer);
+        self
+    }
+
+    /// See `RequestHandler::with_strip_expect`
+    pub fn strip_expect(mut self, strip: bool) -> Self {
+        self.handler = self.handler.with_strip_expect(strip);
+        self
+    }
+
+    /// See `RequestHandler::with_body_transformer`
+    pub fn body_transformer(mut self, transformer: BodyTransformer) -> Self {
+        self.handler = self.handler.with_body_transformer(transformer);
+        self
+    }
+
+    /// See `RequestHandler::with_attempt_log`
+    pub fn attempt_log(mut self, log_attempts: bool) -> Self {
+        self.handler = self.handler.with_attempt_log(log_attempts);
+        self
+    }
+
+    /// See `RequestHandler::with_router_restart_on_network_change`
+    pub fn router_restart_on_network_change(mut self, restart: bool) -> Self {
+        self.handler = self.handler.with_router_restart_on_network_change(restart);
+        self
+    }
+
+    pub fn build(self) -> Result<RequestHandler, String> {
+        let mut handler = self.handler;
+        for pem in &self.root_certificates_pem {
+            handler.add_root_certificate_pem(pem)?;
+        }
+        if let Some(value) = &self.default_accept {
+            handler = handler.with_default_accept(value)?;
+        }
+        if let Some(value) = &self.default_accept_language {
+            handler = handler.with_default_accept_language(value)?;
+        }
+        match self.local_address {
+            Some(addr) => handler.with_local_address(addr),
+            None => Ok(handler),
+        }
+    }
+}
+
+impl RequestHandler {
+    pub fn builder(proxy_selector: Arc<ProxySelector>) -> RequestHandlerBuilder {
+        RequestHandlerBuilder {
+            handler: Self::new(proxy_selector),
+            local_address: None,
+            root_certificates_pem: Vec::new(),
+            default_accept: None,
+            default_accept_language: None,
+        }
+    }
+
+    pub fn new(proxy_selector: Arc<ProxySelector>) -> Self {
+        info!("Initializing RequestHandler");
+        Self {
+            proxy_selector,
+            root_certificates: Vec::new(),
+            router: Arc::new(GlobalRouter),
+            response_cache: None,
+            metrics: Arc::new(parking_lot::Mutex::new(HandlerMetrics::default())),
+            local_address: None,
+            rate_limiter: None,
+            rate_limit_policy: RateLimitPolicy::Wait,
+            timeouts: TimeoutConfig::default(),
+            client_cache: None,
+            challenge_detector: None,
+            http10: Http10Policy::Off,
+            allow_direct_fallback: false,
+            memory_budget: None,
+            body_read_retries: 0,
+            recorder: None,
+            min_tls_version: None,
+            dns_resolver: None,
+            default_headers: reqwest::header::HeaderMap::new(),
+            i2p_exclusions: Vec::new(),
+            header_limits: None,
+            restart_router_on_network_change: false,
+            request_signer: None,
+            strip_expect: false,
+            body_transformer: None,
+            log_attempts: false,
+        }
+    }
+
+    /// Snapshot of the handler's metrics
+    pub fn metrics(&self) -> HandlerMetrics {
+        self.metrics.lock().clone()
+    }
+
+    /// Cache up to `capacity` GET responses carrying an ETag or Last-Modified, and
+    /// revalidate them with conditional requests instead of downloading them again
+    pub fn with_response_cache(mut self, capacity: usize) -> Self {
+        self.response_cache = Some(Arc::new(ResponseCache::new(capacity)));
+        self
+    }
+
+    pub fn response_cache(&self) -> Option<&ResponseCache> {
+        self.response_cache.as_deref()
+    }
+
+    /// Resolve hostnames with `resolver` instead of the system resolver for clearnet
+    /// connections (clearnet proxies, and targets of direct requests), for hosts whose system
+    /// DNS is broken or censored. Requests through the I2P router are not affected.
+    pub fn with_dns_resolver(mut self, resolver: Arc<dyn reqwest::dns::Resolve>) -> Self {
+        self.dns_resolver = Some(resolver);
+        self
+    }
+
+    /// Refuse HTTPS connections that negotiate a TLS version below `version`, e.g.
+    /// `reqwest::tls::Version::TLS_1_2` to rule out downgrades to TLS 1.0/1.1. Unset by
+    /// default, which leaves the TLS backend's own minimum in place.
+    pub fn with_min_tls_version(mut self, version: reqwest::tls::Version) -> Self {
+        self.min_tls_version = Some(version);
+        self
+    }
+
+    pub fn min_tls_version(&self) -> Option<reqwest::tls::Version> {
+        self.min_tls_version
+    }
+
+    /// Send `Accept: value` with every request that doesn't set its own `Accept` header,
+    /// e.g. a browser's value so eepsites serve what they serve browsers. Fails if `value`
+    /// is not a valid header value.
+    pub fn with_default_accept(self, value: &str) -> Result<Self, String> {
+        self.with_default_header(reqwest::header::ACCEPT, value)
+    }
+
+    /// Send `Accept-Language: value` with every request that doesn't set its own
+    /// `Accept-Language` header. Fails if `value` is not a valid header value.
+    pub fn with_default_accept_language(self, value: &str) -> Result<Self, String> {
+        self.with_default_header(reqwest::header::ACCEPT_LANGUAGE, value)
+    }
+
+    fn with_default_header(mut self, name: reqwest::header::HeaderName, value: &str) -> Result<Self, String> {
+        let value = reqwest::header::HeaderValue::from_str(value)
+            .map_err(|e| format!("Invalid {} value {:?}: {}", name, value, e))?;
+        self.default_headers.insert(name, value);
+        Ok(self)
+    }
+
+    /// Record the last `capacity` requests made with `handle_request` and
+    /// `handle_request_with_specific_proxy`: method, URL, headers, status, timing, proxy
+    /// used and body sizes. Credentials in URLs and auth/cookie headers are redacted.
+    pub fn with_recorder(mut self, capacity: usize) -> Self {
+        self.recorder = Some(Arc::new(Recorder::new(capacity)));
+        self
+    }
+
+    pub fn recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_deref()
+    }
+
+    /// The recorded requests as a JSON array, oldest first, or `None` without a recorder
+    pub fn export_recording(&self) -> Option<String> {
+        self.recorder.as_ref().map(|recorder| recorder.export_json())
+    }
+
+    /// Decide whether `config` goes through the cache and, if a copy is stored, make it a
+    /// conditional request. Requests already carrying their own conditions or opting out
+    /// with `Cache-Control: no-store` are left alone.
+    fn prepare_cache_lookup(&self, config: &mut RequestConfig) -> Option<CacheLookup> {
+        let cache = self.response_cache.as_ref()?;
+        if config.method != "GET" || config.stream || config.body.is_some() {
+            return None;
+        }
+        let headers = config.headers.get_or_insert_with(std::collections::HashMap::new);
+        let bypass = headers.iter().any(|(key, value)| {
+            key.eq_ignore_ascii_case("if-none-match")
+                || key.eq_ignore_ascii_case("if-modified-since")
+                || (key.eq_ignore_ascii_case("cache-control") && value.to_lowercase().contains("no-store"))
+        });
+        if bypass {
+            return None;
+        }
+
+        let key = ResponseCache::key(&config.method, &config.url);
+        let cached = cache.get(&key);
+        if let Some(cached) = &cached {
+            debug!("Revalidating cached response for {}", config.url);
+            if let Some(etag) = &cached.etag {
+                headers.insert("If-None-Match".to_string(), etag.clone());
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                headers.insert("If-Modified-Since".to_string(), last_modified.clone());
+            }
+        }
+
+        // A 304 is the expected answer now, so status errors are raised after the cache
+        let error_on_status = std::mem::replace(&mut config.error_on_status, false);
+        Some(CacheLookup { key, cached, error_on_status })
+    }
+
+    /// Serve a 304 from the cache, store or drop the fresh response, then apply
+    /// `error_on_status` as the uncached path would have
+    fn complete_cache_lookup(
+        &self,
+        lookup: Option<CacheLookup>,
+        result: Result<ResponseData, RequestError>,
+    ) -> Result<ResponseData, RequestError> {
+        let (Some(lookup), Some(cache)) = (lookup, self.response_cache.as_ref()) else {
+            return result;
         };
+        let response = result?;
 
-        // Create client from this specific proxy with optional router port hint
-        let (client, proxy_used) = match self.create_client_from_proxy(&selected_proxy, router_port_hint).await {
-            Ok(result) => result,
-            Err(e) => {
-                error!("Failed to create client for specific proxy {}: {}", proxy.url, e);
-                return Err(format!("Failed to create client: {}", e));
+        if response.status == 304 {
+            if let Some(cached) = lookup.cached {
+                info!("Not modified, serving {} from cache", lookup.key);
+                return Ok(ResponseData {
+                    proxy_used: response.proxy_used,
+                    proxy_info: response.proxy_info,
+                    direct: response.direct,
+                    attempts: response.attempts,
+                    ..cached.response
+                });
             }
+        }
+
+        match CachedResponse::from_response(&response) {
+            Some(entry) => cache.insert(&lookup.key, entry),
+            None if CachedResponse::forbids_store(&response) || response.status == 200 => cache.remove(&lookup.key),
+            None => {}
+        }
+
+        if lookup.error_on_status && !(200..300).contains(&response.status) {
+            return Err(RequestError::HttpStatus { code: response.status, body: response.body });
+        }
+        Ok(response)
+    }
+
+    /// Route I2P traffic through `router` instead of the process-wide embedded router. Proxy
+    /// tests go through the selector's router, set with `ProxySelector::with_router`.
+    pub fn with_router(mut self, router: Arc<dyn Router>) -> Self {
+        self.router = router;
+        self
+    }
+
+    /// Originate clearnet proxy connections from `addr` (for multi-homed hosts). I2P traffic
+    /// to the local router is not affected. Fails if the address cannot be bound locally.
+    pub fn with_local_address(mut self, addr: IpAddr) -> Result<Self, String> {
+        // Best-effort check: binding an ephemeral UDP port succeeds only for local addresses
+        UdpSocket::bind((addr, 0))
+            .map_err(|e| format!("Local address {} is not assignable: {}", addr, e))?;
+        info!("Binding clearnet proxy connections to {}", addr);
+        self.local_address = Some(addr);
+        Ok(self)
+    }
+
+    /// Default timeouts for the clients this handler builds. `RequestConfig::timeout_secs`
+    /// still wins for a single request.
+    pub fn with_timeouts(mut self, timeouts: TimeoutConfig) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Fail responses that `detector` recognizes as anti-bot challenge pages with
+    /// `RequestError::ChallengeEncountered`, and try the proxy that got one last for that host
+    pub fn with_challenge_detection(mut self, detector: Arc<dyn ChallengeDetector>) -> Self {
+        self.challenge_detector = Some(detector);
+        self
+    }
+
+    /// Pass `response` on unless it is a challenge page, in which case `proxy` is remembered
+    /// as challenged for the URL's host
+    fn check_challenge(&self, url: &str, proxy: Option<&Proxy>, response: ResponseData) -> Result<ResponseData, RequestError> {
+        let Some(detector) = &self.challenge_detector else {
+            return Ok(response);
+        };
+        let Some(reason) = detector.detect(&response) else {
+            return Ok(response);
         };
 
-        // Build request
-        let mut request = match config.method.as_str() {
-            "GET" => client.get(&config.url),
-            "POST" => client.post(&config.url),
-            "PUT" => client.put(&config.url),
-            "DELETE" => client.delete(&config.url),
-            "PATCH" => client.patch(&config.url),
-            "HEAD" => client.head(&config.url),
-            _ => {
-                return Err(format!("Unsupported HTTP method: {}", config.method));
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|parsed| parsed.host_str().map(str::to_string))
+            .unwrap_or_else(|| url.to_string());
+        warn!("Challenge page from {} through {}: {}", host, response.proxy_used, reason);
+        if let Some(proxy) = proxy {
+            self.proxy_selector.record_challenge(&host, proxy);
+        }
+        Err(RequestError::ChallengeEncountered {
+            proxy_used: response.proxy_used,
+            host,
+            reason,
+        })
+    }
+
+    /// When no proxy is available for a clearnet request, send it directly instead of failing
+    /// (off by default). Such responses have `ResponseData::direct` set and `proxy_used` "direct".
+    /// I2P sites and `force_outproxy` requests never go direct.
+    pub fn with_direct_fallback(mut self, allow_direct_fallback: bool) -> Self {
+        self.allow_direct_fallback = allow_direct_fallback;
+        self
+    }
+
+    /// Send `config` straight to the target, bypassing every proxy
+    async fn send_direct(&self, config: &RequestConfig) -> Result<ResponseData, RequestError> {
+        warn!("No proxy available, sending {} {} DIRECTLY without a proxy", config.method, config.url);
+        let client = self.direct_client()?;
+        let request = self.build_request(&client, config)?;
+        let response = Self::send_within_response_timeout(request, config, "direct")
+            .await?
+            .map_err(|e| format!("Direct request failed: {}", e))?;
+
+        let mut data = self.read_response_data(response, config, "direct".to_string(), None).await?;
+        data.direct = true;
+        Ok(data)
+    }
+
+    fn direct_client(&self) -> Result<Client, String> {
+        self.clearnet_client_builder()
+            .no_proxy()
+            .timeout(self.timeouts.clearnet_request)
+            .build()
+            .map_err(|e| format!("Failed to create direct client: {}", e))
+    }
+
+    /// When the body of a GET breaks off mid-transfer, request it again through the same
+    /// proxy up to `retries` times instead of failing (off by default). The retry resumes
+    /// with a Range request if the server advertised `Accept-Ranges: bytes`, otherwise it
+    /// starts over. Streaming requests are not retried.
+    pub fn with_body_read_retries(mut self, retries: u32) -> Self {
+        self.body_read_retries = retries;
+        self
+    }
+
+    /// Request the body of `config` again through the proxy that delivered the broken one,
+    /// resuming after `received` bytes if `resumable`. Returns the response and whether it
+    /// continues the received bytes (a 206 for the right range) or starts over.
+    async fn restart_body(
+        &self,
+        config: &RequestConfig,
+        proxy_used: &str,
+        proxy: Option<&Proxy>,
+        received: usize,
+        resumable: bool,
+    ) -> Result<(reqwest::Response, bool), String> {
+        let client = if proxy_used == "direct" {
+            self.direct_client()?
+        } else if let Some(proxy) = proxy {
+            let selected = SelectedProxy {
+                proxy: proxy.clone(),
+                speed_bytes_per_sec: 0.0,
+                selected_at: Instant::now(),
+            };
+            if !self.admit(proxy).await {
+                return Err(format!("proxy {} is rate limited", proxy.url));
             }
+            self.create_client_from_proxy(&selected, None).await?.0
+        } else if self.routes_via_i2p(&config.url) {
+            let is_https = config.url.starts_with("https://");
+            self.i2p_client(is_https, self.router.http_proxy_port(), self.router.https_proxy_port())?.0
+        } else {
+            return Err(format!("no way to reach {} again through {}", config.url, proxy_used));
         };
 
-        // Add headers
-        if let Some(headers) = &config.headers {
-            for (key, value) in headers {
-                request = request.header(key, value);
+        let mut request = self.build_request(&client, config)?;
+        let resume = resumable && received > 0;
+        if resume {
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", received));
+        }
+        let response = request.send().await.map_err(|e| format!("retry request failed: {}", e))?;
+
+        let status = response.status().as_u16();
+        if resume && status == 206 {
+            let expected = format!("bytes {}-", received);
+            let continues = response
+                .headers()
+                .get(reqwest::header::CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|range| range.starts_with(&expected));
+            if !continues {
+                return Err("retry returned a different range than requested".to_string());
             }
+            return Ok((response, true));
+        }
+        if !(200..300).contains(&status) || status == 206 {
+            return Err(format!("retry returned status {}", status));
         }
+        Ok((response, false))
+    }
+
+    /// Let bodies being buffered in memory take up at most `bytes` together (capped at 4 GiB).
+    /// Before buffering, a response reserves its `Content-Length` and waits while the budget
+    /// is used up; one larger than the whole budget fails right away. A response without a
+    /// length reserves its body chunk by chunk as it arrives, and fails once a chunk would not
+    /// fit in what is left of the budget. Streaming responses are not buffered and not counted.
+    pub fn with_memory_budget(mut self, bytes: usize) -> Self {
+        let total = bytes.clamp(1, u32::MAX as usize) as u32;
+        info!("Limiting buffered response bodies to {} bytes in total", total);
+        self.memory_budget = Some((Arc::new(tokio::sync::Semaphore::new(total as usize)), total));
+        self
+    }
+
+    /// Reserve memory for buffering a body of `content_length` bytes, waiting for the budget.
+    /// Without a length nothing is reserved yet; the body grows the reservation as it arrives.
+    async fn reserve_body_memory(
+        &self,
+        content_length: Option<u64>,
+        url: &str,
+    ) -> Result<BodyReservation, RequestError> {
+        let mut reservation = BodyReservation { budget: self.memory_budget.clone(), held: 0 };
+        let (Some((budget, total)), Some(length)) = (&self.memory_budget, content_length) else {
+            return Ok(reservation);
+        };
+        if length > *total as u64 {
+            return Err(format!(
+                "Response from {} is {} bytes, more than the memory budget of {} bytes",
+                url, length, total
+            )
+            .into());
+        }
+
+        if budget.available_permits() < length as usize {
+            debug!("Memory budget exhausted, waiting to buffer {} bytes from {}", length, url);
+        }
+        budget
+            .acquire_many(length as u32)
+            .await
+            .map_err(|e| format!("Memory budget unavailable: {}", e))?
+            .forget();
+        reservation.held = length as u32;
+        Ok(reservation)
+    }
+
+    /// Send every request HTTP/1.0-style, as `RequestConfig::http10` does for one request
+    pub fn with_http10_downgrade(mut self) -> Self {
+        self.http10 = Http10Policy::All;
+        self
+    }
+
+    /// Send requests to `hosts` (e.g. legacy eepsites) HTTP/1.0-style
+    pub fn with_http10_hosts(mut self, hosts: Vec<String>) -> Self {
+        self.http10 = Http10Policy::Hosts(hosts.into_iter().map(|host| host.to_lowercase()).collect());
+        self
+    }
+
+    fn apply_http10_policy(&self, config: &mut RequestConfig) {
+        config.http10 |= match &self.http10 {
+            Http10Policy::Off => false,
+            Http10Policy::All => true,
+            Http10Policy::Hosts(hosts) => Url::parse(&config.url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_lowercase))
+                .is_some_and(|host| hosts.contains(&host)),
+        };
+    }
+
+    /// Keep one client per clearnet proxy so later requests through it can reuse pooled
+    /// connections. `HandlerMetrics::clients_reused` counts the requests that did.
+    pub fn with_client_cache(mut self) -> Self {
+        self.client_cache = Some(Arc::new(parking_lot::Mutex::new(std::collections::HashMap::new())));
+        self
+    }
+
+    /// Send at most `per_proxy_rps` requests per second through each proxy, to go easy on
+    /// volunteer-run outproxies. I2P sites reached directly through the router are not limited.
+    pub fn with_rate_limit(mut self, per_proxy_rps: f64) -> Self {
+        info!("Limiting requests to {} per second per proxy", per_proxy_rps);
+        self.rate_limiter = Some(Arc::new(RateLimiter::new(per_proxy_rps)));
+        self
+    }
+
+    /// Copy at most `max_count` header values, and at most `max_bytes` of header names and
+    /// values, into `ResponseData::headers`, so a hostile proxy can't make a response's
+    /// headers take up unbounded memory. `policy` decides whether the excess is dropped or
+    /// fails the request. Unlimited by default.
+    pub fn with_header_limits(mut self, max_count: usize, max_bytes: usize, policy: HeaderLimitPolicy) -> Self {
+        self.header_limits = Some(HeaderLimits { max_count, max_bytes, policy });
+        self
+    }
+
+    /// Call `signer` with the method, URL and body of every outgoing request and attach the
+    /// headers it returns, replacing headers of the same name. Signing happens where the
+    /// request is built, so the signed body is always the one sent.
+    pub fn with_request_signer(mut self, signer: RequestSigner) -> Self {
+        self.request_signer = Some(signer);
+        self
+    }
+
+    /// Drop any `Expect` header (e.g. `Expect: 100-continue`) from outgoing requests, for
+    /// outproxies that never answer it and leave large uploads hanging. Off by default.
+    pub fn with_strip_expect(mut self, strip: bool) -> Self {
+        self.strip_expect = strip;
+        self
+    }
+
+    /// Pass every successfully read response body through `transformer` along with its
+    /// `Content-Type` ("" if there is none) and return what it makes of it. Streamed
+    /// responses and HTTP error bodies are left alone.
+    pub fn with_body_transformer(mut self, transformer: BodyTransformer) -> Self {
+        self.body_transformer = Some(transformer);
+        self
+    }
+
+    /// List the proxies each request went through, failed ones included, in
+    /// `ResponseData::attempts`. Off by default.
+    pub fn with_attempt_log(mut self, log_attempts: bool) -> Self {
+        self.log_attempts = log_attempts;
+        self
+    }
+
+    /// Attempt log for a request that got through `succeeded` after `failures`, empty
+    /// unless attempts are logged
+    fn attempt_log(&self, failures: &[(String, String)], succeeded: &str) -> Vec<AttemptInfo> {
+        if !self.log_attempts {
+            return Vec::new();
+        }
+        failures
+            .iter()
+            .map(|(proxy, error)| AttemptInfo { proxy: proxy.clone(), succeeded: false, error: Some(error.clone()) })
+            .chain(std::iter::once(AttemptInfo { proxy: succeeded.to_string(), succeeded: true, error: None }))
+            .collect()
+    }
+
+    /// Headers of a response through `proxy_used`, within the configured limits
+    fn response_headers(
+        &self,
+        headers: &reqwest::header::HeaderMap,
+        proxy_used: &str,
+    ) -> Result<std::collections::HashMap<String, Vec<String>>, RequestError> {
+        let limits = match self.header_limits {
+            Some(limits) => limits,
+            None => return Ok(extract_headers(headers)),
+        };
+        match extract_headers_within(headers, limits.max_count, limits.max_bytes) {
+            (extracted, None) => Ok(extracted),
+            (extracted, Some((count, bytes))) => match limits.policy {
+                HeaderLimitPolicy::Truncate => {
+                    warn!(
+                        "Dropping response headers through {} over the limit of {} headers / {} bytes",
+                        proxy_used, limits.max_count, limits.max_bytes
+                    );
+                    Ok(extracted)
+                }
+                HeaderLimitPolicy::Reject => Err(RequestError::HeadersTooLarge {
+                    proxy_used: proxy_used.to_string(),
+                    count,
+                    bytes,
+                }),
+            },
+        }
+    }
+
+    /// Whether a rate-limited proxy is waited for (the default) or skipped for the next candidate
+    pub fn with_rate_limit_policy(mut self, policy: RateLimitPolicy) -> Self {
+        self.rate_limit_policy = policy;
+        self
+    }
+
+    /// Apply the rate limit before sending through `proxy`. False when the proxy is over its
+    /// limit and the policy says to skip it.
+    async fn admit(&self, proxy: &Proxy) -> bool {
+        let Some(limiter) = &self.rate_limiter else {
+            return true;
+        };
+        match self.rate_limit_policy {
+            RateLimitPolicy::Wait => {
+                limiter.acquire(&proxy.url).await;
+                true
+            }
+            RateLimitPolicy::Skip => match limiter.try_acquire(&proxy.url) {
+                Ok(()) => true,
+                Err(wait) => {
+                    debug!("Proxy {} is rate limited for another {:?}, skipping it", proxy.url, wait);
+                    false
+                }
+            },
+        }
+    }
+
+    /// Local address clearnet proxy connections are bound to, if any
+    pub fn local_address(&self) -> Option<IpAddr> {
+        self.local_address
+    }
+
+    /// Trust an additional root CA (e.g. a private CA in front of clearnet proxies or targets)
+    /// for every client this handler builds. Safer than accepting invalid certificates.
+    pub fn add_root_certificate(&mut self, cert: reqwest::Certificate) {
+        info!("Adding custom root certificate");
+        self.root_certificates.push(cert);
+    }
+
+    /// Trust an additional root CA given in PEM format
+    pub fn add_root_certificate_pem(&mut self, pem: &[u8]) -> Result<(), String> {
+        let cert = reqwest::Certificate::from_pem(pem)
+            .map_err(|e| format!("Invalid PEM certificate: {}", e))?;
+        Self::check_root_certificate(&cert)?;
+        self.add_root_certificate(cert);
+        Ok(())
+    }
+
+    /// Trust an additional root CA given in DER format
+    pub fn add_root_certificate_der(&mut self, der: &[u8]) -> Result<(), String> {
+        let cert = reqwest::Certificate::from_der(der)
+            .map_err(|e| format!("Invalid DER certificate: {}", e))?;
+        Self::check_root_certificate(&cert)?;
+        self.add_root_certificate(cert);
+        Ok(())
+    }
+
+    // Certificates are only fully parsed when a client is built, so surface errors now
+    // instead of on the first request
+    fn check_root_certificate(cert: &reqwest::Certificate) -> Result<(), String> {
+        Client::builder()
+            .add_root_certificate(cert.clone())
+            .build()
+            .map(|_| ())
+            .map_err(|e| format!("Failed to use root certificate: {}", e))
+    }
+
+    /// Client builder with the handler-wide settings applied
+    fn client_builder(&self) -> reqwest::ClientBuilder {
+        let mut builder = Client::builder();
+        for cert in &self.root_certificates {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+        if let Some(version) = self.min_tls_version {
+            builder = builder.min_tls_version(version);
+        }
+        if !self.default_headers.is_empty() {
+            builder = builder.default_headers(self.default_headers.clone());
+        }
+        builder
+    }
+
+    /// Client builder for clearnet proxies, bound to the configured local address and
+    /// resolving names with the configured resolver
+    fn clearnet_client_builder(&self) -> reqwest::ClientBuilder {
+        let builder = self.client_builder().local_address(self.local_address);
+        match &self.dns_resolver {
+            Some(resolver) => builder.dns_resolver(Arc::new(SharedResolver(resolver.clone()))),
+            None => builder,
+        }
+    }
+
+    /// Tell the handler the network changed (sleep/wake, VPN toggled, new Wi-Fi), which
+    /// invalidates what it knows about proxies. Resets the selector (see
+    /// `ProxySelector::reset_for_network_change`) and drops cached clients with their pooled
+    /// connections, so the next request selects afresh. Restarts the router as well if
+    /// `with_router_restart_on_network_change` is set.
+    pub fn notify_network_changed(&self) -> Result<(), String> {
+        info!("Network change reported, resetting proxy state");
+        self.proxy_selector.reset_for_network_change();
+        if let Some(cache) = &self.client_cache {
+            cache.lock().clear();
+        }
+        if self.restart_router_on_network_change {
+            self.router.restart()?;
+        }
+        Ok(())
+    }
+
+    /// Also restart the router in `notify_network_changed` (off by default), so its tunnels
+    /// are rebuilt on the new network instead of timing out one by one
+    pub fn with_router_restart_on_network_change(mut self, restart: bool) -> Self {
+        self.restart_router_on_network_change = restart;
+        self
+    }
+
+    /// Treat `hosts` as clearnet even if they look like I2P sites, e.g. internal names that
+    /// end in `.i2p`: requests to them go through clearnet proxies (or direct) instead of the
+    /// router. `"wiki.corp.i2p"` matches that host only, `".corp.i2p"` also its subdomains.
+    pub fn with_i2p_exclusions(mut self, hosts: Vec<String>) -> Self {
+        self.i2p_exclusions = hosts.into_iter().map(|host| host.to_lowercase()).collect();
+        self
+    }
+
+    /// Whether requests to `url` go through the I2P router: an I2P domain that is not excluded
+    pub fn routes_via_i2p(&self, url: &str) -> bool {
+        if !Self::is_i2p_domain(url) {
+            return false;
+        }
+        let host = match Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_lowercase)) {
+            Some(host) => host,
+            None => return true,
+        };
+        let excluded = self.i2p_exclusions.iter().any(|exclusion| match exclusion.strip_prefix('.') {
+            Some(domain) => host == domain || host.ends_with(exclusion.as_str()),
+            None => host == *exclusion,
+        });
+        if excluded {
+            debug!("{} is excluded from I2P routing", host);
+        }
+        !excluded
+    }
+
+    /// Check if a URL points to an I2P domain (.i2p or .b32.i2p)
+    pub fn is_i2p_domain(url: &str) -> bool {
+        match Url::parse(url) {
+            Ok(parsed_url) => {
+                if let Some(host) = parsed_url.host_str() {
+                    host.ends_with(".i2p") || host.ends_with(".b32.i2p")
+                } else {
+                    false
+                }
+            }
+            Err(_) => {
+                // Fallback: simple string check if URL parsing fails
+                url.contains(".i2p") || url.contains(".b32.i2p")
+            }
+        }
+    }
+
+    /// Check if an error is a proxy connection error (unreachable, timeout, etc.)
+    fn is_proxy_connection_error(error: &str) -> bool {
+        let error_lower = error.to_lowercase();
+        error_lower.contains("unreachable") 
+            || error_lower.contains("connection refused")
+            || error_lower.contains("connection reset")
+            || error_lower.contains("connection timed out")
+            || error_lower.contains("timeout")
+            || error_lower.contains("socks connect error")
+            || error_lower.contains("proxy server unreachable")
+    }
+
+    /// Whether sending failed in the SOCKS handshake rather than after connecting, e.g.
+    /// because the listed SOCKS proxy only speaks HTTP
+    fn is_socks_handshake_error(error: &reqwest::Error) -> bool {
+        error.is_connect() && format_error_full(error).to_lowercase().contains("socks connect error")
+    }
+
+    /// Client using a listed SOCKS proxy as an HTTP proxy on the same port, labelled as a
+    /// fallback so a success corrects the proxy's type
+    fn socks_as_http_client(&self, proxy: &Proxy) -> Result<(Client, String), String> {
+        let http_url = ProxyType::Http.url_for(&proxy.host, proxy.port);
+        let client = reqwest::Proxy::all(&http_url)
+            .and_then(|p| {
+                self.clearnet_client_builder()
+                    .proxy(p)
+                    .timeout(self.timeouts.clearnet_request)
+                    .build()
+            })
+            .map_err(|e| format!("Failed to create HTTP fallback client for {}: {}", proxy.url, e))?;
+        Ok((client, format!("{}{}", http_url, SOCKS_FALLBACK_SUFFIX)))
+    }
+
+    /// Create a client from a proxy candidate with optional router port hint
+    async fn create_client_from_proxy(
+        &self,
+        selected_proxy: &SelectedProxy,
+        router_port_hint: Option<u16>,
+    ) -> Result<(Client, String), String> {
+        let is_i2p_outproxy = selected_proxy.proxy.is_i2p_proxy();
+        let cache_key = format!("{:?} {}", selected_proxy.proxy.proxy_type, selected_proxy.proxy.url);
+
+        if !is_i2p_outproxy {
+            if let Some(cache) = &self.client_cache {
+                if let Some(cached) = cache.lock().get(&cache_key).cloned() {
+                    debug!("Reusing cached client for {}", selected_proxy.proxy.url);
+                    self.metrics.lock().clients_reused += 1;
+                    return Ok(cached);
+                }
+            }
+        }
+        
+        let client = if is_i2p_outproxy {
+            // Ensure i2pd router is running for I2P outproxies
+            if let Err(e) = self.router.ensure_running() {
+                return Err(format!("Failed to ensure i2pd router is running: {}", e));
+            }
+            
+            // For I2P-based outproxies, connect to them through the router's HTTP/HTTPS proxy
+            // SOCKS5 cannot handle .b32.i2p addresses, so we skip SOCKS5 entirely
+            debug!("Connecting to I2P outproxy {} through router (HTTP/HTTPS only, no SOCKS5)", selected_proxy.proxy.url);
+            
+            let http_port = self.router.http_proxy_port();
+            let https_port = self.router.https_proxy_port();
+
+            // If router port hint is provided (for parallel downloads), use exactly that proxy
+            if let Some(port) = router_port_hint {
+                let kind = router_proxy_kind(port, self.router.as_ref())?;
+                let (client, proxy_used) = self.router_client(selected_proxy, kind, port)?;
+                info!("Using {} for I2P outproxy {} (parallel download)", proxy_used, selected_proxy.proxy.url);
+                return Ok((client, proxy_used));
+            }
+
+            // No router port hint: try HTTP proxy first, then HTTPS proxy
+            // HTTP proxy is better for streaming large files and can handle .b32.i2p addresses
+            match self.router_client(selected_proxy, RouterProxyKind::Http, http_port) {
+                Ok((client, proxy_used)) => {
+                    info!("Using {} for I2P outproxy {} (better for streaming)", proxy_used, selected_proxy.proxy.url);
+                    Ok((client, proxy_used))
+                }
+                Err(e) => {
+                    warn!("Router HTTP proxy not usable, falling back to HTTPS: {}", e);
+                    self.router_client(selected_proxy, RouterProxyKind::Https, https_port)
+                        .map(|(client, _)| {
+                            let proxy_used = format!(
+                                "router-https://127.0.0.1:{} (for {}, fallback from HTTP)",
+                                https_port, selected_proxy.proxy.url
+                            );
+                            (client, proxy_used)
+                        })
+                        .map_err(|e| format!("{} (tried HTTP port {})", e, http_port))
+                }
+            }
+        } else {
+            // For non-I2P outproxies, use them directly based on type
+            match &selected_proxy.proxy.proxy_type {
+                crate::proxy_manager::ProxyType::Socks => {
+                    // Try SOCKS first, fallback to HTTPS if SOCKS fails
+                    let socks_url = ProxyType::Socks.url_for(&selected_proxy.proxy.host, selected_proxy.proxy.port);
+                    let https_url = ProxyType::Https.url_for(&selected_proxy.proxy.host, selected_proxy.proxy.port);
+                    
+                    // Try SOCKS first
+                    match reqwest::Proxy::all(&socks_url) {
+                        Ok(socks_proxy) => {
+                            match self.clearnet_client_builder()
+                                .proxy(socks_proxy)
+                                .timeout(self.timeouts.clearnet_request)
+                                .build()
+                            {
+                                Ok(client) => Ok((client, selected_proxy.proxy.url.clone())),
+                                Err(e) => {
+                                    warn!("SOCKS proxy {} failed to create client, falling back to HTTPS: {}", selected_proxy.proxy.url, e);
+                                    // Fallback to HTTPS
+                                    reqwest::Proxy::https(&https_url)
+                                        .map_err(|e| format!("Failed to create HTTPS fallback proxy for {}: {}", selected_proxy.proxy.url, e))
+                                        .and_then(|p| {
+                                            self.clearnet_client_builder()
+                                                .proxy(p)
+                                                .timeout(self.timeouts.clearnet_request)
+                                                .build()
+                                                .map_err(|e| format!("Failed to create HTTPS fallback client for {}: {}", selected_proxy.proxy.url, e))
+                                        })
+                                        .map(|client| (client, format!("{}{}", https_url, SOCKS_FALLBACK_SUFFIX)))
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            warn!("SOCKS proxy {} not available, falling back to HTTPS: {}", selected_proxy.proxy.url, e);
+                            // Fallback to HTTPS
+                            reqwest::Proxy::https(&https_url)
+                                .map_err(|e| format!("Failed to create HTTPS fallback proxy for {}: {}", selected_proxy.proxy.url, e))
+                                .and_then(|p| {
+                                    self.clearnet_client_builder()
+                                        .proxy(p)
+                                        .timeout(self.timeouts.clearnet_request)
+                                        .build()
+                                        .map_err(|e| format!("Failed to create HTTPS fallback client for {}: {}", selected_proxy.proxy.url, e))
+                                })
+                                .map(|client| (client, format!("{}{}", https_url, SOCKS_FALLBACK_SUFFIX)))
+                        }
+                    }
+                }
+                crate::proxy_manager::ProxyType::Https => {
+                    reqwest::Proxy::https(&selected_proxy.proxy.url)
+                        .map_err(|e| format!("Failed to create HTTPS proxy for {}: {}", selected_proxy.proxy.url, e))
+                        .and_then(|p| {
+                            self.clearnet_client_builder()
+                                .proxy(p)
+                                .timeout(self.timeouts.clearnet_request)
+                                .build()
+                                .map_err(|e| format!("Failed to create client for {}: {}", selected_proxy.proxy.url, e))
+                        })
+                        .map(|client| (client, selected_proxy.proxy.url.clone()))
+                }
+                crate::proxy_manager::ProxyType::Http => {
+                    reqwest::Proxy::http(&selected_proxy.proxy.url)
+                        .map_err(|e| format!("Failed to create HTTP proxy for {}: {}", selected_proxy.proxy.url, e))
+                        .and_then(|p| {
+                            self.clearnet_client_builder()
+                                .proxy(p)
+                                .timeout(self.timeouts.clearnet_request)
+                                .build()
+                                .map_err(|e| format!("Failed to create client for {}: {}", selected_proxy.proxy.url, e))
+                        })
+                        .map(|client| (client, selected_proxy.proxy.url.clone()))
+                }
+            }
+        };
+
+        if let (false, Ok(created)) = (is_i2p_outproxy, &client) {
+            self.metrics.lock().clients_created += 1;
+            if let Some(cache) = &self.client_cache {
+                let mut cache = cache.lock();
+                if cache.len() >= MAX_CACHED_CLIENTS {
+                    cache.clear();
+                }
+                cache.insert(cache_key, created.clone());
+            }
+        }
+
+        client
+    }
+
+    // Helper method to create client and send request (extracted for reuse)
+    pub async fn create_client_and_send_request(
+        &self,
+        config: &RequestConfig,
+        proxy_candidates: Vec<SelectedProxy>,
+    ) -> Result<(reqwest::Response, String, bool), RequestError> {
+        let (response, proxy_used, is_i2p, _) = self.send_logging_attempts(config, proxy_candidates).await?;
+        Ok((response, proxy_used, is_i2p))
+    }
+
+    /// `create_client_and_send_request`, also returning the attempt log
+    async fn send_logging_attempts(
+        &self,
+        config: &RequestConfig,
+        proxy_candidates: Vec<SelectedProxy>,
+    ) -> Result<(reqwest::Response, String, bool, Vec<AttemptInfo>), RequestError> {
+        // Check if this is an I2P domain
+        let is_i2p = self.routes_via_i2p(&config.url);
+
+        // Never let a mixed candidate list carry traffic across the network boundary
+        if let Some(mismatched) = self.network_mismatch(is_i2p, &proxy_candidates) {
+            error!("Refusing proxy {} for {}: wrong network", mismatched.proxy.url, config.url);
+            return Err(RequestError::ProxyNetworkMismatch {
+                url: config.url.clone(),
+                proxy: mismatched.proxy.url.clone(),
+            });
+        }
+        
+        // For I2P sites, use local I2P proxy (no retry needed)
+        if is_i2p {
+            info!("Detected I2P domain, using local I2P proxy");
+            
+            // Ensure i2pd router is running, timed separately so slow tunnel builds can be
+            // told apart from slow eepsites
+            let ready_start = Instant::now();
+            if let Err(e) = self.router.ensure_running() {
+                return Err(format!("Failed to ensure i2pd router is running: {}", e).into());
+            }
+            let router_ready = ready_start.elapsed();
+
+            let is_https = config.url.starts_with("https://");
+            let (client, proxy_url) =
+                self.i2p_client(is_https, self.router.http_proxy_port(), self.router.https_proxy_port())?;
+
+            // Build request
+            let request = self.build_request(&client, config)?;
+
+            debug!("Sending request through I2P proxy: {}", proxy_url);
+
+            // Send request
+            let send_start = Instant::now();
+            let response = Self::send_within_response_timeout(request, config, &proxy_url)
+                .await?
+                .map_err(|e| format!("Request failed through I2P proxy {}: {}", proxy_url, e))?;
+            let round_trip = send_start.elapsed();
+
+            debug!("I2P request timing: router ready in {:?}, response after {:?}", router_ready, round_trip);
+            {
+                let mut metrics = self.metrics.lock();
+                metrics.i2p_requests += 1;
+                metrics.router_ready_total += router_ready;
+                metrics.i2p_round_trip_total += round_trip;
+                metrics.last_router_ready = Some(router_ready);
+                metrics.last_i2p_round_trip = Some(round_trip);
+            }
+
+            let attempts = self.attempt_log(&[], &proxy_url);
+            return Ok((response, proxy_url, true, attempts));
+        }
+
+        let proxy_candidates = if config.force_outproxy {
+            let outproxies = Self::outproxy_candidates(proxy_candidates);
+            if outproxies.is_empty() {
+                error!("force_outproxy is set but no I2P outproxy candidates are available");
+                return Err("force_outproxy is set but no I2P outproxy candidates are available".to_string().into());
+            }
+            info!("Forcing clearnet request through {} I2P outproxy candidates", outproxies.len());
+            outproxies
+        } else {
+            proxy_candidates
+        };
+
+        let proxy_candidates = if config.no_failover && proxy_candidates.len() > 1 {
+            info!("Failover disabled for this request, only trying the first candidate");
+            proxy_candidates.into_iter().take(1).collect()
+        } else {
+            proxy_candidates
+        };
+
+        // For clearnet sites, try multiple proxy candidates with retry logic
+        info!("Clearnet site detected, trying {} proxy candidates", proxy_candidates.len());
+        
+        if proxy_candidates.is_empty() {
+            error!("No proxy candidates available for clearnet request");
+            return Err(RequestError::NoProxyCandidates {
+                reason: "No proxy candidates available for clearnet request".to_string(),
+            });
+        }
+
+        let mut attempts: Vec<(String, String)> = Vec::new();
+        let mut failed_proxies: Vec<&SelectedProxy> = Vec::new();
+
+        // Try each proxy candidate in order (fastest first)
+        for (idx, selected_proxy) in proxy_candidates.iter().enumerate() {
+            info!("Trying proxy {} of {}: {} ({:.2} KB/s)", 
+                  idx + 1, proxy_candidates.len(), 
+                  selected_proxy.proxy.url,
+                  selected_proxy.speed_bytes_per_sec / 1024.0);
+
+            if !self.admit(&selected_proxy.proxy).await {
+                attempts.push((selected_proxy.proxy.url.clone(), RATE_LIMITED.to_string()));
+                continue;
+            }
+
+            // Create client from this proxy
+            let (client, proxy_used) = match self.create_client_from_proxy(selected_proxy, None).await {
+                Ok(result) => result,
+                Err(e) => {
+                    warn!("Failed to create client for proxy {}: {}", selected_proxy.proxy.url, e);
+                    attempts.push((selected_proxy.proxy.url.clone(), e.to_string()));
+                    failed_proxies.push(selected_proxy);
+                    continue;
+                }
+            };
+
+            // Build request
+            let request = self.build_request(&client, config)?;
+
+            debug!("Sending request through proxy: {}", proxy_used);
+
+            // Try to send request
+            let mut sent = Self::send_within_response_timeout(request, config, &proxy_used).await;
+            let mut proxy_used = proxy_used;
+            if let Ok(Err(e)) = &sent {
+                if selected_proxy.proxy.proxy_type == ProxyType::Socks && Self::is_socks_handshake_error(e) {
+                    warn!("SOCKS handshake with {} failed, retrying it as an HTTP proxy: {}", proxy_used, e);
+                    match self.socks_as_http_client(&selected_proxy.proxy) {
+                        Ok((client, fallback_used)) => {
+                            let request = self.build_request(&client, config)?;
+                            sent = Self::send_within_response_timeout(request, config, &fallback_used).await;
+                            proxy_used = fallback_used;
+                        }
+                        Err(e) => warn!("{}", e),
+                    }
+                }
+            }
+
+            match sent {
+                Err(no_response) => {
+                    // A proxy that never answers is as good as unreachable
+                    warn!("{}", no_response);
+                    self.proxy_selector.handle_proxy_failure(&selected_proxy.proxy).await;
+                    failed_proxies.push(selected_proxy);
+                    attempts.push((selected_proxy.proxy.url.clone(), no_response.to_string()));
+                    continue;
+                }
+                Ok(Ok(response)) => {
+                    info!("Request succeeded through proxy: {}", proxy_used);
+                    self.proxy_selector.handle_proxy_success(&selected_proxy.proxy).await;
+                    if let Some(host) = Url::parse(&config.url).ok().as_ref().and_then(Url::host_str) {
+                        self.proxy_selector.record_host_success(host, selected_proxy);
+                    }
+                    if proxy_used.ends_with(SOCKS_FALLBACK_SUFFIX) {
+                        // Skip the failing SOCKS attempt from now on
+                        let working_type = if proxy_used.starts_with("http://") {
+                            ProxyType::Http
+                        } else {
+                            ProxyType::Https
+                        };
+                        self.proxy_selector.update_proxy_type(&selected_proxy.proxy, working_type);
+                    }
+                    // Mark any previously failed proxies
+                    for failed_proxy in failed_proxies {
+                        self.proxy_selector.handle_proxy_failure(&failed_proxy.proxy).await;
+                    }
+                    let attempts = self.attempt_log(&attempts, &selected_proxy.proxy.url);
+                    return Ok((response, proxy_used, false, attempts));
+                }
+                Ok(Err(e)) => {
+                    let error_str = format!("{}", e);
+                    let is_connection_error = Self::is_proxy_connection_error(&error_str);
+                    
+                    if is_connection_error {
+                        warn!("Proxy {} unreachable or connection error: {}", proxy_used, error_str);
+                        log_error_full(&format!("Full error details for proxy {}:", proxy_used), &e);
+                        // Mark this proxy as failed
+                        self.proxy_selector.handle_proxy_failure(&selected_proxy.proxy).await;
+                        failed_proxies.push(selected_proxy);
+                        attempts.push((selected_proxy.proxy.url.clone(), error_str));
+                        // Continue to next proxy
+                        continue;
+                    } else {
+                        // For non-connection errors (like HTTP errors), return immediately
+                        // as retrying won't help
+                        let prefix = format!("Request failed through proxy {} with non-connection error:", proxy_used);
+                        log_error_full(&prefix, &e);
+                        return Err(format!("Request failed through proxy {}: {}", proxy_used, error_str).into());
+                    }
+                }
+            }
+        }
+
+        // All proxies failed
+        let error = RequestError::AllProxiesFailed { attempts };
+        error!("{}", error);
+        Err(error)
+    }
+
+    /// First candidate that is on the wrong network for the request: any candidate for an I2P
+    /// site (those only use the router), or the router's own local proxy for a clearnet site
+    fn network_mismatch<'a>(&self, is_i2p: bool, candidates: &'a [SelectedProxy]) -> Option<&'a SelectedProxy> {
+        if is_i2p {
+            return candidates.first();
+        }
+        if candidates.is_empty() {
+            return None;
+        }
+        let router_ports = [self.router.http_proxy_port(), self.router.https_proxy_port()];
+        candidates.iter().find(|candidate| {
+            let host = candidate.proxy.host.as_str();
+            let is_loopback = host == "localhost"
+                || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback());
+            is_loopback && router_ports.contains(&candidate.proxy.port)
+        })
+    }
+
+    /// Client reaching an I2P outproxy through one of the router's local proxies
+    fn router_client(
+        &self,
+        selected_proxy: &SelectedProxy,
+        kind: RouterProxyKind,
+        port: u16,
+    ) -> Result<(Client, String), String> {
+        let router_url = format!("http://127.0.0.1:{}", port);
+        let (i2p_proxy, label) = match kind {
+            RouterProxyKind::Http => (
+                reqwest::Proxy::http(&router_url).map_err(|e| format!("Failed to create HTTP proxy: {}", e))?,
+                "router-http",
+            ),
+            // HTTPS proxy (not SOCKS5, as SOCKS5 cannot handle .b32.i2p addresses)
+            RouterProxyKind::Https => (
+                reqwest::Proxy::https(&router_url).map_err(|e| format!("Failed to create HTTPS proxy: {}", e))?,
+                "router-https",
+            ),
+        };
+
+        let client = self.client_builder()
+            .proxy(i2p_proxy)
+            .timeout(self.timeouts.outproxy_request)  // Longer timeout for streaming
+            .build()
+            .map_err(|e| {
+                log_error_full("Failed to create router client:", &e);
+                format!("Failed to create {} client: {}", label, e)
+            })?;
+
+        Ok((client, format!("{}://127.0.0.1:{} (for {})", label, port, selected_proxy.proxy.url)))
+    }
+
+    /// Client for I2P sites through the router's local proxies; HTTPS sites use the
+    /// HTTPS proxy port. Returns the client and the proxy URL used.
+    fn i2p_client(&self, is_https: bool, http_port: u16, https_port: u16) -> Result<(Client, String), String> {
+        let proxy_url = if is_https {
+            format!("http://127.0.0.1:{}", https_port)
+        } else {
+            format!("http://127.0.0.1:{}", http_port)
+        };
+
+        debug!("Using local I2P proxy: {}", proxy_url);
+
+        let http_proxy = reqwest::Proxy::http(&proxy_url)
+            .map_err(|e| format!("Failed to create I2P HTTP proxy: {}", e))?;
+
+        let mut builder = self.client_builder()
+            .proxy(http_proxy)
+            .timeout(self.timeouts.i2p_request);
+
+        // Add HTTPS proxy if needed
+        if is_https {
+            let https_proxy = reqwest::Proxy::https(format!("http://127.0.0.1:{}", https_port))
+                .map_err(|e| format!("Failed to create I2P HTTPS proxy: {}", e))?;
+            builder = builder.proxy(https_proxy);
+        }
+
+        let client = builder.build()
+            .map_err(|e| format!("Failed to create I2P client: {}", e))?;
+
+        Ok((client, proxy_url))
+    }
+
+    /// Send a HEAD for `url` through each of `proxies` concurrently and return up to `want`
+    /// of them that answered with a 2xx or 3xx status, with their response times, fastest
+    /// first. Probing stops once `want` proxies worked.
+    pub async fn probe_working(&self, url: &str, proxies: Vec<Proxy>, want: usize) -> Vec<(Proxy, Duration)> {
+        info!("Probing {} proxies for {} working ones against {}", proxies.len(), want, url);
+        if want == 0 {
+            return Vec::new();
+        }
+
+        let probes = stream::iter(proxies)
+            .map(|proxy| async move {
+                if !self.admit(&proxy).await {
+                    debug!("Not probing {}, it is rate limited", proxy.url);
+                    return None;
+                }
+                let selected = SelectedProxy {
+                    proxy: proxy.clone(),
+                    speed_bytes_per_sec: 1024.0 * 50.0, // Default speed assumption
+                    selected_at: std::time::Instant::now(),
+                };
+                let (client, proxy_used) = match self.create_client_from_proxy(&selected, None).await {
+                    Ok(result) => result,
+                    Err(e) => {
+                        debug!("Probe could not build a client for {}: {}", proxy.url, e);
+                        return None;
+                    }
+                };
+
+                let start = Instant::now();
+                match client.head(url).send().await {
+                    Ok(response) if response.status().is_success() || response.status().is_redirection() => {
+                        Some((proxy, start.elapsed()))
+                    }
+                    Ok(response) => {
+                        debug!("Probe through {} got status {}", proxy_used, response.status());
+                        None
+                    }
+                    Err(e) => {
+                        debug!("Probe through {} failed: {}", proxy_used, e);
+                        None
+                    }
+                }
+            })
+            .buffer_unordered(PROBE_CONCURRENCY)
+            .filter_map(|working| async move { working });
+        futures::pin_mut!(probes);
+
+        let mut working = Vec::new();
+        while let Some(found) = probes.next().await {
+            working.push(found);
+            if working.len() >= want {
+                break;
+            }
+        }
+        working.sort_by_key(|(_, elapsed)| *elapsed);
+        info!("{} proxies working for {}", working.len(), url);
+        working
+    }
+
+    /// Check that the I2P destination `b32_address` (e.g. `abc...xyz.b32.i2p`) answers, by
+    /// sending a HEAD for its root through the router. Returns the round-trip time. Any HTTP
+    /// answer from the destination counts as reachable; gateway errors from the router's
+    /// proxy (502-504) mean the destination could not be reached.
+    pub async fn ping_i2p(&self, b32_address: &str) -> Result<Duration, String> {
+        let host = b32_address
+            .trim()
+            .trim_start_matches("http://")
+            .trim_end_matches('/');
+        let url = format!("http://{}/", host);
+        if host.is_empty() || host.contains('/') || !host.ends_with(".i2p") {
+            return Err(format!("{} is not an I2P address", b32_address));
+        }
+
+        self.router
+            .ensure_running()
+            .map_err(|e| format!("Failed to ensure i2pd router is running: {}", e))?;
+        let (client, proxy_used) =
+            self.i2p_client(false, self.router.http_proxy_port(), self.router.https_proxy_port())?;
+
+        debug!("Pinging {} through {}", host, proxy_used);
+        let start = Instant::now();
+        let send = async {
+            client
+                .head(&url)
+                .send()
+                .await
+                .map_err(|e| RequestError::Other(format!("{} is unreachable: {}", host, e)))
+        };
+        let response = self.abort_on_router_shutdown(send).await.map_err(|e| e.to_string())?;
+        let elapsed = start.elapsed();
+
+        let status = response.status().as_u16();
+        if (502..=504).contains(&status) {
+            warn!("Router could not reach {} (status {})", host, status);
+            return Err(format!("{} is unreachable: router answered {}", host, status));
+        }
+        info!("{} answered in {:?}", host, elapsed);
+        Ok(elapsed)
+    }
+
+    /// Fetch several paths from one I2P site over a single client, so the tunnel to the
+    /// destination is set up once and its connections are reused. Paths are resolved against
+    /// `base_url` and must stay on its host. Results come back in the order of `paths`.
+    pub async fn fetch_all_from_host(
+        &self,
+        base_url: &str,
+        paths: Vec<String>,
+    ) -> Vec<Result<ResponseData, RequestError>> {
+        self.fetch_all(base_url, paths, None).await
+    }
+
+    /// Like `fetch_all_from_host`, but the whole batch gets `batch_deadline` of wall-clock
+    /// time. Paths still loading (or not started) when it passes fail with
+    /// `RequestError::DeadlineExceeded`; results that finished in time are kept.
+    pub async fn fetch_all_from_host_within(
+        &self,
+        base_url: &str,
+        paths: Vec<String>,
+        batch_deadline: Duration,
+    ) -> Vec<Result<ResponseData, RequestError>> {
+        self.fetch_all(base_url, paths, Some(Instant::now() + batch_deadline)).await
+    }
+
+    async fn fetch_all(
+        &self,
+        base_url: &str,
+        paths: Vec<String>,
+        deadline: Option<Instant>,
+    ) -> Vec<Result<ResponseData, RequestError>> {
+        info!("Fetching {} paths from {}", paths.len(), base_url);
+
+        let setup = if !self.routes_via_i2p(base_url) {
+            Err(format!("{} is not an I2P site", base_url))
+        } else if let Err(e) = self.router.ensure_running() {
+            Err(format!("Failed to ensure i2pd router is running: {}", e))
+        } else {
+            self.i2p_client(
+                base_url.starts_with("https://"),
+                self.router.http_proxy_port(),
+                self.router.https_proxy_port(),
+            )
+        };
+
+        match setup {
+            Ok((client, proxy_used)) => {
+                self.fetch_all_with_client(&client, &proxy_used, base_url, paths, deadline).await
+            }
+            Err(e) => {
+                error!("Cannot fetch from {}: {}", base_url, e);
+                paths.iter().map(|_| Err(RequestError::Other(e.clone()))).collect()
+            }
+        }
+    }
+
+    async fn fetch_all_with_client(
+        &self,
+        client: &Client,
+        proxy_used: &str,
+        base_url: &str,
+        paths: Vec<String>,
+        deadline: Option<Instant>,
+    ) -> Vec<Result<ResponseData, RequestError>> {
+        let base = match Url::parse(base_url) {
+            Ok(base) => base,
+            Err(e) => {
+                let e = format!("Invalid base URL {}: {}", base_url, e);
+                return paths.iter().map(|_| Err(RequestError::Other(e.clone()))).collect();
+            }
+        };
+
+        stream::iter(paths)
+            .map(|path| {
+                let resolved = Self::resolve_on_host(&base, &path);
+                let fetch = async move {
+                    let config = RequestConfig {
+                        url: resolved?,
+                        method: "GET".to_string(),
+                        ..Default::default()
+                    };
+                    let request = self.build_request(client, &config)?;
+                    let response = request.send().await.map_err(|e| {
+                        format!("Request failed through I2P proxy {}: {}", proxy_used, e)
+                    })?;
+                    self.read_response_data(response, &config, proxy_used.to_string(), None).await
+                };
+                let fetch = self.abort_on_router_shutdown(fetch);
+                async move {
+                    match deadline {
+                        Some(deadline) => tokio::time::timeout_at(deadline, fetch).await.unwrap_or_else(|_| {
+                            warn!("Batch deadline passed before {} finished", path);
+                            Err(RequestError::DeadlineExceeded)
+                        }),
+                        None => fetch.await,
+                    }
+                }
+            })
+            .buffered(FETCH_ALL_CONCURRENCY)
+            .collect()
+            .await
+    }
+
+    /// Resolve `path` against `base`, refusing anything that leaves the base host
+    fn resolve_on_host(base: &Url, path: &str) -> Result<String, String> {
+        let url = base
+            .join(path)
+            .map_err(|e| format!("Invalid path {}: {}", path, e))?;
+        if url.host_str() != base.host_str() || url.port_or_known_default() != base.port_or_known_default() {
+            return Err(format!(
+                "{} resolves to {}, not {}",
+                path,
+                url.host_str().unwrap_or("no host"),
+                base.host_str().unwrap_or("no host")
+            ));
+        }
+        Ok(url.to_string())
+    }
+
+    /// Keep only I2P outproxies, which create_client_from_proxy reaches through the router
+    fn outproxy_candidates(candidates: Vec<SelectedProxy>) -> Vec<SelectedProxy> {
+        candidates
+            .into_iter()
+            .filter(|candidate| candidate.proxy.is_i2p_proxy())
+            .collect()
+    }
+
+    /// Get proxy candidates for a request (public helper method)
+    pub async fn get_proxy_candidates_for_request(
+        &self,
+        available_proxies: Vec<Proxy>,
+        count: usize,
+    ) -> Result<Vec<SelectedProxy>, Box<dyn std::error::Error>> {
+        self.proxy_selector.ensure_multiple_proxy_candidates(available_proxies, count).await
+    }
+
+    /// Handle a request using a specific proxy (for parallel downloads)
+    pub async fn handle_request_with_specific_proxy(
+        &self,
+        mut config: RequestConfig,
+        proxy: Proxy,
+        router_port_hint: Option<u16>,
+    ) -> Result<ResponseData, RequestError> {
+        self.apply_http10_policy(&mut config);
+        let recording = self.recorder.as_ref().map(|recorder| recorder.start(&config));
+        let lookup = self.prepare_cache_lookup(&mut config);
+        let routed = self.routes_via_i2p(&config.url) || proxy.is_i2p_proxy();
+        let send = self.send_with_specific_proxy(config, proxy, router_port_hint);
+        let result = self.abort_if_routed(routed, send).await;
+        let result = self.complete_cache_lookup(lookup, result);
+        if let (Some(recorder), Some(pending)) = (&self.recorder, recording) {
+            recorder.finish(pending, &result);
+        }
+        result
+    }
+
+    async fn send_with_specific_proxy(
+        &self,
+        config: RequestConfig,
+        proxy: Proxy,
+        router_port_hint: Option<u16>,
+    ) -> Result<ResponseData, RequestError> {
+        info!("Handling request with specific proxy: {} {} -> {}", config.method, config.url, proxy.url);
+
+        // Create a SelectedProxy from the provided proxy
+        let selected_proxy = SelectedProxy {
+            proxy: proxy.clone(),
+            speed_bytes_per_sec: 1024.0 * 50.0, // Default speed assumption
+            selected_at: std::time::Instant::now(),
+        };
+
+        // Create client from this specific proxy with optional router port hint
+        let (client, proxy_used) = match self.create_client_from_proxy(&selected_proxy, router_port_hint).await {
+            Ok(result) => result,
+            Err(e) => {
+                error!("Failed to create client for specific proxy {}: {}", proxy.url, e);
+                return Err(format!("Failed to create client: {}", e).into());
+            }
+        };
+
+        if config.preflight && config.method == "GET" {
+            if let Err(failure) = self.preflight(&client, &config).await {
+                let reason = match failure {
+                    PreflightFailure::Proxy(reason) => {
+                        self.proxy_selector.handle_proxy_failure(&proxy).await;
+                        reason
+                    }
+                    // The proxy delivered the answer, the target just didn't have the download
+                    PreflightFailure::Answer(reason) => reason,
+                };
+                warn!("Preflight through {} failed, not starting the download: {}", proxy_used, reason);
+                return Err(RequestError::PreflightFailed { proxy_used, reason });
+            }
+            debug!("Preflight through {} succeeded", proxy_used);
+        }
+
+        if !self.admit(&proxy).await {
+            return Err(format!("Proxy {} is rate limited", proxy.url).into());
+        }
+
+        // Build request
+        let request = self.build_request(&client, &config)?;
+
+        debug!("Sending request through specific proxy: {}", proxy_used);
+
+        // Send request; a proxy that never answers is as good as unreachable
+        let sent = match Self::send_within_response_timeout(request, &config, &proxy_used).await {
+            Ok(sent) => sent,
+            Err(no_response) => {
+                warn!("{}", no_response);
+                self.proxy_selector.handle_proxy_failure(&proxy).await;
+                return Err(no_response);
+            }
+        };
+        let response = sent.map_err(|e| {
+            let prefix = format!("Request failed through proxy {}:", proxy_used);
+            log_error_full(&prefix, &e);
+            format!("Request failed through proxy {}: {}", proxy_used, e)
+        })?;
+
+        let response = self.read_response_data(response, &config, proxy_used, Some(&proxy)).await?;
+        self.check_challenge(&config.url, Some(&proxy), response)
+    }
+
+    pub async fn handle_request(
+        &self,
+        mut config: RequestConfig,
+        available_proxies: Vec<Proxy>,
+    ) -> Result<ResponseData, RequestError> {
+        self.apply_http10_policy(&mut config);
+        let recording = self.recorder.as_ref().map(|recorder| recorder.start(&config));
+        let lookup = self.prepare_cache_lookup(&mut config);
+        let result = self.send_with_candidates(config, available_proxies).await;
+        let result = self.complete_cache_lookup(lookup, result);
+        if let (Some(recorder), Some(pending)) = (&self.recorder, recording) {
+            recorder.finish(pending, &result);
+        }
+        result
+    }
+
+    /// Like `handle_request`, but answer with `fallback()` (e.g. content the application
+    /// cached earlier) instead of failing when no proxy got the request through: every
+    /// candidate failed (`AllProxiesFailed`) or there was none (`NoProxyCandidates`). Any
+    /// other error, such as an HTTP error status or an invalid request, is returned as is.
+    pub async fn handle_request_or_else(
+        &self,
+        config: RequestConfig,
+        available_proxies: Vec<Proxy>,
+        fallback: impl FnOnce() -> ResponseData,
+    ) -> Result<ResponseData, RequestError> {
+        let url = config.url.clone();
+        match self.handle_request(config, available_proxies).await {
+            Err(e @ (RequestError::AllProxiesFailed { .. } | RequestError::NoProxyCandidates { .. })) => {
+                warn!("Request for {} failed ({}), answering with the fallback response", url, e);
+                Ok(fallback())
+            }
+            result => result,
+        }
+    }
+
+    async fn send_with_candidates(
+        &self,
+        config: RequestConfig,
+        available_proxies: Vec<Proxy>,
+    ) -> Result<ResponseData, RequestError> {
+        info!("Handling request: {} {} (stream={})", config.method, config.url, config.stream);
+
+        let proxy_candidates = match self.request_candidates(&config, available_proxies).await {
+            Err(e) if self.allow_direct_fallback && !config.force_outproxy && !self.routes_via_i2p(&config.url) => {
+                warn!("No proxy candidates ({}), falling back to a direct request", e);
+                return self.send_direct(&config).await;
+            }
+            candidates => candidates?,
+        };
+
+        let send = async {
+            // Use helper to create client and send request
+            let (response, proxy_used, _is_i2p, attempts) = self
+                .send_logging_attempts(&config, proxy_candidates.clone())
+                .await?;
+
+            let proxy = Self::candidate_for_proxy_used(&proxy_candidates, &proxy_used);
+            let mut response = self.read_response_data(response, &config, proxy_used, proxy).await?;
+            response.attempts = attempts;
+            self.check_challenge(&config.url, proxy, response)
+        };
+
+        let routed = self.depends_on_router(&config, &proxy_candidates);
+        self.abort_if_routed(routed, send).await
+    }
+
+    /// Run `request`, which depends on the router, unless the router starts shutting down first.
+    /// The router counts as in use until `request` is done.
+    async fn abort_on_router_shutdown<T>(
+        &self,
+        request: impl std::future::Future<Output = Result<T, RequestError>>,
+    ) -> Result<T, RequestError> {
+        let shutdown = self.router.shutdown_signal();
+        let _in_use = self.router.in_use();
+        tokio::select! {
+            result = request => result,
+            _ = shutdown.notified() => {
+                warn!("Router shutting down, aborting request");
+                Err(RequestError::RouterShuttingDown)
+            }
+        }
+    }
+
+    /// `abort_on_router_shutdown` for requests that only depend on the router if `routed`
+    async fn abort_if_routed<T>(
+        &self,
+        routed: bool,
+        request: impl std::future::Future<Output = Result<T, RequestError>>,
+    ) -> Result<T, RequestError> {
+        if routed {
+            self.abort_on_router_shutdown(request).await
+        } else {
+            request.await
+        }
+    }
+
+    /// Whether sending `config` through any of `candidates` goes through the router: I2P
+    /// sites, forced outproxies and I2P outproxy candidates all do
+    fn depends_on_router(&self, config: &RequestConfig, candidates: &[SelectedProxy]) -> bool {
+        self.routes_via_i2p(&config.url)
+            || config.force_outproxy
+            || candidates.iter().any(|candidate| candidate.proxy.is_i2p_proxy())
+    }
+
+    /// Proxy candidates for `config`: none for I2P sites (the router is used), otherwise the
+    /// top 5 proxies for clearnet retries, picked among I2P outproxies only for `force_outproxy`
+    async fn request_candidates(
+        &self,
+        config: &RequestConfig,
+        available_proxies: Vec<Proxy>,
+    ) -> Result<Vec<SelectedProxy>, RequestError> {
+        if self.routes_via_i2p(&config.url) {
+            return Ok(Vec::new());
+        }
+        // Filtered before the top 5 are taken, or faster clearnet proxies could fill them all
+        let available_proxies = if config.force_outproxy {
+            available_proxies.into_iter().filter(|proxy| proxy.is_i2p_proxy()).collect()
+        } else {
+            available_proxies
+        };
+        let candidates = if config.require_fresh_proxy {
+            info!("Fresh proxy required for {}, testing proxies again", config.url);
+            self.proxy_selector.ensure_fresh_proxy_candidates(available_proxies, 5).await
+        } else {
+            self.proxy_selector.ensure_multiple_proxy_candidates(available_proxies, 5).await
+        };
+        match candidates {
+            Ok(candidates) => {
+                if candidates.is_empty() {
+                    return Err(RequestError::NoProxyCandidates {
+                        reason: "No available proxy candidates found".to_string(),
+                    });
+                }
+                info!("Got {} proxy candidates for request", candidates.len());
+                match Url::parse(&config.url).ok().as_ref().and_then(Url::host_str) {
+                    Some(host) => {
+                        let candidates = if config.require_fresh_proxy {
+                            candidates
+                        } else {
+                            self.proxy_selector.prefer_sticky(host, candidates)
+                        };
+                        Ok(self.proxy_selector.demote_challenged(host, candidates))
+                    }
+                    None => Ok(candidates),
+                }
+            }
+            Err(e) => {
+                error!("Failed to get proxy candidates: {}", e);
+                Err(RequestError::NoProxyCandidates { reason: format!("Proxy selection failed: {}", e) })
+            }
+        }
+    }
+
+    /// Stream the response body for `config` straight into a file at `path` instead of
+    /// buffering it, for large downloads. The body is written to `<path>.part` and only moved
+    /// onto `path` once complete, so a failed download leaves an existing file alone.
+    ///
+    /// GETs without their own Range header can be resumed from a `.part` file an interrupted
+    /// call left behind: they ask for the rest with `Range: bytes=<its length>-` and append if
+    /// the server answers 206 from that offset, or start over from zero if the server ignores
+    /// the range and answers 200. A 416 for exactly its length means it already holds the whole
+    /// body. Any other answer fails without touching it, and so does a failed transfer, so a
+    /// later call can continue. Whatever `path` itself holds is never resumed, only replaced.
+    /// For other requests the `.part` file is removed again if the download fails.
+    pub async fn download_to_file(
+        &self,
+        mut config: RequestConfig,
+        available_proxies: Vec<Proxy>,
+        path: impl AsRef<Path>,
+    ) -> Result<DownloadSummary, RequestError> {
+        self.apply_http10_policy(&mut config);
+        let path = path.as_ref();
+        info!("Downloading {} {} to {}", config.method, config.url, path.display());
+
+        let resumable = Self::resumable(&config);
+        let partial = partial_download_path(path);
+        let resume_from = if resumable { Self::resume_offset(&partial).await } else { None };
+        if let Some(offset) = resume_from {
+            info!("{} already holds {} bytes, asking for the rest", partial.display(), offset);
+            config
+                .headers
+                .get_or_insert_with(std::collections::HashMap::new)
+                .insert("Range".to_string(), format!("bytes={}-", offset));
+        }
+
+        let proxy_candidates = self.request_candidates(&config, available_proxies).await?;
+        let routed = self.depends_on_router(&config, &proxy_candidates);
+        let download = async {
+            let (mut response, proxy_used, _is_i2p) = self
+                .create_client_and_send_request(&config, proxy_candidates.clone())
+                .await?;
+            let proxy = Self::candidate_for_proxy_used(&proxy_candidates, &proxy_used);
+
+            let status = response.status().as_u16();
+            let final_url = response.url().to_string();
+            let content_range = response
+                .headers()
+                .get(reqwest::header::CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("")
+                .to_string();
+            if let Some(offset) = resume_from {
+                if status == 416 && content_range == format!("bytes */{}", offset) {
+                    info!("{} already holds all {} bytes of {}", partial.display(), offset, config.url);
+                    Self::finish_partial_download(&partial, path).await?;
+                    return Ok(DownloadSummary {
+                        status,
+                        bytes_written: 0,
+                        proxy_used,
+                        final_url,
+                        resumed_from: Some(offset),
+                    });
+                }
+            }
+            if config.error_on_status && !(200..300).contains(&status) {
+                let body = Self::read_error_snippet(&mut response).await;
+                return Err(RequestError::HttpStatus { code: status, body });
+            }
+
+            let appending = match resume_from {
+                Some(offset) if status == 206 => {
+                    let expected = format!("bytes {}-", offset);
+                    if !content_range.starts_with(&expected) {
+                        return Err(format!(
+                            "Server resumed {} at the wrong offset (asked for {}, got {:?})",
+                            config.url, offset, content_range
+                        )
+                        .into());
+                    }
+                    true
+                }
+                Some(_) if status == 200 => {
+                    info!("Server ignored the range for {}, downloading from the start", config.url);
+                    false
+                }
+                Some(_) => {
+                    warn!("Resuming {} got status {}, keeping {} as it is", config.url, status, partial.display());
+                    let body = Self::read_error_snippet(&mut response).await;
+                    return Err(RequestError::HttpStatus { code: status, body });
+                }
+                None => false,
+            };
+            let resumed_from = resume_from.filter(|_| appending);
+
+            let opened = if appending {
+                tokio::fs::OpenOptions::new().append(true).open(&partial).await
+            } else {
+                tokio::fs::File::create(&partial).await
+            };
+            let mut file = opened.map_err(|e| format!("Failed to create {}: {}", partial.display(), e))?;
+            let written = self
+                .write_body_to_file(&mut response, &mut file, &config, &proxy_used, proxy)
+                .await;
+            drop(file);
+
+            match written {
+                Ok(bytes_written) => {
+                    Self::finish_partial_download(&partial, path).await?;
+                    info!("Downloaded {} bytes to {} via {}", bytes_written, path.display(), proxy_used);
+                    Ok(DownloadSummary {
+                        status,
+                        bytes_written,
+                        proxy_used,
+                        final_url,
+                        resumed_from,
+                    })
+                }
+                Err(e) if resumable => {
+                    warn!(
+                        "Download to {} failed, keeping {} to resume later: {}",
+                        path.display(),
+                        partial.display(),
+                        e
+                    );
+                    Err(e)
+                }
+                Err(e) => {
+                    warn!("Download to {} failed, removing partial file: {}", path.display(), e);
+                    if let Err(remove_err) = tokio::fs::remove_file(&partial).await {
+                        warn!("Failed to remove partial download {}: {}", partial.display(), remove_err);
+                    }
+                    Err(e)
+                }
+            }
+        };
+        self.abort_if_routed(routed, download).await
+    }
+
+    /// Only GETs without their own Range header can continue an earlier `.part` file
+    fn resumable(config: &RequestConfig) -> bool {
+        let has_range = config
+            .headers
+            .iter()
+            .flatten()
+            .any(|(key, _)| key.eq_ignore_ascii_case("range"));
+        config.method == "GET" && !has_range
+    }
+
+    /// Length of the partial download at `partial` to resume from, if it exists and is not empty
+    async fn resume_offset(partial: &Path) -> Option<u64> {
+        let metadata = tokio::fs::metadata(partial).await.ok()?;
+        Some(metadata.len()).filter(|len| metadata.is_file() && *len > 0)
+    }
+
+    /// Move a completed `.part` download onto `path`, replacing what was there
+    async fn finish_partial_download(partial: &Path, path: &Path) -> Result<(), RequestError> {
+        tokio::fs::rename(partial, path)
+            .await
+            .map_err(|e| format!("Failed to move {} to {}: {}", partial.display(), path.display(), e).into())
+    }
+
+    async fn write_body_to_file(
+        &self,
+        response: &mut reqwest::Response,
+        file: &mut tokio::fs::File,
+        config: &RequestConfig,
+        proxy_used: &str,
+        proxy: Option<&Proxy>,
+    ) -> Result<u64, RequestError> {
+        let started = Instant::now();
+        let mut written = 0u64;
+        loop {
+            let next = self
+                .within_body_timeout(response.chunk(), config, written > 0, proxy_used, proxy)
+                .await?;
+            match next {
+                Ok(Some(chunk)) => {
+                    file.write_all(&chunk)
+                        .await
+                        .map_err(|e| format!("Failed to write download: {}", e))?;
+                    written += chunk.len() as u64;
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    log_error_full("Failed to read response body:", &e);
+                    return Err(format!("Failed to read body: {}", e).into());
+                }
+            }
+        }
+        file.flush()
+            .await
+            .map_err(|e| format!("Failed to flush download: {}", e))?;
+        if let Some(proxy) = proxy {
+            self.proxy_selector.record_realized(proxy, written, started.elapsed());
+        }
+        Ok(written)
+    }
+
+    /// GET `url` and deserialize the JSON body into `T`. Non-2xx answers fail with
+    /// `RequestError::HttpStatus`; a body that does not parse fails with the start of the body
+    /// in the message.
+    pub async fn get_json<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        available_proxies: Vec<Proxy>,
+    ) -> Result<T, RequestError> {
+        let config = RequestConfig {
+            error_on_status: true,
+            ..RequestConfig::get(url)
+        };
+        let response = self.handle_request(config, available_proxies).await?;
+
+        serde_json::from_slice(&response.body).map_err(|e| {
+            let cut = &response.body[..response.body.len().min(ERROR_SNIPPET_BYTES)];
+            let snippet = String::from_utf8_lossy(cut);
+            warn!("Response from {} is not the expected JSON: {}", url, e);
+            RequestError::Other(format!("Invalid JSON from {}: {} (body: {})", url, e, snippet.trim()))
+        })
+    }
+
+    /// POST `form` as multipart/form-data to `url`, e.g. a file upload to a paste or image
+    /// site. The form can only be sent once, so unlike other requests a failed send is not
+    /// retried through the next candidate.
+    pub async fn post_multipart(
+        &self,
+        url: &str,
+        form: reqwest::multipart::Form,
+        available_proxies: Vec<Proxy>,
+    ) -> Result<ResponseData, RequestError> {
+        info!("Posting multipart form to {}", url);
+        let config = RequestConfig {
+            url: url.to_string(),
+            method: "POST".to_string(),
+            ..Default::default()
+        };
+
+        let proxy_candidates = self.request_candidates(&config, available_proxies).await?;
+        let is_i2p = self.routes_via_i2p(url);
+        if let Some(mismatched) = self.network_mismatch(is_i2p, &proxy_candidates) {
+            return Err(RequestError::ProxyNetworkMismatch {
+                url: config.url.clone(),
+                proxy: mismatched.proxy.url.clone(),
+            });
+        }
+
+        let routed = self.depends_on_router(&config, &proxy_candidates);
+        let upload = async {
+            let (client, proxy_used, proxy) = if is_i2p {
+                self.router
+                    .ensure_running()
+                    .map_err(|e| format!("Failed to ensure i2pd router is running: {}", e))?;
+                let (client, proxy_used) = self.i2p_client(
+                    url.starts_with("https://"),
+                    self.router.http_proxy_port(),
+                    self.router.https_proxy_port(),
+                )?;
+                (client, proxy_used, None)
+            } else {
+                let selected = proxy_candidates
+                    .first()
+                    .ok_or_else(|| "No proxy candidates available for clearnet request".to_string())?;
+                if !self.admit(&selected.proxy).await {
+                    return Err(format!("Proxy {} is rate limited", selected.proxy.url).into());
+                }
+                let (client, proxy_used) = self.create_client_from_proxy(selected, None).await?;
+                (client, proxy_used, Some(&selected.proxy))
+            };
+
+            debug!("Sending multipart form through proxy: {}", proxy_used);
+            let response = match self.build_request(&client, &config)?.multipart(form).send().await {
+                Ok(response) => response,
+                Err(e) => {
+                    log_error_full(&format!("Multipart upload through {} failed:", proxy_used), &e);
+                    if let Some(proxy) = proxy {
+                        if Self::is_proxy_connection_error(&e.to_string()) {
+                            self.proxy_selector.handle_proxy_failure(proxy).await;
+                        }
+                    }
+                    return Err(format!("Request failed through proxy {}: {}", proxy_used, e).into());
+                }
+            };
+            if let Some(proxy) = proxy {
+                self.proxy_selector.handle_proxy_success(proxy).await;
+            }
+
+            self.read_response_data(response, &config, proxy_used, proxy).await
+        };
+        self.abort_if_routed(routed, upload).await
+    }
+
+    /// Find the candidate behind a `proxy_used` label ("<url>" or "router-... (for <url>)")
+    fn candidate_for_proxy_used<'a>(candidates: &'a [SelectedProxy], proxy_used: &str) -> Option<&'a Proxy> {
+        candidates
+            .iter()
+            .map(|candidate| &candidate.proxy)
+            .find(|proxy| {
+                proxy_used == proxy.url || proxy_used.ends_with(&format!("(for {})", proxy.url))
+            })
+    }
+
+    /// Race proxy candidates with staggered starts: send through the fastest candidate and
+    /// only hedge with the next one when no response headers arrived within `hedge_delay`,
+    /// launching at most `max_hedges` extra requests. The first response wins and the
+    /// remaining in-flight requests are dropped. I2P sites go through the router as usual.
+    pub async fn handle_request_hedged(
+        &self,
+        mut config: RequestConfig,
+        available_proxies: Vec<Proxy>,
+        hedge_delay: Duration,
+        max_hedges: usize,
+    ) -> Result<ResponseData, RequestError> {
+        self.apply_http10_policy(&mut config);
+        info!(
+            "Handling hedged request: {} {} (hedge delay {:?}, max hedges {})",
+            config.method, config.url, hedge_delay, max_hedges
+        );
+
+        if self.routes_via_i2p(&config.url) {
+            return self.handle_request(config, available_proxies).await;
+        }
+
+        let candidates = self
+            .proxy_selector
+            .ensure_multiple_proxy_candidates(available_proxies, max_hedges + 1)
+            .await
+            .map_err(|e| RequestError::NoProxyCandidates { reason: format!("Proxy selection failed: {}", e) })?;
+
+        let routed = self.depends_on_router(&config, &candidates);
+        let send = async {
+            let (response, proxy_used) = self.send_hedged(&config, candidates.clone(), hedge_delay).await?;
+            let proxy = Self::candidate_for_proxy_used(&candidates, &proxy_used);
+            self.read_response_data(response, &config, proxy_used, proxy).await
+        };
+        self.abort_if_routed(routed, send).await
+    }
+
+    async fn send_hedged(
+        &self,
+        config: &RequestConfig,
+        candidates: Vec<SelectedProxy>,
+        hedge_delay: Duration,
+    ) -> Result<(reqwest::Response, String), RequestError> {
+        if candidates.is_empty() {
+            return Err(RequestError::NoProxyCandidates {
+                reason: "No proxy candidates available for clearnet request".to_string(),
+            });
+        }
+
+        let mut in_flight = FuturesUnordered::new();
+        in_flight.push(self.send_through_candidate(config, &candidates[0]));
+        let mut launched = 1;
+        let mut next_hedge_at = Instant::now() + hedge_delay;
+        let mut attempts: Vec<(String, String)> = Vec::new();
+
+        loop {
+            tokio::select! {
+                Some(result) = in_flight.next() => match result {
+                    Ok(success) => {
+                        info!("Hedged request succeeded through proxy: {} ({} launched)", success.1, launched);
+                        return Ok(success);
+                    }
+                    Err((failed, e)) => {
+                        warn!("Hedged attempt through {} failed: {}", failed.proxy.url, e);
+                        if e != RATE_LIMITED {
+                            self.proxy_selector.handle_proxy_failure(&failed.proxy).await;
+                        }
+                        attempts.push((failed.proxy.url.clone(), e));
+                        if in_flight.is_empty() {
+                            if launched == candidates.len() {
+                                break;
+                            }
+                            // Nothing left in flight, don't wait for the hedge timer
+                            in_flight.push(self.send_through_candidate(config, &candidates[launched]));
+                            launched += 1;
+                            next_hedge_at = Instant::now() + hedge_delay;
+                        }
+                    }
+                },
+                _ = tokio::time::sleep_until(next_hedge_at), if launched < candidates.len() => {
+                    debug!("No response within {:?}, hedging with {}", hedge_delay, candidates[launched].proxy.url);
+                    in_flight.push(self.send_through_candidate(config, &candidates[launched]));
+                    launched += 1;
+                    next_hedge_at = Instant::now() + hedge_delay;
+                }
+            }
+        }
+
+        // Attempts overlap, so list them in the order they were launched
+        attempts.sort_by_key(|(url, _)| candidates.iter().position(|c| &c.proxy.url == url));
+        let error = RequestError::AllProxiesFailed { attempts };
+        error!("{}", error);
+        Err(error)
+    }
+
+    /// Send one request through a single candidate, returning the candidate on failure
+    async fn send_through_candidate<'a>(
+        &self,
+        config: &RequestConfig,
+        candidate: &'a SelectedProxy,
+    ) -> Result<(reqwest::Response, String), (&'a SelectedProxy, String)> {
+        if !self.admit(&candidate.proxy).await {
+            return Err((candidate, RATE_LIMITED.to_string()));
+        }
+        let (client, proxy_used) = self
+            .create_client_from_proxy(candidate, None)
+            .await
+            .map_err(|e| (candidate, e))?;
+        let request = self.build_request(&client, config).map_err(|e| (candidate, e))?;
+        match Self::send_within_response_timeout(request, config, &proxy_used).await {
+            Ok(Ok(response)) => Ok((response, proxy_used)),
+            Ok(Err(e)) => Err((candidate, format!("Proxy {}: {}", proxy_used, e))),
+            Err(no_response) => Err((candidate, no_response.to_string())),
+        }
+    }
+
+    /// Send `request`, giving up with `RequestError::NoResponse` when its response headers
+    /// take longer than `config.response_timeout`. The outer error is the timeout, the inner
+    /// one whatever sending failed with.
+    async fn send_within_response_timeout(
+        request: reqwest::RequestBuilder,
+        config: &RequestConfig,
+        proxy_used: &str,
+    ) -> Result<Result<reqwest::Response, reqwest::Error>, RequestError> {
+        let Some(limit) = config.response_timeout else {
+            return Ok(request.send().await);
+        };
+        tokio::time::timeout(limit, request.send())
+            .await
+            .map_err(|_| RequestError::NoResponse { proxy_used: proxy_used.to_string(), waited: limit })
+    }
+
+    /// Request for `config` on `client`: method, headers and body. Handler-wide default
+    /// headers are set on the client (see `client_builder`), and headers given here replace them.
+    fn build_request(&self, client: &Client, config: &RequestConfig) -> Result<reqwest::RequestBuilder, String> {
+        let mut request = match config.method.as_str() {
+            "GET" => client.get(&config.url),
+            "POST" => client.post(&config.url),
+            "PUT" => client.put(&config.url),
+            "DELETE" => client.delete(&config.url),
+            "PATCH" => client.patch(&config.url),
+            "HEAD" => client.head(&config.url),
+            _ => {
+                return Err(format!("Unsupported HTTP method: {}", config.method));
+            }
+        };
+
+        // Add headers
+        if let Some(headers) = &config.headers {
+            for (key, value) in headers {
+                if self.strip_expect && key.eq_ignore_ascii_case("expect") {
+                    debug!("Not sending Expect: {} to {}", value, config.url);
+                    continue;
+                }
+                request = request.header(key, value);
+            }
+        }
+
+        // An explicit Host is kept by the HTTP client instead of being derived from the URL
+        if let Some(host) = &config.host_override {
+            let value = reqwest::header::HeaderValue::from_str(host)
+                .map_err(|e| format!("Invalid host override {}: {}", host, e))?;
+            let mut host_header = reqwest::header::HeaderMap::new();
+            host_header.insert(reqwest::header::HOST, value);
+            request = request.headers(host_header);
+        }
+
+        // HTTP/1.0 semantics on top of the client's HTTP/1.1: one request per connection
+        // and a plain, length-delimited answer
+        if config.http10 {
+            request = request
+                .header(reqwest::header::CONNECTION, "close")
+                .header(reqwest::header::ACCEPT_ENCODING, "identity");
+        }
+
+        // Add body
+        if let Some(body) = &config.body {
+            request = request.body(body.clone());
+        }
+
+        // Replaces the client's default timeout for this request only
+        if let Some(secs) = config.timeout_secs {
+            request = request.timeout(Duration::from_secs(secs));
+        }
+
+        if let Some(signer) = &self.request_signer {
+            let parts = RequestParts {
+                method: &config.method,
+                url: &config.url,
+                body: config.body.as_deref().unwrap_or_default(),
+            };
+            request = request.headers(signer(&parts));
+        }
+
+        Ok(request)
+    }
+
+    /// Ask for the first `PREFLIGHT_PROBE_BYTES` of the target and check the answer looks
+    /// like the start of the real thing: a 2xx status, a partial answer that starts at byte 0
+    /// and is no longer than asked for, and some data unless it is declared empty. The content
+    /// type is not judged, since any type can be a real download.
+    async fn preflight(&self, client: &Client, config: &RequestConfig) -> Result<(), PreflightFailure> {
+        let probe_config = RequestConfig {
+            url: config.url.clone(),
+            method: "GET".to_string(),
+            headers: config.headers.clone(),
+            ..Default::default()
+        };
+        let mut response = self
+            .build_request(client, &probe_config)
+            .map_err(PreflightFailure::Answer)?
+            .header(reqwest::header::RANGE, format!("bytes=0-{}", PREFLIGHT_PROBE_BYTES - 1))
+            .send()
+            .await
+            .map_err(|e| PreflightFailure::Proxy(format!("probe request failed: {}", e)))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(PreflightFailure::Answer(format!("probe returned status {}", status)));
+        }
+        if status == reqwest::StatusCode::PARTIAL_CONTENT {
+            let range = response
+                .headers()
+                .get(reqwest::header::CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("");
+            if !range.starts_with("bytes 0-") {
+                return Err(PreflightFailure::Answer(format!("probe got range {:?} instead of the start", range)));
+            }
+            let length = response.content_length().unwrap_or(0);
+            if length > PREFLIGHT_PROBE_BYTES as u64 {
+                return Err(PreflightFailure::Answer(format!(
+                    "probe got {} bytes for a {} byte range",
+                    length, PREFLIGHT_PROBE_BYTES
+                )));
+            }
+        }
+        let declared_empty = response.content_length() == Some(0);
+
+        // Servers ignoring Range send everything; stop after the probe size either way
+        let mut received = 0;
+        while received < PREFLIGHT_PROBE_BYTES {
+            match response.chunk().await {
+                Ok(Some(chunk)) => received += chunk.len(),
+                Ok(None) => break,
+                Err(e) => {
+                    return Err(PreflightFailure::Proxy(format!("probe body failed after {} bytes: {}", received, e)))
+                }
+            }
+        }
+
+        if received == 0 && !declared_empty {
+            return Err(PreflightFailure::Answer("probe returned no data".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Read at most `ERROR_SNIPPET_BYTES` of an error response, giving up after a few seconds
+    async fn read_error_snippet(response: &mut reqwest::Response) -> Vec<u8> {
+        let mut body = Vec::new();
+        let read = async {
+            while body.len() < ERROR_SNIPPET_BYTES {
+                match response.chunk().await {
+                    Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+                    _ => break,
+                }
+            }
+        };
+        let _ = tokio::time::timeout(Duration::from_secs(5), read).await;
+        body.truncate(ERROR_SNIPPET_BYTES);
+        body
+    }
+
+    /// Await a body read, giving up after `first_byte_timeout` while nothing was received yet
+    /// or `body_stall_timeout` afterwards. A stall counts as a failure of `proxy`.
+    async fn within_body_timeout<T>(
+        &self,
+        read: impl std::future::Future<Output = T>,
+        config: &RequestConfig,
+        received_any: bool,
+        proxy_used: &str,
+        proxy: Option<&Proxy>,
+    ) -> Result<T, RequestError> {
+        let limit = if received_any {
+            config.body_stall_timeout
+        } else {
+            config.first_byte_timeout
+        };
+        let Some(limit) = limit else {
+            return Ok(read.await);
+        };
+        match tokio::time::timeout(limit, read).await {
+            Ok(value) => Ok(value),
+            Err(_) => {
+                warn!("Response body through {} stalled for {:?}", proxy_used, limit);
+                if let Some(proxy) = proxy {
+                    self.proxy_selector.handle_proxy_failure(proxy).await;
+                }
+                Err(RequestError::Stalled { proxy_used: proxy_used.to_string(), waited: limit })
+            }
+        }
+    }
+
+    /// Turn a response into `ResponseData`, reading the full body unless streaming.
+    /// A body that stalls past the configured timeouts marks `proxy` as failed.
+    async fn read_response_data(
+        &self,
+        mut response: reqwest::Response,
+        config: &RequestConfig,
+        proxy_used: String,
+        proxy: Option<&Proxy>,
+    ) -> Result<ResponseData, RequestError> {
+        let status = response.status().as_u16();
+        info!("Received response: status {}", status);
+
+        let response_headers = self.response_headers(response.headers(), &proxy_used)?;
+        let proxy_info = ProxyInfo::from_headers(&response_headers);
+        let final_url = response.url().to_string();
+        if final_url != config.url {
+            debug!("Request for {} ended up at {}", config.url, final_url);
+        }
+
+        // Handle streaming vs non-streaming
+        if config.stream {
+            // For streaming, return empty body - the response will be read in chunks
+            debug!("Streaming mode: response headers received, body will be streamed");
+            if config.error_on_status && !(200..300).contains(&status) {
+                // Nobody streams an error page; keep its start for the error message
+                let body = Self::read_error_snippet(&mut response).await;
+                return Err(RequestError::HttpStatus { code: status, body });
+            }
+            Ok(ResponseData {
+                status,
+                headers: response_headers,
+                body: Vec::new(), // Empty body for streaming
+                proxy_used,
+                final_url,
+                proxy_info,
+                direct: false,
+                attempts: Vec::new(),
+            })
+        } else {
+            // Held until the body is handed over
+            let mut reservation = self.reserve_body_memory(response.content_length(), &config.url).await?;
+
+            let mut retries_left = if config.method == "GET" { self.body_read_retries } else { 0 };
+            let resumable = response_headers
+                .get("accept-ranges")
+                .is_some_and(|values| values.iter().any(|value| value.eq_ignore_ascii_case("bytes")));
+
+            // Read full body chunk by chunk so a stalled transfer can be cut off
+            let started = Instant::now();
+            let mut body = Vec::new();
+            loop {
+                let next = self
+                    .within_body_timeout(response.chunk(), config, !body.is_empty(), &proxy_used, proxy)
+                    .await?;
+                match next {
+                    Ok(Some(chunk)) => {
+                        reservation.grow_to(body.len() + chunk.len(), &config.url)?;
+                        body.extend_from_slice(&chunk);
+                    }
+                    Ok(None) => break,
+                    Err(e) if retries_left > 0 => {
+                        retries_left -= 1;
+                        warn!(
+                            "Body of {} broke off after {} bytes ({}), retrying ({} retries left)",
+                            config.url,
+                            body.len(),
+                            e,
+                            retries_left
+                        );
+                        let (retry, resumed) = self
+                            .restart_body(config, &proxy_used, proxy, body.len(), resumable)
+                            .await
+                            .map_err(|reason| format!("Failed to read body: {} (retry: {})", e, reason))?;
+                        if !resumed {
+                            body.clear();
+                        }
+                        response = retry;
+                    }
+                    Err(e) => {
+                        log_error_full("Failed to read response body:", &e);
+                        return Err(format!("Failed to read body: {}", e).into());
+                    }
+                }
+            }
+
+            debug!(
+                "Request completed: status {}, body size: {} bytes",
+                status,
+                body.len()
+            );
+
+            if config.error_on_status && !(200..300).contains(&status) {
+                return Err(RequestError::HttpStatus { code: status, body });
+            }
+            if let Some(proxy) = proxy {
+                self.proxy_selector.record_realized(proxy, body.len() as u64, started.elapsed());
+            }
+
+            if let Some(transformer) = &self.body_transformer {
+                let content_type = response_headers
+                    .get("content-type")
+                    .and_then(|values| values.first())
+                    .map(String::as_str)
+                    .unwrap_or("");
+                body = transformer(&body, content_type);
+            }
+
+            Ok(ResponseData {
+                status,
+                headers: response_headers,
+                body,
+                proxy_used,
+                final_url,
+                proxy_info,
+                direct: false,
+                attempts: Vec::new(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FakeRouter, MockResponse, MockServer};
+
+    fn candidate(proxy: Proxy) -> SelectedProxy {
+        SelectedProxy {
+            proxy,
+            speed_bytes_per_sec: 1024.0 * 50.0,
+            selected_at: std::time::Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_request_config_constructors() {
+        let get = RequestConfig::get("http://site.i2p/");
+        assert_eq!(get.url, "http://site.i2p/");
+        assert_eq!(get.method, "GET");
+        assert!(get.headers.is_none());
+        assert!(get.body.is_none());
+        assert!(!get.stream);
+
+        let post = RequestConfig::post("http://site.i2p/form", "a=1");
+        assert_eq!(post.url, "http://site.i2p/form");
+        assert_eq!(post.method, "POST");
+        assert!(post.headers.is_none());
+        assert_eq!(post.body, Some(b"a=1".to_vec()));
+        assert!(!post.stream);
+        assert!(!post.force_outproxy);
+        assert!(post.first_byte_timeout.is_none());
+    }
+
+    #[test]
+    fn test_is_i2p_domain() {
+        // Test .i2p domains
+        assert!(RequestHandler::is_i2p_domain("http://example.i2p"));
+        assert!(RequestHandler::is_i2p_domain("https://example.i2p/path"));
+        assert!(RequestHandler::is_i2p_domain("http://site.i2p:8080"));
+        
+        // Test .b32.i2p domains
+        assert!(RequestHandler::is_i2p_domain("http://abc123.b32.i2p"));
+        assert!(RequestHandler::is_i2p_domain("https://xyz789.b32.i2p/path"));
+        
+        // Test non-I2P domains
+        assert!(!RequestHandler::is_i2p_domain("http://example.com"));
+        assert!(!RequestHandler::is_i2p_domain("https://google.com"));
+        assert!(!RequestHandler::is_i2p_domain("http://localhost:8080"));
+        
+        // Test edge cases
+        assert!(!RequestHandler::is_i2p_domain(""));
+        assert!(!RequestHandler::is_i2p_domain("i2p"));
+        assert!(!RequestHandler::is_i2p_domain("not-i2p.com"));
+    }
+
+    #[test]
+    fn test_request_config_creation() {
+        let config = RequestConfig {
+            url: "https://example.com".to_string(),
+            method: "GET".to_string(),
+            headers: None,
+            body: None,
+            stream: false,
+            ..Default::default()
+        };
+        
+        assert_eq!(config.url, "https://example.com");
+        assert_eq!(config.method, "GET");
+        assert!(config.headers.is_none());
+        assert!(config.body.is_none());
+        assert!(!config.stream);
+    }
+
+    #[test]
+    fn test_request_config_with_stream() {
+        let config = RequestConfig {
+            url: "https://example.com".to_string(),
+            method: "GET".to_string(),
+            headers: None,
+            body: None,
+            stream: true,
+            ..Default::default()
+        };
+        
+        assert!(config.stream);
+    }
+
+    #[test]
+    fn test_request_config_with_headers() {
+        let mut headers = std::collections::HashMap::new();
+        headers.insert("User-Agent".to_string(), "test".to_string());
+        
+        let config = RequestConfig {
+            url: "https://example.com".to_string(),
+            method: "GET".to_string(),
+            headers: Some(headers),
+            body: None,
+            stream: false,
+            ..Default::default()
+        };
+        
+        assert!(config.headers.is_some());
+        let headers = config.headers.unwrap();
+        assert_eq!(headers.get("User-Agent"), Some(&"test".to_string()));
+    }
+
+    #[test]
+    fn test_response_data_creation() {
+        let mut headers = std::collections::HashMap::new();
+        headers.insert("Content-Type".to_string(), vec!["text/html".to_string()]);
+        
+        let response = ResponseData {
+            status: 200,
+            headers,
+            body: b"Hello World".to_vec(),
+            proxy_used: "http://proxy.i2p:443".to_string(),
+            ..Default::default()
+        };
+        
+        assert_eq!(response.status, 200);
+        assert_eq!(response.headers.get("Content-Type"), Some(&vec!["text/html".to_string()]));
+        assert_eq!(response.header("content-type"), Some("text/html"));
+        assert_eq!(response.body, b"Hello World");
+        assert_eq!(response.proxy_used, "http://proxy.i2p:443");
+    }
+
+    #[test]
+    fn test_is_i2p_domain_edge_cases() {
+        // Test various edge cases
+        assert!(!RequestHandler::is_i2p_domain("http://.i2p")); // Empty host
+        assert!(!RequestHandler::is_i2p_domain("http://i2p")); // Just i2p, not .i2p
+        assert!(RequestHandler::is_i2p_domain("http://a.b32.i2p")); // Valid b32
+        assert!(RequestHandler::is_i2p_domain("https://test.i2p:8080/path?query=1")); // With port and path
+        assert!(!RequestHandler::is_i2p_domain("http://i2p.example.com")); // i2p as subdomain
+    }
+
+    #[test]
+    fn test_is_proxy_connection_error() {
+        assert!(RequestHandler::is_proxy_connection_error("Connection unreachable"));
+        assert!(RequestHandler::is_proxy_connection_error("connection refused"));
+        assert!(RequestHandler::is_proxy_connection_error("Connection timed out"));
+        assert!(RequestHandler::is_proxy_connection_error("SOCKS connect error"));
+        assert!(!RequestHandler::is_proxy_connection_error("HTTP 404 Not Found"));
+        assert!(!RequestHandler::is_proxy_connection_error("Invalid response"));
+    }
+
+    #[test]
+    fn test_request_config_all_methods() {
+        let methods = vec!["GET", "POST", "PUT", "DELETE", "PATCH", "HEAD"];
+        
+        for method in methods {
+            let config = RequestConfig {
+                url: "https://example.com".to_string(),
+                method: method.to_string(),
+                headers: None,
+                body: None,
+                stream: false,
+                ..Default::default()
+            };
+            assert_eq!(config.method, method);
+        }
+    }
+
+    #[test]
+    fn test_request_config_with_body() {
+        let body = b"test body data".to_vec();
+        let config = RequestConfig {
+            url: "https://example.com".to_string(),
+            method: "POST".to_string(),
+            headers: None,
+            body: Some(body.clone()),
+            stream: false,
+            ..Default::default()
+        };
+        
+        assert!(config.body.is_some());
+        assert_eq!(config.body.unwrap(), body);
+    }
+
+    #[test]
+    fn test_response_data_empty_body() {
+        let response = ResponseData {
+            status: 204,
+            headers: std::collections::HashMap::new(),
+            body: vec![],
+            proxy_used: "http://proxy.i2p:443".to_string(),
+            ..Default::default()
+        };
+        
+        assert_eq!(response.status, 204);
+        assert_eq!(response.body.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_send_hedged_hedges_slow_first_candidate() {
+        let slow = MockServer::respond_with(MockResponse::ok("slow").delay(Duration::from_secs(3))).await;
+        let fast = MockServer::respond_with(MockResponse::ok("fast")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+
+        let started = Instant::now();
+        let (response, proxy_used) = handler
+            .send_hedged(
+                &RequestConfig::get("http://example.com/"),
+                vec![candidate(slow.proxy()), candidate(fast.proxy())],
+                Duration::from_millis(200),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(proxy_used, fast.proxy().url);
+        assert_eq!(response.text().await.unwrap(), "fast");
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn test_send_hedged_does_not_hedge_fast_first_candidate() {
+        let first = MockServer::respond_with(MockResponse::ok("first").delay(Duration::from_millis(100))).await;
+        let second = MockServer::respond_with(MockResponse::ok("second")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+
+        let (_, proxy_used) = handler
+            .send_hedged(
+                &RequestConfig::get("http://example.com/"),
+                vec![candidate(first.proxy()), candidate(second.proxy())],
+                Duration::from_secs(2),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(proxy_used, first.proxy().url);
+        assert!(second.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_send_hedged_moves_on_after_failure() {
+        // Nothing listens on the first candidate's port
+        let dead = Proxy::new_with_type("127.0.0.1".to_string(), 1, crate::proxy_manager::ProxyType::Http);
+        let alive = MockServer::respond_with(MockResponse::ok("alive")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+
+        let started = Instant::now();
+        let (_, proxy_used) = handler
+            .send_hedged(
+                &RequestConfig::get("http://example.com/"),
+                vec![candidate(dead), candidate(alive.proxy())],
+                Duration::from_secs(10),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(proxy_used, alive.proxy().url);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_send_hedged_reports_every_failed_candidate() {
+        let dead = |port| Proxy::new_with_type("127.0.0.1".to_string(), port, crate::proxy_manager::ProxyType::Http);
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+
+        let error = handler
+            .send_hedged(
+                &RequestConfig::get("http://example.com/"),
+                vec![candidate(dead(1)), candidate(dead(2))],
+                Duration::from_millis(50),
+            )
+            .await
+            .unwrap_err();
+
+        let RequestError::AllProxiesFailed { attempts } = &error else {
+            panic!("unexpected error: {}", error);
+        };
+        let proxies: Vec<String> = attempts.iter().map(|(proxy, _)| proxy.clone()).collect();
+        assert_eq!(proxies, vec![dead(1).url, dead(2).url]);
+    }
+
+    #[test]
+    fn test_outproxy_candidates_keeps_only_i2p_outproxies() {
+        let candidates = vec![
+            candidate(Proxy::new_with_type("127.0.0.1".to_string(), 8080, crate::proxy_manager::ProxyType::Http)),
+            candidate(Proxy::new("outproxy.b32.i2p".to_string(), 443)),
+            candidate(Proxy::new("clearnet.example.com".to_string(), 1080)),
+        ];
+
+        let outproxies = RequestHandler::outproxy_candidates(candidates);
+        assert_eq!(outproxies.len(), 1);
+        assert_eq!(outproxies[0].proxy.host, "outproxy.b32.i2p");
+    }
+
+    #[tokio::test]
+    async fn test_force_outproxy_without_i2p_candidates_fails() {
+        let clearnet = MockServer::respond_with(MockResponse::ok("direct")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let config = RequestConfig {
+            force_outproxy: true,
+            ..RequestConfig::get("http://example.com/")
+        };
+
+        let result = handler
+            .create_client_and_send_request(&config, vec![candidate(clearnet.proxy())])
+            .await;
+
+        assert!(result.unwrap_err().to_string().contains("no I2P outproxy"));
+        assert!(clearnet.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_force_outproxy_candidates_chosen_among_outproxies() {
+        // Six clearnet proxies that test faster than the assumed speed of an I2P outproxy
+        let mut clearnet = Vec::new();
+        for _ in 0..6 {
+            clearnet.push(MockServer::respond_with(MockResponse::ok(vec![0u8; 10240])).await);
+        }
+        let mut proxies: Vec<Proxy> = clearnet.iter().map(MockServer::proxy).collect();
+        proxies.push(Proxy::new("outproxy.b32.i2p".to_string(), 443));
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let config = RequestConfig {
+            force_outproxy: true,
+            ..RequestConfig::get("http://example.com/")
+        };
+
+        let candidates = handler.request_candidates(&config, proxies).await.unwrap();
+        let hosts: Vec<&str> = candidates.iter().map(|c| c.proxy.host.as_str()).collect();
+        assert_eq!(hosts, vec!["outproxy.b32.i2p"]);
+        assert!(clearnet.iter().all(|server| server.requests().is_empty()));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running i2pd router"]
+    async fn test_force_outproxy_routes_through_router() {
+        let clearnet = MockServer::respond_with(MockResponse::ok("direct")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let config = RequestConfig {
+            force_outproxy: true,
+            ..RequestConfig::get("http://example.com/")
+        };
+        let candidates = vec![
+            candidate(clearnet.proxy()),
+            candidate(Proxy::new("outproxy.b32.i2p".to_string(), 443)),
+        ];
+
+        let (_, proxy_used, _) = handler
+            .create_client_and_send_request(&config, candidates)
+            .await
+            .unwrap();
+
+        assert!(proxy_used.starts_with("router-"));
+        assert!(clearnet.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_response_headers_preserved() {
+        let server = MockServer::respond_with(
+            MockResponse::ok("cookies")
+                .header("Set-Cookie", "a=1")
+                .header("Set-Cookie", "b=2"),
+        )
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+
+        let response = handler
+            .handle_request_with_specific_proxy(RequestConfig::get(server.url("/")), server.proxy(), None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response.headers.get("set-cookie"),
+            Some(&vec!["a=1".to_string(), "b=2".to_string()])
+        );
+        assert_eq!(response.header("Set-Cookie"), Some("a=1"));
+    }
+
+    #[tokio::test]
+    async fn test_completed_request_feeds_realized_speed_back() {
+        use crate::proxy_tester::ProxyTestResult;
+
+        let mid = MockServer::respond_with(MockResponse::ok(vec![7u8; 256 * 1024])).await;
+        let fast = Proxy::new("fast.example".to_string(), 3128);
+        let results = || {
+            vec![
+                ProxyTestResult::succeeded(fast.clone(), 1000.0, 50.0),
+                ProxyTestResult::succeeded(mid.proxy(), 600.0, 50.0),
+            ]
+        };
+        let selector = Arc::new(ProxySelector::new(300));
+        assert_eq!(selector.select_fastest(results()).await.unwrap().proxy.url, fast.url);
+
+        // The local server delivers far faster than the mid proxy's tested speed
+        let handler = RequestHandler::new(selector.clone());
+        handler
+            .handle_request_with_specific_proxy(RequestConfig::get(mid.url("/big")), mid.proxy(), None)
+            .await
+            .unwrap();
+
+        let ranked = selector.select_fastest_multiple(results(), 2).await;
+        assert_eq!(ranked[0].proxy.url, mid.proxy().url);
+        assert_eq!(ranked[1].proxy.url, fast.url);
+    }
+
+    #[test]
+    fn test_response_data_headers_serde_roundtrip() {
+        let mut headers = std::collections::HashMap::new();
+        headers.insert("set-cookie".to_string(), vec!["a=1".to_string(), "b=2".to_string()]);
+        let response = ResponseData {
+            status: 200,
+            headers,
+            body: Vec::new(),
+            proxy_used: "direct".to_string(),
+            ..Default::default()
+        };
+
+        let json = serde_json::to_string(&response).unwrap();
+        let restored: ResponseData = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.headers, response.headers);
+    }
+
+    #[tokio::test]
+    async fn test_stalled_body_fails_with_stalled_error() {
+        let server = MockServer::respond_with(MockResponse::ok("never sent").stall_after_headers()).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let config = RequestConfig {
+            first_byte_timeout: Some(Duration::from_millis(200)),
+            ..RequestConfig::get(server.url("/"))
+        };
+
+        let started = Instant::now();
+        let result = handler
+            .handle_request_with_specific_proxy(config, server.proxy(), None)
+            .await;
+
+        match result {
+            Err(RequestError::Stalled { waited, .. }) => assert_eq!(waited, Duration::from_millis(200)),
+            other => panic!("expected a stall, got {:?}", other),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_body_read_retry_resumes_broken_body() {
+        let server = MockServer::start(|request| match request.header("range") {
+            // The first answer promises 11 bytes but the connection closes after 5
+            None => MockResponse::ok("hello")
+                .header("Content-Length", "11")
+                .header("Accept-Ranges", "bytes"),
+            Some(range) => {
+                assert_eq!(range, "bytes=5-");
+                MockResponse::new(206, " world").header("Content-Range", "bytes 5-10/11")
+            }
+        })
+        .await;
+
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_body_read_retries(2);
+        let response = handler
+            .handle_request_with_specific_proxy(RequestConfig::get(server.url("/file")), server.proxy(), None)
+            .await
+            .unwrap();
+        assert_eq!(response.body, b"hello world");
+        assert_eq!(server.requests().len(), 2);
+
+        // Without the option the broken body is an error, and POSTs are never retried
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        assert!(handler
+            .handle_request_with_specific_proxy(RequestConfig::get(server.url("/file")), server.proxy(), None)
+            .await
+            .is_err());
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_body_read_retries(2);
+        assert!(handler
+            .handle_request_with_specific_proxy(RequestConfig::post(server.url("/file"), "x"), server.proxy(), None)
+            .await
+            .is_err());
+        assert_eq!(server.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_response_timeout_trips_on_slow_headers() {
+        let server = MockServer::respond_with(MockResponse::ok("late").delay(Duration::from_secs(2))).await;
+        let selector = Arc::new(ProxySelector::new(300));
+        let handler = RequestHandler::new(selector.clone());
+        let config = RequestConfig {
+            response_timeout: Some(Duration::from_millis(200)),
+            ..RequestConfig::get(server.url("/"))
+        };
+
+        let started = Instant::now();
+        let result = handler
+            .handle_request_with_specific_proxy(config, server.proxy(), None)
+            .await;
+        match result {
+            Err(RequestError::NoResponse { waited, .. }) => assert_eq!(waited, Duration::from_millis(200)),
+            other => panic!("expected no response, got {:?}", other),
+        }
+        assert!(started.elapsed() < Duration::from_secs(1));
+        // Counts against the proxy like a failed connection
+        let known = selector.known_proxies();
+        assert!(known.iter().any(|k| k.proxy.url == server.proxy().url && k.failures == 1));
+    }
+
+    #[tokio::test]
+    async fn test_response_timeout_does_not_limit_body() {
+        // Headers come right away, the body takes ~500ms
+        let server = MockServer::respond_with(
+            MockResponse::ok(vec![b'x'; 40]).chunked(4, Duration::from_millis(50)),
+        )
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let config = RequestConfig {
+            response_timeout: Some(Duration::from_millis(200)),
+            ..RequestConfig::get(server.url("/"))
+        };
+
+        let response = handler
+            .handle_request_with_specific_proxy(config, server.proxy(), None)
+            .await
+            .unwrap();
+        assert_eq!(response.body.len(), 40);
+    }
+
+    #[tokio::test]
+    async fn test_dns_resolver_resolves_clearnet_proxy_host() {
+        // Knows a single name, and counts the lookups
+        struct StubResolver {
+            addr: std::net::SocketAddr,
+            lookups: Arc<std::sync::atomic::AtomicUsize>,
+        }
+        impl reqwest::dns::Resolve for StubResolver {
+            fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+                self.lookups.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let found = name.as_str() == "stub-proxy.test";
+                let addr = self.addr;
+                Box::pin(async move {
+                    if found {
+                        let addrs: reqwest::dns::Addrs = Box::new(std::iter::once(addr));
+                        Ok(addrs)
+                    } else {
+                        Err("unknown name".into())
+                    }
+                })
+            }
+        }
+
+        let server = MockServer::respond_with(MockResponse::ok("resolved")).await;
+        let lookups = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_dns_resolver(Arc::new(StubResolver {
+            addr: server.addr,
+            lookups: lookups.clone(),
+        }));
+        let proxy = Proxy::new_with_type("stub-proxy.test".to_string(), server.addr.port(), ProxyType::Http);
+
+        let response = handler
+            .handle_request_with_specific_proxy(RequestConfig::get("http://example.com/"), proxy, None)
+            .await
+            .unwrap();
+
+        assert_eq!(response.body, b"resolved");
+        assert!(lookups.load(std::sync::atomic::Ordering::SeqCst) >= 1);
+    }
+
+    #[tokio::test]
+    async fn test_min_tls_version_applied_to_clients() {
+        let handler = RequestHandler::builder(Arc::new(ProxySelector::new(300)))
+            .min_tls_version(reqwest::tls::Version::TLS_1_3)
+            .build()
+            .unwrap();
+        assert_eq!(handler.min_tls_version(), Some(reqwest::tls::Version::TLS_1_3));
+        assert!(RequestHandler::new(Arc::new(ProxySelector::new(300))).min_tls_version().is_none());
+
+        // Clients still build and work with the minimum set
+        let server = MockServer::respond_with(MockResponse::ok("fine")).await;
+        let response = handler
+            .handle_request_with_specific_proxy(RequestConfig::get(server.url("/")), server.proxy(), None)
+            .await
+            .unwrap();
+        assert_eq!(response.body, b"fine");
+    }
+
+    #[tokio::test]
+    async fn test_recorder_captures_exchange() {
+        let server = MockServer::respond_with(
+            MockResponse::ok("recorded body")
+                .header("Content-Type", "text/plain")
+                .header("Set-Cookie", "session=abc"),
+        )
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_recorder(8);
+        let mut headers = std::collections::HashMap::new();
+        headers.insert("Authorization".to_string(), "Bearer secret-token".to_string());
+        headers.insert("Accept".to_string(), "text/plain".to_string());
+        let config = RequestConfig {
+            headers: Some(headers),
+            ..RequestConfig::get(server.url("/page"))
+        };
+
+        handler
+            .handle_request_with_specific_proxy(config, server.proxy(), None)
+            .await
+            .unwrap();
+
+        let exported = handler.export_recording().unwrap();
+        assert!(!exported.contains("secret-token"));
+        assert!(!exported.contains("session=abc"));
+        let entries: Vec<crate::recorder::RecordedExchange> = serde_json::from_str(&exported).unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.method, "GET");
+        assert_eq!(entry.url, server.url("/page"));
+        assert_eq!(entry.status, Some(200));
+        assert_eq!(entry.request_headers["Accept"], "text/plain");
+        assert_eq!(entry.request_headers["Authorization"], "[REDACTED]");
+        assert_eq!(entry.response_headers["content-type"], vec!["text/plain".to_string()]);
+        assert_eq!(entry.response_body_bytes, "recorded body".len());
+        assert_eq!(entry.proxy_used.as_deref(), Some(server.proxy().url.as_str()));
+        assert!(entry.error.is_none());
+
+        assert!(RequestHandler::new(Arc::new(ProxySelector::new(300))).export_recording().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_slow_body_within_stall_timeout_completes() {
+        // 40 bytes at 4 bytes per 50ms takes ~500ms overall, but never pauses for 300ms
+        let server = MockServer::respond_with(
+            MockResponse::ok(vec![b'x'; 40]).chunked(4, Duration::from_millis(50)),
+        )
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let config = RequestConfig {
+            first_byte_timeout: Some(Duration::from_millis(300)),
+            body_stall_timeout: Some(Duration::from_millis(300)),
+            ..RequestConfig::get(server.url("/"))
+        };
+
+        let response = handler
+            .handle_request_with_specific_proxy(config, server.proxy(), None)
+            .await
+            .unwrap();
+        assert_eq!(response.body.len(), 40);
+    }
+
+    #[test]
+    fn test_candidate_for_proxy_used() {
+        let candidates = vec![
+            candidate(Proxy::new_with_type("127.0.0.1".to_string(), 8080, crate::proxy_manager::ProxyType::Http)),
+            candidate(Proxy::new("outproxy.b32.i2p".to_string(), 443)),
+        ];
+        let clearnet_url = candidates[0].proxy.url.clone();
+        let i2p_url = candidates[1].proxy.url.clone();
+
+        let found = RequestHandler::candidate_for_proxy_used(&candidates, &clearnet_url).unwrap();
+        assert_eq!(found.url, clearnet_url);
+        let label = format!("router-http://127.0.0.1:4444 (for {})", i2p_url);
+        let found = RequestHandler::candidate_for_proxy_used(&candidates, &label).unwrap();
+        assert_eq!(found.url, i2p_url);
+        assert!(RequestHandler::candidate_for_proxy_used(&candidates, "http://127.0.0.1:4444").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_all_from_host_reuses_one_client() {
+        // Stands in for the router's HTTP proxy: echoes the requested URL
+        let router = MockServer::start(|request| MockResponse::ok(request.target.clone())).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let (client, proxy_used) = handler.i2p_client(false, router.addr.port(), router.addr.port()).unwrap();
+        let paths = vec![
+            "/index.html".to_string(),
+            "style.css".to_string(),
+            "/img/logo.png?v=2".to_string(),
+            "http://elsewhere.i2p/steal".to_string(),
+        ];
+
+        let results = handler
+            .fetch_all_with_client(&client, &proxy_used, "http://site.i2p/", paths, None)
+            .await;
+
+        assert_eq!(results.len(), 4);
+        let bodies: Vec<String> = results[..3]
+            .iter()
+            .map(|r| String::from_utf8(r.as_ref().unwrap().body.clone()).unwrap())
+            .collect();
+        assert_eq!(
+            bodies,
+            vec![
+                "http://site.i2p/index.html",
+                "http://site.i2p/style.css",
+                "http://site.i2p/img/logo.png?v=2",
+            ]
+        );
+        assert!(results[3].as_ref().unwrap_err().to_string().contains("elsewhere.i2p"));
+        assert_eq!(router.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_require_fresh_proxy_retests_and_skips_sticky() {
+        let proxy_server = MockServer::respond_with(MockResponse::ok("fresh")).await;
+        let selector = Arc::new(ProxySelector::new(300));
+        let dead = Proxy::new_with_type("127.0.0.1".to_string(), 1, ProxyType::Http);
+        selector
+            .select_fastest(vec![crate::proxy_tester::ProxyTestResult::succeeded(dead.clone(), 1000.0, 10.0)])
+            .await;
+        selector.record_host_success("site.test", &selector.get_current_proxy().unwrap());
+        let handler = RequestHandler::new(selector.clone());
+
+        let config = RequestConfig {
+            require_fresh_proxy: true,
+            ..RequestConfig::get("http://site.test/")
+        };
+        let response = handler.handle_request(config, vec![proxy_server.proxy()]).await.unwrap();
+
+        assert_eq!(response.body, b"fresh");
+        // The proxy was tested before carrying the request, and the cached one never tried
+        let requests = proxy_server.requests();
+        assert_ne!(requests[0].target, "http://site.test/");
+        assert_eq!(requests.last().unwrap().target, "http://site.test/");
+        assert!(selector.known_proxies().iter().all(|known| known.proxy.url != dead.url || known.failures == 0));
+    }
+
+    #[tokio::test]
+    async fn test_header_limits_truncate_or_reject() {
+        let server = MockServer::respond_with(
+            (0..200).fold(MockResponse::ok("body"), |response, i| response.header(&format!("X-Filler-{}", i), "x")),
+        )
+        .await;
+        let send = |policy| {
+            let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+                .with_header_limits(20, 64 * 1024, policy);
+            let proxy = server.proxy();
+            async move {
+                handler
+                    .handle_request_with_specific_proxy(RequestConfig::get("http://headers.test/"), proxy, None)
+                    .await
+            }
+        };
+
+        let truncated = send(HeaderLimitPolicy::Truncate).await.unwrap();
+        assert_eq!(truncated.headers.values().map(Vec::len).sum::<usize>(), 20);
+        assert_eq!(truncated.body, b"body");
+        // Sent after all the filler, but never dropped
+        assert_eq!(truncated.headers["content-length"], vec!["4".to_string()]);
+
+        match send(HeaderLimitPolicy::Reject).await {
+            Err(RequestError::HeadersTooLarge { count, .. }) => assert_eq!(count, 21),
+            other => panic!("expected HeadersTooLarge, got {:?}", other),
+        }
+
+        // A byte limit applies on its own as well
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::SERVER,
+            reqwest::header::HeaderValue::from_static("a-rather-long-server-name"),
+        );
+        let (extracted, over) = extract_headers_within(&headers, 10, 16);
+        assert!(extracted.is_empty());
+        assert_eq!(over, Some((1, 31)));
+
+        // Protected headers are kept even when they alone go over
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            reqwest::header::HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+        let (extracted, over) = extract_headers_within(&headers, 10, 16);
+        assert_eq!(extracted.keys().collect::<Vec<_>>(), vec!["content-type"]);
+        assert_eq!(over, Some((1, 36)));
+    }
+
+    #[tokio::test]
+    async fn test_no_failover_stops_after_first_candidate() {
+        let backup = MockServer::respond_with(MockResponse::ok("duplicated")).await;
+        let dead = Proxy::new_with_type("127.0.0.1".to_string(), 1, crate::proxy_manager::ProxyType::Http);
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let config = RequestConfig {
+            no_failover: true,
+            ..RequestConfig::post("http://shop.example/order", "once")
+        };
+
+        let result = handler
+            .create_client_and_send_request(&config, vec![candidate(dead.clone()), candidate(backup.proxy())])
+            .await;
+
+        assert!(result.is_err());
+        assert!(backup.requests().is_empty());
+
+        // Failover stays the default
+        let (_, proxy_used, _) = handler
+            .create_client_and_send_request(
+                &RequestConfig::post("http://shop.example/order", "once"),
+                vec![candidate(dead), candidate(backup.proxy())],
+            )
+            .await
+            .unwrap();
+        assert_eq!(proxy_used, backup.proxy().url);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_answers_when_all_candidates_fail() {
+        let dead = |port| Proxy::new_with_type("127.0.0.1".to_string(), port, ProxyType::Http);
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(0)));
+        let fallback = || ResponseData {
+            status: 200,
+            body: b"cached copy".to_vec(),
+            proxy_used: "fallback".to_string(),
+            ..Default::default()
+        };
+
+        let response = handler
+            .handle_request_or_else(RequestConfig::get("http://example.com/"), vec![dead(1), dead(2)], fallback)
+            .await
+            .unwrap();
+        assert_eq!(response.body, b"cached copy");
+        assert_eq!(response.proxy_used, "fallback");
+
+        // Failing stays the default
+        assert!(handler
+            .handle_request(RequestConfig::get("http://example.com/"), vec![dead(1), dead(2)])
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_all_proxies_failed_lists_every_attempt() {
+        let dead = |port| Proxy::new_with_type("127.0.0.1".to_string(), port, ProxyType::Http);
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(0)));
+
+        let error = handler
+            .create_client_and_send_request(
+                &RequestConfig::get("http://example.com/"),
+                vec![candidate(dead(1)), candidate(dead(2))],
+            )
+            .await
+            .unwrap_err();
+
+        let RequestError::AllProxiesFailed { attempts } = &error else {
+            panic!("unexpected error: {}", error);
+        };
+        let proxies: Vec<String> = attempts.iter().map(|(proxy, _)| proxy.clone()).collect();
+        assert_eq!(proxies, vec![dead(1).url, dead(2).url]);
+        assert!(attempts.iter().all(|(_, reason)| !reason.is_empty()));
+        assert!(error.to_string().starts_with("All 2 proxy candidates failed: "));
+    }
+
+    #[tokio::test]
+    async fn test_fallback_not_used_for_http_errors() {
+        // Passes the proxy test, but the target itself answers 404
+        let server = MockServer::start(|request| {
+            if request.target.contains("clearnet.test") {
+                MockResponse::new(404, "missing")
+            } else {
+                MockResponse::ok(vec![0u8; 1024])
+            }
+        })
+        .await;
+        let selector = Arc::new(ProxySelector::new(0));
+        let handler = RequestHandler::new(selector);
+        let config = RequestConfig {
+            error_on_status: true,
+            ..RequestConfig::get("http://clearnet.test/")
+        };
+        let called = std::cell::Cell::new(false);
+
+        let result = handler
+            .handle_request_or_else(config, vec![server.proxy()], || {
+                called.set(true);
+                ResponseData::default()
+            })
+            .await;
+
+        assert!(matches!(result, Err(RequestError::HttpStatus { code: 404, .. })));
+        assert!(!called.get());
+    }
+
+    #[tokio::test]
+    async fn test_fallback_not_used_for_invalid_requests() {
+        let server = MockServer::respond_with(MockResponse::ok(vec![0u8; 1024])).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(0)));
+        let config = RequestConfig {
+            method: "BREW".to_string(),
+            ..RequestConfig::get("http://clearnet.test/")
+        };
+        let called = std::cell::Cell::new(false);
+
+        let result = handler
+            .handle_request_or_else(config, vec![server.proxy()], || {
+                called.set(true);
+                ResponseData::default()
+            })
+            .await;
+
+        let Err(RequestError::Other(message)) = result else {
+            panic!("expected the invalid method to fail the request");
+        };
+        assert!(message.contains("Unsupported HTTP method"));
+        assert!(!called.get());
+    }
+
+    #[tokio::test]
+    async fn test_attempt_log_lists_failed_and_successful_proxies() {
+        // Fastest in the proxy test, but never answers the real request in time
+        let stuck = MockServer::start(|request| {
+            if request.target.contains("site.test") {
+                MockResponse::ok("late").delay(Duration::from_secs(2))
+            } else {
+                MockResponse::ok(vec![0u8; 64 * 1024])
+            }
+        })
+        .await;
+        let working = MockServer::start(|request| {
+            if request.target.contains("site.test") {
+                MockResponse::ok("hello")
+            } else {
+                MockResponse::ok(vec![0u8; 1024]).delay(Duration::from_millis(300))
+            }
+        })
+        .await;
+        let config = || RequestConfig {
+            response_timeout: Some(Duration::from_millis(500)),
+            ..RequestConfig::get("http://site.test/")
+        };
+
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(0))).with_attempt_log(true);
+        let response = handler
+            .handle_request(config(), vec![stuck.proxy(), working.proxy()])
+            .await
+            .unwrap();
+        assert_eq!(response.body, b"hello");
+        assert_eq!(response.attempts.len(), 2);
+        assert_eq!(response.attempts[0].proxy, stuck.proxy().url);
+        assert!(!response.attempts[0].succeeded);
+        assert!(response.attempts[0].error.is_some());
+        assert_eq!(
+            response.attempts[1],
+            AttemptInfo { proxy: working.proxy().url, succeeded: true, error: None }
+        );
+
+        // Not logged by default
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(0)));
+        let response = handler
+            .handle_request(config(), vec![stuck.proxy(), working.proxy()])
+            .await
+            .unwrap();
+        assert!(response.attempts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_memory_budget_serializes_large_bodies() {
+        let server = MockServer::respond_with(MockResponse::ok(vec![7u8; 800]).chunked(200, Duration::from_millis(100))).await;
+        let handler = Arc::new(RequestHandler::new(Arc::new(ProxySelector::new(300))).with_memory_budget(1000));
+
+        let start = Instant::now();
+        let downloads: Vec<_> = (0..2)
+            .map(|_| {
+                let handler = handler.clone();
+                let proxy = server.proxy();
+                tokio::spawn(async move {
+                    handler
+                        .handle_request_with_specific_proxy(RequestConfig::get("http://big.test/"), proxy, None)
+                        .await
+                })
+            })
+            .collect();
+        for download in downloads {
+            assert_eq!(download.await.unwrap().unwrap().body.len(), 800);
+        }
+
+        // Each body takes ~400 ms and both together do not fit the budget
+        assert!(start.elapsed() >= Duration::from_millis(750));
+    }
+
+    #[tokio::test]
+    async fn test_memory_budget_rejects_oversized_body() {
+        let server = MockServer::respond_with(MockResponse::ok(vec![0u8; 2000])).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_memory_budget(1000);
+
+        let result = handler
+            .handle_request_with_specific_proxy(RequestConfig::get("http://big.test/"), server.proxy(), None)
+            .await;
+        assert!(result.unwrap_err().to_string().contains("memory budget"));
+    }
+
+    #[tokio::test]
+    async fn test_memory_budget_reserves_unknown_length_as_it_arrives() {
+        let server = MockServer::start(|request| {
+            let size = if request.target.contains("huge") { 2000 } else { 400 };
+            MockResponse::ok(vec![7u8; size]).unknown_length().chunked(100, Duration::from_millis(100))
+        })
+        .await;
+        let handler = Arc::new(RequestHandler::new(Arc::new(ProxySelector::new(300))).with_memory_budget(1000));
+
+        // Both fit together, so neither waits for the other
+        let start = Instant::now();
+        let downloads: Vec<_> = (0..2)
+            .map(|_| {
+                let handler = handler.clone();
+                let proxy = server.proxy();
+                tokio::spawn(async move {
+                    handler
+                        .handle_request_with_specific_proxy(RequestConfig::get("http://small.test/"), proxy, None)
+                        .await
+                })
+            })
+            .collect();
+        for download in downloads {
+            assert_eq!(download.await.unwrap().unwrap().body.len(), 400);
+        }
+        assert!(start.elapsed() < Duration::from_millis(750));
+
+        let result = handler
+            .handle_request_with_specific_proxy(RequestConfig::get("http://huge.test/"), server.proxy(), None)
+            .await;
+        assert!(result.unwrap_err().to_string().contains("memory budget"));
+        // What the failed body held is given back
+        let (budget, total) = handler.memory_budget.as_ref().unwrap();
+        assert_eq!(budget.available_permits(), *total as usize);
+    }
+
+    #[tokio::test]
+    async fn test_direct_fallback_without_proxies() {
+        let site = MockServer::respond_with(MockResponse::ok("straight")).await;
+
+        let strict = RequestHandler::new(Arc::new(ProxySelector::new(0)));
+        assert!(strict.handle_request(RequestConfig::get(site.url("/")), Vec::new()).await.is_err());
+        assert!(site.requests().is_empty());
+
+        let lenient = RequestHandler::new(Arc::new(ProxySelector::new(0))).with_direct_fallback(true);
+        let response = lenient
+            .handle_request(RequestConfig::get(site.url("/")), Vec::new())
+            .await
+            .unwrap();
+        assert!(response.direct);
+        assert_eq!(response.proxy_used, "direct");
+        assert_eq!(response.body, b"straight");
+    }
+
+    #[tokio::test]
+    async fn test_probe_working_keeps_only_successful_proxies() {
+        let good = MockServer::respond_with(MockResponse::ok("")).await;
+        let erroring = MockServer::respond_with(MockResponse::new(503, "")).await;
+        let dead = Proxy::new_with_type("127.0.0.1".to_string(), 1, crate::proxy_manager::ProxyType::Http);
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+
+        let working = handler
+            .probe_working("http://example.com/", vec![dead, erroring.proxy(), good.proxy()], 3)
+            .await;
+
+        assert_eq!(working.len(), 1);
+        assert_eq!(working[0].0.url, good.proxy().url);
+        assert_eq!(good.requests()[0].method, "HEAD");
+    }
+
+    #[tokio::test]
+    async fn test_batch_deadline_cuts_off_slow_paths() {
+        let router_proxy = MockServer::start(|request| {
+            if request.target.contains("slow") {
+                MockResponse::ok("slow").delay(Duration::from_secs(5))
+            } else {
+                MockResponse::ok("fast")
+            }
+        })
+        .await;
+        let router = Arc::new(FakeRouter::new(router_proxy.addr.port(), 1));
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_router(router);
+        let paths = vec!["/fast1".to_string(), "/slow".to_string(), "/fast2".to_string()];
+
+        let start = Instant::now();
+        let results = handler
+            .fetch_all_from_host_within("http://site.i2p/", paths, Duration::from_millis(500))
+            .await;
+
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(results[0].as_ref().unwrap().body, b"fast");
+        assert_eq!(results[1].as_ref().unwrap_err(), &RequestError::DeadlineExceeded);
+        assert_eq!(results[2].as_ref().unwrap().body, b"fast");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_all_from_host_rejects_clearnet_base() {
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let results = handler
+            .fetch_all_from_host("http://example.com/", vec!["/a".to_string(), "/b".to_string()])
+            .await;
+
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.is_err()));
+    }
+
+    #[tokio::test]
+    async fn test_final_url_follows_redirects() {
+        let server = MockServer::start(|request| {
+            if request.target.ends_with("/start") {
+                MockResponse::new(302, "").header("Location", "http://landing.test/final")
+            } else {
+                MockResponse::ok("landed")
+            }
+        })
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+
+        let response = handler
+            .handle_request_with_specific_proxy(RequestConfig::get("http://origin.test/start"), server.proxy(), None)
+            .await
+            .unwrap();
+
+        assert_eq!(response.body, b"landed");
+        assert_eq!(response.final_url, "http://landing.test/final");
+    }
+
+    #[tokio::test]
+    async fn test_non_2xx_is_ok_by_default() {
+        let server = MockServer::respond_with(MockResponse::new(404, "missing")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+
+        let response = handler
+            .handle_request_with_specific_proxy(RequestConfig::get(server.url("/")), server.proxy(), None)
+            .await
+            .unwrap();
+
+        assert_eq!(response.status, 404);
+        assert_eq!(response.body, b"missing");
+    }
+
+    #[tokio::test]
+    async fn test_error_on_status_returns_http_status_error() {
+        let server = MockServer::respond_with(MockResponse::new(503, "overloaded")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let config = RequestConfig {
+            error_on_status: true,
+            ..RequestConfig::get(server.url("/"))
+        };
+
+        let result = handler
+            .handle_request_with_specific_proxy(config, server.proxy(), None)
+            .await;
+
+        assert_eq!(
+            result.unwrap_err(),
+            RequestError::HttpStatus { code: 503, body: b"overloaded".to_vec() }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_on_status_does_not_fail_over() {
+        let first = MockServer::respond_with(MockResponse::new(500, "broken")).await;
+        let second = MockServer::respond_with(MockResponse::ok("fine")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let config = RequestConfig {
+            error_on_status: true,
+            ..RequestConfig::get("http://example.com/")
+        };
+
+        // An HTTP error is delivered by a working proxy, so the next candidate is not tried
+        let (response, proxy_used, _) = handler
+            .create_client_and_send_request(&config, vec![candidate(first.proxy()), candidate(second.proxy())])
+            .await
+            .unwrap();
+        assert_eq!(proxy_used, first.proxy().url);
+        assert!(second.requests().is_empty());
+
+        let result = handler.read_response_data(response, &config, proxy_used, None).await;
+        assert!(matches!(result, Err(RequestError::HttpStatus { code: 500, .. })));
+    }
+
+    #[tokio::test]
+    async fn test_http_status_error_includes_body_snippet() {
+        let server = MockServer::respond_with(MockResponse::new(
+            502,
+            "<html><body>Outproxy not reachable</body></html>",
+        ))
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let config = RequestConfig {
+            error_on_status: true,
+            ..RequestConfig::get(server.url("/"))
+        };
+
+        let err = handler
+            .handle_request_with_specific_proxy(config, server.proxy(), None)
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().starts_with("HTTP error 502: "));
+        assert!(err.to_string().contains("Outproxy not reachable"));
+    }
+
+    #[tokio::test]
+    async fn test_body_snippet_is_capped() {
+        let server = MockServer::respond_with(MockResponse::new(500, vec![b'e'; 10 * 1024])).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+
+        for stream in [false, true] {
+            let config = RequestConfig {
+                error_on_status: true,
+                stream,
+                ..RequestConfig::get(server.url("/"))
+            };
+            let err = handler
+                .handle_request_with_specific_proxy(config, server.proxy(), None)
+                .await
+                .unwrap_err();
+
+            let snippet = err.body_snippet().unwrap();
+            assert!(snippet.len() <= ERROR_SNIPPET_BYTES + 3);
+            assert!(snippet.starts_with("eeee"));
+        }
+    }
+
+    #[test]
+    fn test_body_snippet_absent_without_body() {
+        let err = RequestError::HttpStatus { code: 404, body: Vec::new() };
+        assert!(err.body_snippet().is_none());
+        assert_eq!(err.to_string(), "HTTP error 404");
+        assert!(RequestError::Other("boom".to_string()).body_snippet().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_preflight_success_proceeds_with_download() {
+        let server = MockServer::start(|request| match request.header("Range") {
+            Some(_) => MockResponse::new(206, vec![b'p'; 1024]).header("Content-Range", "bytes 0-1023/4096"),
+            None => MockResponse::ok(vec![b'f'; 4096]),
+        })
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let config = RequestConfig {
+            preflight: true,
+            ..RequestConfig::get(server.url("/big.bin"))
+        };
+
+        let response = handler
+            .handle_request_with_specific_proxy(config, server.proxy(), None)
+            .await
+            .unwrap();
+
+        assert_eq!(response.body.len(), 4096);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].header("Range"), Some("bytes=0-1023"));
+        assert!(requests[1].header("Range").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_preflight_failure_skips_download() {
+        let server = MockServer::start(|request| match request.header("Range") {
+            Some(_) => MockResponse::new(503, "outproxy overloaded"),
+            None => MockResponse::ok(vec![b'f'; 4096]),
+        })
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let config = RequestConfig {
+            preflight: true,
+            ..RequestConfig::get(server.url("/big.bin"))
+        };
+
+        let result = handler
+            .handle_request_with_specific_proxy(config, server.proxy(), None)
+            .await;
+
+        match result {
+            Err(RequestError::PreflightFailed { reason, .. }) => assert!(reason.contains("503")),
+            other => panic!("expected a preflight failure, got {:?}", other),
+        }
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_preflight_error_status_does_not_fail_proxy() {
+        let server = MockServer::respond_with(MockResponse::new(404, "no such file")).await;
+        let selector = Arc::new(ProxySelector::new(300));
+        let handler = RequestHandler::new(selector.clone());
+        let config = RequestConfig {
+            preflight: true,
+            ..RequestConfig::get(server.url("/missing.bin"))
+        };
+
+        let result = handler
+            .handle_request_with_specific_proxy(config, server.proxy(), None)
+            .await;
+
+        assert!(matches!(result, Err(RequestError::PreflightFailed { .. })));
+        assert!(selector.known_proxies().iter().all(|known| known.failures == 0));
+    }
+
+    #[tokio::test]
+    async fn test_preflight_rejects_range_not_at_start() {
+        let server = MockServer::respond_with(
+            MockResponse::new(206, vec![b'p'; 1024]).header("Content-Range", "bytes 4096-5119/8192"),
+        )
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let config = RequestConfig {
+            preflight: true,
+            ..RequestConfig::get(server.url("/big.bin"))
+        };
+
+        let result = handler
+            .handle_request_with_specific_proxy(config, server.proxy(), None)
+            .await;
+
+        match result {
+            Err(RequestError::PreflightFailed { reason, .. }) => assert!(reason.contains("instead of the start")),
+            other => panic!("expected a preflight failure, got {:?}", other),
+        }
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_preflight_rejects_empty_probe() {
+        let server = MockServer::respond_with(
+            MockResponse::new(206, "")
+                .header("Content-Length", "10")
+                .header("Content-Range", "bytes 0-9/10"),
+        )
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let config = RequestConfig {
+            preflight: true,
+            ..RequestConfig::get(server.url("/big.bin"))
+        };
+
+        let result = handler
+            .handle_request_with_specific_proxy(config, server.proxy(), None)
+            .await;
+        assert!(matches!(result, Err(RequestError::PreflightFailed { .. })));
+    }
+
+    #[test]
+    fn test_router_proxy_kind_custom_ports() {
+        let router = FakeRouter::new(14444, 14447);
+
+        assert_eq!(router_proxy_kind(14444, &router), Ok(RouterProxyKind::Http));
+        assert_eq!(router_proxy_kind(14447, &router), Ok(RouterProxyKind::Https));
+        // The stock ports are not special once the router listens elsewhere
+        assert!(router_proxy_kind(4444, &router).is_err());
+    }
+
+    #[test]
+    fn test_router_proxy_kind_unknown_port() {
+        let err = router_proxy_kind(9999, &FakeRouter::new(4444, 4447)).unwrap_err();
+        assert!(err.contains("9999"));
+        assert!(err.contains("4444"));
+        assert!(err.contains("4447"));
+    }
+
+    #[test]
+    fn test_router_client_labels_custom_port() {
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let outproxy = candidate(Proxy::new("outproxy.b32.i2p".to_string(), 443));
+
+        let (_, proxy_used) = handler.router_client(&outproxy, RouterProxyKind::Https, 14447).unwrap();
+        assert_eq!(proxy_used, format!("router-https://127.0.0.1:14447 (for {})", outproxy.proxy.url));
+        assert_eq!(RequestHandler::candidate_for_proxy_used(&[outproxy.clone()], &proxy_used).unwrap().url, outproxy.proxy.url);
+    }
+
+    #[tokio::test]
+    async fn test_host_override_reaches_the_wire() {
+        let server = MockServer::respond_with(MockResponse::ok("vhost")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let mut headers = std::collections::HashMap::new();
+        headers.insert("Host".to_string(), "ignored.example".to_string());
+        let config = RequestConfig {
+            headers: Some(headers),
+            host_override: Some("blog.example.i2p".to_string()),
+            ..RequestConfig::get("http://shared.b32.example/")
+        };
+
+        handler
+            .handle_request_with_specific_proxy(config, server.proxy(), None)
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        let hosts: Vec<&str> = requests[0]
+            .headers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case("host"))
+            .map(|(_, value)| value.as_str())
+            .collect();
+        assert_eq!(hosts, vec!["blog.example.i2p"]);
+        assert_eq!(requests[0].target, "http://shared.b32.example/");
+    }
+
+    #[test]
+    fn test_invalid_host_override_rejected() {
+        let client = Client::new();
+        let config = RequestConfig {
+            host_override: Some("bad\nhost".to_string()),
+            ..RequestConfig::get("http://example.com/")
+        };
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        assert!(handler.build_request(&client, &config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_excluded_i2p_hosts_bypass_router() {
+        let router_proxy = MockServer::respond_with(MockResponse::ok("eepsite")).await;
+        let clearnet_proxy = MockServer::respond_with(MockResponse::ok("internal")).await;
+        let router = Arc::new(FakeRouter::new(router_proxy.addr.port(), 1));
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_router(router)
+            .with_i2p_exclusions(vec!["Wiki.corp.i2p".to_string(), ".internal.i2p".to_string()]);
+
+        for url in ["http://wiki.corp.i2p/", "http://build.internal.i2p/status"] {
+            let response = handler
+                .handle_request(RequestConfig::get(url), vec![clearnet_proxy.proxy()])
+                .await
+                .unwrap();
+            assert_eq!(response.body, b"internal");
+        }
+        assert!(router_proxy.requests().is_empty());
+
+        // Exact entries don't cover subdomains, suffix entries cover the domain itself
+        assert!(handler.routes_via_i2p("http://docs.wiki.corp.i2p/"));
+        assert!(!handler.routes_via_i2p("http://internal.i2p/"));
+        assert!(handler.routes_via_i2p("http://site.i2p/"));
+    }
+
+    #[tokio::test]
+    async fn test_network_change_forces_fresh_selection() {
+        let old_proxy = MockServer::respond_with(MockResponse::ok("old network")).await;
+        let new_proxy = MockServer::respond_with(MockResponse::ok("new network")).await;
+        let router = Arc::new(FakeRouter::new(1, 1));
+        let selector = Arc::new(ProxySelector::new(300));
+        let handler = RequestHandler::new(selector.clone()).with_router(router.clone());
+
+        handler.get_proxy_candidates_for_request(vec![old_proxy.proxy()], 1).await.unwrap();
+        assert_eq!(selector.get_current_proxy().unwrap().proxy.url, old_proxy.proxy().url);
+
+        handler.notify_network_changed().unwrap();
+        assert!(selector.get_current_proxy().is_none());
+        assert_eq!(router.restart_calls(), 0);
+
+        // Within the retest interval, the next selection tests the proxies it is given again
+        let selected = handler.get_proxy_candidates_for_request(vec![new_proxy.proxy()], 1).await.unwrap();
+        assert_eq!(selected[0].proxy.url, new_proxy.proxy().url);
+        assert!(!new_proxy.requests().is_empty());
+
+        let restarting = RequestHandler::new(selector.clone())
+            .with_router(router.clone())
+            .with_router_restart_on_network_change(true);
+        restarting.notify_network_changed().unwrap();
+        assert_eq!(router.restart_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_i2p_site_uses_injected_router_ports() {
+        let router_proxy = MockServer::respond_with(MockResponse::ok("eepsite")).await;
+        let router = Arc::new(FakeRouter::new(router_proxy.addr.port(), 1));
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_router(router.clone());
+
+        let response = handler
+            .handle_request(RequestConfig::get("http://site.i2p/page"), Vec::new())
+            .await
+            .unwrap();
+
+        assert_eq!(response.body, b"eepsite");
+        assert_eq!(response.proxy_used, format!("http://127.0.0.1:{}", router_proxy.addr.port()));
+        assert_eq!(router_proxy.requests()[0].target, "http://site.i2p/page");
+        assert_eq!(router.ensure_running_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_ping_i2p_through_router() {
+        let router_proxy = MockServer::respond_with(MockResponse::ok("")).await;
+        let router = Arc::new(FakeRouter::new(router_proxy.addr.port(), 1));
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_router(router.clone());
+
+        handler.ping_i2p("abcdef.b32.i2p").await.unwrap();
+
+        let request = &router_proxy.requests()[0];
+        assert_eq!(request.method, "HEAD");
+        assert_eq!(request.target, "http://abcdef.b32.i2p/");
+        assert_eq!(router.ensure_running_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_ping_i2p_reports_unreachable_destination() {
+        let router_proxy = MockServer::respond_with(MockResponse::new(504, "Host not found")).await;
+        let router = Arc::new(FakeRouter::new(router_proxy.addr.port(), 1));
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_router(router);
+
+        let err = handler.ping_i2p("abcdef.b32.i2p").await.unwrap_err();
+        assert!(err.contains("unreachable"));
+
+        let err = handler.ping_i2p("example.com").await.unwrap_err();
+        assert!(err.contains("not an I2P address"));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running i2pd router"]
+    async fn test_ping_i2p_live_destination() {
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let rtt = handler
+            .ping_i2p("proxygwdhg5z7mn326hfqqzsbnkrbzea4xrss2v7exrjx4c65uka.b32.i2p")
+            .await
+            .unwrap();
+        assert!(rtt > Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_router_shutdown_aborts_in_flight_i2p_request() {
+        let router_proxy = MockServer::respond_with(MockResponse::ok("late").delay(Duration::from_secs(10))).await;
+        let router = Arc::new(FakeRouter::new(router_proxy.addr.port(), 1));
+        let handler = Arc::new(RequestHandler::new(Arc::new(ProxySelector::new(300))).with_router(router.clone()));
+
+        let request = tokio::spawn({
+            let handler = handler.clone();
+            async move { handler.handle_request(RequestConfig::get("http://site.i2p/"), Vec::new()).await }
+        });
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let start = Instant::now();
+        router.begin_shutdown();
+        let result = request.await.unwrap();
+
+        assert!(matches!(result, Err(RequestError::RouterShuttingDown)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_router_shutdown_aborts_specific_proxy_request() {
+        let router_proxy = MockServer::respond_with(MockResponse::ok("late").delay(Duration::from_secs(10))).await;
+        let router = Arc::new(FakeRouter::new(router_proxy.addr.port(), 1));
+        let handler = Arc::new(RequestHandler::new(Arc::new(ProxySelector::new(300))).with_router(router.clone()));
+        let outproxy = Proxy::new("outproxy.b32.i2p".to_string(), 443);
+
+        let request = tokio::spawn({
+            let handler = handler.clone();
+            async move {
+                handler
+                    .handle_request_with_specific_proxy(RequestConfig::get("http://example.com/"), outproxy, None)
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let start = Instant::now();
+        router.begin_shutdown();
+        let result = request.await.unwrap();
+
+        assert!(matches!(result, Err(RequestError::RouterShuttingDown)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_i2p_outproxy_uses_injected_router_ports() {
+        let router_proxy = MockServer::respond_with(MockResponse::ok("via outproxy")).await;
+        let router = Arc::new(FakeRouter::new(router_proxy.addr.port(), 1));
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_router(router);
+        let outproxy = candidate(Proxy::new("outproxy.b32.i2p".to_string(), 443));
+
+        let (response, proxy_used, _) = handler
+            .create_client_and_send_request(&RequestConfig::get("http://example.com/"), vec![outproxy])
+            .await
+            .unwrap();
+
+        assert_eq!(response.text().await.unwrap(), "via outproxy");
+        assert!(proxy_used.starts_with(&format!("router-http://127.0.0.1:{}", router_proxy.addr.port())));
+    }
+
+    #[tokio::test]
+    async fn test_i2p_request_with_proxy_candidate_is_rejected() {
+        let clearnet = MockServer::respond_with(MockResponse::ok("leaked")).await;
+        let router_proxy = MockServer::respond_with(MockResponse::ok("eepsite")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_router(Arc::new(FakeRouter::new(router_proxy.addr.port(), 1)));
+
+        let err = handler
+            .create_client_and_send_request(&RequestConfig::get("http://site.i2p/"), vec![candidate(clearnet.proxy())])
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err,
+            RequestError::ProxyNetworkMismatch {
+                url: "http://site.i2p/".to_string(),
+                proxy: clearnet.proxy().url,
+            }
+        );
+        assert!(clearnet.requests().is_empty());
+        assert!(router_proxy.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_clearnet_request_through_router_proxy_is_rejected() {
+        let router_proxy = MockServer::respond_with(MockResponse::ok("via router")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_router(Arc::new(FakeRouter::new(router_proxy.addr.port(), 1)));
+        let outproxy = MockServer::respond_with(MockResponse::ok("via outproxy")).await;
+        let mixed = vec![candidate(outproxy.proxy()), candidate(router_proxy.proxy())];
+
+        let err = handler
+            .create_client_and_send_request(&RequestConfig::get("http://example.com/"), mixed)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, RequestError::ProxyNetworkMismatch { ref proxy, .. } if *proxy == router_proxy.proxy().url));
+        assert!(router_proxy.requests().is_empty());
+        assert!(outproxy.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_router_start_failure_is_reported() {
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_router(Arc::new(FakeRouter::new(4444, 4447).failing()));
+
+        let err = handler
+            .handle_request(RequestConfig::get("http://site.i2p/"), Vec::new())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Failed to ensure i2pd router is running"));
+    }
+
+    #[tokio::test]
+    async fn test_response_cache_revalidates_with_304() {
+        let server = MockServer::start(|request| match request.header("If-None-Match") {
+            Some("\"v1\"") => MockResponse::new(304, ""),
+            _ => MockResponse::ok("static asset").header("ETag", "\"v1\""),
+        })
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_response_cache(8);
+        let url = server.url("/style.css");
+
+        let first = handler
+            .handle_request_with_specific_proxy(RequestConfig::get(&url), server.proxy(), None)
+            .await
+            .unwrap();
+        assert_eq!(first.body, b"static asset");
+        assert_eq!(handler.response_cache().unwrap().len(), 1);
+
+        let second = handler
+            .handle_request_with_specific_proxy(RequestConfig::get(&url), server.proxy(), None)
+            .await
+            .unwrap();
+        assert_eq!(second.status, 200);
+        assert_eq!(second.body, b"static asset");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].header("If-None-Match").is_none());
+        assert_eq!(requests[1].header("If-None-Match"), Some("\"v1\""));
+    }
+
+    #[tokio::test]
+    async fn test_response_cache_uses_last_modified() {
+        let stamp = "Mon, 01 Jan 2024 00:00:00 GMT";
+        let server = MockServer::start(move |request| match request.header("If-Modified-Since") {
+            Some(_) => MockResponse::new(304, ""),
+            None => MockResponse::ok("page").header("Last-Modified", stamp),
+        })
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_response_cache(8);
+        let config = || RequestConfig {
+            error_on_status: true,
+            ..RequestConfig::get(server.url("/"))
+        };
+
+        handler.handle_request_with_specific_proxy(config(), server.proxy(), None).await.unwrap();
+        // The 304 must not trip error_on_status
+        let second = handler.handle_request_with_specific_proxy(config(), server.proxy(), None).await.unwrap();
+
+        assert_eq!(second.body, b"page");
+        assert_eq!(server.requests()[1].header("If-Modified-Since"), Some(stamp));
+    }
+
+    #[tokio::test]
+    async fn test_response_cache_respects_no_store() {
+        let server = MockServer::respond_with(
+            MockResponse::ok("secret")
+                .header("ETag", "\"v1\"")
+                .header("Cache-Control", "no-store"),
+        )
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_response_cache(8);
+
+        for _ in 0..2 {
+            handler
+                .handle_request_with_specific_proxy(RequestConfig::get(server.url("/")), server.proxy(), None)
+                .await
+                .unwrap();
+        }
+
+        assert!(handler.response_cache().unwrap().is_empty());
+        assert!(server.requests().iter().all(|r| r.header("If-None-Match").is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_router_ready_and_round_trip_timed_separately() {
+        // Slow router, fast site
+        let fast_site = MockServer::respond_with(MockResponse::ok("fast")).await;
+        let slow_router = FakeRouter::new(fast_site.addr.port(), 1).start_delay(Duration::from_millis(300));
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_router(Arc::new(slow_router));
+        handler.handle_request(RequestConfig::get("http://site.i2p/"), Vec::new()).await.unwrap();
+
+        let metrics = handler.metrics();
+        assert_eq!(metrics.i2p_requests, 1);
+        assert!(metrics.last_router_ready.unwrap() >= Duration::from_millis(300));
+        assert!(metrics.last_i2p_round_trip.unwrap() < Duration::from_millis(300));
+
+        // Fast router, slow site
+        let slow_site = MockServer::respond_with(MockResponse::ok("slow").delay(Duration::from_millis(300))).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_router(Arc::new(FakeRouter::new(slow_site.addr.port(), 1)));
+        handler.handle_request(RequestConfig::get("http://site.i2p/"), Vec::new()).await.unwrap();
+
+        let metrics = handler.metrics();
+        assert!(metrics.last_router_ready.unwrap() < Duration::from_millis(300));
+        assert!(metrics.last_i2p_round_trip.unwrap() >= Duration::from_millis(300));
+        assert_eq!(metrics.router_ready_total, metrics.last_router_ready.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_download_to_file_streams_body_to_disk() {
+        let body = "x".repeat(256 * 1024);
+        let server = MockServer::respond_with(MockResponse::ok(body.clone())).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_router(Arc::new(FakeRouter::new(server.addr.port(), 1)));
+        let path = std::env::temp_dir().join(format!("i2ptunnel-download-{}.bin", std::process::id()));
+
+        let summary = handler
+            .download_to_file(RequestConfig::get("http://site.i2p/big.bin"), Vec::new(), &path)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.status, 200);
+        assert_eq!(summary.bytes_written, body.len() as u64);
+        assert!(summary.proxy_used.contains(&server.addr.port().to_string()));
+        assert_eq!(std::fs::read(&path).unwrap(), body.as_bytes());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_to_file_resumes_partial_file() {
+        let server = MockServer::start(|request| match request.header("range") {
+            Some("bytes=6-") => MockResponse::new(206, "world").header("Content-Range", "bytes 6-10/11"),
+            _ => MockResponse::ok("hello world"),
+        })
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_router(Arc::new(FakeRouter::new(server.addr.port(), 1)));
+        let path = std::env::temp_dir().join(format!("i2ptunnel-resume-{}.bin", std::process::id()));
+        std::fs::write(partial_download_path(&path), "hello ").unwrap();
+
+        let summary = handler
+            .download_to_file(RequestConfig::get("http://site.i2p/big.bin"), Vec::new(), &path)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.status, 206);
+        assert_eq!(summary.resumed_from, Some(6));
+        assert_eq!(summary.bytes_written, 5);
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+        assert!(!partial_download_path(&path).exists());
+        assert_eq!(server.requests()[0].header("range"), Some("bytes=6-"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_to_file_restarts_when_range_ignored() {
+        let server = MockServer::respond_with(MockResponse::ok("fresh contents")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_router(Arc::new(FakeRouter::new(server.addr.port(), 1)));
+        let path = std::env::temp_dir().join(format!("i2ptunnel-restart-{}.bin", std::process::id()));
+        std::fs::write(partial_download_path(&path), "stale partial data that is longer").unwrap();
+
+        let summary = handler
+            .download_to_file(RequestConfig::get("http://site.i2p/big.bin"), Vec::new(), &path)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.status, 200);
+        assert_eq!(summary.resumed_from, None);
+        assert_eq!(std::fs::read(&path).unwrap(), b"fresh contents");
+        assert!(server.requests()[0].header("range").is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_to_file_complete_file_answered_416() {
+        let server = MockServer::respond_with(
+            MockResponse::new(416, "range not satisfiable").header("Content-Range", "bytes */11"),
+        )
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_router(Arc::new(FakeRouter::new(server.addr.port(), 1)));
+        let path = std::env::temp_dir().join(format!("i2ptunnel-complete-{}.bin", std::process::id()));
+        std::fs::write(partial_download_path(&path), "hello world").unwrap();
+
+        let summary = handler
+            .download_to_file(RequestConfig::get("http://site.i2p/big.bin"), Vec::new(), &path)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.status, 416);
+        assert_eq!(summary.bytes_written, 0);
+        assert_eq!(summary.resumed_from, Some(11));
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+        assert!(!partial_download_path(&path).exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_to_file_server_error_keeps_partial_file() {
+        let server = MockServer::respond_with(MockResponse::new(502, "bad gateway")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_router(Arc::new(FakeRouter::new(server.addr.port(), 1)));
+        let path = std::env::temp_dir().join(format!("i2ptunnel-keep-{}.bin", std::process::id()));
+        let partial = partial_download_path(&path);
+        std::fs::write(&partial, "hello ").unwrap();
+
+        let result = handler
+            .download_to_file(RequestConfig::get("http://site.i2p/big.bin"), Vec::new(), &path)
+            .await;
+
+        assert!(matches!(result, Err(RequestError::HttpStatus { code: 502, .. })));
+        assert_eq!(std::fs::read(&partial).unwrap(), b"hello ");
+        assert!(!path.exists());
+        std::fs::remove_file(&partial).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_to_file_replaces_unrelated_file_without_resuming() {
+        let server = MockServer::respond_with(MockResponse::ok("new contents")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_router(Arc::new(FakeRouter::new(server.addr.port(), 1)));
+        let path = std::env::temp_dir().join(format!("i2ptunnel-unrelated-{}.bin", std::process::id()));
+        std::fs::write(&path, "an older, unrelated file").unwrap();
+
+        let summary = handler
+            .download_to_file(RequestConfig::get("http://site.i2p/big.bin"), Vec::new(), &path)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.resumed_from, None);
+        assert!(server.requests()[0].header("range").is_none());
+        assert_eq!(std::fs::read(&path).unwrap(), b"new contents");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_download_keeps_part_file_for_resume() {
+        // The first piece reaches the file, then the body stalls
+        let server = MockServer::respond_with(
+            MockResponse::ok("partial body").chunked(4, Duration::from_millis(500)),
+        )
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_router(Arc::new(FakeRouter::new(server.addr.port(), 1)));
+        let path = std::env::temp_dir().join(format!("i2ptunnel-partial-{}.bin", std::process::id()));
+        let config = RequestConfig {
+            body_stall_timeout: Some(Duration::from_millis(50)),
+            ..RequestConfig::get("http://site.i2p/big.bin")
+        };
+
+        let result = handler.download_to_file(config, Vec::new(), &path).await;
+
+        assert!(matches!(result, Err(RequestError::Stalled { .. })));
+        assert!(!path.exists());
+        let partial = partial_download_path(&path);
+        assert!(partial.exists());
+        std::fs::remove_file(&partial).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_download_keeps_existing_file() {
+        let server = MockServer::respond_with(
+            MockResponse::ok("partial body").chunked(4, Duration::from_millis(500)),
+        )
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_router(Arc::new(FakeRouter::new(server.addr.port(), 1)));
+        let path = std::env::temp_dir().join(format!("i2ptunnel-existing-{}.bin", std::process::id()));
+        std::fs::write(&path, "the user's own file").unwrap();
+        let config = RequestConfig {
+            body_stall_timeout: Some(Duration::from_millis(50)),
+            ..RequestConfig::post("http://site.i2p/export", "format=csv")
+        };
+
+        let result = handler.download_to_file(config, Vec::new(), &path).await;
+
+        assert!(matches!(result, Err(RequestError::Stalled { .. })));
+        assert_eq!(std::fs::read(&path).unwrap(), b"the user's own file");
+        assert!(!partial_download_path(&path).exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_post_multipart_sends_form() {
+        let server = MockServer::respond_with(MockResponse::ok("uploaded")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_router(Arc::new(FakeRouter::new(server.addr.port(), 1)));
+        let form = reqwest::multipart::Form::new()
+            .text("title", "hello")
+            .part(
+                "file",
+                reqwest::multipart::Part::bytes(b"file contents".to_vec()).file_name("note.txt"),
+            );
+
+        let response = handler
+            .post_multipart("http://paste.i2p/upload", form, Vec::new())
+            .await
+            .unwrap();
+
+        assert_eq!(response.body, b"uploaded");
+        let request = &server.requests()[0];
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.target, "http://paste.i2p/upload");
+        assert!(request.header("Content-Type").unwrap().starts_with("multipart/form-data; boundary="));
+        let body = String::from_utf8_lossy(&request.body);
+        assert!(body.contains("name=\"title\""));
+        assert!(body.contains("hello"));
+        assert!(body.contains("filename=\"note.txt\""));
+        assert!(body.contains("file contents"));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_throttles_bursts_to_one_proxy() {
+        let server = MockServer::respond_with(MockResponse::ok("ok")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_rate_limit(5.0);
+
+        let start = Instant::now();
+        for _ in 0..8 {
+            handler
+                .handle_request_with_specific_proxy(RequestConfig::get("http://example.com/"), server.proxy(), None)
+                .await
+                .unwrap();
+        }
+
+        // A burst of 5, then 3 more at 200 ms intervals
+        assert!(start.elapsed() >= Duration::from_millis(550));
+        assert_eq!(server.requests().len(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_socks_proxy_speaking_http_corrected_after_fallback() {
+        let server = MockServer::respond_with(MockResponse::ok("over http")).await;
+        let listed = Proxy::new_with_type("127.0.0.1".to_string(), server.addr.port(), ProxyType::Socks);
+        let selector = Arc::new(ProxySelector::new(300));
+        selector
+            .select_fastest(vec![crate::proxy_tester::ProxyTestResult::succeeded(listed.clone(), 1000.0, 10.0)])
+            .await;
+        let handler = RequestHandler::new(selector.clone());
+
+        let (response, proxy_used, _) = handler
+            .create_client_and_send_request(&RequestConfig::get(server.url("/page")), vec![candidate(listed)])
+            .await
+            .unwrap();
+
+        assert_eq!(response.text().await.unwrap(), "over http");
+        assert!(proxy_used.ends_with(SOCKS_FALLBACK_SUFFIX));
+        assert_eq!(server.requests().len(), 1);
+        let current = selector.get_current_proxy().unwrap();
+        assert_eq!(current.proxy.proxy_type, ProxyType::Http);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_proxy_skipped_with_skip_policy() {
+        let first = MockServer::respond_with(MockResponse::ok("first")).await;
+        let second = MockServer::respond_with(MockResponse::ok("second")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_rate_limit(1.0)
+            .with_rate_limit_policy(RateLimitPolicy::Skip);
+        let candidates = || vec![candidate(first.proxy()), candidate(second.proxy())];
+        let config = RequestConfig::get("http://example.com/");
+
+        let (_, used_first, _) = handler.create_client_and_send_request(&config, candidates()).await.unwrap();
+        let (_, used_second, _) = handler.create_client_and_send_request(&config, candidates()).await.unwrap();
+
+        assert_eq!(used_first, first.proxy().url);
+        assert_eq!(used_second, second.proxy().url);
+        assert_eq!(first.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_applies_to_probes() {
+        let server = MockServer::respond_with(MockResponse::ok("ok")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_rate_limit(1.0)
+            .with_rate_limit_policy(RateLimitPolicy::Skip);
+
+        let first = handler.probe_working("http://example.com/", vec![server.proxy()], 1).await;
+        let second = handler.probe_working("http://example.com/", vec![server.proxy()], 1).await;
+
+        assert_eq!(first.len(), 1);
+        assert!(second.is_empty());
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Status {
+        name: String,
+        peers: u32,
+    }
+
+    #[tokio::test]
+    async fn test_get_json_deserializes_body() {
+        let server = MockServer::respond_with(
+            MockResponse::ok(r#"{"name": "router", "peers": 42}"#).header("Content-Type", "application/json"),
+        )
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_router(Arc::new(FakeRouter::new(server.addr.port(), 1)));
+
+        let status: Status = handler.get_json("http://api.i2p/status", Vec::new()).await.unwrap();
 
-        // Add body
-        if let Some(body) = &config.body {
-            request = request.body(body.clone());
-        }
+        assert_eq!(status, Status { name: "router".to_string(), peers: 42 });
+    }
 
-        debug!("Sending request through specific proxy: {}", proxy_used);
+    #[tokio::test]
+    async fn test_get_json_reports_malformed_body() {
+        let server = MockServer::respond_with(MockResponse::ok("<html>maintenance</html>")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_router(Arc::new(FakeRouter::new(server.addr.port(), 1)));
 
-        // Send request
-        let response = request.send().await.map_err(|e| {
-            let prefix = format!("Request failed through proxy {}:", proxy_used);
-            log_error_full(&prefix, &e);
-            format!("Request failed through proxy {}: {}", proxy_used, e)
-        })?;
+        let err = handler
+            .get_json::<Status>("http://api.i2p/status", Vec::new())
+            .await
+            .unwrap_err()
+            .to_string();
 
-        let status = response.status().as_u16();
-        info!("Received response: status {}", status);
+        assert!(err.contains("Invalid JSON from http://api.i2p/status"));
+        assert!(err.contains("<html>maintenance</html>"));
+    }
 
-        // Extract headers
-        let mut response_headers = std::collections::HashMap::new();
-        for (key, value) in response.headers() {
-            if let Ok(value_str) = value.to_str() {
-                response_headers.insert(key.to_string(), value_str.to_string());
-            }
-        }
+    #[tokio::test]
+    async fn test_proxy_diagnostic_headers_extracted() {
+        let server = MockServer::respond_with(
+            MockResponse::ok("served")
+                .header("Via", "1.1 outproxy-a (squid/5.7), 1.1 edge")
+                .header("X-Forwarded-For", "10.0.0.1"),
+        )
+        .await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
 
-        // Handle streaming vs non-streaming
-        if config.stream {
-            // For streaming, return empty body - the response will be read in chunks
-            debug!("Streaming mode: response headers received, body will be streamed");
-            Ok(ResponseData {
-                status,
-                headers: response_headers,
-                body: Vec::new(), // Empty body for streaming
-                proxy_used,
-            })
-        } else {
-            // Read full body
-            let body = match response.bytes().await {
-                Ok(b) => b.to_vec(),
-                Err(e) => {
-                    log_error_full("Failed to read response body:", &e);
-                    return Err(format!("Failed to read body: {}", e));
-                }
-            };
+        let response = handler
+            .handle_request_with_specific_proxy(RequestConfig::get("http://example.com/"), server.proxy(), None)
+            .await
+            .unwrap();
 
-            debug!(
-                "Request completed: status {}, body size: {} bytes",
-                status,
-                body.len()
-            );
+        let info = response.proxy_info.unwrap();
+        assert_eq!(info.via, vec!["1.1 outproxy-a (squid/5.7)", "1.1 edge"]);
+        assert_eq!(info.forwarded_for, vec!["10.0.0.1"]);
+        assert!(info.forwarded.is_empty());
+        assert_eq!(response.proxy_used, server.proxy().url);
+    }
 
-            Ok(ResponseData {
-                status,
-                headers: response_headers,
-                body,
-                proxy_used,
-            })
-        }
+    #[test]
+    fn test_no_proxy_info_without_diagnostic_headers() {
+        let mut headers = std::collections::HashMap::new();
+        headers.insert("content-type".to_string(), vec!["text/plain".to_string()]);
+        assert!(ProxyInfo::from_headers(&headers).is_none());
     }
 
-    pub async fn handle_request(
-        &self,
-        config: RequestConfig,
-        available_proxies: Vec<Proxy>,
-    ) -> Result<ResponseData, String> {
-        info!("Handling request: {} {} (stream={})", config.method, config.url, config.stream);
+    #[tokio::test]
+    async fn test_request_timeout_override_wins_over_config() {
+        let server = MockServer::respond_with(MockResponse::ok("late").delay(Duration::from_millis(1200))).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_timeouts(TimeoutConfig {
+            clearnet_request: Duration::from_millis(300),
+            ..TimeoutConfig::default()
+        });
 
-        // Check if this is an I2P domain
-        let is_i2p = Self::is_i2p_domain(&config.url);
-        
-        // Get proxy candidates (for clearnet sites, get multiple candidates for retry)
-        let proxy_candidates = if is_i2p {
-            // For I2P sites, we don't need proxy candidates
-            Vec::new()
-        } else {
-            // Get top 5 proxy candidates for clearnet sites
-            match self.proxy_selector
-                .ensure_multiple_proxy_candidates(available_proxies, 5)
-                .await
-            {
-                Ok(candidates) => {
-                    if candidates.is_empty() {
-                        return Err("No available proxy candidates found".to_string());
-                    }
-                    info!("Got {} proxy candidates for request", candidates.len());
-                    candidates
-                }
-                Err(e) => {
-                    error!("Failed to get proxy candidates: {}", e);
-                    return Err(format!("Proxy selection failed: {}", e));
-                }
-            }
+        // The configured default applies when the request has no timeout of its own
+        let result = handler
+            .handle_request_with_specific_proxy(RequestConfig::get("http://example.com/"), server.proxy(), None)
+            .await;
+        assert!(result.is_err());
+
+        let config = RequestConfig {
+            timeout_secs: Some(5),
+            ..RequestConfig::get("http://example.com/")
         };
-        
-        // Use helper to create client and send request
-        let (response, proxy_used, _is_i2p) = self.create_client_and_send_request(&config, proxy_candidates).await?;
+        let response = handler
+            .handle_request_with_specific_proxy(config, server.proxy(), None)
+            .await
+            .unwrap();
+        assert_eq!(response.body, b"late");
+    }
 
-        let status = response.status().as_u16();
-        info!("Received response: status {}", status);
+    #[tokio::test]
+    async fn test_client_cache_reuses_client_per_proxy() {
+        let server = MockServer::respond_with(MockResponse::ok("pooled")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_client_cache();
 
-        // Extract headers
-        let mut response_headers = std::collections::HashMap::new();
-        for (key, value) in response.headers() {
-            if let Ok(value_str) = value.to_str() {
-                response_headers.insert(key.to_string(), value_str.to_string());
-            }
+        for _ in 0..2 {
+            handler
+                .handle_request_with_specific_proxy(RequestConfig::get("http://origin.test/"), server.proxy(), None)
+                .await
+                .unwrap();
         }
 
-        // Handle streaming vs non-streaming
-        if config.stream {
-            // For streaming, return empty body - the response will be read in chunks
-            debug!("Streaming mode: response headers received, body will be streamed");
-            Ok(ResponseData {
-                status,
-                headers: response_headers,
-                body: Vec::new(), // Empty body for streaming
-                proxy_used,
-            })
-        } else {
-            // Read full body
-            let body = match response.bytes().await {
-                Ok(b) => b.to_vec(),
-                Err(e) => {
-                    error!("Failed to read response body: {}", e);
-                    return Err(format!("Failed to read body: {}", e));
-                }
-            };
-
-            debug!(
-                "Request completed: status {}, body size: {} bytes",
-                status,
-                body.len()
-            );
+        let metrics = handler.metrics();
+        assert_eq!(metrics.clients_created, 1);
+        assert_eq!(metrics.clients_reused, 1);
 
-            Ok(ResponseData {
-                status,
-                headers: response_headers,
-                body,
-                proxy_used,
-            })
+        // Without the cache every request builds its own client
+        let uncached = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        for _ in 0..2 {
+            uncached
+                .handle_request_with_specific_proxy(RequestConfig::get("http://origin.test/"), server.proxy(), None)
+                .await
+                .unwrap();
         }
+        assert_eq!(uncached.metrics().clients_created, 2);
+        assert_eq!(uncached.metrics().clients_reused, 0);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[tokio::test]
+    async fn test_local_address_binds_clearnet_connections() {
+        // All of 127.0.0.0/8 is routed to loopback, so 127.0.0.2 is assignable on Linux
+        let local: IpAddr = "127.0.0.2".parse().unwrap();
+        let server = MockServer::respond_with(MockResponse::ok("bound")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_local_address(local)
+            .unwrap();
+        assert_eq!(handler.local_address(), Some(local));
 
-    #[test]
-    fn test_is_i2p_domain() {
-        // Test .i2p domains
-        assert!(RequestHandler::is_i2p_domain("http://example.i2p"));
-        assert!(RequestHandler::is_i2p_domain("https://example.i2p/path"));
-        assert!(RequestHandler::is_i2p_domain("http://site.i2p:8080"));
-        
-        // Test .b32.i2p domains
-        assert!(RequestHandler::is_i2p_domain("http://abc123.b32.i2p"));
-        assert!(RequestHandler::is_i2p_domain("https://xyz789.b32.i2p/path"));
-        
-        // Test non-I2P domains
-        assert!(!RequestHandler::is_i2p_domain("http://example.com"));
-        assert!(!RequestHandler::is_i2p_domain("https://google.com"));
-        assert!(!RequestHandler::is_i2p_domain("http://localhost:8080"));
-        
-        // Test edge cases
-        assert!(!RequestHandler::is_i2p_domain(""));
-        assert!(!RequestHandler::is_i2p_domain("i2p"));
-        assert!(!RequestHandler::is_i2p_domain("not-i2p.com"));
+        handler
+            .handle_request_with_specific_proxy(RequestConfig::get("http://origin.test/"), server.proxy(), None)
+            .await
+            .unwrap();
+
+        assert_eq!(server.requests()[0].peer.ip(), local);
     }
 
     #[test]
-    fn test_request_config_creation() {
-        let config = RequestConfig {
-            url: "https://example.com".to_string(),
-            method: "GET".to_string(),
-            headers: None,
-            body: None,
-            stream: false,
-        };
-        
-        assert_eq!(config.url, "https://example.com");
-        assert_eq!(config.method, "GET");
-        assert!(config.headers.is_none());
-        assert!(config.body.is_none());
-        assert!(!config.stream);
+    fn test_unassignable_local_address_rejected() {
+        // TEST-NET-1, never configured on a test host
+        let result = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_local_address("192.0.2.1".parse().unwrap());
+        assert!(result.is_err());
     }
 
+    const TEST_CA_PEM: &str = "-----BEGIN CERTIFICATE-----
+MIIBjzCCATWgAwIBAgIUbHaBBJvSzHZh/w8vM4bZVzsCBv8wCgYIKoZIzj0EAwIw
+HDEaMBgGA1UEAwwRaTJwdHVubmVsIHRlc3QgQ0EwIBcNMjYxMDE2MDAwMDA3WhgP
+MjEyNjA5MjIwMDAwMDdaMBwxGjAYBgNVBAMMEWkycHR1bm5lbCB0ZXN0IENBMFkw
+EwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEnWSuhyTS8mAQir0xlLYORSW/YckRatTO
+Ry+pqey+SvJBA5ErM8n7twcbozvBqqd5Rs5XM6OdKs4NQLQoYR0396NTMFEwHQYD
+VR0OBBYEFGU6WGNIgVbxzHTL4eYc97X2g9BNMB8GA1UdIwQYMBaAFGU6WGNIgVbx
+zHTL4eYc97X2g9BNMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSAAwRQIg
+R9hvdlI+1pEAXVggjyPyVJ5ViwlFotmaBTAMFdwzlJACIQC8dycTi7BZhyccwdvB
+j49iaEfMR3XyNqTEl3bMkPWrbg==
+-----END CERTIFICATE-----
+";
+
     #[test]
-    fn test_request_config_with_stream() {
-        let config = RequestConfig {
-            url: "https://example.com".to_string(),
-            method: "GET".to_string(),
-            headers: None,
-            body: None,
-            stream: true,
-        };
-        
-        assert!(config.stream);
+    fn test_add_root_certificate_pem() {
+        let mut handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        handler.add_root_certificate_pem(TEST_CA_PEM.as_bytes()).unwrap();
+
+        assert_eq!(handler.root_certificates.len(), 1);
+        assert!(handler.client_builder().build().is_ok());
     }
 
     #[test]
-    fn test_request_config_with_headers() {
-        let mut headers = std::collections::HashMap::new();
-        headers.insert("User-Agent".to_string(), "test".to_string());
-        
+    fn test_add_root_certificate() {
+        let mut handler = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let cert = reqwest::Certificate::from_pem(TEST_CA_PEM.as_bytes()).unwrap();
+        handler.add_root_certificate(cert);
+
+        assert_eq!(handler.root_certificates.len(), 1);
+        assert!(handler.client_builder().build().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_challenge_page_detected_and_proxy_demoted() {
+        let server = MockServer::respond_with(MockResponse::ok(
+            "<html><head><title>Just a moment...</title></head></html>",
+        ))
+        .await;
+        let selector = Arc::new(ProxySelector::new(300));
+        let handler = RequestHandler::new(selector.clone())
+            .with_challenge_detection(Arc::new(crate::challenge::ChallengeMarkers::default()));
+
+        let result = handler
+            .handle_request_with_specific_proxy(RequestConfig::get("http://shop.example/item"), server.proxy(), None)
+            .await;
+
+        match result {
+            Err(RequestError::ChallengeEncountered { host, .. }) => assert_eq!(host, "shop.example"),
+            other => panic!("expected a challenge error, got {:?}", other),
+        }
+        assert!(selector.is_challenged("shop.example", &server.proxy()));
+
+        // Without detection the page is returned as is
+        let plain = RequestHandler::new(Arc::new(ProxySelector::new(300)));
+        let response = plain
+            .handle_request_with_specific_proxy(RequestConfig::get("http://shop.example/item"), server.proxy(), None)
+            .await
+            .unwrap();
+        assert_eq!(response.status, 200);
+    }
+
+    #[tokio::test]
+    async fn test_http10_downgrade_for_listed_hosts() {
+        let server = MockServer::respond_with(MockResponse::ok("ok")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_http10_hosts(vec!["Legacy.test".to_string()]);
+
+        for url in ["http://legacy.test/", "http://modern.test/"] {
+            handler
+                .handle_request_with_specific_proxy(RequestConfig::get(url), server.proxy(), None)
+                .await
+                .unwrap();
+        }
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("connection"), Some("close"));
+        assert_eq!(requests[0].header("accept-encoding"), Some("identity"));
+        assert_eq!(requests[1].header("connection"), None);
+
+        // A single request can ask for it too
         let config = RequestConfig {
-            url: "https://example.com".to_string(),
-            method: "GET".to_string(),
-            headers: Some(headers),
-            body: None,
-            stream: false,
+            http10: true,
+            ..RequestConfig::get("http://modern.test/")
         };
-        
-        assert!(config.headers.is_some());
-        let headers = config.headers.unwrap();
-        assert_eq!(headers.get("User-Agent"), Some(&"test".to_string()));
+        RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .handle_request_with_specific_proxy(config, server.proxy(), None)
+            .await
+            .unwrap();
+        assert_eq!(server.requests()[2].header("connection"), Some("close"));
     }
 
-    #[test]
-    fn test_response_data_creation() {
-        let mut headers = std::collections::HashMap::new();
-        headers.insert("Content-Type".to_string(), "text/html".to_string());
-        
-        let response = ResponseData {
-            status: 200,
-            headers,
-            body: b"Hello World".to_vec(),
-            proxy_used: "http://proxy.i2p:443".to_string(),
+    #[tokio::test]
+    async fn test_default_accept_headers_overridable_per_request() {
+        let server = MockServer::respond_with(MockResponse::ok("ok")).await;
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_default_accept("text/html,application/xhtml+xml")
+            .unwrap()
+            .with_default_accept_language("en-US,en;q=0.5")
+            .unwrap();
+
+        handler
+            .handle_request_with_specific_proxy(RequestConfig::get("http://site.test/"), server.proxy(), None)
+            .await
+            .unwrap();
+        let config = RequestConfig {
+            headers: Some([("accept".to_string(), "application/json".to_string())].into_iter().collect()),
+            ..RequestConfig::get("http://site.test/api")
         };
-        
-        assert_eq!(response.status, 200);
-        assert_eq!(response.headers.get("Content-Type"), Some(&"text/html".to_string()));
-        assert_eq!(response.body, b"Hello World");
-        assert_eq!(response.proxy_used, "http://proxy.i2p:443");
-    }
+        handler
+            .handle_request_with_specific_proxy(config, server.proxy(), None)
+            .await
+            .unwrap();
 
-    #[test]
-    fn test_is_i2p_domain_edge_cases() {
-        // Test various edge cases
-        assert!(!RequestHandler::is_i2p_domain("http://.i2p")); // Empty host
-        assert!(!RequestHandler::is_i2p_domain("http://i2p")); // Just i2p, not .i2p
-        assert!(RequestHandler::is_i2p_domain("http://a.b32.i2p")); // Valid b32
-        assert!(RequestHandler::is_i2p_domain("https://test.i2p:8080/path?query=1")); // With port and path
-        assert!(!RequestHandler::is_i2p_domain("http://i2p.example.com")); // i2p as subdomain
+        let requests = server.requests();
+        assert_eq!(requests[0].header("accept"), Some("text/html,application/xhtml+xml"));
+        assert_eq!(requests[0].header("accept-language"), Some("en-US,en;q=0.5"));
+        assert_eq!(requests[1].header("accept"), Some("application/json"));
+        assert_eq!(requests[1].header("accept-language"), Some("en-US,en;q=0.5"));
+
+        assert!(RequestHandler::new(Arc::new(ProxySelector::new(300)))
+            .with_default_accept("bad\nvalue")
+            .is_err());
     }
 
-    #[test]
-    fn test_is_proxy_connection_error() {
-        assert!(RequestHandler::is_proxy_connection_error("Connection unreachable"));
-        assert!(RequestHandler::is_proxy_connection_error("connection refused"));
-        assert!(RequestHandler::is_proxy_connection_error("Connection timed out"));
-        assert!(RequestHandler::is_proxy_connection_error("SOCKS connect error"));
-        assert!(!RequestHandler::is_proxy_connection_error("HTTP 404 Not Found"));
-        assert!(!RequestHandler::is_proxy_connection_error("Invalid response"));
+    #[tokio::test]
+    async fn test_request_signer_headers_sent() {
+        let server = MockServer::respond_with(MockResponse::ok("ok")).await;
+        let signer: RequestSigner = Arc::new(|parts: &RequestParts| {
+            let signature = format!("{} {} {}", parts.method, parts.path(), String::from_utf8_lossy(parts.body));
+            let mut headers = reqwest::header::HeaderMap::new();
+            headers.insert("x-signature", signature.parse().unwrap());
+            headers
+        });
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_request_signer(signer);
+
+        handler
+            .handle_request_with_specific_proxy(
+                RequestConfig::post("http://site.test/api/items?page=2", "{\"id\":1}"),
+                server.proxy(),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("x-signature"), Some("POST /api/items?page=2 {\"id\":1}"));
+        assert_eq!(requests[0].body, b"{\"id\":1}");
     }
 
-    #[test]
-    fn test_request_config_all_methods() {
-        let methods = vec!["GET", "POST", "PUT", "DELETE", "PATCH", "HEAD"];
-        
-        for method in methods {
+    #[tokio::test]
+    async fn test_strip_expect_header() {
+        let server = MockServer::respond_with(MockResponse::ok("ok")).await;
+        for strip in [false, true] {
             let config = RequestConfig {
-                url: "https://example.com".to_string(),
-                method: method.to_string(),
-                headers: None,
-                body: None,
-                stream: false,
+                headers: Some([("Expect".to_string(), "100-continue".to_string())].into_iter().collect()),
+                ..RequestConfig::post("http://site.test/upload", "payload")
             };
-            assert_eq!(config.method, method);
+            let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_strip_expect(strip);
+            handler
+                .handle_request_with_specific_proxy(config, server.proxy(), None)
+                .await
+                .unwrap();
         }
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("expect"), Some("100-continue"));
+        assert_eq!(requests[1].header("expect"), None);
+        assert_eq!(requests[1].body, b"payload");
+    }
+
+    #[tokio::test]
+    async fn test_body_transformer_rewrites_body() {
+        let server = MockServer::respond_with(
+            MockResponse::ok("<a href=\"http://site.test/next\">next</a>").header("Content-Type", "text/html"),
+        )
+        .await;
+        let transformer: BodyTransformer = Arc::new(|body: &[u8], content_type: &str| {
+            if content_type.starts_with("text/html") {
+                String::from_utf8_lossy(body).replace("http://site.test/", "/tunnel/").into_bytes()
+            } else {
+                body.to_vec()
+            }
+        });
+        let handler = RequestHandler::new(Arc::new(ProxySelector::new(300))).with_body_transformer(transformer);
+
+        let response = handler
+            .handle_request_with_specific_proxy(RequestConfig::get("http://site.test/"), server.proxy(), None)
+            .await
+            .unwrap();
+        assert_eq!(response.body, b"<a href=\"/tunnel/next\">next</a>");
+    }
+
+    #[tokio::test]
+    async fn test_builder_applies_options() {
+        let router_proxy = MockServer::respond_with(MockResponse::ok("late").delay(Duration::from_millis(1200))).await;
+        let router = Arc::new(FakeRouter::new(router_proxy.addr.port(), 1));
+        let handler = RequestHandler::builder(Arc::new(ProxySelector::new(300)))
+            .router(router.clone())
+            .timeouts(TimeoutConfig {
+                i2p_request: Duration::from_millis(300),
+                ..TimeoutConfig::default()
+            })
+            .response_cache(8)
+            .rate_limit(2.0)
+            .rate_limit_policy(RateLimitPolicy::Skip)
+            .local_address("127.0.0.1".parse().unwrap())
+            .root_certificate_pem(TEST_CA_PEM.as_bytes())
+            .build()
+            .unwrap();
+
+        assert!(handler.response_cache().is_some());
+        assert_eq!(handler.rate_limiter.as_ref().map(|limiter| limiter.requests_per_sec()), Some(2.0));
+        assert_eq!(handler.rate_limit_policy, RateLimitPolicy::Skip);
+        assert_eq!(handler.local_address(), Some("127.0.0.1".parse().unwrap()));
+        assert_eq!(handler.root_certificates.len(), 1);
+
+        // The injected router and the shortened I2P timeout are both in use
+        let result = handler.handle_request(RequestConfig::get("http://site.i2p/"), Vec::new()).await;
+        assert!(result.is_err());
+        assert_eq!(router.ensure_running_calls(), 1);
     }
 
     #[test]
-    fn test_request_config_with_body() {
-        let body = b"test body data".to_vec();
-        let config = RequestConfig {
-            url: "https://example.com".to_string(),
-            method: "POST".to_string(),
-            headers: None,
-            body: Some(body.clone()),
-            stream: false,
-        };
-        
-        assert!(config.body.is_some());
-        assert_eq!(config.body.unwrap(), body);
+    fn test_builder_applies_request_options() {
+        let handler = RequestHandler::builder(Arc::new(ProxySelector::new(300)))
+            .default_accept("text/html")
+            .default_accept_language("en-US")
+            .recorder(4)
+            .direct_fallback(true)
+            .body_read_retries(2)
+            .http10_hosts(vec!["old.example".to_string()])
+            .i2p_exclusions(vec![".corp.i2p".to_string()])
+            .header_limits(20, 4096, HeaderLimitPolicy::Reject)
+            .strip_expect(true)
+            .attempt_log(true)
+            .router_restart_on_network_change(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(handler.default_headers.get("accept").unwrap(), "text/html");
+        assert_eq!(handler.default_headers.get("accept-language").unwrap(), "en-US");
+        assert!(handler.recorder().is_some());
+        assert!(handler.allow_direct_fallback);
+        assert_eq!(handler.body_read_retries, 2);
+        assert_eq!(handler.http10, Http10Policy::Hosts(vec!["old.example".to_string()]));
+        assert!(!handler.routes_via_i2p("http://wiki.corp.i2p/"));
+        assert!(handler.header_limits.is_some());
+        assert!(handler.strip_expect && handler.log_attempts && handler.restart_router_on_network_change);
+
+        let result = RequestHandler::builder(Arc::new(ProxySelector::new(300)))
+            .default_accept("bad\nvalue")
+            .build();
+        assert!(result.is_err());
     }
 
     #[test]
-    fn test_response_data_empty_body() {
-        let response = ResponseData {
-            status: 204,
-            headers: std::collections::HashMap::new(),
-            body: vec![],
-            proxy_used: "http://proxy.i2p:443".to_string(),
-        };
-        
-        assert_eq!(response.status, 204);
-        assert_eq!(response.body.len(), 0);
+    fn test_builder_rejects_invalid_certificate() {
+        let result = RequestHandler::builder(Arc::new(ProxySelector::new(300)))
+            .root_certificate_pem(b"not a certificate")
+            .build();
+        assert!(result.is_err());
     }
 
     #[test]
@@ -868,6 +5540,7 @@ mod tests {
             headers: std::collections::HashMap::new(),
             body: large_body.clone(),
             proxy_used: "http://proxy.i2p:443".to_string(),
+            ..Default::default()
         };
         
         assert_eq!(response.body.len(), 10000);
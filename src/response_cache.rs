@@ -0,0 +1,187 @@
+use crate::request_handler::ResponseData;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use tracing::debug;
+
+/// A stored response together with the validators used to revalidate it
+#[derive(Debug, Clone)]
+pub(crate) struct CachedResponse {
+    pub response: ResponseData,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl CachedResponse {
+    /// Build a cache entry if the response may be stored: a 200 with a validator and
+    /// without `Cache-Control: no-store`
+    pub fn from_response(response: &ResponseData) -> Option<Self> {
+        if response.status != 200 || Self::forbids_store(response) {
+            return None;
+        }
+        let etag = response.header("etag").map(str::to_string);
+        let last_modified = response.header("last-modified").map(str::to_string);
+        if etag.is_none() && last_modified.is_none() {
+            return None;
+        }
+        Some(Self {
+            response: response.clone(),
+            etag,
+            last_modified,
+        })
+    }
+
+    pub fn forbids_store(response: &ResponseData) -> bool {
+        response.headers.iter().any(|(key, values)| {
+            key.eq_ignore_ascii_case("cache-control")
+                && values.iter().any(|value| value.to_lowercase().contains("no-store"))
+        })
+    }
+}
+
+struct CacheInner {
+    entries: HashMap<String, CachedResponse>,
+    // Least recently used first
+    order: VecDeque<String>,
+}
+
+/// Bounded in-memory HTTP cache, evicting the least recently used entry when full
+pub struct ResponseCache {
+    capacity: usize,
+    inner: Mutex<CacheInner>,
+}
+
+impl ResponseCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            inner: Mutex::new(CacheInner {
+                entries: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub(crate) fn key(method: &str, url: &str) -> String {
+        format!("{} {}", method.to_uppercase(), url)
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<CachedResponse> {
+        let mut inner = self.inner.lock();
+        let cached = inner.entries.get(key).cloned()?;
+        Self::touch(&mut inner.order, key);
+        Some(cached)
+    }
+
+    pub(crate) fn insert(&self, key: &str, cached: CachedResponse) {
+        let mut inner = self.inner.lock();
+        if inner.entries.insert(key.to_string(), cached).is_some() {
+            Self::touch(&mut inner.order, key);
+            return;
+        }
+        inner.order.push_back(key.to_string());
+        while inner.entries.len() > self.capacity {
+            match inner.order.pop_front() {
+                Some(evicted) => {
+                    debug!("Evicting {} from the response cache", evicted);
+                    inner.entries.remove(&evicted);
+                }
+                None => break,
+            }
+        }
+    }
+
+    pub(crate) fn remove(&self, key: &str) {
+        let mut inner = self.inner.lock();
+        if inner.entries.remove(key).is_some() {
+            inner.order.retain(|k| k != key);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock();
+        inner.entries.clear();
+        inner.order.clear();
+    }
+
+    fn touch(order: &mut VecDeque<String>, key: &str) {
+        if let Some(pos) = order.iter().position(|k| k == key) {
+            if let Some(k) = order.remove(pos) {
+                order.push_back(k);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(headers: &[(&str, &str)], body: &str) -> ResponseData {
+        let mut map: HashMap<String, Vec<String>> = HashMap::new();
+        for (key, value) in headers {
+            map.entry(key.to_string()).or_default().push(value.to_string());
+        }
+        ResponseData {
+            status: 200,
+            headers: map,
+            body: body.as_bytes().to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_only_validated_storable_responses_are_cached() {
+        assert!(CachedResponse::from_response(&response(&[("etag", "\"v1\"")], "a")).is_some());
+        assert!(CachedResponse::from_response(&response(&[("last-modified", "Mon, 01 Jan 2024 00:00:00 GMT")], "a")).is_some());
+        assert!(CachedResponse::from_response(&response(&[], "a")).is_none());
+        assert!(CachedResponse::from_response(&response(
+            &[("etag", "\"v1\""), ("cache-control", "private, no-store")],
+            "a"
+        ))
+        .is_none());
+
+        let mut not_ok = response(&[("etag", "\"v1\"")], "a");
+        not_ok.status = 404;
+        assert!(CachedResponse::from_response(&not_ok).is_none());
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let cache = ResponseCache::new(2);
+        let entry = |body| CachedResponse::from_response(&response(&[("etag", "\"x\"")], body)).unwrap();
+
+        cache.insert("GET a", entry("a"));
+        cache.insert("GET b", entry("b"));
+        // Reading "a" makes "b" the oldest
+        assert!(cache.get("GET a").is_some());
+        cache.insert("GET c", entry("c"));
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("GET a").is_some());
+        assert!(cache.get("GET b").is_none());
+        assert!(cache.get("GET c").is_some());
+    }
+
+    #[test]
+    fn test_cache_remove_and_clear() {
+        let cache = ResponseCache::new(4);
+        let entry = CachedResponse::from_response(&response(&[("etag", "\"x\"")], "a")).unwrap();
+        cache.insert(&ResponseCache::key("get", "http://site.i2p/"), entry.clone());
+        assert!(cache.get("GET http://site.i2p/").is_some());
+
+        cache.remove("GET http://site.i2p/");
+        assert!(cache.is_empty());
+
+        cache.insert("GET x", entry);
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
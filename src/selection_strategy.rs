@@ -0,0 +1,157 @@
+use crate::proxy_selector::SelectedProxy;
+use crate::proxy_tester::{compare_by_latency, compare_by_speed, ProxyTestResult};
+use std::cmp::Ordering;
+use std::time::Instant;
+
+/// Decides which tested proxies `ProxySelector` picks and in what order
+pub trait SelectionStrategy: Send + Sync {
+    /// Up to `count` of `results`, best first. `results` only holds successful tests of
+    /// proxies the selector would accept (not quarantined, fast enough).
+    fn select(&self, results: &[ProxyTestResult], count: usize) -> Vec<SelectedProxy>;
+
+    /// Score of `result` among `results` as shown by `ProxySelector::explain_selection`,
+    /// higher for proxies this strategy prefers. Speed unless overridden.
+    fn score(&self, result: &ProxyTestResult, _results: &[ProxyTestResult]) -> f64 {
+        result.speed_bytes_per_sec
+    }
+
+    /// What the strategy looks for, as in "selected: highest download speed"
+    fn describe(&self) -> &str {
+        "first pick of the selection strategy"
+    }
+}
+
+fn selected(result: &ProxyTestResult) -> SelectedProxy {
+    SelectedProxy {
+        proxy: result.proxy.clone(),
+        speed_bytes_per_sec: result.speed_bytes_per_sec,
+        selected_at: Instant::now(),
+    }
+}
+
+fn top_by(
+    results: &[ProxyTestResult],
+    count: usize,
+    compare: impl FnMut(&ProxyTestResult, &ProxyTestResult) -> Ordering,
+) -> Vec<SelectedProxy> {
+    let mut ranked = results.to_vec();
+    ranked.sort_by(compare);
+    ranked.iter().take(count).map(selected).collect()
+}
+
+/// Highest throughput first (the default)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FastestStrategy;
+
+impl SelectionStrategy for FastestStrategy {
+    fn select(&self, results: &[ProxyTestResult], count: usize) -> Vec<SelectedProxy> {
+        top_by(results, count, compare_by_speed)
+    }
+
+    fn describe(&self) -> &str {
+        "highest download speed"
+    }
+}
+
+/// Lowest latency first, for interactive browsing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LowestLatencyStrategy;
+
+impl SelectionStrategy for LowestLatencyStrategy {
+    fn select(&self, results: &[ProxyTestResult], count: usize) -> Vec<SelectedProxy> {
+        top_by(results, count, compare_by_latency)
+    }
+
+    /// The lowest latency among `results` relative to this one: 1 for the quickest
+    fn score(&self, result: &ProxyTestResult, results: &[ProxyTestResult]) -> f64 {
+        let min_latency = results.iter().map(|r| r.latency_ms).fold(f64::INFINITY, f64::min);
+        if result.latency_ms > 0.0 { min_latency.max(0.0) / result.latency_ms } else { 1.0 }
+    }
+
+    fn describe(&self) -> &str {
+        "lowest latency"
+    }
+}
+
+/// Balances throughput and latency. Each proxy scores `speed_weight` times its speed relative
+/// to the fastest plus `latency_weight` times the lowest latency relative to its own; the
+/// highest score comes first.
+#[derive(Debug, Clone, Copy)]
+pub struct WeightedStrategy {
+    pub speed_weight: f64,
+    pub latency_weight: f64,
+}
+
+impl Default for WeightedStrategy {
+    fn default() -> Self {
+        Self::new(0.5, 0.5)
+    }
+}
+
+impl WeightedStrategy {
+    pub fn new(speed_weight: f64, latency_weight: f64) -> Self {
+        Self {
+            speed_weight: speed_weight.max(0.0),
+            latency_weight: latency_weight.max(0.0),
+        }
+    }
+
+    fn weighted_score(&self, result: &ProxyTestResult, max_speed: f64, min_latency: f64) -> f64 {
+        let speed = if max_speed > 0.0 { result.speed_bytes_per_sec / max_speed } else { 0.0 };
+        let latency = if result.latency_ms > 0.0 { min_latency.max(0.0) / result.latency_ms } else { 1.0 };
+        self.speed_weight * speed + self.latency_weight * latency
+    }
+}
+
+impl SelectionStrategy for WeightedStrategy {
+    fn select(&self, results: &[ProxyTestResult], count: usize) -> Vec<SelectedProxy> {
+        let max_speed = results.iter().map(|r| r.speed_bytes_per_sec).fold(0.0, f64::max);
+        let min_latency = results.iter().map(|r| r.latency_ms).fold(f64::INFINITY, f64::min);
+        top_by(results, count, |a, b| {
+            self.weighted_score(b, max_speed, min_latency)
+                .partial_cmp(&self.weighted_score(a, max_speed, min_latency))
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| compare_by_speed(a, b))
+        })
+    }
+
+    fn score(&self, result: &ProxyTestResult, results: &[ProxyTestResult]) -> f64 {
+        let max_speed = results.iter().map(|r| r.speed_bytes_per_sec).fold(0.0, f64::max);
+        let min_latency = results.iter().map(|r| r.latency_ms).fold(f64::INFINITY, f64::min);
+        self.weighted_score(result, max_speed, min_latency)
+    }
+
+    fn describe(&self) -> &str {
+        "best balance of speed and latency"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy_manager::Proxy;
+
+    fn results() -> Vec<ProxyTestResult> {
+        let proxy = |host: &str| Proxy::new(host.to_string(), 443);
+        vec![
+            ProxyTestResult::succeeded(proxy("bulk.i2p"), 10000.0, 1000.0),
+            ProxyTestResult::succeeded(proxy("snappy.i2p"), 1000.0, 50.0),
+            ProxyTestResult::succeeded(proxy("balanced.i2p"), 8000.0, 100.0),
+        ]
+    }
+
+    fn hosts(selected: &[SelectedProxy]) -> Vec<&str> {
+        selected.iter().map(|s| s.proxy.host.as_str()).collect()
+    }
+
+    #[test]
+    fn test_builtin_strategies() {
+        let results = results();
+        assert_eq!(hosts(&FastestStrategy.select(&results, 3)), vec!["bulk.i2p", "balanced.i2p", "snappy.i2p"]);
+        assert_eq!(hosts(&LowestLatencyStrategy.select(&results, 2)), vec!["snappy.i2p", "balanced.i2p"]);
+        // 0.5 * 0.8 + 0.5 * 0.5 beats both extremes
+        assert_eq!(hosts(&WeightedStrategy::default().select(&results, 1)), vec!["balanced.i2p"]);
+        assert_eq!(hosts(&WeightedStrategy::new(1.0, 0.0).select(&results, 1)), vec!["bulk.i2p"]);
+        assert!(FastestStrategy.select(&[], 3).is_empty());
+    }
+}
@@ -0,0 +1,371 @@
+//! Local HTTP fixtures shared by the unit tests.
+//!
+//! `MockServer` speaks just enough HTTP/1.1 to stand in for a target site or for a plain
+//! HTTP proxy (requests arrive in absolute form, which is recorded as-is in `target`).
+//! `FakeRouter` replaces the embedded i2pd, usually pointing at a `MockServer`.
+
+use crate::i2pd_router::Router;
+use crate::proxy_manager::{Proxy, ProxyType};
+use parking_lot::Mutex;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+
+/// A request as received by the mock server
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: String,
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Address the client connected from
+    pub peer: SocketAddr,
+}
+
+impl MockRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Canned response, optionally delayed or stalled to simulate slow proxies
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub delay: Duration,
+    pub stall_after_headers: bool,
+    pub chunks: Option<(usize, Duration)>,
+    pub unknown_length: bool,
+}
+
+impl MockResponse {
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+            delay: Duration::ZERO,
+            stall_after_headers: false,
+            chunks: None,
+            unknown_length: false,
+        }
+    }
+
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Self::new(200, body)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Wait before sending anything, like a proxy that is slow to answer
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Send the headers, then never send the body
+    pub fn stall_after_headers(mut self) -> Self {
+        self.stall_after_headers = true;
+        self
+    }
+
+    /// Send the body in pieces of `size` bytes with `pause` after each one
+    pub fn chunked(mut self, size: usize, pause: Duration) -> Self {
+        self.chunks = Some((size.max(1), pause));
+        self
+    }
+
+    /// Leave out Content-Length, so the body ends when the connection closes
+    pub fn unknown_length(mut self) -> Self {
+        self.unknown_length = true;
+        self
+    }
+}
+
+pub struct MockServer {
+    pub addr: SocketAddr,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+}
+
+impl MockServer {
+    /// Start a server answering every request with whatever `handler` returns
+    pub async fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&MockRequest) -> MockResponse + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler = Arc::new(handler);
+
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = handler.clone();
+                let recorded = recorded.clone();
+                tokio::spawn(async move {
+                    let _ = serve_connection(stream, handler, recorded).await;
+                });
+            }
+        });
+
+        Self { addr, requests }
+    }
+
+    /// Start a server answering every request with the same response
+    pub async fn respond_with(response: MockResponse) -> Self {
+        Self::start(move |_| response.clone()).await
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// This server used as a plain HTTP proxy
+    pub fn proxy(&self) -> Proxy {
+        Proxy::new_with_type("127.0.0.1".to_string(), self.addr.port(), ProxyType::Http)
+    }
+
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().clone()
+    }
+}
+
+/// Router whose proxies listen on the given ports and which never touches i2pd
+pub struct FakeRouter {
+    http_port: u16,
+    https_port: u16,
+    fail_to_start: bool,
+    start_delay: Duration,
+    ensure_running_calls: AtomicUsize,
+    restart_calls: AtomicUsize,
+    shutdown: Arc<Notify>,
+}
+
+impl FakeRouter {
+    pub fn new(http_port: u16, https_port: u16) -> Self {
+        Self {
+            http_port,
+            https_port,
+            fail_to_start: false,
+            start_delay: Duration::ZERO,
+            ensure_running_calls: AtomicUsize::new(0),
+            restart_calls: AtomicUsize::new(0),
+            shutdown: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Make `ensure_running` fail like a router that cannot start
+    pub fn failing(mut self) -> Self {
+        self.fail_to_start = true;
+        self
+    }
+
+    /// Make `ensure_running` block for `delay`, like a router still building tunnels
+    pub fn start_delay(mut self, delay: Duration) -> Self {
+        self.start_delay = delay;
+        self
+    }
+
+    pub fn ensure_running_calls(&self) -> usize {
+        self.ensure_running_calls.load(Ordering::SeqCst)
+    }
+
+    pub fn restart_calls(&self) -> usize {
+        self.restart_calls.load(Ordering::SeqCst)
+    }
+
+    /// Signal shutdown like `I2PDRouter::stop` does
+    pub fn begin_shutdown(&self) {
+        self.shutdown.notify_waiters();
+    }
+}
+
+impl Router for FakeRouter {
+    fn ensure_running(&self) -> Result<(), String> {
+        self.ensure_running_calls.fetch_add(1, Ordering::SeqCst);
+        std::thread::sleep(self.start_delay);
+        if self.fail_to_start {
+            Err("fake router refused to start".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        !self.fail_to_start
+    }
+
+    fn http_proxy_port(&self) -> u16 {
+        self.http_port
+    }
+
+    fn https_proxy_port(&self) -> u16 {
+        self.https_port
+    }
+
+    fn stop(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn restart(&self) -> Result<(), String> {
+        self.restart_calls.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn shutdown_signal(&self) -> Arc<Notify> {
+        self.shutdown.clone()
+    }
+}
+
+fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn decode_chunked(mut raw: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    while let Some(line_end) = find_subsequence(raw, b"\r\n") {
+        let size_line = String::from_utf8_lossy(&raw[..line_end]);
+        let size = usize::from_str_radix(size_line.split(';').next().unwrap_or("0").trim(), 16).unwrap_or(0);
+        raw = &raw[line_end + 2..];
+        if size == 0 || raw.len() < size {
+            break;
+        }
+        body.extend_from_slice(&raw[..size]);
+        raw = &raw[(size + 2).min(raw.len())..];
+    }
+    body
+}
+
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Option<MockRequest>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    let header_end = loop {
+        if let Some(pos) = find_subsequence(&buf, b"\r\n\r\n") {
+            break pos + 4;
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        // Not HTTP (a SOCKS5 greeting): hang up like an HTTP-only proxy would
+        if buf[0] == 0x05 {
+            return Ok(None);
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_string();
+    let target = request_line.next().unwrap_or_default().to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    let header_value = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.to_lowercase())
+    };
+    let is_chunked = header_value("transfer-encoding").is_some_and(|v| v.contains("chunked"));
+    let content_length = header_value("content-length")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    let mut body = buf[header_end..].to_vec();
+    if is_chunked {
+        while find_subsequence(&body, b"0\r\n\r\n").is_none() {
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..n]);
+        }
+        body = decode_chunked(&body);
+    } else {
+        while body.len() < content_length {
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..n]);
+        }
+        body.truncate(content_length);
+    }
+
+    Ok(Some(MockRequest {
+        method,
+        target,
+        headers,
+        body,
+        peer: stream.peer_addr()?,
+    }))
+}
+
+async fn serve_connection<F>(
+    mut stream: TcpStream,
+    handler: Arc<F>,
+    recorded: Arc<Mutex<Vec<MockRequest>>>,
+) -> std::io::Result<()>
+where
+    F: Fn(&MockRequest) -> MockResponse + Send + Sync + 'static,
+{
+    let request = match read_request(&mut stream).await? {
+        Some(request) => request,
+        None => return Ok(()),
+    };
+    recorded.lock().push(request.clone());
+    let response = handler(&request);
+
+    if !response.delay.is_zero() {
+        tokio::time::sleep(response.delay).await;
+    }
+
+    let mut head = format!("HTTP/1.1 {} Mock\r\n", response.status);
+    for (key, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", key, value));
+    }
+    let has_length = response
+        .headers
+        .iter()
+        .any(|(key, _)| key.eq_ignore_ascii_case("content-length"));
+    if !has_length && !response.unknown_length {
+        head.push_str(&format!("Content-Length: {}\r\n", response.body.len()));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    stream.flush().await?;
+
+    if response.stall_after_headers {
+        std::future::pending::<()>().await;
+    }
+
+    match response.chunks {
+        Some((size, pause)) => {
+            for piece in response.body.chunks(size) {
+                stream.write_all(piece).await?;
+                stream.flush().await?;
+                tokio::time::sleep(pause).await;
+            }
+        }
+        None => stream.write_all(&response.body).await?,
+    }
+    stream.shutdown().await
+}
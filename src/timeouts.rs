@@ -0,0 +1,88 @@
+use std::time::Duration;
+use tracing::warn;
+
+/// Default timeouts for everything that talks to the network.
+///
+/// Precedence, strongest first: `RequestConfig::timeout_secs` on a single request, then the
+/// `TimeoutConfig` given to the handler, tester or manager, then the built-in defaults.
+/// `TimeoutConfig::from_env` starts from the defaults and applies any `I2PTUNNEL_*_TIMEOUT_SECS`
+/// variables that are set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeoutConfig {
+    /// Requests through clearnet proxies
+    pub clearnet_request: Duration,
+    /// Requests to I2P sites through the router
+    pub i2p_request: Duration,
+    /// Clearnet requests through an I2P outproxy via the router, usually large downloads
+    pub outproxy_request: Duration,
+    /// A single proxy speed test
+    pub proxy_test: Duration,
+    /// Fetching the proxy list
+    pub fetch: Duration,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            clearnet_request: Duration::from_secs(60),
+            i2p_request: Duration::from_secs(60),
+            outproxy_request: Duration::from_secs(300),
+            proxy_test: Duration::from_secs(10),
+            fetch: Duration::from_secs(30),
+        }
+    }
+}
+
+impl TimeoutConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defaults overridden by `I2PTUNNEL_CLEARNET_TIMEOUT_SECS`, `I2PTUNNEL_I2P_TIMEOUT_SECS`,
+    /// `I2PTUNNEL_OUTPROXY_TIMEOUT_SECS`, `I2PTUNNEL_TEST_TIMEOUT_SECS` and
+    /// `I2PTUNNEL_FETCH_TIMEOUT_SECS`. Unparseable values are ignored with a warning.
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let mut config = Self::default();
+        let fields: [(&str, &mut Duration); 5] = [
+            ("I2PTUNNEL_CLEARNET_TIMEOUT_SECS", &mut config.clearnet_request),
+            ("I2PTUNNEL_I2P_TIMEOUT_SECS", &mut config.i2p_request),
+            ("I2PTUNNEL_OUTPROXY_TIMEOUT_SECS", &mut config.outproxy_request),
+            ("I2PTUNNEL_TEST_TIMEOUT_SECS", &mut config.proxy_test),
+            ("I2PTUNNEL_FETCH_TIMEOUT_SECS", &mut config.fetch),
+        ];
+        for (name, field) in fields {
+            if let Some(value) = lookup(name) {
+                match value.trim().parse::<u64>() {
+                    Ok(secs) if secs > 0 => *field = Duration::from_secs(secs),
+                    _ => warn!("Ignoring {}={:?}: expected a positive number of seconds", name, value),
+                }
+            }
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_overrides_defaults() {
+        let config = TimeoutConfig::from_vars(|name| match name {
+            "I2PTUNNEL_I2P_TIMEOUT_SECS" => Some("120".to_string()),
+            "I2PTUNNEL_TEST_TIMEOUT_SECS" => Some("soon".to_string()),
+            "I2PTUNNEL_FETCH_TIMEOUT_SECS" => Some("0".to_string()),
+            _ => None,
+        });
+
+        assert_eq!(config.i2p_request, Duration::from_secs(120));
+        // Invalid values keep the defaults
+        assert_eq!(config.proxy_test, Duration::from_secs(10));
+        assert_eq!(config.fetch, Duration::from_secs(30));
+        assert_eq!(config.clearnet_request, TimeoutConfig::default().clearnet_request);
+    }
+}